@@ -0,0 +1,372 @@
+use glfw::{Action, Key};
+
+use ash::vk;
+
+use nalgebra_glm as glm;
+
+use trekanten::mesh;
+use trekanten::pipeline;
+use trekanten::texture;
+use trekanten::uniform;
+use trekanten::Handle;
+use trekanten::ResourceManager;
+
+const GRID_SIZE: usize = 32;
+
+const VERT_GLSL: &str = r"
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} u_matrices;
+
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec3 col;
+layout(location = 2) in vec2 tex_coord;
+
+// Per instance
+layout(location = 3) in vec3 instance_offset;
+layout(location = 4) in vec3 instance_color;
+
+layout(location = 0) out vec3 frag_color;
+layout(location = 1) out vec2 frag_tex_coord;
+
+void main() {
+    vec4 world_pos = u_matrices.model * vec4(pos, 1.0) + vec4(instance_offset, 0.0);
+    gl_Position = u_matrices.proj * u_matrices.view * world_pos;
+    frag_color = col * instance_color;
+    frag_tex_coord = tex_coord;
+}
+";
+
+const FRAG_GLSL: &str = r"
+#version 450
+
+layout(set = 0, binding = 1) uniform sampler2D u_color_map;
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = texture(u_color_map, frag_tex_coord) * vec4(frag_color, 1.0);
+}
+";
+
+#[repr(C, packed)]
+struct Vertex {
+    pos: glm::Vec3,
+    col: glm::Vec3,
+    tex_coord: glm::Vec2,
+}
+
+impl trekanten::vertex::VertexDefinition for Vertex {
+    fn binding_description() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    fn attribute_description() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: memoffset::offset_of!(Vertex, pos) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: memoffset::offset_of!(Vertex, col) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: memoffset::offset_of!(Vertex, tex_coord) as u32,
+            },
+        ]
+    }
+}
+
+#[repr(C, packed)]
+struct Instance {
+    offset: glm::Vec3,
+    color: glm::Vec3,
+}
+
+// Binding 0 here becomes binding 1 in the pipeline, after the vertex binding
+impl trekanten::vertex::VertexDefinition for Instance {
+    fn binding_description() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Instance>() as u32,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }]
+    }
+
+    fn attribute_description() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 3,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: memoffset::offset_of!(Instance, offset) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 4,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: memoffset::offset_of!(Instance, color) as u32,
+            },
+        ]
+    }
+}
+
+#[repr(C)]
+struct UniformBufferObject {
+    model: glm::Mat4,
+    view: glm::Mat4,
+    proj: glm::Mat4,
+}
+
+fn cube() -> (Vec<Vertex>, Vec<u16>) {
+    let corners = [
+        [-0.5, -0.5, -0.5],
+        [0.5, -0.5, -0.5],
+        [0.5, 0.5, -0.5],
+        [-0.5, 0.5, -0.5],
+        [-0.5, -0.5, 0.5],
+        [0.5, -0.5, 0.5],
+        [0.5, 0.5, 0.5],
+        [-0.5, 0.5, 0.5],
+    ];
+
+    let vertices = corners
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Vertex {
+            pos: glm::vec3(c[0], c[1], c[2]),
+            col: glm::vec3(1.0, 1.0, 1.0),
+            tex_coord: glm::vec2((i % 2) as f32, ((i / 2) % 2) as f32),
+        })
+        .collect();
+
+    #[rustfmt::skip]
+    let indices = vec![
+        0, 2, 1, 0, 3, 2,
+        4, 5, 6, 4, 6, 7,
+        0, 1, 5, 0, 5, 4,
+        3, 7, 6, 3, 6, 2,
+        0, 4, 7, 0, 7, 3,
+        1, 2, 6, 1, 6, 5,
+    ];
+
+    (vertices, indices)
+}
+
+fn grid() -> Vec<Instance> {
+    let spacing = 1.5;
+    let half = GRID_SIZE as f32 * spacing / 2.0;
+    let mut instances = Vec::with_capacity(GRID_SIZE * GRID_SIZE);
+    for x in 0..GRID_SIZE {
+        for y in 0..GRID_SIZE {
+            let u = x as f32 / GRID_SIZE as f32;
+            let v = y as f32 / GRID_SIZE as f32;
+            instances.push(Instance {
+                offset: glm::vec3(x as f32 * spacing - half, y as f32 * spacing - half, 0.0),
+                color: glm::vec3(u, v, 1.0 - u),
+            });
+        }
+    }
+
+    instances
+}
+
+/// Compile to SPIR-V files in a temporary directory. Absolute shader paths are used as is by the
+/// pipeline descriptor.
+fn compile_shaders() -> (std::path::PathBuf, std::path::PathBuf) {
+    let dir = std::env::temp_dir().join("trekanten_instancing");
+    std::fs::create_dir_all(&dir).expect("Failed to create shader dir");
+
+    let mut compiler = shaderc::Compiler::new().expect("Failed to initialize shaderc");
+    let mut compile = |source, kind, name| {
+        let artifact = compiler
+            .compile_into_spirv(source, kind, name, "main", None)
+            .expect("Failed to compile shader");
+        let path = dir.join(name);
+        std::fs::write(&path, artifact.as_binary_u8()).expect("Failed to write shader");
+        path
+    };
+
+    let vert = compile(
+        VERT_GLSL,
+        shaderc::ShaderKind::Vertex,
+        "instancing.vert.spv",
+    );
+    let frag = compile(
+        FRAG_GLSL,
+        shaderc::ShaderKind::Fragment,
+        "instancing.frag.spv",
+    );
+
+    (vert, frag)
+}
+
+fn handle_window_event(window: &mut glfw::Window, event: glfw::WindowEvent) {
+    match event {
+        glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => window.set_should_close(true),
+        _ => {}
+    }
+}
+
+fn get_next_mvp(start: &std::time::Instant, aspect_ratio: f32) -> UniformBufferObject {
+    let time = std::time::Instant::now() - *start;
+    let time = time.as_secs_f32();
+
+    UniformBufferObject {
+        model: glm::rotate(
+            &glm::identity(),
+            time * std::f32::consts::FRAC_PI_2,
+            &glm::vec3(1.0, 1.0, 0.0),
+        ),
+        view: glm::look_at(
+            &glm::vec3(0.0, -40.0, 40.0),
+            &glm::vec3(0.0, 0.0, 0.0),
+            &glm::vec3(0.0, 0.0, 1.0),
+        ),
+        proj: glm::perspective_zo(aspect_ratio, std::f32::consts::FRAC_PI_4, 0.1, 200.0),
+    }
+}
+
+fn main() -> Result<(), trekanten::RenderError> {
+    env_logger::init();
+
+    let (vertices, indices) = cube();
+    let instances = grid();
+    let (vert_path, frag_path) = compile_shaders();
+
+    let mut window = trekanten::window::GlfwWindow::new();
+    let mut renderer = trekanten::Renderer::new(&window)?;
+
+    let vertex_buffer_handle: Handle<mesh::VertexBuffer> = renderer
+        .create_resource(mesh::VertexBufferDescriptor::from_slice(&vertices))
+        .expect("Failed to create vertex buffer");
+
+    let instance_buffer_handle: Handle<mesh::VertexBuffer> = renderer
+        .create_resource(mesh::VertexBufferDescriptor::from_slice(&instances))
+        .expect("Failed to create instance buffer");
+
+    let index_buffer_handle = renderer
+        .create_resource(mesh::IndexBufferDescriptor::from_slice(&indices))
+        .expect("Failed to create index buffer");
+
+    let pipeline_descriptor = pipeline::GraphicsPipelineDescriptor::builder()
+        .vertex_shader(vert_path)
+        .fragment_shader(frag_path)
+        .vertex_type::<Vertex>()
+        .instance_type::<Instance>()
+        .flip_viewport_y(true)
+        .build()
+        .expect("Failed to create graphics pipeline desc");
+
+    let gfx_pipeline_handle = renderer
+        .create_resource(pipeline_descriptor)
+        .expect("Failed to create graphics pipeline");
+
+    let uniform_buffer_handle = renderer
+        .create_resource(uniform::UniformBufferDescriptor::uninitialized::<
+            UniformBufferObject,
+        >(1))
+        .expect("Failed to create uniform buffer");
+
+    #[rustfmt::skip]
+    let checker = vec![
+        255, 255, 255, 255,  64, 64, 64, 255,
+         64,  64,  64, 255, 255, 255, 255, 255,
+    ];
+    let texture_handle = renderer
+        .create_resource(texture::TextureDescriptor::from_raw(
+            checker,
+            2,
+            2,
+            vk::Format::R8G8B8A8_SRGB,
+            false,
+        ))
+        .expect("Failed to create texture");
+
+    let desc_set_handle = renderer
+        .create_descriptor_set(
+            &gfx_pipeline_handle,
+            &uniform_buffer_handle,
+            &texture_handle,
+        )
+        .expect("Failed to create descriptor set");
+
+    let start = std::time::Instant::now();
+    let mut last = start;
+    while !window.window.should_close() {
+        let now = std::time::Instant::now();
+        window.set_frame_ms(now - last);
+        last = now;
+
+        window.glfw.poll_events();
+        for (_, event) in glfw::flush_messages(&window.events) {
+            handle_window_event(&mut window.window, event);
+        }
+
+        let result = renderer.render_frame(&window, |frame, renderer| {
+            let next_mvp = get_next_mvp(&start, renderer.aspect_ratio());
+            renderer
+                .update_uniform(frame, &uniform_buffer_handle, &next_mvp)
+                .expect("Failed to update uniform buffer!");
+
+            let render_pass = renderer.render_pass();
+            let extent = renderer.render_extent();
+            let framebuffer = renderer.framebuffer(frame);
+
+            let gfx_pipeline = renderer
+                .get_resource(&gfx_pipeline_handle)
+                .expect("Missing graphics pipeline");
+            let index_buffer = renderer
+                .get_resource(&index_buffer_handle)
+                .expect("Missing index buffer");
+            let vertex_buffer = renderer
+                .get_resource(&vertex_buffer_handle)
+                .expect("Missing vertex buffer");
+            let instance_buffer = renderer
+                .get_resource(&instance_buffer_handle)
+                .expect("Missing instance buffer");
+            let desc_set = renderer
+                .get_descriptor_set(&desc_set_handle)
+                .expect("Missing descriptor set");
+
+            let cmd_buf = frame
+                .new_command_buffer()?
+                .begin_render_pass(render_pass, framebuffer, extent)
+                .bind_graphics_pipeline(&gfx_pipeline)
+                .bind_descriptor_set(&desc_set, &gfx_pipeline)
+                .bind_index_buffer(&index_buffer)
+                .bind_vertex_buffers(0, &[vertex_buffer, instance_buffer], &[0, 0])
+                .draw_indexed(indices.len() as u32, instances.len() as u32)
+                .end_render_pass()
+                .end()?;
+
+            frame.add_command_buffer(cmd_buf)
+        });
+
+        match result {
+            Err(trekanten::RenderError::Minimized) => window.glfw.wait_events(),
+            x => x?,
+        }
+    }
+
+    Ok(())
+}
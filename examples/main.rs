@@ -1,17 +1,15 @@
 use glfw::{Action, Key};
 
-use ash::vk;
-
 use nalgebra_glm as glm;
 
 use trekanten::mesh;
 use trekanten::pipeline;
 use trekanten::texture;
 use trekanten::uniform;
-use trekanten::window::Window;
 use trekanten::Handle;
 use trekanten::ResourceManager;
 
+#[derive(trekanten::vertex::Vertex)]
 #[repr(C, packed)]
 struct Vertex {
     pos: glm::Vec3,
@@ -19,39 +17,6 @@ struct Vertex {
     tex_coord: glm::Vec2,
 }
 
-impl trekanten::vertex::VertexDefinition for Vertex {
-    fn binding_description() -> Vec<vk::VertexInputBindingDescription> {
-        vec![vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: std::mem::size_of::<Vertex>() as u32,
-            input_rate: vk::VertexInputRate::VERTEX,
-        }]
-    }
-
-    fn attribute_description() -> Vec<vk::VertexInputAttributeDescription> {
-        vec![
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: memoffset::offset_of!(Vertex, pos) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: memoffset::offset_of!(Vertex, col) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: memoffset::offset_of!(Vertex, tex_coord) as u32,
-            },
-        ]
-    }
-}
-
 #[repr(C)]
 struct UniformBufferObject {
     model: glm::Mat4,
@@ -136,7 +101,6 @@ fn load_viking_house() -> (Vec<Vertex>, Vec<u32>) {
 
 // TODO:
 // * Handle window requested resize
-// * Wait while minimized
 fn handle_window_event(window: &mut glfw::Window, event: glfw::WindowEvent) {
     match event {
         glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => window.set_should_close(true),
@@ -148,7 +112,7 @@ fn get_next_mvp(start: &std::time::Instant, aspect_ratio: f32) -> UniformBufferO
     let time = std::time::Instant::now() - *start;
     let time = time.as_secs_f32();
 
-    let mut ubo = UniformBufferObject {
+    UniformBufferObject {
         model: glm::rotate(
             &glm::identity(),
             time * std::f32::consts::FRAC_PI_2,
@@ -160,11 +124,7 @@ fn get_next_mvp(start: &std::time::Instant, aspect_ratio: f32) -> UniformBufferO
             &glm::vec3(0.0, 0.0, 1.0),
         ),
         proj: glm::perspective_zo(aspect_ratio, std::f32::consts::FRAC_PI_4, 0.1, 10.0),
-    };
-
-    ubo.proj[(1, 1)] *= -1.0;
-
-    ubo
+    }
 }
 
 fn main() -> Result<(), trekanten::RenderError> {
@@ -175,20 +135,16 @@ fn main() -> Result<(), trekanten::RenderError> {
     let mut window = trekanten::window::GlfwWindow::new();
     let mut renderer = trekanten::Renderer::new(&window)?;
 
-    let vertex_buffer_descriptor = mesh::VertexBufferDescriptor::from_slice(&vertices);
-    let vertex_buffer_handle: Handle<mesh::VertexBuffer> = renderer
-        .create_resource(vertex_buffer_descriptor)
-        .expect("Failed to create vertex buffer");
-
-    let index_buffer_descriptor = mesh::IndexBufferDescriptor::from_slice(&indices);
-    let index_buffer_handle = renderer
-        .create_resource(index_buffer_descriptor)
-        .expect("Failed to create index buffer");
+    let mesh_descriptor = mesh::MeshDescriptor::from_slices(&vertices, &indices);
+    let mesh_handle: Handle<mesh::Mesh> = renderer
+        .create_resource(mesh_descriptor)
+        .expect("Failed to create mesh");
 
     let pipeline_descriptor = pipeline::GraphicsPipelineDescriptor::builder()
         .vertex_shader("vert.spv")
         .fragment_shader("frag.spv")
         .vertex_type::<Vertex>()
+        .flip_viewport_y(true)
         .build()
         .expect("Failed to create graphics pipeline desc");
 
@@ -206,7 +162,7 @@ fn main() -> Result<(), trekanten::RenderError> {
     let _ = load_url("textures", TEX_URL);
     let tex_path = get_fname("textures", TEX_URL);
     let texture_handle = renderer
-        .create_resource(texture::TextureDescriptor::new(tex_path.into()))
+        .create_resource(texture::TextureDescriptor::new(tex_path.into(), true))
         .expect("Failed to create texture");
 
     let desc_set_handle = renderer
@@ -230,58 +186,42 @@ fn main() -> Result<(), trekanten::RenderError> {
             handle_window_event(&mut window.window, event);
         }
 
-        let mut frame = match renderer.next_frame() {
-            Err(trekanten::RenderError::NeedsResize(reason)) => {
-                log::info!("Resize reason: {:?}", reason);
-                renderer.resize(window.extents())?;
-                renderer.next_frame()
-            }
-            x => x,
-        }?;
-
-        let next_mvp = get_next_mvp(&start, renderer.aspect_ratio());
-        renderer
-            .update_uniform(&uniform_buffer_handle, &next_mvp)
-            .expect("Failed to update uniform buffer!");
-
-        let render_pass = renderer.render_pass();
-        let extent = renderer.swapchain_extent();
-        let framebuffer = renderer.framebuffer(&frame);
-
-        let gfx_pipeline = renderer
-            .get_resource(&gfx_pipeline_handle)
-            .expect("Missing graphics pipeline");
-        let index_buffer = renderer
-            .get_resource(&index_buffer_handle)
-            .expect("Missing index buffer");
-        let vertex_buffer = renderer
-            .get_resource(&vertex_buffer_handle)
-            .expect("Missing vertex buffer");
-        let desc_set = renderer
-            .get_descriptor_set(&desc_set_handle)
-            .expect("Missing descriptor set");
-
-        let cmd_buf = frame
-            .new_command_buffer()?
-            .begin_render_pass(render_pass, framebuffer, extent)
-            .bind_graphics_pipeline(&gfx_pipeline)
-            .bind_descriptor_set(&desc_set, &gfx_pipeline)
-            .bind_index_buffer(&index_buffer)
-            .bind_vertex_buffer(&vertex_buffer)
-            .draw_indexed(indices.len() as u32)
-            .end_render_pass()
-            .end()?;
-
-        frame.add_command_buffer(cmd_buf);
-
-        renderer.submit(frame).or_else(|e| {
-            if let trekanten::RenderError::NeedsResize(reason) = e {
-                log::info!("Resize reason: {:?}", reason);
-                renderer.resize(window.extents())
-            } else {
-                Err(e)
-            }
-        })?;
+        let result = renderer.render_frame(&window, |frame, renderer| {
+            let next_mvp = get_next_mvp(&start, renderer.aspect_ratio());
+            renderer
+                .update_uniform(frame, &uniform_buffer_handle, &next_mvp)
+                .expect("Failed to update uniform buffer!");
+
+            let render_pass = renderer.render_pass();
+            let extent = renderer.render_extent();
+            let framebuffer = renderer.framebuffer(frame);
+
+            let gfx_pipeline = renderer
+                .get_resource(&gfx_pipeline_handle)
+                .expect("Missing graphics pipeline");
+            let mesh = renderer.get_resource(&mesh_handle).expect("Missing mesh");
+            let desc_set = renderer
+                .get_descriptor_set(&desc_set_handle)
+                .expect("Missing descriptor set");
+
+            let cmd_buf = frame
+                .new_command_buffer()?
+                .begin_render_pass(render_pass, framebuffer, extent)
+                .bind_graphics_pipeline(&gfx_pipeline)
+                .bind_descriptor_set(&desc_set, &gfx_pipeline)
+                .draw_mesh(&mesh)
+                .end_render_pass()
+                .end()?;
+
+            frame.add_command_buffer(cmd_buf)
+        });
+
+        match result {
+            // Block until there are new events, one of them might be the window being restored.
+            // render_frame resizes when the extent of the window has changed.
+            Err(trekanten::RenderError::Minimized) => window.glfw.wait_events(),
+            x => x?,
+        }
     }
 
     Ok(())
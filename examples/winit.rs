@@ -0,0 +1,63 @@
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+
+use trekanten::window::Window;
+use trekanten::window::WinitWindow;
+use trekanten::RenderError;
+use trekanten::Renderer;
+
+fn draw(renderer: &mut Renderer, window: &WinitWindow) -> Result<(), RenderError> {
+    let result = renderer.render_frame(window, |frame, renderer| {
+        let render_pass = renderer.render_pass();
+        let extent = renderer.render_extent();
+        let framebuffer = renderer.framebuffer(frame);
+
+        let cmd_buf = frame
+            .new_command_buffer()?
+            .begin_render_pass(render_pass, framebuffer, extent)
+            .end_render_pass()
+            .end()?;
+
+        frame.add_command_buffer(cmd_buf)
+    });
+
+    match result {
+        // Resumes when the window is resized to a non-zero extent
+        Err(RenderError::Minimized) => Ok(()),
+        x => x,
+    }
+}
+
+fn main() -> Result<(), RenderError> {
+    env_logger::init();
+
+    let event_loop = EventLoop::new();
+    let window = WinitWindow::new(&event_loop);
+    let mut renderer = Renderer::new(&window)?;
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(_) => {
+                    renderer.resize(window.extents()).expect("Failed to resize")
+                }
+                _ => (),
+            },
+            Event::MainEventsCleared => window.window.request_redraw(),
+            Event::RedrawRequested(_) => draw(&mut renderer, &window).expect("Failed to render"),
+            _ => (),
+        }
+    });
+}
@@ -1,25 +1,38 @@
 use ash::version::DeviceV1_0;
 use ash::vk;
 
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
 use thiserror::Error;
 
 use crate::descriptor::DescriptorSet;
 use crate::device::Device;
 use crate::device::HasVkDevice;
 use crate::device::VkDeviceHandle;
+#[cfg(feature = "dynamic_rendering")]
+use crate::dynamic_rendering::{DynamicRenderingFn, RenderingAttachmentInfo, RenderingInfo};
 use crate::framebuffer::Framebuffer;
+#[cfg(feature = "dynamic_rendering")]
+use crate::image::ImageView;
 use crate::mesh::IndexBuffer;
+use crate::mesh::Mesh;
 use crate::mesh::VertexBuffer;
 use crate::pipeline::GraphicsPipeline;
 use crate::pipeline::Pipeline;
+use crate::query::TimestampQueryPool;
 use crate::queue::QueueFamily;
-use crate::render_pass::RenderPass;
+#[cfg(feature = "dynamic_rendering")]
+use crate::render_pass::default_clear_values;
+use crate::render_pass::{ClearValue, RenderPass};
 use crate::util;
 
 #[derive(Debug, Error)]
 pub enum CommandError {
     #[error("Command pool creation failed: {0}")]
     PoolCreation(vk::Result),
+    #[error("Command pool reset failed: {0}")]
+    PoolReset(vk::Result),
     #[error("Command buffer allocation failed: {0}")]
     BufferAlloc(vk::Result),
     #[error("Command buffer begin() failed: {0}")]
@@ -31,7 +44,11 @@ pub enum CommandError {
 pub struct CommandPool {
     queue_family: QueueFamily,
     vk_command_pool: vk::CommandPool,
+    // Freed on reset so that a reused pool doesn't keep growing
+    allocated: RefCell<Vec<vk::CommandBuffer>>,
     vk_device: VkDeviceHandle,
+    #[cfg(feature = "dynamic_rendering")]
+    dynamic_rendering: Option<DynamicRenderingFn>,
 }
 
 impl std::ops::Drop for CommandPool {
@@ -61,7 +78,10 @@ impl CommandPool {
         Ok(Self {
             queue_family: qfam,
             vk_command_pool,
+            allocated: RefCell::new(Vec::new()),
             vk_device,
+            #[cfg(feature = "dynamic_rendering")]
+            dynamic_rendering: device.dynamic_rendering_fn(),
         })
     }
 
@@ -73,6 +93,19 @@ impl CommandPool {
         Self::new(device, device.util_queue_family().clone())
     }
 
+    pub fn transfer(device: &Device) -> Result<Self, CommandError> {
+        Self::new(device, device.transfer_queue_family().clone())
+    }
+
+    pub fn queue_family(&self) -> &QueueFamily {
+        &self.queue_family
+    }
+
+    /// If `cmd_buffer` was allocated from this pool, since it was last reset
+    pub fn owns<S>(&self, cmd_buffer: &CommandBuffer<S>) -> bool {
+        self.allocated.borrow().contains(&cmd_buffer.vk_cmd_buffer)
+    }
+
     pub fn create_command_buffer(
         &self,
         submission_type: CommandBufferSubmission,
@@ -82,14 +115,14 @@ impl CommandPool {
         Ok(r.remove(0))
     }
 
-    pub fn create_command_buffers(
+    fn allocate(
         &self,
         amount: u32,
-        submission_type: CommandBufferSubmission,
-    ) -> Result<Vec<CommandBuffer>, CommandError> {
+        level: vk::CommandBufferLevel,
+    ) -> Result<Vec<vk::CommandBuffer>, CommandError> {
         let info = vk::CommandBufferAllocateInfo::builder()
             .command_pool(self.vk_command_pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
+            .level(level)
             .command_buffer_count(amount);
 
         let allocated = unsafe {
@@ -98,6 +131,38 @@ impl CommandPool {
                 .map_err(CommandError::BufferAlloc)?
         };
 
+        self.allocated.borrow_mut().extend_from_slice(&allocated);
+        Ok(allocated)
+    }
+
+    /// Free all command buffers allocated from this pool and return its memory to the system.
+    /// None of the command buffers may be pending execution.
+    pub fn reset(&self) -> Result<(), CommandError> {
+        let mut allocated = self.allocated.borrow_mut();
+        unsafe {
+            if !allocated.is_empty() {
+                self.vk_device
+                    .free_command_buffers(self.vk_command_pool, &allocated);
+            }
+            self.vk_device
+                .reset_command_pool(
+                    self.vk_command_pool,
+                    vk::CommandPoolResetFlags::RELEASE_RESOURCES,
+                )
+                .map_err(CommandError::PoolReset)?;
+        }
+        allocated.clear();
+
+        Ok(())
+    }
+
+    pub fn create_command_buffers(
+        &self,
+        amount: u32,
+        submission_type: CommandBufferSubmission,
+    ) -> Result<Vec<CommandBuffer>, CommandError> {
+        let allocated = self.allocate(amount, vk::CommandBufferLevel::PRIMARY)?;
+
         Ok(allocated
             .into_iter()
             .map(|vk_cmd_buf| {
@@ -106,11 +171,56 @@ impl CommandPool {
                     vk_cmd_buf,
                     self.queue_family.props.queue_flags,
                     submission_type,
+                    None,
                 )
+                .map(|cmd_buf| self.with_extension_fns(cmd_buf))
             })
             .collect::<Result<Vec<CommandBuffer>, CommandError>>()?)
     }
 
+    /// Create a secondary command buffer that is recorded to be executed inside `subpass` of
+    /// `render_pass`, using `framebuffer`. Execute it from a primary command buffer with
+    /// `CommandBuffer::execute_commands`, inside a render pass started with
+    /// `begin_render_pass_with_secondaries`.
+    pub fn create_secondary_command_buffer(
+        &self,
+        submission_type: CommandBufferSubmission,
+        render_pass: &RenderPass,
+        subpass: u32,
+        framebuffer: &Framebuffer,
+    ) -> Result<CommandBuffer<InRenderPass>, CommandError> {
+        let mut allocated = self.allocate(1, vk::CommandBufferLevel::SECONDARY)?;
+        debug_assert_eq!(allocated.len(), 1);
+
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(*render_pass.vk_render_pass())
+            .subpass(subpass)
+            .framebuffer(*framebuffer.vk_framebuffer());
+
+        CommandBuffer::new(
+            VkDeviceHandle::clone(&self.vk_device),
+            allocated.remove(0),
+            self.queue_family.props.queue_flags,
+            submission_type,
+            Some(&inheritance_info),
+        )
+        .map(|cmd_buf| self.with_extension_fns(cmd_buf))
+    }
+
+    // Extension entry points that the device has loaded, for the command buffers to record with
+    #[cfg(feature = "dynamic_rendering")]
+    fn with_extension_fns<S>(&self, cmd_buffer: CommandBuffer<S>) -> CommandBuffer<S> {
+        CommandBuffer {
+            dynamic_rendering: self.dynamic_rendering,
+            ..cmd_buffer
+        }
+    }
+
+    #[cfg(not(feature = "dynamic_rendering"))]
+    fn with_extension_fns<S>(&self, cmd_buffer: CommandBuffer<S>) -> CommandBuffer<S> {
+        cmd_buffer
+    }
+
     pub fn begin_single_submit(&self) -> Result<CommandBuffer, CommandError> {
         self.create_command_buffer(CommandBufferSubmission::Single)
     }
@@ -122,30 +232,97 @@ pub enum CommandBufferSubmission {
     Multi,
 }
 
-// TODO: That we have to call all of these through a device means that might mean that we can't
-// "easily" record command buffers on other threads?
-// TODO: Builder pattern?
-pub struct CommandBuffer {
+fn buffer_copy(src_offset: u64, dst_offset: u64, size: u64) -> vk::BufferCopy {
+    vk::BufferCopy {
+        src_offset,
+        dst_offset,
+        size,
+    }
+}
+
+fn clear_rect(rect: vk::Rect2D) -> vk::ClearRect {
+    vk::ClearRect {
+        rect,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+fn buffer_image_copy(
+    extent: &util::Extent2D,
+    buffer_offset: u64,
+    mip_level: u32,
+) -> vk::BufferImageCopy {
+    vk::BufferImageCopy {
+        buffer_offset,
+        // For e.g. padded rows
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        },
+    }
+}
+
+/// Command buffer state: outside of a render pass
+pub struct Recording;
+/// Command buffer state: inside a render pass, where draw commands can be recorded. Secondary
+/// command buffers are always in this state.
+pub struct InRenderPass;
+/// Command buffer state: recording has ended and it can be submitted
+pub struct Finished;
+
+// Thread-safety: All recording goes through the VkDeviceHandle, which is an Rc, so neither
+// CommandPool nor CommandBuffer is Send and both have to stay on the thread that created the Device.
+// Secondary command buffers can still be used to split up recording, but recording them in
+// parallel would require VkDeviceHandle to be an Arc and one CommandPool per thread, as Vulkan
+// requires command pools (and the buffers allocated from them) to be externally synchronized.
+/// A command buffer that has begun recording. The state `S` (`Recording`, `InRenderPass` or
+/// `Finished`) decides which commands are available, so that e.g. draws outside of a render pass
+/// don't compile.
+pub struct CommandBuffer<S = Recording> {
     queue_flags: vk::QueueFlags,
+    level: vk::CommandBufferLevel,
     vk_cmd_buffer: vk::CommandBuffer,
     vk_device: VkDeviceHandle,
-    is_started: bool,
+    // See CommandPool::with_extension_fns
+    #[cfg(feature = "dynamic_rendering")]
+    dynamic_rendering: Option<DynamicRenderingFn>,
+    _state: PhantomData<S>,
 }
 
-impl CommandBuffer {
+impl<S> CommandBuffer<S> {
     fn new(
         vk_device: VkDeviceHandle,
         vk_cmd_buffer: vk::CommandBuffer,
         queue_flags: vk::QueueFlags,
         submission_type: CommandBufferSubmission,
+        inheritance_info: Option<&vk::CommandBufferInheritanceInfo>,
     ) -> Result<Self, CommandError> {
-        let flags = match submission_type {
+        let mut flags = match submission_type {
             CommandBufferSubmission::Single => vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
             _ => vk::CommandBufferUsageFlags::empty(),
         };
 
+        let level = if inheritance_info.is_some() {
+            flags |= vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE;
+            vk::CommandBufferLevel::SECONDARY
+        } else {
+            vk::CommandBufferLevel::PRIMARY
+        };
+
         let info = vk::CommandBufferBeginInfo {
             flags,
+            p_inheritance_info: inheritance_info.map_or(std::ptr::null(), |i| i),
             ..Default::default()
         };
 
@@ -159,33 +336,81 @@ impl CommandBuffer {
             vk_cmd_buffer,
             vk_device,
             queue_flags,
-            is_started: true,
+            level,
+            #[cfg(feature = "dynamic_rendering")]
+            dynamic_rendering: None,
+            _state: PhantomData,
         })
     }
 
-    pub fn vk_command_buffer(&self) -> &vk::CommandBuffer {
-        &self.vk_cmd_buffer
-    }
-
-    pub fn is_started(&self) -> bool {
-        self.is_started
+    fn transition<T>(self) -> CommandBuffer<T> {
+        CommandBuffer {
+            queue_flags: self.queue_flags,
+            level: self.level,
+            vk_cmd_buffer: self.vk_cmd_buffer,
+            vk_device: self.vk_device,
+            #[cfg(feature = "dynamic_rendering")]
+            dynamic_rendering: self.dynamic_rendering,
+            _state: PhantomData,
+        }
     }
 
-    pub fn end(self) -> Result<Self, CommandError> {
+    fn end_any(self) -> Result<CommandBuffer<Finished>, CommandError> {
         unsafe {
             self.vk_device
                 .end_command_buffer(self.vk_cmd_buffer)
                 .map_err(CommandError::BufferEnd)?;
         }
-        Ok(self)
+        Ok(self.transition())
     }
 
-    pub fn begin_render_pass(
+    pub fn is_secondary(&self) -> bool {
+        self.level == vk::CommandBufferLevel::SECONDARY
+    }
+
+    /// Escape hatch for recording commands that are not wrapped by this type.
+    ///
+    /// # Safety
+    /// Whatever is recorded has to be valid in state `S` and leave the command buffer in that
+    /// state, e.g. no ending of render passes while `InRenderPass`.
+    pub unsafe fn raw(&self) -> vk::CommandBuffer {
+        self.vk_cmd_buffer
+    }
+
+    /// Write a timestamp to `query` when all previous commands have reached `stage`. The query
+    /// has to be reset first, see `reset_query_pool`.
+    pub fn write_timestamp(
+        self,
+        pool: &TimestampQueryPool,
+        stage: vk::PipelineStageFlags,
+        query: u32,
+    ) -> Self {
+        unsafe {
+            self.vk_device.cmd_write_timestamp(
+                self.vk_cmd_buffer,
+                stage,
+                *pool.vk_query_pool(),
+                query,
+            );
+        }
+
+        self
+    }
+}
+
+impl CommandBuffer<Recording> {
+    pub fn end(self) -> Result<CommandBuffer<Finished>, CommandError> {
+        self.end_any()
+    }
+
+    fn begin_render_pass_with_contents(
         self,
         render_pass: &RenderPass,
         framebuffer: &Framebuffer,
         extent: util::Extent2D,
-    ) -> Self {
+        contents: vk::SubpassContents,
+    ) -> CommandBuffer<InRenderPass> {
+        assert!(!self.is_secondary());
         let info = vk::RenderPassBeginInfo::builder()
             .render_pass(*render_pass.vk_render_pass())
             .framebuffer(*framebuffer.vk_framebuffer())
@@ -196,24 +421,310 @@ impl CommandBuffer {
             .clear_values(render_pass.vk_clear_values());
 
         unsafe {
-            self.vk_device.cmd_begin_render_pass(
+            self.vk_device
+                .cmd_begin_render_pass(self.vk_cmd_buffer, &info, contents);
+        }
+
+        self.transition()
+    }
+
+    pub fn begin_render_pass(
+        self,
+        render_pass: &RenderPass,
+        framebuffer: &Framebuffer,
+        extent: util::Extent2D,
+    ) -> CommandBuffer<InRenderPass> {
+        self.begin_render_pass_with_contents(
+            render_pass,
+            framebuffer,
+            extent,
+            vk::SubpassContents::INLINE,
+        )
+    }
+
+    /// Begin rendering into `color_views` (and `depth_view`) without a render pass or framebuffer,
+    /// with VK_KHR_dynamic_rendering. End it with `end_rendering`, not `end_render_pass`.
+    /// Pipelines used within it are created with `GraphicsPipelineBuilder::rendering_formats`.
+    ///
+    /// The attachments are cleared to the same values as in render passes and stored. The color
+    /// views have to be in COLOR_ATTACHMENT_OPTIMAL and the depth view in
+    /// DEPTH_STENCIL_ATTACHMENT_OPTIMAL, and only its depth aspect is used. Panics if the device
+    /// doesn't support it, see `Device::dynamic_rendering_enabled`.
+    #[cfg(feature = "dynamic_rendering")]
+    pub fn begin_rendering(
+        self,
+        color_views: &[&ImageView],
+        depth_view: Option<&ImageView>,
+        extent: util::Extent2D,
+    ) -> CommandBuffer<InRenderPass> {
+        assert!(!self.is_secondary());
+        let fns = self
+            .dynamic_rendering
+            .expect("Dynamic rendering is not enabled for the device");
+        let [color_clear, depth_clear] = default_clear_values();
+        let color_attachments = color_views
+            .iter()
+            .map(|view| {
+                RenderingAttachmentInfo::cleared(
+                    *view.vk_image_view(),
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    color_clear.into(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let depth_attachment = depth_view.map(|view| {
+            RenderingAttachmentInfo::cleared(
+                *view.vk_image_view(),
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                depth_clear.into(),
+            )
+        });
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: extent.into(),
+        };
+        let info = RenderingInfo::new(
+            render_area,
+            &color_attachments,
+            depth_attachment.as_ref(),
+            None,
+        );
+
+        unsafe {
+            fns.cmd_begin_rendering(self.vk_cmd_buffer, &info);
+        }
+
+        self.transition()
+    }
+
+    /// Begin a render pass where the commands are recorded in secondary command buffers, see
+    /// `execute_commands`. No other commands may be recorded inline in the subpass.
+    pub fn begin_render_pass_with_secondaries(
+        self,
+        render_pass: &RenderPass,
+        framebuffer: &Framebuffer,
+        extent: util::Extent2D,
+    ) -> CommandBuffer<InRenderPass> {
+        self.begin_render_pass_with_contents(
+            render_pass,
+            framebuffer,
+            extent,
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+        )
+    }
+
+    /// Copy all of `src` to the start of `dst`
+    pub fn reset_query_pool(
+        self,
+        pool: &TimestampQueryPool,
+        first_query: u32,
+        query_count: u32,
+    ) -> Self {
+        unsafe {
+            self.vk_device.cmd_reset_query_pool(
                 self.vk_cmd_buffer,
-                &info,
-                vk::SubpassContents::INLINE,
+                *pool.vk_query_pool(),
+                first_query,
+                query_count,
             );
         }
 
         self
     }
 
-    pub fn end_render_pass(self) -> Self {
+    pub fn copy_buffer(self, src: &vk::Buffer, dst: &vk::Buffer, size: usize) -> Self {
+        self.copy_buffer_region(src, dst, 0, 0, size as u64)
+    }
+
+    /// Copy `size` bytes from `src_offset` in `src` to `dst_offset` in `dst`. The regions may not
+    /// overlap if `src` and `dst` are the same buffer.
+    pub fn copy_buffer_region(
+        self,
+        src: &vk::Buffer,
+        dst: &vk::Buffer,
+        src_offset: u64,
+        dst_offset: u64,
+        size: u64,
+    ) -> Self {
+        let info = buffer_copy(src_offset, dst_offset, size);
+
         unsafe {
-            self.vk_device.cmd_end_render_pass(self.vk_cmd_buffer);
+            self.vk_device
+                .cmd_copy_buffer(self.vk_cmd_buffer, *src, *dst, &[info]);
+        }
+
+        self
+    }
+
+    /// Copy tightly packed pixels starting at `buffer_offset` in `src` to `mip_level` of `dst`.
+    /// `extent` is the extent of that mip level.
+    pub fn copy_buffer_to_image(
+        self,
+        src: &vk::Buffer,
+        dst: &vk::Image,
+        extent: &util::Extent2D,
+        buffer_offset: u64,
+        mip_level: u32,
+    ) -> Self {
+        // TODO: Read this info from dst (by passing not just the vk::Image)
+        let info = buffer_image_copy(extent, buffer_offset, mip_level);
+
+        unsafe {
+            self.vk_device.cmd_copy_buffer_to_image(
+                self.vk_cmd_buffer,
+                *src,
+                *dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[info],
+            );
+        }
+
+        self
+    }
+
+    pub fn copy_image_to_buffer(
+        self,
+        src: &vk::Image,
+        dst: &vk::Buffer,
+        extent: &util::Extent2D,
+    ) -> Self {
+        let info = buffer_image_copy(extent, 0, 0);
+
+        unsafe {
+            self.vk_device.cmd_copy_image_to_buffer(
+                self.vk_cmd_buffer,
+                *src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *dst,
+                &[info],
+            );
+        }
+
+        self
+    }
+
+    pub fn pipeline_barrier(
+        self,
+        barrier: &vk::ImageMemoryBarrier,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) -> Self {
+        unsafe {
+            self.vk_device.cmd_pipeline_barrier(
+                self.vk_cmd_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[*barrier],
+            );
+        }
+
+        self
+    }
+
+    pub fn buffer_barrier(
+        self,
+        barrier: &vk::BufferMemoryBarrier,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) -> Self {
+        unsafe {
+            self.vk_device.cmd_pipeline_barrier(
+                self.vk_cmd_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[*barrier],
+                &[],
+            );
+        }
+
+        self
+    }
+
+    pub fn blit_image(
+        self,
+        src: &vk::Image,
+        dst: &vk::Image,
+        vk_image_blit: &vk::ImageBlit,
+    ) -> Self {
+        unsafe {
+            self.vk_device.cmd_blit_image(
+                self.vk_cmd_buffer,
+                *src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[*vk_image_blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        self
+    }
+}
+
+impl CommandBuffer<InRenderPass> {
+    /// Only for secondary command buffers, primary ones need to `end_render_pass` first
+    pub fn end(self) -> Result<CommandBuffer<Finished>, CommandError> {
+        assert!(self.is_secondary());
+        self.end_any()
+    }
+
+    pub fn execute_commands(self, secondaries: &[&CommandBuffer<Finished>]) -> Self {
+        assert!(!self.is_secondary());
+        assert!(secondaries.iter().all(|cb| cb.is_secondary()));
+
+        let vk_cmd_buffers = secondaries
+            .iter()
+            .map(|cb| cb.vk_cmd_buffer)
+            .collect::<Vec<_>>();
+
+        unsafe {
+            self.vk_device
+                .cmd_execute_commands(self.vk_cmd_buffer, &vk_cmd_buffers);
+        }
+
+        self
+    }
+
+    /// Move on to the next subpass of the current render pass, with its commands recorded inline
+    pub fn next_subpass(self) -> Self {
+        assert!(!self.is_secondary());
+        unsafe {
+            self.vk_device
+                .cmd_next_subpass(self.vk_cmd_buffer, vk::SubpassContents::INLINE);
         }
 
         self
     }
 
+    pub fn end_render_pass(self) -> CommandBuffer<Recording> {
+        assert!(!self.is_secondary());
+        unsafe {
+            self.vk_device.cmd_end_render_pass(self.vk_cmd_buffer);
+        }
+
+        self.transition()
+    }
+
+    /// End what was begun with `begin_rendering`
+    #[cfg(feature = "dynamic_rendering")]
+    pub fn end_rendering(self) -> CommandBuffer<Recording> {
+        assert!(!self.is_secondary());
+        let fns = self
+            .dynamic_rendering
+            .expect("Dynamic rendering is not enabled for the device");
+        unsafe {
+            fns.cmd_end_rendering(self.vk_cmd_buffer);
+        }
+
+        self.transition()
+    }
+
     pub fn bind_graphics_pipeline(self, graphics_pipeline: &GraphicsPipeline) -> Self {
         assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
 
@@ -228,15 +739,67 @@ impl CommandBuffer {
         self
     }
 
+    /// Only for pipelines created with `dynamic_viewport`
+    pub fn set_viewport(self, viewport: util::Viewport) -> Self {
+        let viewports = [viewport.into()];
+        unsafe {
+            self.vk_device
+                .cmd_set_viewport(self.vk_cmd_buffer, 0, &viewports);
+        }
+
+        self
+    }
+
+    /// Only for pipelines created with `dynamic_viewport`
+    pub fn set_scissor(self, scissor: util::Rect2D) -> Self {
+        let scissors = [scissor.into()];
+        unsafe {
+            self.vk_device
+                .cmd_set_scissor(self.vk_cmd_buffer, 0, &scissors);
+        }
+
+        self
+    }
+
+    /// For both faces, only for pipelines with a stencil mode
+    pub fn set_stencil_reference(self, reference: u32) -> Self {
+        unsafe {
+            self.vk_device.cmd_set_stencil_reference(
+                self.vk_cmd_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                reference,
+            );
+        }
+
+        self
+    }
+
     pub fn bind_vertex_buffer(self, buffer: &VertexBuffer) -> Self {
+        self.bind_vertex_buffers(0, &[buffer], &[0])
+    }
+
+    /// Bind `buffers[i]`, starting at `offsets[i]` bytes into it, to binding `first_binding + i`
+    pub fn bind_vertex_buffers(
+        self,
+        first_binding: u32,
+        buffers: &[&VertexBuffer],
+        offsets: &[vk::DeviceSize],
+    ) -> Self {
         assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+        assert_eq!(
+            buffers.len(),
+            offsets.len(),
+            "Expected one offset per vertex buffer"
+        );
+
+        let vk_buffers = buffers.iter().map(|b| *b.vk_buffer()).collect::<Vec<_>>();
 
         unsafe {
             self.vk_device.cmd_bind_vertex_buffers(
                 self.vk_cmd_buffer,
-                0,
-                &[*buffer.vk_buffer()],
-                &[0],
+                first_binding,
+                &vk_buffers,
+                offsets,
             );
         }
 
@@ -258,128 +821,411 @@ impl CommandBuffer {
         self
     }
 
+    /// Bind the vertex buffer of `mesh` to binding 0, its index buffer and draw all its indices
+    pub fn draw_mesh(self, mesh: &Mesh) -> Self {
+        self.bind_vertex_buffer(mesh.vertex_buffer())
+            .bind_index_buffer(mesh.index_buffer())
+            .draw_indexed(mesh.index_count(), 1)
+    }
+
     pub fn bind_descriptor_set(self, set: &DescriptorSet, pipeline: &GraphicsPipeline) -> Self {
+        self.bind_descriptor_sets(0, &[set], pipeline, &[])
+    }
+
+    /// Bind a descriptor set that contains a dynamic uniform buffer, reading from `offset` bytes
+    /// into it. See `UniformBuffer::dynamic_offset`.
+    pub fn bind_descriptor_set_with_offset(
+        self,
+        set: &DescriptorSet,
+        pipeline: &GraphicsPipeline,
+        offset: u32,
+    ) -> Self {
+        self.bind_descriptor_sets(0, &[set], pipeline, &[offset])
+    }
+
+    /// Bind `sets[i]` to set index `first_set + i`, e.g. per-frame, per-material and per-object
+    /// sets at 0, 1 and 2. `dynamic_offsets` has one entry per dynamic descriptor in `sets`, in
+    /// set and then binding order.
+    pub fn bind_descriptor_sets(
+        self,
+        first_set: u32,
+        sets: &[&DescriptorSet],
+        pipeline: &GraphicsPipeline,
+        dynamic_offsets: &[u32],
+    ) -> Self {
         assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+        assert!(
+            first_set as usize + sets.len() <= pipeline.vk_descriptor_set_layouts().len(),
+            "Descriptor sets out of range of the pipeline layout"
+        );
 
-        let sets = [*set.vk_descriptor_set()];
+        let vk_sets = sets
+            .iter()
+            .map(|set| *set.vk_descriptor_set())
+            .collect::<Vec<_>>();
         unsafe {
             self.vk_device.cmd_bind_descriptor_sets(
                 self.vk_cmd_buffer,
                 GraphicsPipeline::BIND_POINT,
                 *pipeline.vk_pipeline_layout(),
-                0,
-                &sets,
-                &[],
+                first_set,
+                &vk_sets,
+                dynamic_offsets,
             );
         }
 
         self
     }
 
-    pub fn draw_indexed(self, n_vertices: u32) -> Self {
-        assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+    /// Clear `rect` of color attachment 0 of the current subpass, e.g. one half of the screen for
+    /// split-screen. Unlike the clear from the load op this can be done at any point in the
+    /// render pass.
+    pub fn clear_color_attachment(self, color: [f32; 4], rect: impl Into<vk::Rect2D>) -> Self {
+        let attachment = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: 0,
+            clear_value: ClearValue::Color(color).into(),
+        };
+
+        self.clear_attachment(&attachment, rect.into())
+    }
+
+    /// Clear `rect` of the depth/stencil attachment of the current subpass, which has `format`.
+    /// The stencil value is ignored unless the format has a stencil component.
+    pub fn clear_depth_attachment(
+        self,
+        format: vk::Format,
+        depth: f32,
+        stencil: u32,
+        rect: impl Into<vk::Rect2D>,
+    ) -> Self {
+        let attachment = vk::ClearAttachment {
+            aspect_mask: util::Format::from(format).depth_stencil_aspect(),
+            color_attachment: 0,
+            clear_value: ClearValue::DepthStencil { depth, stencil }.into(),
+        };
 
+        self.clear_attachment(&attachment, rect.into())
+    }
+
+    fn clear_attachment(self, attachment: &vk::ClearAttachment, rect: vk::Rect2D) -> Self {
+        let rects = [clear_rect(rect)];
         unsafe {
             self.vk_device
-                .cmd_draw_indexed(self.vk_cmd_buffer, n_vertices, 1, 0, 0, 0);
+                .cmd_clear_attachments(self.vk_cmd_buffer, &[*attachment], &rects);
         }
 
         self
     }
 
-    pub fn copy_buffer(self, src: &vk::Buffer, dst: &vk::Buffer, size: usize) -> Self {
-        let info = vk::BufferCopy {
-            src_offset: 0,
-            dst_offset: 0,
-            size: size as u64,
-        };
+    /// Draw `instance_count` instances, with per-instance data from the vertex buffers of the
+    /// bindings with `vk::VertexInputRate::INSTANCE`, see `bind_vertex_buffers`
+    pub fn draw_indexed(self, n_indices: u32, instance_count: u32) -> Self {
+        assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
 
         unsafe {
             self.vk_device
-                .cmd_copy_buffer(self.vk_cmd_buffer, *src, *dst, &[info]);
+                .cmd_draw_indexed(self.vk_cmd_buffer, n_indices, instance_count, 0, 0, 0);
         }
 
         self
     }
+}
 
-    pub fn copy_buffer_to_image(
-        self,
-        src: &vk::Buffer,
-        dst: &vk::Image,
-        extent: &util::Extent2D,
-    ) -> Self {
-        // TODO: Read this info from dst (by passing not just the vk::Image)
-        let info = vk::BufferImageCopy {
-            buffer_offset: 0,
-            // For e.g. padded rows
-            buffer_row_length: 0,
-            buffer_image_height: 0,
-            image_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
-            image_extent: vk::Extent3D {
-                width: extent.width,
-                height: extent.height,
-                depth: 1,
-            },
+impl CommandBuffer<Finished> {
+    pub fn vk_command_buffer(&self) -> &vk::CommandBuffer {
+        &self.vk_cmd_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::DeviceBuffer;
+    use crate::test_util::headless_renderer;
+    use crate::{Renderer, RendererConfig};
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn buffer_copy_offsets() {
+        let renderer = headless_renderer();
+        let device = &renderer.device;
+        let src: Vec<u8> = (0..16).collect();
+        let src = DeviceBuffer::staging_with_data(device, &src).expect("Failed to create buffer");
+        let zeros =
+            DeviceBuffer::staging_with_data(device, &[0; 16]).expect("Failed to create buffer");
+        let dst = DeviceBuffer::empty(
+            device,
+            16,
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuOnly,
+        )
+        .expect("Failed to create buffer");
+
+        // The second copy writes over part of the first
+        let barrier = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: *dst.vk_buffer(),
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
         };
+        let cmd_buf = renderer
+            .util_command_pool
+            .begin_single_submit()
+            .expect("Failed to begin")
+            .copy_buffer(zeros.vk_buffer(), dst.vk_buffer(), 16)
+            .buffer_barrier(
+                &barrier,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+            .copy_buffer_region(src.vk_buffer(), dst.vk_buffer(), 4, 8, 4)
+            .end()
+            .expect("Failed to end");
+        device
+            .util_queue()
+            .submit_and_wait(&cmd_buf)
+            .expect("Failed to submit");
 
-        unsafe {
-            self.vk_device.cmd_copy_buffer_to_image(
-                self.vk_cmd_buffer,
-                *src,
-                *dst,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[info],
-            );
+        let data = dst
+            .read_back(device, device.util_queue(), &renderer.util_command_pool)
+            .expect("Failed to read back");
+        assert_eq!(&data[..8], &[0; 8]);
+        assert_eq!(&data[8..12], &[4, 5, 6, 7]);
+        assert_eq!(&data[12..], &[0; 4]);
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn frame_pools_are_reused() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            frames_in_flight: 2,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        let vk_pools = |renderer: &Renderer| {
+            renderer
+                .gfx_command_pools
+                .iter()
+                .map(|pool| pool.vk_command_pool)
+                .collect::<Vec<_>>()
+        };
+        let created = vk_pools(&renderer);
+
+        for _ in 0..10 {
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            // The pool of this frame was reset, so only this frame's command buffer is allocated
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            assert_eq!(frame.gfx_command_pool.allocated.borrow().len(), 1);
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer.submit(frame).expect("Failed to submit");
         }
 
-        self
+        assert_eq!(vk_pools(&renderer), created);
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
     }
 
-    pub fn pipeline_barrier(
-        self,
-        barrier: &vk::ImageMemoryBarrier,
-        src_stage: vk::PipelineStageFlags,
-        dst_stage: vk::PipelineStageFlags,
-    ) -> Self {
-        unsafe {
-            self.vk_device.cmd_pipeline_barrier(
-                self.vk_cmd_buffer,
-                src_stage,
-                dst_stage,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[*barrier],
-            );
-        }
+    #[test]
+    fn buffer_image_copy_mip_level() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 2,
+        };
+        let info = buffer_image_copy(&extent, 256, 2);
+        assert_eq!(info.buffer_offset, 256);
+        assert_eq!(info.image_subresource.mip_level, 2);
+        assert_eq!(info.image_extent.width, 4);
+        assert_eq!(info.image_extent.height, 2);
+        assert_eq!(info.image_extent.depth, 1);
+    }
 
-        self
+    #[test]
+    fn clear_rect_from_extent() {
+        let extent = util::Extent2D {
+            width: 640,
+            height: 480,
+        };
+        let clear = clear_rect(extent.into());
+        assert_eq!(clear.rect.offset.x, 0);
+        assert_eq!(clear.rect.offset.y, 0);
+        assert_eq!(clear.rect.extent.width, 640);
+        assert_eq!(clear.rect.extent.height, 480);
+        assert_eq!(clear.layer_count, 1);
     }
 
-    pub fn blit_image(
-        self,
-        src: &vk::Image,
-        dst: &vk::Image,
-        vk_image_blit: &vk::ImageBlit,
-    ) -> Self {
-        unsafe {
-            self.vk_device.cmd_blit_image(
-                self.vk_cmd_buffer,
-                *src,
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                *dst,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[*vk_image_blit],
-                vk::Filter::LINEAR,
-            );
+    #[cfg(feature = "dynamic_rendering")]
+    static RED_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = vec4(1.0, 0.0, 0.0, 1.0);
         }
+    ",
+        frag
+    );
 
-        self
+    // Needs a Vulkan device with VK_KHR_dynamic_rendering, run with --ignored
+    #[cfg(feature = "dynamic_rendering")]
+    #[test]
+    #[ignore]
+    fn dynamic_rendering_draws_quad() {
+        use crate::image::ImageView;
+        use crate::mem::DeviceImage;
+        use crate::mesh::MeshDescriptor;
+        use crate::test_util::{write_spirv, Position, POSITION_SPV_VERT};
+        use crate::vertex::VertexDefinition;
+        use crate::ResourceManager;
+
+        let dir = std::env::temp_dir().join("trekanten_dynamic_rendering_draws_quad");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, red) = (dir.join("vert.spv"), dir.join("red.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&red, RED_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        assert!(renderer.device.dynamic_rendering_enabled());
+        let extent = renderer.swapchain_extent();
+        let format = vk::Format::R8G8B8A8_UNORM;
+
+        let image = DeviceImage::empty_2d(
+            &renderer.device,
+            extent,
+            format.into(),
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk_mem::MemoryUsage::GpuOnly,
+            1,
+            vk::SampleCountFlags::TYPE_1,
+        )
+        .expect("Failed to create image");
+        let view = ImageView::new(
+            &renderer.device,
+            image.vk_image(),
+            format.into(),
+            vk::ImageAspectFlags::COLOR,
+            1,
+        )
+        .expect("Failed to create image view");
+
+        let vertex_format = Position::format();
+        let pipeline = GraphicsPipeline::builder(&renderer.device)
+            .vertex_shader(&vert)
+            .expect("Failed to load vertex shader")
+            .fragment_shader(&red)
+            .expect("Failed to load fragment shader")
+            .vertex_input(
+                &vertex_format.attribute_description,
+                &vertex_format.binding_description,
+            )
+            .viewport_extent(extent)
+            .rendering_formats(&[format], None)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .depth_test_enable(false)
+            .build()
+            .expect("Failed to create pipeline");
+
+        let quad = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(MeshDescriptor::from_slices(&quad, &[0u32, 1, 2, 2, 1, 3]))
+            .expect("Failed to create mesh");
+
+        // Without a render pass, the layout transitions are explicit
+        let barrier =
+            |old_layout, new_layout, src_access_mask, dst_access_mask| vk::ImageMemoryBarrier {
+                old_layout,
+                new_layout,
+                src_access_mask,
+                dst_access_mask,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: *image.vk_image(),
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
+            };
+        let to_attachment = barrier(
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        let to_transfer = barrier(
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+
+        let size = (extent.width * extent.height * 4) as usize;
+        let readback =
+            DeviceBuffer::readback_empty(&renderer.device, size).expect("Failed to create buffer");
+        let cmd_buf = renderer
+            .util_command_pool
+            .begin_single_submit()
+            .expect("Failed to begin")
+            .pipeline_barrier(
+                &to_attachment,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .begin_rendering(&[&view], None, extent)
+            .bind_graphics_pipeline(&pipeline)
+            .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+            .end_rendering()
+            .pipeline_barrier(
+                &to_transfer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+            .copy_image_to_buffer(image.vk_image(), readback.vk_buffer(), &extent)
+            .end()
+            .expect("Failed to end");
+        renderer
+            .device
+            .util_queue()
+            .submit_and_wait(&cmd_buf)
+            .expect("Failed to submit");
+
+        let pixels = readback.read_data().expect("Failed to read");
+        assert!(pixels.chunks(4).all(|p| p == [255, 0, 0, 255]));
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
     }
 }
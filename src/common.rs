@@ -1 +1 @@
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
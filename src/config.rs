@@ -0,0 +1,177 @@
+use ash::vk;
+
+use std::path::PathBuf;
+
+use crate::common::DEFAULT_FRAMES_IN_FLIGHT;
+use crate::device::DevicePreference;
+use crate::error::RenderError;
+use crate::instance::InstanceConfig;
+use crate::swapchain::DEFAULT_SURFACE_FORMATS;
+use crate::util::vk_debug::{ValidationErrorAction, ValidationErrors};
+use crate::window::Window;
+use crate::Renderer;
+
+/// Settings that are fixed when the renderer is created. See `Renderer::builder`.
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// Preferred present mode, falls back to FIFO if the surface doesn't support it
+    pub present_mode: vk::PresentModeKHR,
+    /// Surface formats in order of preference. If none of them are available, an 8-bit sRGB
+    /// format is used if there is one.
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    /// Has to be between 1 and the number of swapchain images
+    pub frames_in_flight: usize,
+    /// Clamped to what the device supports. None uses the max supported sample count.
+    pub msaa: Option<vk::SampleCountFlags>,
+    /// Enable the vulkan validation layers. `TREK_DISABLE_VALIDATION_LAYERS` overrides this.
+    pub validation: bool,
+    /// Panic on or collect validation messages at or above a severity, see
+    /// `Renderer::take_validation_errors`. `TREK_VALIDATION_PANIC` overrides this.
+    pub validation_errors: Option<ValidationErrors>,
+    /// Initial contents of the pipeline cache, see `Renderer::save_pipeline_cache`
+    pub pipeline_cache_path: Option<PathBuf>,
+    /// Relative shader paths are resolved against this. If None, `TREK_SHADER_DIR` or
+    /// `<current dir>/src/pipeline/shaders` is used.
+    pub shader_dir: Option<PathBuf>,
+    /// Added to the main render pass, e.g. to make its depth output visible to a later pass
+    pub subpass_dependencies: Vec<vk::SubpassDependency>,
+    /// If the main render pass has a depth buffer. 2D apps that don't need one can save the
+    /// memory and bandwidth.
+    pub depth: bool,
+    /// If the depth buffer of the main render pass also has a stencil component, for pipelines
+    /// with a `StencilMode`. Implies `depth`.
+    pub stencil: bool,
+    /// Which device to use if there are several suitable ones. None prefers discrete GPUs.
+    pub device_preference: Option<DevicePreference>,
+    /// The application and engine names and versions that are reported to the driver
+    pub instance: InstanceConfig,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            present_mode: vk::PresentModeKHR::MAILBOX,
+            surface_formats: DEFAULT_SURFACE_FORMATS.to_vec(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            msaa: None,
+            validation: true,
+            validation_errors: None,
+            pipeline_cache_path: None,
+            shader_dir: None,
+            subpass_dependencies: Vec::new(),
+            depth: true,
+            stencil: false,
+            device_preference: None,
+            instance: InstanceConfig::default(),
+        }
+    }
+}
+
+pub struct RendererBuilder<'a, W> {
+    window: &'a W,
+    config: RendererConfig,
+}
+
+impl<'a, W> RendererBuilder<'a, W>
+where
+    W: raw_window_handle::HasRawWindowHandle + Window,
+{
+    pub fn new(window: &'a W) -> Self {
+        Self {
+            window,
+            config: RendererConfig::default(),
+        }
+    }
+
+    pub fn config(mut self, config: RendererConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.config.present_mode = present_mode;
+        self
+    }
+
+    pub fn surface_formats(mut self, surface_formats: &[vk::SurfaceFormatKHR]) -> Self {
+        self.config.surface_formats = surface_formats.to_vec();
+        self
+    }
+
+    /// The number of frames that can be recorded before waiting for the GPU
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.config.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    pub fn msaa(mut self, sample_count: vk::SampleCountFlags) -> Self {
+        self.config.msaa = Some(sample_count);
+        self
+    }
+
+    pub fn validation(mut self, validation: bool) -> Self {
+        self.config.validation = validation;
+        self
+    }
+
+    pub fn validation_errors(
+        mut self,
+        min_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        action: ValidationErrorAction,
+    ) -> Self {
+        self.config.validation_errors = Some(ValidationErrors {
+            min_severity,
+            action,
+        });
+        self
+    }
+
+    /// Load the pipeline cache from `path`, if it exists
+    pub fn pipeline_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.pipeline_cache_path = Some(path.into());
+        self
+    }
+
+    pub fn shader_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.shader_dir = Some(dir.into());
+        self
+    }
+
+    pub fn subpass_dependency(mut self, dependency: vk::SubpassDependency) -> Self {
+        self.config.subpass_dependencies.push(dependency);
+        self
+    }
+
+    pub fn depth(mut self, depth: bool) -> Self {
+        self.config.depth = depth;
+        self
+    }
+
+    pub fn stencil(mut self, stencil: bool) -> Self {
+        self.config.stencil = stencil;
+        self
+    }
+
+    pub fn device_preference(mut self, preference: DevicePreference) -> Self {
+        self.config.device_preference = Some(preference);
+        self
+    }
+
+    /// The application name and (major, minor, patch) version that are reported to the driver
+    pub fn app_name(mut self, name: impl Into<String>, version: (u32, u32, u32)) -> Self {
+        self.config.instance.app_name = name.into();
+        self.config.instance.app_version = version;
+        self
+    }
+
+    /// The requested Vulkan version, defaults to 1.2. Lowered if the loader doesn't support it,
+    /// see `Renderer::api_version`.
+    pub fn api_version(mut self, major: u32, minor: u32) -> Self {
+        self.config.instance.api_version = (major, minor, 0);
+        self
+    }
+
+    pub fn build(self) -> Result<Renderer, RenderError> {
+        Renderer::with_config(self.window, self.config)
+    }
+}
@@ -22,12 +22,17 @@ pub struct DepthBuffer {
 }
 
 impl DepthBuffer {
+    /// `format` is e.g. `Device::depth_buffer_format`, or `Device::depth_stencil_format` for a
+    /// depth buffer with a stencil component
     pub fn new(
         device: &Device,
         extents: &util::Extent2D,
         msaa_sample_count: vk::SampleCountFlags,
+        format: vk::Format,
     ) -> Result<Self, DepthBufferError> {
-        let format = device.depth_buffer_format().into();
+        let format = util::Format::from(format);
+        // Views that are used as framebuffer attachments need all aspects of the format
+        let aspect = format.depth_stencil_aspect();
         let usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
         let props = vk_mem::MemoryUsage::GpuOnly;
         let mip_levels = 1; // No mip maps
@@ -40,13 +45,7 @@ impl DepthBuffer {
             mip_levels,
             msaa_sample_count,
         )?;
-        let image_view = ImageView::new(
-            device,
-            _image.vk_image(),
-            format,
-            vk::ImageAspectFlags::DEPTH,
-            mip_levels,
-        )?;
+        let image_view = ImageView::new(device, _image.vk_image(), format, aspect, mip_levels)?;
         Ok(Self {
             _image,
             image_view,
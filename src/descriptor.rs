@@ -7,15 +7,16 @@ use thiserror::Error;
 use crate::device::Device;
 use crate::device::HasVkDevice;
 use crate::device::VkDeviceHandle;
+use crate::image::ImageView;
+use crate::offscreen::OffscreenTarget;
 use crate::resource::{BufferedStorage, Handle};
-use crate::texture::Texture;
+use crate::storage_buffer::StorageBuffer;
+use crate::texture::{Sampler, Texture};
 use crate::uniform::UniformBuffer;
 
-use crate::common::MAX_FRAMES_IN_FLIGHT;
-
 #[derive(Debug, Error)]
 pub enum DescriptorError {
-    #[error("Failed to allocate descriptor set: {0}")]
+    #[error("Failed to create descriptor pool: {0}")]
     PoolCreation(vk::Result),
     #[error("Failed to allocate descriptor set: {0}")]
     SetAllocation(vk::Result),
@@ -24,6 +25,7 @@ pub enum DescriptorError {
 struct DescriptorPool {
     vk_device: VkDeviceHandle,
     vk_descriptor_pool: vk::DescriptorPool,
+    capacity: usize,
     n_allocated: usize,
 }
 
@@ -37,36 +39,67 @@ impl std::ops::Drop for DescriptorPool {
 }
 
 impl DescriptorPool {
-    fn new(device: &Device) -> Result<Self, DescriptorError> {
+    /// Room for `capacity` sets, each with one descriptor of every supported type
+    fn new<D: HasVkDevice>(
+        device: &D,
+        capacity: usize,
+        flags: vk::DescriptorPoolCreateFlags,
+    ) -> Result<Self, DescriptorError> {
         let pool_sizes = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+                descriptor_count: capacity as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: capacity as u32,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+                descriptor_count: capacity as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: capacity as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: capacity as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: capacity as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::INPUT_ATTACHMENT,
+                descriptor_count: capacity as u32,
             },
         ];
 
         let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(flags)
             .pool_sizes(&pool_sizes)
-            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+            .max_sets(capacity as u32);
 
+        let vk_device = device.vk_device();
         let vk_descriptor_pool = unsafe {
-            device
-                .vk_device()
+            vk_device
                 .create_descriptor_pool(&pool_create_info, None)
                 .map_err(DescriptorError::PoolCreation)?
         };
 
         Ok(Self {
-            vk_device: device.vk_device(),
+            vk_device,
             vk_descriptor_pool,
+            capacity,
             n_allocated: 0,
         })
     }
 
+    fn has_room_for(&self, count: usize) -> bool {
+        self.n_allocated + count <= self.capacity
+    }
+
     fn alloc(
         &mut self,
         layout: &vk::DescriptorSetLayout,
@@ -92,6 +125,69 @@ impl DescriptorPool {
     }
 }
 
+/// Capacity of the pool that is created when the current one (of `current` sets) is exhausted
+fn next_pool_capacity(current: usize, requested: usize) -> usize {
+    std::cmp::max(current * 2, requested)
+}
+
+/// Allocates from the most recently created pool and creates a larger one when it is full. Sets
+/// are never freed individually so the older pools are only kept alive.
+struct DescriptorPools {
+    vk_device: VkDeviceHandle,
+    pools: Vec<DescriptorPool>,
+    flags: vk::DescriptorPoolCreateFlags,
+}
+
+impl DescriptorPools {
+    fn new(device: &Device, initial_capacity: usize) -> Result<Self, DescriptorError> {
+        // Sets with bindless layouts, see GraphicsPipelineBuilder::bindless, can only be
+        // allocated from update-after-bind pools
+        let flags = if device.descriptor_indexing_enabled() {
+            vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+        } else {
+            vk::DescriptorPoolCreateFlags::empty()
+        };
+        Ok(Self {
+            vk_device: device.vk_device(),
+            pools: vec![DescriptorPool::new(device, initial_capacity, flags)?],
+            flags,
+        })
+    }
+
+    fn grow(&mut self, requested: usize) -> Result<(), DescriptorError> {
+        let current = self.pools.last().map_or(0, |p| p.capacity);
+        let capacity = next_pool_capacity(current, requested);
+        log::trace!("Creating descriptor pool with capacity {}", capacity);
+        self.pools
+            .push(DescriptorPool::new(&self.vk_device, capacity, self.flags)?);
+        Ok(())
+    }
+
+    fn alloc(
+        &mut self,
+        layout: &vk::DescriptorSetLayout,
+        count: usize,
+    ) -> Result<Vec<DescriptorSet>, DescriptorError> {
+        if !self.pools.last().map_or(false, |p| p.has_room_for(count)) {
+            self.grow(count)?;
+        }
+
+        let pool = self.pools.last_mut().expect("No descriptor pool");
+        match pool.alloc(layout, count) {
+            // The layout might need more descriptors of a type than what is reserved per set
+            Err(DescriptorError::SetAllocation(vk::Result::ERROR_OUT_OF_POOL_MEMORY))
+            | Err(DescriptorError::SetAllocation(vk::Result::ERROR_FRAGMENTED_POOL)) => {
+                self.grow(count)?;
+                self.pools
+                    .last_mut()
+                    .expect("No descriptor pool")
+                    .alloc(layout, count)
+            }
+            x => x,
+        }
+    }
+}
+
 // TODO: Rename? (to avoid DescriptorSetDescriptor)
 pub struct DescriptorSet {
     vk_descriptor_set: vk::DescriptorSet,
@@ -102,48 +198,192 @@ impl DescriptorSet {
         Self { vk_descriptor_set }
     }
 
-    fn bind_resources(
+    fn buffer_write(
+        &self,
+        binding: u32,
+        ty: vk::DescriptorType,
+        buffer_infos: &[vk::DescriptorBufferInfo],
+    ) -> vk::WriteDescriptorSet {
+        vk::WriteDescriptorSet::builder()
+            .dst_set(self.vk_descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(ty)
+            .buffer_info(buffer_infos)
+            .build()
+    }
+
+    fn image_write(
+        &self,
+        binding: u32,
+        ty: vk::DescriptorType,
+        image_infos: &[vk::DescriptorImageInfo],
+    ) -> vk::WriteDescriptorSet {
+        vk::WriteDescriptorSet::builder()
+            .dst_set(self.vk_descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(ty)
+            .image_info(image_infos)
+            .build()
+    }
+
+    /// Bind a single element of `buffer` to `binding`. The element to read from is selected
+    /// when binding the set, with `CommandBuffer::bind_descriptor_set_with_offset`.
+    pub fn bind_dynamic_uniform_buffer(
         &self,
         vk_device: &VkDeviceHandle,
+        binding: u32,
         buffer: &UniformBuffer,
-        texture: &Texture,
     ) {
-        let buffer_info = vk::DescriptorBufferInfo {
+        assert!(buffer.is_dynamic());
+        let buffer_infos = [vk::DescriptorBufferInfo {
             buffer: *buffer.vk_buffer(),
             offset: 0,
             range: buffer.elem_size() as u64,
-        };
-        let buffer_infos = [buffer_info];
+        }];
+
+        let write = self.buffer_write(
+            binding,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            &buffer_infos,
+        );
+
+        unsafe {
+            vk_device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    pub fn bind_storage_buffer(
+        &self,
+        vk_device: &VkDeviceHandle,
+        binding: u32,
+        buffer: &StorageBuffer,
+    ) {
+        let buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: *buffer.vk_buffer(),
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }];
+
+        let write = self.buffer_write(binding, vk::DescriptorType::STORAGE_BUFFER, &buffer_infos);
+
+        unsafe {
+            vk_device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    fn bind_resources(
+        &self,
+        vk_device: &VkDeviceHandle,
+        buffer: &UniformBuffer,
+        texture: &Texture,
+    ) {
+        if buffer.is_dynamic() {
+            // TODO: Use the values from the layout
+            self.bind_dynamic_uniform_buffer(vk_device, 0, buffer);
+        } else {
+            let buffer_info = vk::DescriptorBufferInfo {
+                buffer: *buffer.vk_buffer(),
+                offset: 0,
+                range: buffer.elem_size() as u64,
+            };
+            let buffer_infos = [buffer_info];
+
+            // TODO: Use the values from the layout
+            let buffer_write =
+                self.buffer_write(0, vk::DescriptorType::UNIFORM_BUFFER, &buffer_infos);
+
+            unsafe {
+                vk_device.update_descriptor_sets(&[buffer_write], &[]);
+            }
+        }
 
         // TODO: Use the values from the layout
-        let buffer_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.vk_descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .buffer_info(&buffer_infos)
-            .build();
+        self.bind_combined_image_sampler(vk_device, 1, texture.image_view(), texture.sampler());
+    }
 
+    /// The image is expected to be in SHADER_READ_ONLY_OPTIMAL when the set is used
+    pub fn bind_combined_image_sampler(
+        &self,
+        vk_device: &VkDeviceHandle,
+        binding: u32,
+        image_view: &ImageView,
+        sampler: &Sampler,
+    ) {
         let image_info = vk::DescriptorImageInfo {
             image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            image_view: *texture.vk_image_view(),
-            sampler: *texture.vk_sampler(),
+            image_view: *image_view.vk_image_view(),
+            sampler: *sampler.vk_sampler(),
         };
         let image_infos = [image_info];
 
-        // TODO: Use the values from the layout
-        let image_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.vk_descriptor_set)
-            .dst_binding(1)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(&image_infos)
-            .build();
+        let image_write = self.image_write(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            &image_infos,
+        );
 
-        let writes = [buffer_write, image_write];
+        unsafe {
+            vk_device.update_descriptor_sets(&[image_write], &[]);
+        }
+    }
+
+    /// Bind an image without a sampler, e.g. a `texture2D` in GLSL that is sampled with one
+    /// bound by `bind_sampler`. The image is expected to be in SHADER_READ_ONLY_OPTIMAL when the
+    /// set is used.
+    pub fn bind_sampled_image(
+        &self,
+        vk_device: &VkDeviceHandle,
+        binding: u32,
+        image_view: &ImageView,
+    ) {
+        let image_infos = [vk::DescriptorImageInfo {
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image_view: *image_view.vk_image_view(),
+            sampler: vk::Sampler::null(),
+        }];
+
+        let write = self.image_write(binding, vk::DescriptorType::SAMPLED_IMAGE, &image_infos);
+
+        unsafe {
+            vk_device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Bind a sampler on its own, so that it can be shared by several sampled images
+    pub fn bind_sampler(&self, vk_device: &VkDeviceHandle, binding: u32, sampler: &Sampler) {
+        let image_infos = [vk::DescriptorImageInfo {
+            image_layout: vk::ImageLayout::UNDEFINED,
+            image_view: vk::ImageView::null(),
+            sampler: *sampler.vk_sampler(),
+        }];
+
+        let write = self.image_write(binding, vk::DescriptorType::SAMPLER, &image_infos);
 
         unsafe {
-            vk_device.update_descriptor_sets(&writes, &[]);
+            vk_device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Bind an attachment that an earlier subpass wrote, read with `subpassLoad` in GLSL. The
+    /// subpass that reads it has to use it as an input attachment.
+    pub fn bind_input_attachment(
+        &self,
+        vk_device: &VkDeviceHandle,
+        binding: u32,
+        image_view: &ImageView,
+    ) {
+        let image_infos = [vk::DescriptorImageInfo {
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image_view: *image_view.vk_image_view(),
+            sampler: vk::Sampler::null(),
+        }];
+
+        let write = self.image_write(binding, vk::DescriptorType::INPUT_ATTACHMENT, &image_infos);
+
+        unsafe {
+            vk_device.update_descriptor_sets(&[write], &[]);
         }
     }
 
@@ -154,47 +394,316 @@ impl DescriptorSet {
 
 pub struct DescriptorSetDescriptor<'a> {
     pub layout: vk::DescriptorSetLayout,
-    pub uniform_buffers: &'a [UniformBuffer; MAX_FRAMES_IN_FLIGHT],
+    /// One per frame in flight
+    pub uniform_buffers: &'a [UniformBuffer],
     pub texture: &'a Texture,
 }
 
 pub struct DescriptorSets {
     vk_device: VkDeviceHandle,
-    descriptor_pool: DescriptorPool,
+    descriptor_pools: DescriptorPools,
     storage: BufferedStorage<DescriptorSet>,
+    frames_in_flight: usize,
 }
 
 impl DescriptorSets {
-    pub fn new(device: &Device) -> Result<Self, DescriptorError> {
+    pub fn new(device: &Device, frames_in_flight: usize) -> Result<Self, DescriptorError> {
         Ok(Self {
             vk_device: device.vk_device(),
-            descriptor_pool: DescriptorPool::new(device)?,
+            descriptor_pools: DescriptorPools::new(device, frames_in_flight)?,
             storage: Default::default(),
+            frames_in_flight,
         })
     }
 
     pub fn create<'a>(
         &mut self,
+        device: &Device,
         descriptor: DescriptorSetDescriptor<'a>,
     ) -> Result<Handle<DescriptorSet>, DescriptorError> {
-        let mut desc_sets = self
-            .descriptor_pool
-            .alloc(&descriptor.layout, MAX_FRAMES_IN_FLIGHT)?;
-        let set0 = desc_sets.remove(0);
-        let set1 = desc_sets.remove(0);
-
-        for (i, s) in [&set0, &set1].iter().enumerate() {
-            s.bind_resources(
-                &self.vk_device,
-                &descriptor.uniform_buffers[i],
-                descriptor.texture,
+        assert_eq!(descriptor.uniform_buffers.len(), self.frames_in_flight);
+        let desc_sets = self
+            .descriptor_pools
+            .alloc(&descriptor.layout, self.frames_in_flight)?;
+
+        for (s, buffer) in desc_sets.iter().zip(descriptor.uniform_buffers.iter()) {
+            s.bind_resources(&self.vk_device, buffer, descriptor.texture);
+        }
+
+        let handle = self.storage.add(desc_sets);
+        for (frame, set) in self
+            .storage
+            .get_all(&handle)
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            device.name_object(
+                set.vk_descriptor_set,
+                &format!("DescriptorSet {:?} (frame {})", handle.id(), frame),
             );
         }
 
-        Ok(self.storage.add([set0, set1]))
+        Ok(handle)
     }
 
     pub fn get(&self, h: &Handle<DescriptorSet>, frame_idx: usize) -> Option<&DescriptorSet> {
         self.storage.get(h, frame_idx)
     }
+
+    /// Bind `buffer` to `binding` in the sets of all frames in flight
+    pub fn bind_storage_buffer(
+        &self,
+        h: &Handle<DescriptorSet>,
+        binding: u32,
+        buffer: &StorageBuffer,
+    ) -> Option<()> {
+        for set in self.storage.get_all(h)? {
+            set.bind_storage_buffer(&self.vk_device, binding, buffer);
+        }
+        Some(())
+    }
+
+    /// Bind the image of `texture`, without its sampler, to `binding` in the sets of all frames
+    /// in flight
+    pub fn bind_sampled_image(
+        &self,
+        h: &Handle<DescriptorSet>,
+        binding: u32,
+        texture: &Texture,
+    ) -> Option<()> {
+        for set in self.storage.get_all(h)? {
+            set.bind_sampled_image(&self.vk_device, binding, texture.image_view());
+        }
+        Some(())
+    }
+
+    /// Bind `sampler` to `binding` in the sets of all frames in flight
+    pub fn bind_sampler(
+        &self,
+        h: &Handle<DescriptorSet>,
+        binding: u32,
+        sampler: &Sampler,
+    ) -> Option<()> {
+        for set in self.storage.get_all(h)? {
+            set.bind_sampler(&self.vk_device, binding, sampler);
+        }
+        Some(())
+    }
+
+    /// Bind `image_view` as an input attachment to `binding` in the sets of all frames in flight
+    pub fn bind_input_attachment(
+        &self,
+        h: &Handle<DescriptorSet>,
+        binding: u32,
+        image_view: &ImageView,
+    ) -> Option<()> {
+        for set in self.storage.get_all(h)? {
+            set.bind_input_attachment(&self.vk_device, binding, image_view);
+        }
+        Some(())
+    }
+
+    /// Bind the color image of `target` to `binding` in the sets of all frames in flight
+    pub fn bind_offscreen_target(
+        &self,
+        h: &Handle<DescriptorSet>,
+        binding: u32,
+        target: &OffscreenTarget,
+    ) -> Option<()> {
+        for set in self.storage.get_all(h)? {
+            set.bind_combined_image_sampler(
+                &self.vk_device,
+                binding,
+                target.image_view(),
+                target.sampler(),
+            );
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+    use crate::pipeline;
+    use crate::test_util::{headless_renderer, write_spirv, Position, POSITION_SPV_VERT};
+    use crate::texture;
+    use crate::uniform;
+    use crate::ResourceManager;
+
+    #[test]
+    fn pool_growth() {
+        assert_eq!(next_pool_capacity(2, 2), 4);
+        assert_eq!(next_pool_capacity(4, 2), 8);
+        assert_eq!(next_pool_capacity(2, 16), 16);
+        assert_eq!(next_pool_capacity(0, 3), 3);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn pools_grow_when_full() {
+        let renderer = headless_renderer();
+        let device = &renderer.device;
+        let vk_device = device.vk_device();
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        let layout = unsafe {
+            vk_device
+                .create_descriptor_set_layout(&info, None)
+                .expect("Failed to create layout")
+        };
+
+        let mut pools = DescriptorPools::new(device, 2).expect("Failed to create pools");
+        let mut sets = pools.alloc(&layout, 2).expect("Failed to allocate");
+        assert_eq!(pools.pools.len(), 1);
+        sets.extend(pools.alloc(&layout, 3).expect("Failed to allocate"));
+        assert_eq!(pools.pools.len(), 2);
+        assert_eq!(pools.pools[1].capacity, 4);
+        assert_eq!(sets.len(), 5);
+        assert!(sets
+            .iter()
+            .all(|s| s.vk_descriptor_set != vk::DescriptorSet::null()));
+
+        drop(pools);
+        unsafe { vk_device.destroy_descriptor_set_layout(layout, None) };
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    static SEPARATE_SAMPLER_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0) uniform Params {
+            vec4 inv_extent;
+        } params;
+        layout(set = 0, binding = 1) uniform sampler2D tint;
+        layout(set = 0, binding = 2) uniform texture2D image;
+        layout(set = 0, binding = 3) uniform sampler samp;
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            vec2 uv = gl_FragCoord.xy * params.inv_extent.xy;
+            color = texture(tint, uv) * texture(sampler2D(image, samp), uv);
+        }
+    ",
+        frag
+    );
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn separate_sampler() {
+        let dir = std::env::temp_dir().join("trekanten_separate_sampler");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, SEPARATE_SAMPLER_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+
+        let pipeline_handle = renderer
+            .create_resource(
+                pipeline::GraphicsPipelineDescriptor::builder()
+                    .vertex_shader(&vert)
+                    .fragment_shader(&frag)
+                    .vertex_type::<Position>()
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .depth_test_enable(false)
+                    .build()
+                    .expect("Failed to create pipeline descriptor"),
+            )
+            .expect("Failed to create pipeline");
+        let params = [[0.25f32, 0.25, 0.0, 0.0]];
+        let params_handle = renderer
+            .create_resource(uniform::UniformBufferDescriptor::from_slice(&params))
+            .expect("Failed to create uniform buffer");
+        let quad = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad,
+                &[0u32, 1, 2, 2, 1, 3],
+            ))
+            .expect("Failed to create mesh");
+
+        let mut texture = |rgba: [u8; 4]| {
+            let image = ::image::RgbaImage::from_pixel(2, 2, ::image::Rgba(rgba));
+            renderer
+                .create_resource(texture::TextureDescriptor::from_rgba_image(
+                    &image,
+                    vk::Format::R8G8B8A8_UNORM,
+                    false,
+                ))
+                .expect("Failed to create texture")
+        };
+        let white = texture([255, 255, 255, 255]);
+        let red = texture([255, 0, 0, 255]);
+        let green = texture([0, 255, 0, 255]);
+
+        let desc_set_handle = renderer
+            .create_descriptor_set(&pipeline_handle, &params_handle, &white)
+            .expect("Failed to create descriptor set");
+        let sampler = renderer
+            .create_sampler(&texture::SamplerDescriptor::default())
+            .expect("Failed to create sampler");
+        renderer
+            .bind_sampler(&desc_set_handle, 3, &sampler)
+            .expect("Failed to bind sampler");
+
+        let mut render = |image: &Handle<Texture>| {
+            // The set might be used by the previous frame
+            renderer.wait_idle().expect("Failed to wait");
+            renderer
+                .bind_sampled_image(&desc_set_handle, 2, image)
+                .expect("Failed to bind sampled image");
+
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            let gfx_pipeline = renderer.get_resource(&pipeline_handle).unwrap();
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .bind_graphics_pipeline(gfx_pipeline)
+                .bind_descriptor_set(
+                    renderer.get_descriptor_set(&desc_set_handle).unwrap(),
+                    gfx_pipeline,
+                )
+                .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer
+                .request_readback(&mut frame)
+                .expect("Failed to request readback");
+            renderer.submit(frame).expect("Failed to submit");
+            renderer.read_framebuffer().expect("Failed to read back")
+        };
+
+        // The same sampler is used with both images
+        let with_red = render(&red);
+        assert!(with_red.data.chunks(4).all(|p| p == [255, 0, 0, 255]));
+        let with_green = render(&green);
+        assert!(with_green.data.chunks(4).all(|p| p == [0, 255, 0, 255]));
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
 }
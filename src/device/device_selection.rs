@@ -1,4 +1,5 @@
 use ash::version::InstanceV1_0;
+use ash::version::InstanceV1_1;
 use ash::vk;
 
 use std::ffi::CStr;
@@ -42,35 +43,70 @@ fn log_device(instance: &Instance, device: &vk::PhysicalDevice) {
     });
 }
 
-fn required_device_extensions() -> Vec<CString> {
-    vec![ash::extensions::khr::Swapchain::name().to_owned()]
+/// The swapchain extension is only needed when presenting to a surface
+fn required_device_extensions(surface: Option<&Surface>) -> Vec<CString> {
+    match surface {
+        Some(_) => vec![ash::extensions::khr::Swapchain::name().to_owned()],
+        None => Vec::new(),
+    }
 }
 
 #[derive(Clone, Debug)]
 struct QueueFamiliesQuery {
     graphics: Option<QueueFamily>,
     present: Option<QueueFamily>,
+    transfer: Option<QueueFamily>,
+}
+
+impl QueueFamiliesQuery {
+    fn same_graphics_and_present(&self) -> bool {
+        match (&self.graphics, &self.present) {
+            (Some(graphics), Some(present)) => graphics.index == present.index,
+            _ => false,
+        }
+    }
 }
 
 impl TryFrom<QueueFamiliesQuery> for QueueFamilies {
-    type Error = DeviceCreationError;
+    type Error = DeviceSuitability;
     fn try_from(v: QueueFamiliesQuery) -> Result<Self, Self::Error> {
         match (v.graphics, v.present) {
-            (None, _) => Err(DeviceCreationError::UnsuitableDevice(
-                DeviceSuitability::MissingGraphicsQueue,
-            )),
-            (_, None) => Err(DeviceCreationError::UnsuitableDevice(
-                DeviceSuitability::MissingPresentQueue,
-            )),
-            (Some(graphics), Some(present)) => Ok(QueueFamilies { graphics, present }),
+            (None, _) => Err(DeviceSuitability::MissingGraphicsQueue),
+            (_, None) => Err(DeviceSuitability::MissingPresentQueue),
+            (Some(graphics), Some(present)) => {
+                let transfer = v.transfer.unwrap_or_else(|| graphics.clone());
+                Ok(QueueFamilies {
+                    graphics,
+                    present,
+                    transfer,
+                })
+            }
         }
     }
 }
 
+/// Find a queue family that is suitable for async uploads, i.e. one that supports transfers but
+/// not graphics. Transfer-only families (often backed by a DMA engine) are preferred over
+/// compute ones. Graphics families implicitly support transfers so they are not considered.
+fn find_transfer_family(queue_fam_props: &[vk::QueueFamilyProperties]) -> Option<usize> {
+    let candidates = || {
+        queue_fam_props.iter().enumerate().filter(|(_, fam)| {
+            fam.queue_count > 0
+                && fam.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                && !fam.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+        })
+    };
+
+    candidates()
+        .find(|(_, fam)| !fam.queue_flags.contains(vk::QueueFlags::COMPUTE))
+        .or_else(|| candidates().next())
+        .map(|(i, _)| i)
+}
+
 fn find_queue_families(
     instance: &Instance,
     device: &vk::PhysicalDevice,
-    surface: &Surface,
+    surface: Option<&Surface>,
 ) -> Result<QueueFamiliesQuery, DeviceCreationError> {
     log::trace!("Checking queues for:");
     log_device(instance, device);
@@ -89,6 +125,7 @@ fn find_queue_families(
     let mut families = QueueFamiliesQuery {
         graphics: None,
         present: None,
+        transfer: None,
     };
 
     for (i, fam) in queue_fam_props.iter().enumerate() {
@@ -105,10 +142,14 @@ fn find_queue_families(
             .as_ref()
             .map(|f| f.index as usize == i)
             .unwrap_or(false);
+        let supports_present = match surface {
+            Some(surface) => surface.is_supported_by(device, i as u32)?,
+            // Without a surface, nothing is presented and the graphics family is used
+            None => same_as_gfx,
+        };
         // According to vulkan tutorial, "drawing and presentation" is more performant on the same
         // queue
-        if surface.is_supported_by(device, i as u32)? && (same_as_gfx || families.present.is_none())
-        {
+        if supports_present && (same_as_gfx || families.present.is_none()) {
             families.present = Some(QueueFamily {
                 props: *fam,
                 index: i as u32,
@@ -116,6 +157,11 @@ fn find_queue_families(
         }
     }
 
+    families.transfer = find_transfer_family(&queue_fam_props).map(|i| QueueFamily {
+        props: queue_fam_props[i],
+        index: i as u32,
+    });
+
     Ok(families)
 }
 
@@ -149,7 +195,7 @@ fn device_supports_extensions<T: AsRef<CStr>>(
 }
 
 // TODO: Improve granularity of MissingRequiredExtensions
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceSuitability {
     Suitable,
     MissingRequiredExtensions,
@@ -173,7 +219,22 @@ impl DeviceSuitability {
 
 impl std::fmt::Display for DeviceSuitability {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        let msg = match self {
+            DeviceSuitability::Suitable => "suitable",
+            DeviceSuitability::MissingRequiredExtensions => "missing required extensions",
+            DeviceSuitability::MissingRequiredFeatures => "missing required features",
+            DeviceSuitability::MissingGraphicsQueue => "no graphics queue",
+            DeviceSuitability::MissingPresentQueue => "no queue that can present to the surface",
+            DeviceSuitability::MissingDepthFormat => "no supported depth buffer format",
+            DeviceSuitability::UnsuitableSwapchainFormat => "no supported swapchain format",
+            DeviceSuitability::UnsuitableSwapchainPresentMode => {
+                "no supported swapchain present mode"
+            }
+            DeviceSuitability::MissingMipmapGenerationSupport => {
+                "no linear blit support for mipmap generation"
+            }
+        };
+        write!(f, "{}", msg)
     }
 }
 
@@ -184,10 +245,11 @@ fn required_device_features() -> vk::PhysicalDeviceFeatures {
 }
 
 // TODO: ash does not support struct eq for features :(
+// Only checks the features that are part of required_device_features()
 fn device_supports_features(
     instance: &Instance,
     phys_device: &vk::PhysicalDevice,
-    _features: &vk::PhysicalDeviceFeatures,
+    required: &vk::PhysicalDeviceFeatures,
 ) -> bool {
     let supported = unsafe {
         instance
@@ -195,7 +257,124 @@ fn device_supports_features(
             .get_physical_device_features(*phys_device)
     };
 
-    supported.sampler_anisotropy == vk::TRUE
+    required.sampler_anisotropy == vk::FALSE || supported.sampler_anisotropy == vk::TRUE
+}
+
+fn supports_vulkan_version(
+    instance: &Instance,
+    vk_phys_device: &vk::PhysicalDevice,
+    version: u32,
+) -> bool {
+    let props = unsafe {
+        instance
+            .vk_instance()
+            .get_physical_device_properties(*vk_phys_device)
+    };
+
+    // The instance version limits what can be used of the device
+    instance.api_version() >= version && props.api_version >= version
+}
+
+fn supports_vulkan_1_2(instance: &Instance, vk_phys_device: &vk::PhysicalDevice) -> bool {
+    supports_vulkan_version(instance, vk_phys_device, vk::make_version(1, 2, 0))
+}
+
+/// Negative viewport heights, see `GraphicsPipelineBuilder::flip_viewport_y`, are core in Vulkan
+/// 1.1. Before that they need `VK_KHR_maintenance1`, which is returned if it has to be enabled.
+fn negative_viewport_height_support(
+    instance: &Instance,
+    vk_phys_device: &vk::PhysicalDevice,
+) -> Result<(bool, Option<CString>), DeviceCreationError> {
+    if supports_vulkan_version(instance, vk_phys_device, vk::make_version(1, 1, 0)) {
+        return Ok((true, None));
+    }
+
+    let maintenance1 = vk::KhrMaintenance1Fn::name();
+    if device_supports_extensions(instance, vk_phys_device, &[maintenance1])? {
+        Ok((true, Some(maintenance1.to_owned())))
+    } else {
+        Ok((false, None))
+    }
+}
+
+/// The extensions that VK_KHR_dynamic_rendering depends on are core in Vulkan 1.2. Its feature
+/// has to be supported if the extension is.
+#[cfg(feature = "dynamic_rendering")]
+fn device_supports_dynamic_rendering(
+    instance: &Instance,
+    vk_phys_device: &vk::PhysicalDevice,
+) -> Result<bool, DeviceCreationError> {
+    Ok(supports_vulkan_1_2(instance, vk_phys_device)
+        && device_supports_extensions(
+            instance,
+            vk_phys_device,
+            &[crate::dynamic_rendering::DynamicRenderingFn::name()],
+        )?)
+}
+
+/// Timeline semaphores are core in Vulkan 1.2 but still an optional feature
+fn device_supports_timeline_semaphores(
+    instance: &Instance,
+    vk_phys_device: &vk::PhysicalDevice,
+) -> bool {
+    if !supports_vulkan_1_2(instance, vk_phys_device) {
+        return false;
+    }
+
+    let mut timeline = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    // ash does not implement ExtendsPhysicalDeviceFeatures2 for this struct so chain it manually
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut timeline as *mut vk::PhysicalDeviceTimelineSemaphoreFeatures
+            as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        instance
+            .vk_instance()
+            .get_physical_device_features2(*vk_phys_device, &mut features2);
+    }
+
+    timeline.timeline_semaphore == vk::TRUE
+}
+
+/// The parts of descriptor indexing (core in Vulkan 1.2) that bindless descriptor sets need, see
+/// `GraphicsPipelineBuilder::bindless`
+fn descriptor_indexing_features() -> vk::PhysicalDeviceDescriptorIndexingFeatures {
+    vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_storage_buffer_update_after_bind(true)
+        .runtime_descriptor_array(true)
+        .build()
+}
+
+fn device_supports_descriptor_indexing(
+    instance: &Instance,
+    vk_phys_device: &vk::PhysicalDevice,
+) -> bool {
+    if !supports_vulkan_1_2(instance, vk_phys_device) {
+        return false;
+    }
+
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    // Chained manually, see device_supports_timeline_semaphores
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut indexing as *mut vk::PhysicalDeviceDescriptorIndexingFeatures
+            as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        instance
+            .vk_instance()
+            .get_physical_device_features2(*vk_phys_device, &mut features2);
+    }
+
+    indexing.descriptor_binding_partially_bound == vk::TRUE
+        && indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+        && indexing.descriptor_binding_storage_buffer_update_after_bind == vk::TRUE
+        && indexing.runtime_descriptor_array == vk::TRUE
 }
 
 fn device_supports_mipmap_generation(
@@ -215,9 +394,9 @@ fn device_supports_mipmap_generation(
 fn check_device_suitability(
     instance: &Instance,
     device: &vk::PhysicalDevice,
-    surface: &Surface,
+    surface: Option<&Surface>,
 ) -> Result<DeviceSuitability, DeviceCreationError> {
-    if !device_supports_extensions(instance, device, &required_device_extensions())? {
+    if !device_supports_extensions(instance, device, &required_device_extensions(surface))? {
         return Ok(DeviceSuitability::MissingRequiredExtensions);
     }
 
@@ -243,6 +422,11 @@ fn check_device_suitability(
         return Ok(DeviceSuitability::MissingPresentQueue);
     }
 
+    let surface = match surface {
+        Some(surface) => surface,
+        None => return Ok(DeviceSuitability::Suitable),
+    };
+
     let swapchain_query = surface.query_swapchain_support(device)?;
 
     if swapchain_query.formats.is_empty() {
@@ -256,28 +440,84 @@ fn check_device_suitability(
     Ok(DeviceSuitability::Suitable)
 }
 
-fn score_device(
-    instance: &Instance,
-    device: &vk::PhysicalDevice,
-    surface: &Surface,
-) -> Result<u32, DeviceCreationError> {
-    let device_props = unsafe {
-        instance
-            .vk_instance()
-            .get_physical_device_properties(*device)
-    };
+/// Overrides the default preference for discrete GPUs when choosing between suitable devices,
+/// e.g. to save power by using the integrated one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevicePreference {
+    Discrete,
+    Integrated,
+    /// A device whose name contains this, ignoring case
+    Name(String),
+}
+
+impl DevicePreference {
+    fn matches(&self, device_type: vk::PhysicalDeviceType, name: &str) -> bool {
+        match self {
+            Self::Discrete => device_type == vk::PhysicalDeviceType::DISCRETE_GPU,
+            Self::Integrated => device_type == vk::PhysicalDeviceType::INTEGRATED_GPU,
+            Self::Name(part) => name.to_lowercase().contains(&part.to_lowercase()),
+        }
+    }
+}
+
+pub fn device_name(props: &vk::PhysicalDeviceProperties) -> String {
+    unsafe { CStr::from_ptr(props.device_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
 
+// Suitability always wins, then the preference, if any. Sharing a queue family for graphics and
+// present only breaks ties.
+fn score(
+    device_type: vk::PhysicalDeviceType,
+    name: &str,
+    suitable: bool,
+    same_graphics_and_present: bool,
+    preference: Option<&DevicePreference>,
+) -> u32 {
     let mut score = 0;
 
-    if device_props.device_type == vk::PhysicalDeviceType::DISCRETE_GPU {
+    if same_graphics_and_present {
+        score += 10;
+    }
+
+    if device_type == vk::PhysicalDeviceType::DISCRETE_GPU {
         score += 100;
     }
 
-    if check_device_suitability(instance, device, surface)?.is_suitable() {
+    if preference.map_or(false, |p| p.matches(device_type, name)) {
+        score += 500;
+    }
+
+    if suitable {
         score += 1000;
     }
 
-    Ok(score)
+    score
+}
+
+fn score_device(
+    instance: &Instance,
+    device: &vk::PhysicalDevice,
+    surface: Option<&Surface>,
+    preference: Option<&DevicePreference>,
+) -> Result<u32, DeviceCreationError> {
+    let device_props = unsafe {
+        instance
+            .vk_instance()
+            .get_physical_device_properties(*device)
+    };
+    let suitable = check_device_suitability(instance, device, surface)?.is_suitable();
+    let same_graphics_and_present =
+        find_queue_families(instance, device, surface)?.same_graphics_and_present();
+
+    Ok(score(
+        device_props.device_type,
+        &device_name(&device_props),
+        suitable,
+        same_graphics_and_present,
+        preference,
+    ))
 }
 
 fn log_queue_family(fam: &QueueFamily) {
@@ -291,45 +531,73 @@ fn log_queue_families(qfams: &QueueFamilies) {
     log_queue_family(&qfams.graphics);
     log::trace!("Present:");
     log_queue_family(&qfams.present);
+    log::trace!("Transfer:");
+    log_queue_family(&qfams.transfer);
 }
 
 fn create_infos_for_families(
     queue_families: &QueueFamilies,
     prio: &[f32],
 ) -> Result<Vec<vk::DeviceQueueCreateInfo>, DeviceCreationError> {
-    let (gfx, present) = (&queue_families.graphics, &queue_families.present);
     let queue_count = prio.len() as u32;
 
-    let infos = if gfx.index == present.index {
-        vec![vk::DeviceQueueCreateInfo {
-            queue_family_index: gfx.index,
+    // Only one create info is allowed per family
+    let mut indices = vec![
+        queue_families.graphics.index,
+        queue_families.present.index,
+        queue_families.transfer.index,
+    ];
+    indices.sort_unstable();
+    indices.dedup();
+
+    let infos = indices
+        .into_iter()
+        .map(|queue_family_index| vk::DeviceQueueCreateInfo {
+            queue_family_index,
             p_queue_priorities: prio.as_ptr(),
             queue_count,
             ..Default::default()
-        }]
-    } else {
-        vec![
-            vk::DeviceQueueCreateInfo {
-                queue_family_index: gfx.index,
-                p_queue_priorities: prio.as_ptr(),
-                queue_count,
-                ..Default::default()
-            },
-            vk::DeviceQueueCreateInfo {
-                queue_family_index: present.index,
-                p_queue_priorities: prio.as_ptr(),
-                queue_count,
-                ..Default::default()
-            },
-        ]
-    };
+        })
+        .collect();
 
     Ok(infos)
 }
+
+/// Fails with the reason for each device if none of them are suitable
+fn check_any_suitable(
+    devices: Vec<(String, DeviceSuitability)>,
+) -> Result<(), DeviceCreationError> {
+    if devices.iter().any(|(_, s)| s.is_suitable()) {
+        Ok(())
+    } else {
+        Err(DeviceCreationError::UnsuitableDevice(devices))
+    }
+}
+
+pub struct EnabledFeatures {
+    pub core: vk::PhysicalDeviceFeatures,
+    pub timeline_semaphore: bool,
+    pub descriptor_indexing: bool,
+    pub negative_viewport_height: bool,
+    #[cfg(feature = "dynamic_rendering")]
+    pub dynamic_rendering: bool,
+}
+
+/// Without a surface, no device extensions are required and the present queue is the graphics
+/// queue
 pub fn device_selection(
     instance: &Instance,
-    surface: &Surface,
-) -> Result<(ash::Device, vk::PhysicalDevice, QueueFamilies), DeviceCreationError> {
+    surface: Option<&Surface>,
+    preference: Option<&DevicePreference>,
+) -> Result<
+    (
+        ash::Device,
+        vk::PhysicalDevice,
+        QueueFamilies,
+        EnabledFeatures,
+    ),
+    DeviceCreationError,
+> {
     let physical_devices = unsafe {
         instance
             .vk_instance()
@@ -344,24 +612,34 @@ pub fn device_selection(
     log_physical_devices(instance, &physical_devices);
     let suitability_checks = physical_devices
         .iter()
-        .map(|d| check_device_suitability(instance, d, surface))
-        .collect::<Result<Vec<DeviceSuitability>, DeviceCreationError>>()?;
-
-    if !suitability_checks.iter().any(|c| c.is_suitable()) {
-        return Err(DeviceCreationError::UnsuitableDevice(suitability_checks[0]));
-    }
+        .map(|d| {
+            let props = unsafe { instance.vk_instance().get_physical_device_properties(*d) };
+            check_device_suitability(instance, d, surface).map(|s| (device_name(&props), s))
+        })
+        .collect::<Result<Vec<_>, DeviceCreationError>>()?;
+    check_any_suitable(suitability_checks)?;
 
     // The collect() creates a Result<Vec<_>>, using the first Err it finds in the vector (if any). Then ?
     // does an early return if it is Err.
     let mut scored: Vec<(u32, vk::PhysicalDevice)> = physical_devices
         .iter()
-        .map(|d| score_device(instance, d, surface).map(|s| (s, *d)))
+        .map(|d| score_device(instance, d, surface, preference).map(|s| (s, *d)))
         .collect::<Result<Vec<_>, DeviceCreationError>>()?;
 
     // Note that switched args. Higher score should be earlier
     scored.sort_by(|a, b| b.0.cmp(&a.0));
 
     let vk_phys_device = scored[0].1;
+    if let Some(preference) = preference {
+        let props = unsafe {
+            instance
+                .vk_instance()
+                .get_physical_device_properties(vk_phys_device)
+        };
+        if !preference.matches(props.device_type, &device_name(&props)) {
+            log::warn!("No suitable device matches {:?}", preference);
+        }
+    }
     log::trace!("Choosing device:");
     log_device(instance, &vk_phys_device);
 
@@ -378,20 +656,68 @@ pub fn device_selection(
     let queue_infos = create_infos_for_families(&queue_families, &prio)?;
 
     // TODO: Cleanup handling layers together with instance
-    let validation_layers = crate::instance::choose_validation_layers(instance.vk_entry());
+    let validation_layers = crate::instance::choose_validation_layers(
+        instance.vk_entry(),
+        instance.validation_enabled(),
+    );
     let layers_ptrs = util::ffi::vec_cstring_to_raw(validation_layers);
 
-    let extensions = required_device_extensions();
+    let mut extensions = required_device_extensions(surface);
+    let (negative_viewport_height, maintenance1) =
+        negative_viewport_height_support(instance, &vk_phys_device)?;
+    extensions.extend(maintenance1);
+    #[cfg(feature = "dynamic_rendering")]
+    let dynamic_rendering = device_supports_dynamic_rendering(instance, &vk_phys_device)?;
+    #[cfg(feature = "dynamic_rendering")]
+    let mut dynamic_rendering_features =
+        crate::dynamic_rendering::PhysicalDeviceDynamicRenderingFeatures::default();
+    #[cfg(feature = "dynamic_rendering")]
+    if dynamic_rendering {
+        extensions.push(crate::dynamic_rendering::DynamicRenderingFn::name().to_owned());
+    }
     let extensions_ptrs = util::ffi::vec_cstring_to_raw(extensions);
 
-    let features = vk::PhysicalDeviceFeatures::builder().sampler_anisotropy(true);
+    let features = {
+        let supported = unsafe {
+            instance
+                .vk_instance()
+                .get_physical_device_features(vk_phys_device)
+        };
+
+        let mut features = required_device_features();
+        // Optional, enabled if available. Needed for non-FILL polygon modes.
+        features.fill_mode_non_solid = supported.fill_mode_non_solid;
+        // Optional, for block-compressed textures
+        features.texture_compression_bc = supported.texture_compression_bc;
+        features.texture_compression_etc2 = supported.texture_compression_etc2;
+        features
+    };
+
+    let timeline_semaphore = device_supports_timeline_semaphores(instance, &vk_phys_device);
+    let mut timeline_features =
+        vk::PhysicalDeviceTimelineSemaphoreFeatures::builder().timeline_semaphore(true);
 
-    let device_info = vk::DeviceCreateInfo::builder()
+    let mut device_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers_ptrs)
         .enabled_extension_names(&extensions_ptrs)
         .enabled_features(&features);
 
+    if timeline_semaphore {
+        device_info = device_info.push_next(&mut timeline_features);
+    }
+
+    let descriptor_indexing = device_supports_descriptor_indexing(instance, &vk_phys_device);
+    let mut indexing_features = descriptor_indexing_features();
+    if descriptor_indexing {
+        device_info = device_info.push_next(&mut indexing_features);
+    }
+
+    #[cfg(feature = "dynamic_rendering")]
+    if dynamic_rendering {
+        device_info = device_info.push_next(&mut dynamic_rendering_features);
+    }
+
     let vk_device = unsafe {
         instance
             .vk_instance()
@@ -402,5 +728,131 @@ pub fn device_selection(
     let _owned_layers = util::ffi::vec_cstring_from_raw(layers_ptrs);
     let _owned_extensions = util::ffi::vec_cstring_from_raw(extensions_ptrs);
 
-    Ok((vk_device, vk_phys_device, queue_families))
+    let enabled_features = EnabledFeatures {
+        core: features,
+        timeline_semaphore,
+        descriptor_indexing,
+        negative_viewport_height,
+        #[cfg(feature = "dynamic_rendering")]
+        dynamic_rendering,
+    };
+
+    Ok((vk_device, vk_phys_device, queue_families, enabled_features))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fam(queue_flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags,
+            queue_count: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn transfer_family_selection() {
+        let gfx = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let compute = vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let transfer = vk::QueueFlags::TRANSFER;
+
+        assert_eq!(find_transfer_family(&[fam(gfx)]), None);
+        assert_eq!(find_transfer_family(&[fam(gfx), fam(compute)]), Some(1));
+        assert_eq!(
+            find_transfer_family(&[fam(gfx), fam(compute), fam(transfer)]),
+            Some(2)
+        );
+
+        let mut empty = fam(transfer);
+        empty.queue_count = 0;
+        assert_eq!(find_transfer_family(&[fam(gfx), empty]), None);
+    }
+
+    #[test]
+    fn device_preference() {
+        let discrete = vk::PhysicalDeviceType::DISCRETE_GPU;
+        let integrated = vk::PhysicalDeviceType::INTEGRATED_GPU;
+        let best = |devices: &[(vk::PhysicalDeviceType, &'static str, bool)],
+                    preference: Option<&DevicePreference>| {
+            devices
+                .iter()
+                .max_by_key(|(ty, name, suitable)| score(*ty, name, *suitable, false, preference))
+                .map(|(_, name, _)| *name)
+                .unwrap()
+        };
+
+        let devices = [
+            (integrated, "Intel(R) UHD Graphics 620", true),
+            (discrete, "NVIDIA GeForce GTX 1050", true),
+            (discrete, "AMD Radeon Pro", false),
+        ];
+        assert_eq!(best(&devices, None), "NVIDIA GeForce GTX 1050");
+        assert_eq!(
+            best(&devices, Some(&DevicePreference::Integrated)),
+            "Intel(R) UHD Graphics 620"
+        );
+        assert_eq!(
+            best(&devices, Some(&DevicePreference::Name("intel".to_owned()))),
+            "Intel(R) UHD Graphics 620"
+        );
+        // Unsuitable devices are never chosen
+        assert_eq!(
+            best(&devices, Some(&DevicePreference::Name("radeon".to_owned()))),
+            "NVIDIA GeForce GTX 1050"
+        );
+    }
+
+    #[test]
+    fn shared_queue_family_breaks_ties() {
+        let discrete = vk::PhysicalDeviceType::DISCRETE_GPU;
+        let integrated = vk::PhysicalDeviceType::INTEGRATED_GPU;
+        assert!(score(discrete, "a", true, true, None) > score(discrete, "b", true, false, None));
+        // But doesn't outweigh being discrete
+        assert!(score(discrete, "a", true, false, None) > score(integrated, "b", true, true, None));
+    }
+
+    #[test]
+    fn unsuitable_devices_are_all_reported() {
+        let devices = vec![
+            (
+                "llvmpipe".to_owned(),
+                DeviceSuitability::MissingRequiredExtensions,
+            ),
+            (
+                "NVIDIA GeForce GTX 1050".to_owned(),
+                DeviceSuitability::MissingDepthFormat,
+            ),
+        ];
+        match check_any_suitable(devices.clone()) {
+            Err(DeviceCreationError::UnsuitableDevice(rejected)) => {
+                assert_eq!(rejected, devices);
+                let msg = DeviceCreationError::UnsuitableDevice(rejected).to_string();
+                assert!(
+                    msg.contains("llvmpipe (missing required extensions)"),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    msg.contains("NVIDIA GeForce GTX 1050 (no supported depth buffer format)"),
+                    "{}",
+                    msg
+                );
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+
+        let mut with_suitable = devices;
+        with_suitable.push((
+            "Intel(R) UHD Graphics 620".to_owned(),
+            DeviceSuitability::Suitable,
+        ));
+        assert!(check_any_suitable(with_suitable).is_ok());
+    }
+
+    #[test]
+    fn swapchain_extension_only_with_surface() {
+        assert!(required_device_extensions(None).is_empty());
+    }
 }
@@ -3,14 +3,24 @@ use ash::vk;
 use thiserror::Error;
 
 use super::device_selection::DeviceSuitability;
+use crate::pipeline_cache::PipelineCacheError;
 use crate::surface::SurfaceError;
 
+fn rejected_devices(devices: &[(String, DeviceSuitability)]) -> String {
+    devices
+        .iter()
+        .map(|(name, suitability)| format!("{} ({})", name, suitability))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 #[derive(Error, Debug)]
 pub enum DeviceCreationError {
     #[error("creation failed: {0}")]
     Creation(vk::Result),
-    #[error("Not suitable: {0}")]
-    UnsuitableDevice(DeviceSuitability),
+    /// Why each of the physical devices was rejected, by device name
+    #[error("No suitable device: {}", rejected_devices(.0))]
+    UnsuitableDevice(Vec<(String, DeviceSuitability)>),
     #[error("Missing physical device, is vulkan supported?")]
     MissingPhysicalDevice,
     #[error("Internal vulkan error: {0} {1}")]
@@ -27,4 +37,8 @@ pub enum DeviceError {
     WaitIdle(vk::Result),
     #[error("Allocation failure {0}")]
     Allocation(#[from] vk_mem::error::Error),
+    #[error("Pipeline cache failure {0}")]
+    PipelineCache(#[from] PipelineCacheError),
+    #[error("vkSetDebugUtilsObjectNameEXT() failed: {0}")]
+    ObjectName(vk::Result),
 }
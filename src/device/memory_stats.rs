@@ -0,0 +1,141 @@
+use ash::vk;
+
+/// Allocator statistics for one memory heap, or all of them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Size of the heap, zero for the total
+    pub size: vk::DeviceSize,
+    pub device_local: bool,
+    /// Number of `vkDeviceMemory` blocks allocated from the heap
+    pub block_count: u32,
+    pub allocation_count: u32,
+    /// Bytes in the blocks that are used by allocations
+    pub used_bytes: u64,
+    /// Bytes in the blocks that are not used by any allocation
+    pub unused_bytes: u64,
+}
+
+impl HeapStats {
+    fn new(info: &vk_mem::ffi::VmaStatInfo, heap: Option<&vk::MemoryHeap>) -> Self {
+        Self {
+            size: heap.map(|h| h.size).unwrap_or(0),
+            device_local: heap
+                .map(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+                .unwrap_or(false),
+            block_count: info.blockCount,
+            allocation_count: info.allocationCount,
+            used_bytes: info.usedBytes,
+            unused_bytes: info.unusedBytes,
+        }
+    }
+
+    /// Bytes of device memory allocated by the allocator, used or not
+    pub fn allocated_bytes(&self) -> u64 {
+        self.used_bytes + self.unused_bytes
+    }
+}
+
+/// What the allocator has allocated, see `Device::memory_stats`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub total: HeapStats,
+    /// Indexed by the heap index of the physical device
+    pub heaps: Vec<HeapStats>,
+}
+
+impl MemoryStats {
+    pub(super) fn new(
+        stats: &vk_mem::ffi::VmaStats,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        let heaps = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .zip(stats.memoryHeap.iter())
+            .map(|(heap, info)| HeapStats::new(info, Some(heap)))
+            .collect();
+
+        Self {
+            total: HeapStats::new(&stats.total, None),
+            heaps,
+        }
+    }
+}
+
+const MIB: f64 = (1024 * 1024) as f64;
+
+impl std::fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Total: {:.2} MiB used, {:.2} MiB allocated in {} blocks, {} allocations",
+            self.total.used_bytes as f64 / MIB,
+            self.total.allocated_bytes() as f64 / MIB,
+            self.total.block_count,
+            self.total.allocation_count
+        )?;
+        for (i, heap) in self.heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "Heap {} ({:.0} MiB{}): {:.2} MiB used, {:.2} MiB allocated in {} blocks, {} allocations",
+                i,
+                heap.size as f64 / MIB,
+                if heap.device_local { ", device local" } else { "" },
+                heap.used_bytes as f64 / MIB,
+                heap.allocated_bytes() as f64 / MIB,
+                heap.block_count,
+                heap.allocation_count
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heaps_from_vma_stats() {
+        let info = |blocks, used, unused| vk_mem::ffi::VmaStatInfo {
+            blockCount: blocks,
+            allocationCount: blocks * 2,
+            usedBytes: used,
+            unusedBytes: unused,
+            ..Default::default()
+        };
+        let mut stats = vk_mem::ffi::VmaStats {
+            memoryType: [Default::default(); 32],
+            memoryHeap: [Default::default(); 16],
+            total: info(3, 300, 700),
+        };
+        stats.memoryHeap[0] = info(2, 200, 600);
+        stats.memoryHeap[1] = info(1, 100, 100);
+        // Past the heap count, ignored
+        stats.memoryHeap[2] = info(1, 1, 1);
+
+        let mut props = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: 2,
+            ..Default::default()
+        };
+        props.memory_heaps[0] = vk::MemoryHeap {
+            size: 1 << 30,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        props.memory_heaps[1] = vk::MemoryHeap {
+            size: 1 << 32,
+            flags: vk::MemoryHeapFlags::empty(),
+        };
+
+        let stats = MemoryStats::new(&stats, &props);
+        assert_eq!(stats.heaps.len(), 2);
+        assert_eq!(stats.total.allocated_bytes(), 1000);
+        assert!(stats.heaps[0].device_local);
+        assert_eq!(stats.heaps[0].used_bytes, 200);
+        assert_eq!(stats.heaps[1].size, 1 << 32);
+        assert_eq!(stats.heaps[1].allocation_count, 2);
+        assert!(stats
+            .to_string()
+            .contains("Heap 0 (1024 MiB, device local)"));
+    }
+}
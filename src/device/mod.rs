@@ -1,12 +1,15 @@
+use ash::extensions::ext;
 use ash::version::DeviceV1_0;
 use ash::version::InstanceV1_0;
 use ash::vk;
 
 use vk_mem::Allocator;
 
+use std::ffi::CString;
 use std::rc::Rc;
 
 use crate::instance::Instance;
+use crate::pipeline_cache::PipelineCache;
 use crate::queue::Queue;
 use crate::queue::QueueFamilies;
 use crate::queue::QueueFamily;
@@ -15,8 +18,11 @@ use crate::util::lifetime::LifetimeToken;
 
 mod device_selection;
 mod error;
+mod memory_stats;
 
+pub use device_selection::DevicePreference;
 pub use error::DeviceError;
+pub use memory_stats::{HeapStats, MemoryStats};
 
 pub type VkDevice = ash::Device;
 pub type VkDeviceHandle = Rc<VkDevice>;
@@ -33,9 +39,12 @@ impl HasVkDevice for VkDeviceHandle {
 }
 
 struct PhysicalDeviceProperties {
+    properties: vk::PhysicalDeviceProperties,
+    enabled_features: device_selection::EnabledFeatures,
     memory_properties: vk::PhysicalDeviceMemoryProperties,
     depth_buffer_format: vk::Format,
-    _supported_msaa_sample_counts: vk::SampleCountFlags,
+    depth_stencil_format: Option<vk::Format>,
+    supported_msaa_sample_counts: vk::SampleCountFlags,
     max_supported_msaa_sample_count: vk::SampleCountFlags,
 }
 
@@ -43,6 +52,7 @@ struct QueueInfo {
     queue_families: QueueFamilies,
     graphics_queue: Queue,
     present_queue: Queue,
+    transfer_queue: Queue,
 }
 
 // Use this to handle drop-order. Could have been done with unsafe/ManuallyDrop but this seems the easiest
@@ -54,7 +64,7 @@ struct InnerDevice {
 impl std::ops::Drop for InnerDevice {
     fn drop(&mut self) {
         // TODO: Change to weak
-        if !Rc::strong_count(&self.vk_device) == 1 {
+        if Rc::strong_count(&self.vk_device) != 1 {
             log::error!(
                 "References to inner vk device still existing but Device is being destroyed!"
             );
@@ -67,8 +77,16 @@ pub struct Device {
     allocator: AllocatorHandle,
     queue_info: QueueInfo,
     vk_phys_device: vk::PhysicalDevice,
+    // For format queries. Kept alive by the lifetime token below.
+    vk_instance: ash::Instance,
+    // Only loaded with validation, object names are for debugging
+    debug_utils: Option<ext::DebugUtils>,
+    #[cfg(feature = "dynamic_rendering")]
+    dynamic_rendering: Option<crate::dynamic_rendering::DynamicRenderingFn>,
 
     physical_device_properties: PhysicalDeviceProperties,
+    // Needs to be destroyed before the inner device
+    pipeline_cache: PipelineCache,
     inner_device: InnerDevice,
     _parent_lifetime_token: LifetimeToken<Instance>,
 }
@@ -123,7 +141,53 @@ fn find_depth_format(
     )
 }
 
+fn find_depth_stencil_format(
+    instance: &Instance,
+    vk_phys_device: &vk::PhysicalDevice,
+) -> Option<vk::Format> {
+    let cands = [
+        vk::Format::D24_UNORM_S8_UINT,
+        vk::Format::D32_SFLOAT_S8_UINT,
+    ];
+    find_supported_format(
+        instance,
+        vk_phys_device,
+        &cands,
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+    )
+}
+
 fn get_max_supported_msaa(flags: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    clamp_msaa(flags, vk::SampleCountFlags::TYPE_64)
+}
+
+/// The single sample counts in `supported`, lowest first. TYPE_1 is always included.
+fn sample_counts(supported: vk::SampleCountFlags) -> Vec<vk::SampleCountFlags> {
+    let mut counts = vec![vk::SampleCountFlags::TYPE_1];
+    for &count in [
+        vk::SampleCountFlags::TYPE_2,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_64,
+    ]
+    .iter()
+    {
+        if supported.contains(count) {
+            counts.push(count);
+        }
+    }
+
+    counts
+}
+
+/// Highest sample count in `supported` that is not larger than `requested`
+fn clamp_msaa(
+    supported: vk::SampleCountFlags,
+    requested: vk::SampleCountFlags,
+) -> vk::SampleCountFlags {
     for &count in [
         vk::SampleCountFlags::TYPE_64,
         vk::SampleCountFlags::TYPE_32,
@@ -134,7 +198,7 @@ fn get_max_supported_msaa(flags: vk::SampleCountFlags) -> vk::SampleCountFlags {
     ]
     .iter()
     {
-        if flags.contains(count) {
+        if count.as_raw() <= requested.as_raw() && supported.contains(count) {
             return count;
         }
     }
@@ -143,14 +207,22 @@ fn get_max_supported_msaa(flags: vk::SampleCountFlags) -> vk::SampleCountFlags {
 }
 
 impl Device {
-    pub fn new(instance: &Instance, surface: &Surface) -> Result<Self, DeviceError> {
-        let (vk_device, vk_phys_device, queue_families) =
-            device_selection::device_selection(instance, surface)?;
-
-        let (gfx, present) = unsafe {
+    /// `pipeline_cache_path` is used to load the initial data of the pipeline cache, if given.
+    /// Without a surface, the device can't present, see `Renderer::new_headless`.
+    pub fn new(
+        instance: &Instance,
+        surface: Option<&Surface>,
+        preference: Option<&DevicePreference>,
+        pipeline_cache_path: Option<&std::path::Path>,
+    ) -> Result<Self, DeviceError> {
+        let (vk_device, vk_phys_device, queue_families, enabled_features) =
+            device_selection::device_selection(instance, surface, preference)?;
+
+        let (gfx, present, transfer) = unsafe {
             (
                 vk_device.get_device_queue(queue_families.graphics.index, 0),
                 vk_device.get_device_queue(queue_families.present.index, 0),
+                vk_device.get_device_queue(queue_families.transfer.index, 0),
             )
         };
 
@@ -158,6 +230,7 @@ impl Device {
 
         let graphics_queue = Queue::new(Rc::clone(&vk_device), gfx);
         let present_queue = Queue::new(Rc::clone(&vk_device), present);
+        let transfer_queue = Queue::new(Rc::clone(&vk_device), transfer);
 
         let physical_device_properties = unsafe {
             let memory_properties = instance
@@ -166,21 +239,25 @@ impl Device {
 
             let depth_buffer_format = find_depth_format(instance, &vk_phys_device)
                 .expect("Missing depth buffer format, this device should not have been created");
+            let depth_stencil_format = find_depth_stencil_format(instance, &vk_phys_device);
 
             let vk_props = instance
                 .vk_instance()
                 .get_physical_device_properties(vk_phys_device);
 
-            let _supported_msaa_sample_counts = vk_props.limits.framebuffer_color_sample_counts
+            let supported_msaa_sample_counts = vk_props.limits.framebuffer_color_sample_counts
                 & vk_props.limits.framebuffer_depth_sample_counts;
             let max_supported_msaa_sample_count =
-                get_max_supported_msaa(_supported_msaa_sample_counts);
+                get_max_supported_msaa(supported_msaa_sample_counts);
 
             PhysicalDeviceProperties {
                 memory_properties,
                 depth_buffer_format,
-                _supported_msaa_sample_counts,
+                depth_stencil_format,
+                supported_msaa_sample_counts,
                 max_supported_msaa_sample_count,
+                properties: vk_props,
+                enabled_features,
             }
         };
 
@@ -188,6 +265,7 @@ impl Device {
             queue_families,
             graphics_queue,
             present_queue,
+            transfer_queue,
         };
 
         let allocator = Rc::new(Allocator::new(&vk_mem::AllocatorCreateInfo {
@@ -197,15 +275,47 @@ impl Device {
             ..Default::default()
         })?);
 
+        let pipeline_cache = match pipeline_cache_path {
+            Some(path) => PipelineCache::load(Rc::clone(&vk_device), path)?,
+            None => PipelineCache::new(Rc::clone(&vk_device), &[])?,
+        };
+
+        let debug_utils = if instance.validation_enabled() {
+            Some(ext::DebugUtils::new(
+                instance.vk_entry(),
+                instance.vk_instance(),
+            ))
+        } else {
+            None
+        };
+
+        #[cfg(feature = "dynamic_rendering")]
+        let dynamic_rendering = if physical_device_properties
+            .enabled_features
+            .dynamic_rendering
+        {
+            crate::dynamic_rendering::DynamicRenderingFn::load(
+                instance.vk_instance(),
+                vk_device.handle(),
+            )
+        } else {
+            None
+        };
+
         let inner_device = InnerDevice { vk_device };
 
         Ok(Self {
             inner_device,
             allocator,
             vk_phys_device,
+            vk_instance: instance.vk_instance().clone(),
+            debug_utils,
+            #[cfg(feature = "dynamic_rendering")]
+            dynamic_rendering,
             queue_info,
             _parent_lifetime_token: instance.lifetime_token(),
             physical_device_properties,
+            pipeline_cache,
         })
     }
 
@@ -213,10 +323,24 @@ impl Device {
         &self.queue_info.queue_families.graphics
     }
 
+    /// Graphics capable queue family for one-off submissions, e.g. image uploads that need blits
+    /// for mipmap generation.
     pub fn util_queue_family(&self) -> &QueueFamily {
         &self.queue_info.queue_families.graphics
     }
 
+    /// Prefers a family without graphics support, falls back to the graphics family
+    pub fn transfer_queue_family(&self) -> &QueueFamily {
+        &self.queue_info.queue_families.transfer
+    }
+
+    /// If this is true, resources written on the transfer queue need a queue family ownership
+    /// transfer before they are used on the graphics queue
+    pub fn has_separate_transfer_queue(&self) -> bool {
+        self.queue_info.queue_families.transfer.index
+            != self.queue_info.queue_families.graphics.index
+    }
+
     pub fn present_queue_family(&self) -> &QueueFamily {
         &self.queue_info.queue_families.present
     }
@@ -229,6 +353,10 @@ impl Device {
         &self.queue_info.graphics_queue
     }
 
+    pub fn transfer_queue(&self) -> &Queue {
+        &self.queue_info.transfer_queue
+    }
+
     pub fn present_queue(&self) -> &Queue {
         &self.queue_info.present_queue
     }
@@ -244,10 +372,28 @@ impl Device {
         Ok(())
     }
 
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        &self.pipeline_cache
+    }
+
     pub fn vk_phys_device(&self) -> &vk::PhysicalDevice {
         &self.vk_phys_device
     }
 
+    /// Whether images in `format` with optimal tiling support all of `features`
+    pub fn supports_optimal_tiling_features(
+        &self,
+        format: vk::Format,
+        features: vk::FormatFeatureFlags,
+    ) -> bool {
+        let props = unsafe {
+            self.vk_instance
+                .get_physical_device_format_properties(self.vk_phys_device, format)
+        };
+
+        props.optimal_tiling_features.contains(features)
+    }
+
     pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
         &self.physical_device_properties.memory_properties
     }
@@ -257,12 +403,181 @@ impl Device {
         self.physical_device_properties.depth_buffer_format
     }
 
-    pub fn max_msaa_sample_count(&self) -> vk::SampleCountFlags {
+    /// A depth buffer format with a stencil component, None if the device doesn't support any
+    pub fn depth_stencil_format(&self) -> Option<vk::Format> {
+        self.physical_device_properties.depth_stencil_format
+    }
+
+    /// The highest sample count that is supported for both color and depth attachments
+    pub fn max_usable_sample_count(&self) -> vk::SampleCountFlags {
         self.physical_device_properties
             .max_supported_msaa_sample_count
     }
 
+    /// The sample counts that are supported for both color and depth attachments, lowest first
+    pub fn supported_sample_counts(&self) -> Vec<vk::SampleCountFlags> {
+        sample_counts(self.physical_device_properties.supported_msaa_sample_counts)
+    }
+
+    /// Clamp the requested sample count to the closest one that is supported for both color and
+    /// depth attachments
+    pub fn clamp_msaa_sample_count(&self, requested: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        clamp_msaa(
+            self.physical_device_properties.supported_msaa_sample_counts,
+            requested,
+        )
+    }
+
+    pub fn physical_device_properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.physical_device_properties.properties
+    }
+
+    pub fn device_type(&self) -> vk::PhysicalDeviceType {
+        self.physical_device_properties.properties.device_type
+    }
+
+    pub fn name(&self) -> String {
+        device_selection::device_name(&self.physical_device_properties.properties)
+    }
+
+    pub fn limits(&self) -> &vk::PhysicalDeviceLimits {
+        &self.physical_device_properties.properties.limits
+    }
+
+    /// The features that were enabled when the logical device was created
+    pub fn features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.physical_device_properties.enabled_features.core
+    }
+
+    pub fn timeline_semaphores_enabled(&self) -> bool {
+        self.physical_device_properties
+            .enabled_features
+            .timeline_semaphore
+    }
+
+    /// Partially bound and update-after-bind descriptors, see `GraphicsPipelineBuilder::bindless`
+    pub fn descriptor_indexing_enabled(&self) -> bool {
+        self.physical_device_properties
+            .enabled_features
+            .descriptor_indexing
+    }
+
+    /// Viewports with a negative height, see `GraphicsPipelineBuilder::flip_viewport_y`
+    pub fn negative_viewport_height_enabled(&self) -> bool {
+        self.physical_device_properties
+            .enabled_features
+            .negative_viewport_height
+    }
+
+    /// See `CommandBuffer::begin_rendering`
+    #[cfg(feature = "dynamic_rendering")]
+    pub fn dynamic_rendering_enabled(&self) -> bool {
+        self.dynamic_rendering.is_some()
+    }
+
+    #[cfg(feature = "dynamic_rendering")]
+    pub(crate) fn dynamic_rendering_fn(
+        &self,
+    ) -> Option<crate::dynamic_rendering::DynamicRenderingFn> {
+        self.dynamic_rendering
+    }
+
+    pub fn min_uniform_buffer_offset_alignment(&self) -> u64 {
+        self.limits().min_uniform_buffer_offset_alignment
+    }
+
+    /// Name `handle` in validation messages and debuggers like RenderDoc. Does nothing if
+    /// validation is not enabled.
+    pub fn set_object_name<T: vk::Handle>(&self, handle: T, name: &str) -> Result<(), DeviceError> {
+        let debug_utils = match &self.debug_utils {
+            Some(debug_utils) => debug_utils,
+            None => return Ok(()),
+        };
+
+        let name = CString::new(name.replace('\0', "")).expect("Nul bytes were removed");
+        let info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(T::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name);
+
+        unsafe {
+            debug_utils
+                .debug_utils_set_object_name(self.inner_device.vk_device.handle(), &info)
+                .map_err(DeviceError::ObjectName)
+        }
+    }
+
+    /// Like `set_object_name`, but failures are only logged. Used for the default names of
+    /// created resources.
+    pub(crate) fn name_object<T: vk::Handle>(&self, handle: T, name: &str) {
+        if let Err(e) = self.set_object_name(handle, name) {
+            log::warn!("Failed to name {:?} {}: {}", T::TYPE, name, e);
+        }
+    }
+
     pub fn allocator(&self) -> AllocatorHandle {
         Rc::clone(&self.allocator)
     }
+
+    /// Walks all allocations, so this is not meant to be called every frame
+    pub fn memory_stats(&self) -> Result<MemoryStats, DeviceError> {
+        let stats = self.allocator.calculate_stats()?;
+        Ok(MemoryStats::new(&stats, self.memory_properties()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msaa_clamping() {
+        let supported = vk::SampleCountFlags::TYPE_1
+            | vk::SampleCountFlags::TYPE_2
+            | vk::SampleCountFlags::TYPE_4
+            | vk::SampleCountFlags::TYPE_8;
+
+        assert_eq!(
+            get_max_supported_msaa(supported),
+            vk::SampleCountFlags::TYPE_8
+        );
+        assert_eq!(
+            clamp_msaa(supported, vk::SampleCountFlags::TYPE_4),
+            vk::SampleCountFlags::TYPE_4
+        );
+        assert_eq!(
+            clamp_msaa(supported, vk::SampleCountFlags::TYPE_64),
+            vk::SampleCountFlags::TYPE_8
+        );
+        assert_eq!(
+            clamp_msaa(vk::SampleCountFlags::TYPE_1, vk::SampleCountFlags::TYPE_4),
+            vk::SampleCountFlags::TYPE_1
+        );
+        assert_eq!(
+            clamp_msaa(
+                vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_4,
+                vk::SampleCountFlags::TYPE_2
+            ),
+            vk::SampleCountFlags::TYPE_1
+        );
+    }
+
+    #[test]
+    fn supported_sample_counts() {
+        let supported = vk::SampleCountFlags::TYPE_1
+            | vk::SampleCountFlags::TYPE_4
+            | vk::SampleCountFlags::TYPE_8;
+        assert_eq!(
+            sample_counts(supported),
+            vec![
+                vk::SampleCountFlags::TYPE_1,
+                vk::SampleCountFlags::TYPE_4,
+                vk::SampleCountFlags::TYPE_8
+            ]
+        );
+        assert_eq!(
+            sample_counts(vk::SampleCountFlags::empty()),
+            vec![vk::SampleCountFlags::TYPE_1]
+        );
+    }
 }
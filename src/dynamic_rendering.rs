@@ -0,0 +1,204 @@
+// VK_KHR_dynamic_rendering, for rendering without render pass and framebuffer objects. ash 0.31
+// was generated from the Vulkan 1.2 headers, so the types and entry points of the extension are
+// declared here. They match the C declarations in vulkan_core.h.
+
+use ash::version::InstanceV1_0;
+use ash::vk;
+
+use std::ffi::CStr;
+use std::os::raw::c_void;
+
+const STRUCTURE_TYPE_RENDERING_INFO: vk::StructureType = vk::StructureType::from_raw(1_000_044_000);
+const STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_001);
+const STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_002);
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_003);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RenderingAttachmentInfo {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub image_view: vk::ImageView,
+    pub image_layout: vk::ImageLayout,
+    pub resolve_mode: vk::ResolveModeFlags,
+    pub resolve_image_view: vk::ImageView,
+    pub resolve_image_layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+impl RenderingAttachmentInfo {
+    /// Cleared to `clear_value` and stored, without resolve
+    pub fn cleared(
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout,
+        clear_value: vk::ClearValue,
+    ) -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO,
+            p_next: std::ptr::null(),
+            image_view,
+            image_layout,
+            resolve_mode: vk::ResolveModeFlags::NONE,
+            resolve_image_view: vk::ImageView::null(),
+            resolve_image_layout: vk::ImageLayout::UNDEFINED,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RenderingInfo {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub flags: vk::Flags,
+    pub render_area: vk::Rect2D,
+    pub layer_count: u32,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachments: *const RenderingAttachmentInfo,
+    pub p_depth_attachment: *const RenderingAttachmentInfo,
+    pub p_stencil_attachment: *const RenderingAttachmentInfo,
+}
+
+impl RenderingInfo {
+    /// The attachments have to outlive the returned struct
+    pub fn new(
+        render_area: vk::Rect2D,
+        color_attachments: &[RenderingAttachmentInfo],
+        depth_attachment: Option<&RenderingAttachmentInfo>,
+        stencil_attachment: Option<&RenderingAttachmentInfo>,
+    ) -> Self {
+        let ptr = |a: Option<&RenderingAttachmentInfo>| {
+            a.map_or(std::ptr::null(), |a| a as *const RenderingAttachmentInfo)
+        };
+        Self {
+            s_type: STRUCTURE_TYPE_RENDERING_INFO,
+            p_next: std::ptr::null(),
+            flags: 0,
+            render_area,
+            layer_count: 1,
+            view_mask: 0,
+            color_attachment_count: color_attachments.len() as u32,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: ptr(depth_attachment),
+            p_stencil_attachment: ptr(stencil_attachment),
+        }
+    }
+}
+
+/// Replaces the render pass when creating a pipeline for dynamic rendering
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PipelineRenderingCreateInfo {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachment_formats: *const vk::Format,
+    pub depth_attachment_format: vk::Format,
+    pub stencil_attachment_format: vk::Format,
+}
+
+unsafe impl vk::ExtendsGraphicsPipelineCreateInfo for PipelineRenderingCreateInfo {}
+
+impl PipelineRenderingCreateInfo {
+    /// `color_formats` has to outlive the returned struct
+    pub fn new(
+        color_formats: &[vk::Format],
+        depth_format: vk::Format,
+        stencil_format: vk::Format,
+    ) -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO,
+            p_next: std::ptr::null(),
+            view_mask: 0,
+            color_attachment_count: color_formats.len() as u32,
+            p_color_attachment_formats: color_formats.as_ptr(),
+            depth_attachment_format: depth_format,
+            stencil_attachment_format: stencil_format,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PhysicalDeviceDynamicRenderingFeatures {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub dynamic_rendering: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceDynamicRenderingFeatures {}
+
+impl Default for PhysicalDeviceDynamicRenderingFeatures {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES,
+            p_next: std::ptr::null_mut(),
+            dynamic_rendering: vk::TRUE,
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+type PFN_vkCmdBeginRendering = unsafe extern "system" fn(
+    command_buffer: vk::CommandBuffer,
+    p_rendering_info: *const RenderingInfo,
+);
+#[allow(non_camel_case_types)]
+type PFN_vkCmdEndRendering = unsafe extern "system" fn(command_buffer: vk::CommandBuffer);
+// What get_device_proc_addr returns, before it is cast to the actual signature
+type VoidFunction = unsafe extern "system" fn() -> c_void;
+
+/// The entry points of the extension, loaded for a device that it was enabled for
+#[derive(Copy, Clone)]
+pub struct DynamicRenderingFn {
+    cmd_begin_rendering: PFN_vkCmdBeginRendering,
+    cmd_end_rendering: PFN_vkCmdEndRendering,
+}
+
+impl DynamicRenderingFn {
+    pub fn name() -> &'static CStr {
+        CStr::from_bytes_with_nul(b"VK_KHR_dynamic_rendering\0").expect("Wrong extension name")
+    }
+
+    /// None if the entry points couldn't be loaded, e.g. if the extension was not enabled
+    pub fn load(instance: &ash::Instance, device: vk::Device) -> Option<Self> {
+        unsafe {
+            let begin = instance.get_device_proc_addr(
+                device,
+                b"vkCmdBeginRenderingKHR\0".as_ptr() as *const std::os::raw::c_char,
+            )?;
+            let end = instance.get_device_proc_addr(
+                device,
+                b"vkCmdEndRenderingKHR\0".as_ptr() as *const std::os::raw::c_char,
+            )?;
+            Some(Self {
+                cmd_begin_rendering: std::mem::transmute::<VoidFunction, PFN_vkCmdBeginRendering>(
+                    begin,
+                ),
+                cmd_end_rendering: std::mem::transmute::<VoidFunction, PFN_vkCmdEndRendering>(end),
+            })
+        }
+    }
+
+    pub unsafe fn cmd_begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &RenderingInfo,
+    ) {
+        (self.cmd_begin_rendering)(command_buffer, rendering_info)
+    }
+
+    pub unsafe fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        (self.cmd_end_rendering)(command_buffer)
+    }
+}
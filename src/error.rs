@@ -10,35 +10,112 @@ pub enum ResizeReason {
     SubOptimal,
 }
 
+impl std::fmt::Display for ResizeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResizeReason::OutOfDate => write!(f, "the swapchain is out of date with the surface"),
+            ResizeReason::SubOptimal => {
+                write!(f, "the swapchain no longer matches the surface exactly")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum RenderError {
+    #[error("Command error: {0}")]
     Command(#[from] command::CommandError),
+    #[error("Instance error: {0}")]
     Instance(#[from] instance::InstanceError),
+    #[error("Debug utils error: {0}")]
     DebugUtils(#[from] util::vk_debug::DebugUtilsError),
+    #[error("Surface error: {0}")]
     Surface(#[from] surface::SurfaceError),
+    #[error("Device error: {0}")]
     Device(#[from] device::DeviceError),
+    #[error("Render pass error: {0}")]
     RenderPass(#[from] render_pass::RenderPassError),
+    #[error("Pipeline error: {0}")]
     Pipeline(#[from] pipeline::PipelineError),
+    #[error("Pipeline cache error: {0}")]
+    PipelineCache(#[from] pipeline_cache::PipelineCacheError),
+    #[error("Queue error: {0}")]
     Queue(#[from] queue::QueueError),
+    #[error("Query error: {0}")]
+    Query(#[from] query::QueryError),
+    #[error("Descriptor error: {0}")]
     Descriptor(#[from] descriptor::DescriptorError),
+    #[error("Color buffer error: {0}")]
     ColorBuffer(#[from] color_buffer::ColorBufferError),
+    #[error("Depth buffer error: {0}")]
     DepthBuffer(#[from] depth_buffer::DepthBufferError),
+    #[error("Offscreen target error: {0}")]
+    Offscreen(#[from] offscreen::OffscreenError),
+    #[error("Synchronization error: {0}")]
     Sync(#[from] sync::SyncError),
+    #[error("Swapchain error: {0}")]
     Swapchain(swapchain::SwapchainError),
+    #[error("Failed to create uniform buffer: {0}")]
     UniformBuffer(mem::MemoryError),
+    #[error("Failed to create vertex buffer: {0}")]
     VertexBuffer(mem::MemoryError),
+    #[error("Failed to create index buffer: {0}")]
     IndexBuffer(mem::MemoryError),
+    #[error("Texture error: {0}")]
+    Texture(#[from] texture::TextureError),
+    #[error("Failed to read back the framebuffer: {0}")]
+    Readback(mem::MemoryError),
+    #[error("Can't read back images of format {0:?}")]
+    UnsupportedReadbackFormat(ash::vk::Format),
+    #[error("The swapchain images can't be read back, they were not created with TRANSFER_SRC")]
+    ReadbackUnsupported,
+    #[error("Invalid render scale {0}, it has to be positive")]
+    InvalidRenderScale(f32),
+    #[error("The swapchain images can't be blitted to, they were not created with TRANSFER_DST")]
+    RenderScaleUnsupported,
+    #[error("The msaa sample count {0:?} is not supported by the device")]
+    UnsupportedSampleCount(ash::vk::SampleCountFlags),
+    #[error("Stencil was requested but the device has no depth-stencil format")]
+    StencilUnsupported,
+    #[error("No frame has been submitted with a readback request, see Renderer::request_readback")]
+    NoReadback,
     // TODO: Should this be an error?
+    #[error("Resize required, {0}. Call Renderer::resize.")]
     NeedsResize(ResizeReason),
+    /// The last resize was to a zero-sized extent. No frames can be rendered until `resize` is
+    /// called with a non-zero extent.
+    #[error("The window is minimized, no frames can be rendered until it is resized")]
+    Minimized,
     // TODO: Resource typename here as well
+    #[error("Invalid handle {0:?}")]
     InvalidHandle(ID),
+    #[error("Missing uniform buffers for descriptor set")]
     MissingUniformBuffersForDescriptor,
-}
-
-impl std::fmt::Display for RenderError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
+    /// Needs to be between 1 and the number of swapchain images
+    #[error("Invalid number of frames in flight: {0}, it has to be between 1 and the number of swapchain images")]
+    InvalidFramesInFlight(usize),
+    /// A frame was submitted that isn't the one most recently returned by `next_frame`, e.g. one
+    /// that was kept across calls to `next_frame`
+    #[error("Frame {got} was submitted out of order, expected frame {expected}")]
+    FrameOutOfOrder { expected: u32, got: u32 },
+    /// As `FrameOutOfOrder`, for a kept frame that has the same index as the current one but was
+    /// started before one or more other frames were submitted
+    #[error("Frame number {got} is stale, expected frame number {expected}")]
+    StaleFrame { expected: u64, got: u64 },
+    /// A command buffer was added to a frame that it wasn't created from
+    #[error("The command buffer was not created from this frame")]
+    ForeignCommandBuffer,
+    /// A secondary command buffer was added to a frame, these are executed from a primary
+    /// command buffer with `CommandBuffer::execute_commands` instead
+    #[error("Secondary command buffers can't be submitted directly")]
+    SecondaryCommandBuffer,
+    /// The window was removed, or the main window was to be removed
+    #[error("{0:?} is not a window of this renderer")]
+    InvalidWindow(WindowId),
+    /// The device was created for a headless renderer, or its present queue can't present to
+    /// the surface of an added window
+    #[error("The device can't present to the surface of the window")]
+    SurfaceUnsupported,
 }
 
 impl From<swapchain::SwapchainError> for RenderError {
@@ -50,3 +127,17 @@ impl From<swapchain::SwapchainError> for RenderError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_resize_message() {
+        let e = RenderError::from(swapchain::SwapchainError::OutOfDate);
+        assert_eq!(
+            e.to_string(),
+            "Resize required, the swapchain is out of date with the surface. Call Renderer::resize."
+        );
+    }
+}
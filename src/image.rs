@@ -28,6 +28,7 @@ impl std::ops::Drop for ImageView {
 }
 
 impl ImageView {
+    /// 2D view of all `mip_levels` of the first array layer
     pub fn new<D: HasVkDevice>(
         device: &D,
         vk_image: &vk::Image,
@@ -35,14 +36,6 @@ impl ImageView {
         aspect_mask: vk::ImageAspectFlags,
         mip_levels: u32,
     ) -> Result<Self, ImageViewError> {
-        let vk_format = format.into();
-        let comp_mapping = vk::ComponentMapping {
-            r: vk::ComponentSwizzle::R,
-            g: vk::ComponentSwizzle::G,
-            b: vk::ComponentSwizzle::B,
-            a: vk::ComponentSwizzle::A,
-        };
-
         let subresource_range = vk::ImageSubresourceRange {
             aspect_mask,
             base_mip_level: 0,
@@ -51,9 +44,35 @@ impl ImageView {
             layer_count: 1,
         };
 
+        Self::with_range(
+            device,
+            vk_image,
+            format,
+            subresource_range,
+            vk::ImageViewType::TYPE_2D,
+        )
+    }
+
+    /// View of a range of mip levels and array layers, e.g. a single mip level for a downsample
+    /// pass or a face of a cube map
+    pub fn with_range<D: HasVkDevice>(
+        device: &D,
+        vk_image: &vk::Image,
+        format: util::Format,
+        subresource_range: vk::ImageSubresourceRange,
+        view_type: vk::ImageViewType,
+    ) -> Result<Self, ImageViewError> {
+        let vk_format = format.into();
+        let comp_mapping = vk::ComponentMapping {
+            r: vk::ComponentSwizzle::R,
+            g: vk::ComponentSwizzle::G,
+            b: vk::ComponentSwizzle::B,
+            a: vk::ComponentSwizzle::A,
+        };
+
         let info = vk::ImageViewCreateInfo::builder()
             .image(*vk_image)
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(view_type)
             .format(vk_format)
             .components(comp_mapping)
             .subresource_range(subresource_range);
@@ -75,3 +94,47 @@ impl ImageView {
         &self.vk_image_view
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::headless_renderer;
+    use crate::texture;
+    use crate::ResourceManager;
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn mip_level_image_view() {
+        let mut renderer = headless_renderer();
+        let pixels = ::image::RgbaImage::from_pixel(8, 8, ::image::Rgba([255, 0, 0, 255]));
+        let handle = renderer
+            .create_resource(texture::TextureDescriptor::from_rgba_image(
+                &pixels,
+                vk::Format::R8G8B8A8_UNORM,
+                true,
+            ))
+            .expect("Failed to create texture");
+        let texture = renderer.get_resource(&handle).unwrap();
+        assert_eq!(texture.mip_levels(), 4);
+
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 2,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let view = ImageView::with_range(
+            &renderer.device,
+            texture.vk_image(),
+            texture.format().into(),
+            range,
+            vk::ImageViewType::TYPE_2D,
+        )
+        .expect("Failed to create image view");
+        assert_ne!(*view.vk_image_view(), vk::ImageView::null());
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+}
@@ -12,6 +12,8 @@ pub enum InstanceError {
     LoadError(Vec<&'static str>),
     #[error("Internal vulkan error: {0} {1}")]
     InternalVulkan(vk::Result, &'static str),
+    #[error("Application or engine name contains a nul byte: {0}")]
+    InvalidName(#[from] std::ffi::NulError),
 }
 
 impl From<ash::InstanceError> for InstanceError {
@@ -9,9 +9,87 @@ use crate::util::lifetime::LifetimeToken;
 pub mod error;
 pub use error::*;
 
+/// Passed to the driver in `VkApplicationInfo`. Drivers and tools may use the names to apply
+/// application-specific profiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceConfig {
+    pub app_name: String,
+    /// (major, minor, patch)
+    pub app_version: (u32, u32, u32),
+    pub engine_name: String,
+    /// (major, minor, patch)
+    pub engine_version: (u32, u32, u32),
+    /// The requested Vulkan version (major, minor, patch). Lowered to what the loader supports,
+    /// see `Instance::api_version`.
+    pub api_version: (u32, u32, u32),
+}
+
+impl Default for InstanceConfig {
+    fn default() -> Self {
+        Self {
+            app_name: String::from("trekanten"),
+            app_version: (0, 0, 0),
+            engine_name: String::from("trekanten"),
+            engine_version: (0, 0, 0),
+            api_version: (1, 2, 0),
+        }
+    }
+}
+
+// Owns the strings that VkApplicationInfo points to
+struct ApplicationNames {
+    app_name: CString,
+    engine_name: CString,
+}
+
+impl ApplicationNames {
+    fn new(config: &InstanceConfig) -> Result<Self, InstanceError> {
+        Ok(Self {
+            app_name: CString::new(config.app_name.as_str())?,
+            engine_name: CString::new(config.engine_name.as_str())?,
+        })
+    }
+
+    fn app_info<'a>(
+        &'a self,
+        config: &InstanceConfig,
+        api_version: u32,
+    ) -> vk::ApplicationInfoBuilder<'a> {
+        vk::ApplicationInfo::builder()
+            .application_name(&self.app_name)
+            .application_version(make_version(config.app_version))
+            .engine_name(&self.engine_name)
+            .engine_version(make_version(config.engine_version))
+            .api_version(api_version)
+    }
+}
+
+fn make_version((major, minor, patch): (u32, u32, u32)) -> u32 {
+    vk::make_version(major, minor, patch)
+}
+
+/// `supported` is None for a Vulkan 1.0 loader, see `Entry::try_enumerate_instance_version`
+fn choose_api_version(requested: u32, supported: Option<u32>) -> u32 {
+    let supported = supported.unwrap_or_else(|| vk::make_version(1, 0, 0));
+    if requested > supported {
+        log::warn!(
+            "Requested Vulkan {}.{} but only {}.{} is supported, using that instead",
+            vk::version_major(requested),
+            vk::version_minor(requested),
+            vk::version_major(supported),
+            vk::version_minor(supported)
+        );
+        supported
+    } else {
+        requested
+    }
+}
+
 pub struct Instance {
     entry: Entry,
     vk_instance: ash::Instance,
+    validation_enabled: bool,
+    api_version: u32,
     lifetime_token: LifetimeToken<Self>,
 }
 
@@ -60,12 +138,13 @@ fn validation_layers() -> Vec<CString> {
     vec![CString::new("VK_LAYER_KHRONOS_validation").expect("Failed to create CString")]
 }
 
-fn use_vk_validation() -> bool {
-    std::env::var(DISABLE_VALIDATION_LAYERS_ENV_VAR).is_err()
+/// The env var overrides the requested value so validation can be turned off without a rebuild
+fn use_vk_validation(requested: bool, disabled_by_env: bool) -> bool {
+    requested && !disabled_by_env
 }
 
-pub fn choose_validation_layers(entry: &Entry) -> Vec<CString> {
-    if use_vk_validation() {
+pub fn choose_validation_layers(entry: &Entry, validation: bool) -> Vec<CString> {
+    if validation {
         let requested = validation_layers();
         log::trace!("Requested vk layers:");
         log_cstrings(&requested);
@@ -102,9 +181,28 @@ pub fn choose_validation_layers(entry: &Entry) -> Vec<CString> {
     }
 }
 
+/// Validation messages are reported through the debug utils extension, which might not be
+/// available without the Vulkan SDK installed
+fn debug_utils_available(entry: &Entry) -> bool {
+    let available = match entry.enumerate_instance_extension_properties() {
+        Ok(available) => available,
+        Err(_) => return false,
+    };
+
+    let found = check_extensions(&[ext::DebugUtils::name()], &available).is_ok();
+    if !found {
+        log::warn!(
+            "Validation was requested but {:?} is not available, disabling it",
+            ext::DebugUtils::name()
+        );
+    }
+    found
+}
+
 fn choose_instance_extensions<T: AsRef<str>>(
     entry: &Entry,
     required_window_extensions: &[T],
+    validation: bool,
 ) -> Result<Vec<CString>, InstanceError> {
     let available = entry
         .enumerate_instance_extension_properties()
@@ -130,7 +228,7 @@ fn choose_instance_extensions<T: AsRef<str>>(
         instance_extensions.push(ash::extensions::khr::XlibSurface::name().to_owned());
     }
 
-    if use_vk_validation() {
+    if validation {
         instance_extensions.push(ext::DebugUtils::name().to_owned());
     }
 
@@ -141,18 +239,29 @@ fn choose_instance_extensions<T: AsRef<str>>(
 }
 
 impl Instance {
-    pub fn new<T: AsRef<str>>(required_window_extensions: &[T]) -> Result<Self, InstanceError> {
+    pub fn new<T: AsRef<str>>(
+        required_window_extensions: &[T],
+        validation: bool,
+        config: &InstanceConfig,
+    ) -> Result<Self, InstanceError> {
         let entry = Entry::new().expect("Failed to create Entry!");
+        let disabled_by_env = std::env::var(DISABLE_VALIDATION_LAYERS_ENV_VAR).is_ok();
+        let validation =
+            use_vk_validation(validation, disabled_by_env) && debug_utils_available(&entry);
 
-        let app_info = vk::ApplicationInfo {
-            api_version: vk::make_version(1, 2, 0),
-            ..Default::default()
-        };
+        let supported_version = entry
+            .try_enumerate_instance_version()
+            .map_err(|e| InstanceError::InternalVulkan(e, "Instance version enumeration"))?;
+        let api_version = choose_api_version(make_version(config.api_version), supported_version);
 
-        let extensions = choose_instance_extensions(&entry, required_window_extensions)?;
+        let names = ApplicationNames::new(config)?;
+        let app_info = names.app_info(config, api_version);
+
+        let extensions =
+            choose_instance_extensions(&entry, required_window_extensions, validation)?;
         let extensions_ptrs = vec_cstring_to_raw(extensions);
 
-        let validation_layers = choose_validation_layers(&entry);
+        let validation_layers = choose_validation_layers(&entry, validation);
         let layers_ptrs = vec_cstring_to_raw(validation_layers);
 
         let create_info = vk::InstanceCreateInfo::builder()
@@ -174,6 +283,8 @@ impl Instance {
         let instance = Instance {
             entry,
             vk_instance,
+            validation_enabled: validation,
+            api_version,
             lifetime_token,
         };
 
@@ -184,6 +295,18 @@ impl Instance {
         self.lifetime_token.clone()
     }
 
+    /// Whether validation was requested (and not disabled by the env var). The debug utils
+    /// extension is only enabled if this is true.
+    pub fn validation_enabled(&self) -> bool {
+        self.validation_enabled
+    }
+
+    /// The Vulkan version the instance was created with, i.e. the requested version or lower if
+    /// the loader doesn't support it. Devices may support a lower version still.
+    pub fn api_version(&self) -> u32 {
+        self.api_version
+    }
+
     pub fn vk_instance(&self) -> &ash::Instance {
         &self.vk_instance
     }
@@ -196,3 +319,56 @@ impl Instance {
         &self.entry
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_app_info() {
+        let config = InstanceConfig {
+            app_name: String::from("my app"),
+            app_version: (1, 2, 3),
+            ..Default::default()
+        };
+        let names = ApplicationNames::new(&config).expect("Valid names");
+        let app_info = names.app_info(&config, vk::make_version(1, 2, 0));
+
+        let app_name = unsafe { CStr::from_ptr(app_info.p_application_name) };
+        let engine_name = unsafe { CStr::from_ptr(app_info.p_engine_name) };
+        assert_eq!(app_name.to_str(), Ok("my app"));
+        assert_eq!(engine_name.to_str(), Ok("trekanten"));
+        assert_eq!(app_info.application_version, vk::make_version(1, 2, 3));
+        assert_eq!(app_info.api_version, vk::make_version(1, 2, 0));
+    }
+
+    #[test]
+    fn validation_disabled_by_env_var() {
+        assert!(use_vk_validation(true, false));
+        assert!(!use_vk_validation(true, true));
+        assert!(!use_vk_validation(false, false));
+        assert!(!use_vk_validation(false, true));
+    }
+
+    #[test]
+    fn api_version_is_clamped() {
+        let v = |minor| vk::make_version(1, minor, 0);
+        assert_eq!(choose_api_version(v(3), Some(v(3))), v(3));
+        assert_eq!(choose_api_version(v(3), Some(v(2))), v(2));
+        assert_eq!(choose_api_version(v(1), Some(v(2))), v(1));
+        // A 1.0 loader can't enumerate the instance version
+        assert_eq!(choose_api_version(v(2), None), v(0));
+    }
+
+    #[test]
+    fn nul_in_app_name() {
+        let config = InstanceConfig {
+            app_name: String::from("my\0app"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ApplicationNames::new(&config),
+            Err(InstanceError::InvalidName(_))
+        ));
+    }
+}
@@ -1,25 +1,37 @@
 use ash::vk;
 
+use std::rc::Rc;
+
 mod color_buffer;
 mod command;
 mod common;
+mod config;
 mod depth_buffer;
 mod descriptor;
 mod device;
+#[cfg(feature = "dynamic_rendering")]
+mod dynamic_rendering;
 mod error;
 mod framebuffer;
 mod image;
 mod instance;
 mod mem;
 pub mod mesh;
+pub mod offscreen;
+mod pacing;
 pub mod pipeline;
+mod pipeline_cache;
+mod query;
 mod queue;
-mod render_pass;
+pub mod render_pass;
 mod resource;
 mod spirv;
+pub mod storage_buffer;
 mod surface;
 mod swapchain;
-mod sync;
+pub mod sync;
+#[cfg(test)]
+mod test_util;
 pub mod texture;
 pub mod uniform;
 mod util;
@@ -28,17 +40,24 @@ pub mod window;
 
 pub use error::RenderError;
 pub use error::ResizeReason;
+pub use resource::DestroyResource;
 pub use resource::Handle;
+pub use resource::IterResources;
 pub use resource::ResourceManager;
 
-use common::MAX_FRAMES_IN_FLIGHT;
+pub use common::DEFAULT_FRAMES_IN_FLIGHT;
+pub use config::{RendererBuilder, RendererConfig};
+pub use device::{DevicePreference, HeapStats, MemoryStats};
+pub use instance::InstanceConfig;
+pub use util::vk_debug::{ValidationErrorAction, ValidationErrors, ValidationMessage};
+pub use util::{linear_to_srgb, srgb_to_linear};
 
 // Notes:
 // We can have N number of swapchain images, it depends on the backing presentation implementation.
 // Generally, we are aiming for three images + MAILBOX (render one and use the latest of the two waiting)
 //
-// We use MAX_FRAMES_IN_FLIGHT (2, hardcoded atm) frames in flight at once. This allows us to start the next frame directly after we render.
-// Whenever next_frame() is called, it can be thought of as binding one of the two frames to a particular swapchain image.
+// We use frames_in_flight (DEFAULT_FRAMES_IN_FLIGHT = 2 unless configured) frames in flight at once. This allows us to start the next frame directly after we render.
+// Whenever next_frame() is called, it can be thought of as binding one of the frames to a particular swapchain image.
 // All rendering in that frame will be done on that swapchain image/framebuffer.
 
 pub struct FrameSynchronization {
@@ -61,55 +80,116 @@ impl FrameSynchronization {
     }
 }
 
+/// Tightly packed RGBA8 pixels, row by row
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
 pub struct Frame {
+    // Counts up with every submitted frame, unlike frame_idx
+    number: u64,
     frame_idx: u32,
+    window: WindowId,
     swapchain_image_idx: u32,
-    recorded_command_buffers: Vec<vk::CommandBuffer>,
-    gfx_command_pool: command::CommandPool,
+    recorded_command_buffers: Vec<command::CommandBuffer<command::Finished>>,
+    // Owned by the renderer and reset when this frame index is reused
+    gfx_command_pool: Rc<command::CommandPool>,
+    // See Renderer::request_readback
+    read_back: bool,
 }
 
 impl Frame {
+    /// The window that this frame is rendered into, see `Renderer::next_frame_for`
+    pub fn window(&self) -> WindowId {
+        self.window
+    }
+
     pub fn new_command_buffer(&self) -> Result<command::CommandBuffer, command::CommandError> {
         self.gfx_command_pool
             .create_command_buffer(command::CommandBufferSubmission::Single)
     }
 
-    pub fn add_command_buffer(&mut self, cmd_buffer: command::CommandBuffer) {
-        self.recorded_command_buffers
-            .push(*cmd_buffer.vk_command_buffer());
+    pub fn new_secondary_command_buffer(
+        &self,
+        render_pass: &render_pass::RenderPass,
+        framebuffer: &framebuffer::Framebuffer,
+    ) -> Result<command::CommandBuffer<command::InRenderPass>, command::CommandError> {
+        self.gfx_command_pool.create_secondary_command_buffer(
+            command::CommandBufferSubmission::Single,
+            render_pass,
+            0,
+            framebuffer,
+        )
+    }
+
+    /// `cmd_buffer` has to be a primary command buffer created from this frame
+    pub fn add_command_buffer(
+        &mut self,
+        cmd_buffer: command::CommandBuffer<command::Finished>,
+    ) -> Result<(), RenderError> {
+        if cmd_buffer.is_secondary() {
+            return Err(RenderError::SecondaryCommandBuffer);
+        }
+        if !self.gfx_command_pool.owns(&cmd_buffer) {
+            return Err(RenderError::ForeignCommandBuffer);
+        }
+
+        self.recorded_command_buffers.push(cmd_buffer);
+        Ok(())
     }
 }
 
+// Fields are dropped in declaration order. Everything created from the device has to come before
+// it, and the device, surface and debug utils before the instance.
 pub struct Renderer {
     // Resources
     graphics_pipelines: pipeline::GraphicsPipelines,
-    vertex_buffers: resource::Storage<mesh::VertexBuffer>,
-    index_buffers: resource::Storage<mesh::IndexBuffer>,
+    vertex_buffers: mesh::MeshBuffers<mesh::VertexBuffer>,
+    index_buffers: mesh::MeshBuffers<mesh::IndexBuffer>,
+    meshes: resource::Storage<mesh::Mesh>,
+    storage_buffers: resource::Storage<storage_buffer::StorageBuffer>,
     uniform_buffers: uniform::UniformBuffers,
     descriptor_sets: descriptor::DescriptorSets,
     textures: texture::Textures,
+    deletion_queue: resource::DeletionQueue,
 
     // Swapchain-related
-    // TODO: Could render pass be a abstracted as forward-renderer?
-    render_pass: render_pass::RenderPass,
-    swapchain_framebuffers: Vec<framebuffer::Framebuffer>,
-    depth_buffer: depth_buffer::DepthBuffer,
-    color_buffer: color_buffer::ColorBuffer,
-    swapchain: swapchain::Swapchain,
-    swapchain_image_idx: u32, // TODO: Bake this into the swapchain?
-    image_to_frame_idx: Vec<Option<u32>>,
+    windows: Windows,
+    render_scale: f32,
+    msaa_sample_count: vk::SampleCountFlags,
+    present_mode: vk::PresentModeKHR,
+    surface_formats: Vec<vk::SurfaceFormatKHR>,
+    subpass_dependencies: Vec<vk::SubpassDependency>,
+    // Of the main render pass, see main_depth_format
+    depth_format: Option<vk::Format>,
+    // Linear, kept when the render pass is recreated
+    clear_color: Option<[f32; 4]>,
+    frame_pacer: pacing::FramePacer,
 
     util_command_pool: command::CommandPool,
+    transfer_command_pool: command::CommandPool,
+    staging_pool: mem::StagingPool,
+    // One per frame in flight
+    gfx_command_pools: Vec<Rc<command::CommandPool>>,
 
     // Needs to be kept-alive
-    _debug_utils: util::vk_debug::DebugUtils,
+    debug_utils: Option<util::vk_debug::DebugUtils>,
 
-    frame_synchronization: [FrameSynchronization; MAX_FRAMES_IN_FLIGHT],
+    frame_synchronization: Vec<FrameSynchronization>,
     frame_idx: u32,
-    frames: [Option<Frame>; MAX_FRAMES_IN_FLIGHT],
+    frames: Vec<Option<Frame>>,
+    submitted_frames: u64,
+    // Reused by submit, to not allocate every frame
+    submit_scratch: queue::SubmitScratch,
+    frames_in_flight: usize,
+
+    // Two timestamps per frame in flight, None if the graphics queue doesn't support them
+    timestamp_queries: Option<query::TimestampQueryPool>,
+    last_frame_gpu_time_ms: Option<f32>,
 
     device: device::Device,
-    surface: surface::Surface,
     instance: instance::Instance,
 }
 
@@ -122,14 +202,265 @@ impl std::ops::Drop for Renderer {
     }
 }
 
-// Result holder struct
+// TODO: Could render pass be a abstracted as forward-renderer?
 struct SwapchainAndCo {
-    swapchain: swapchain::Swapchain,
-    depth_buffer: depth_buffer::DepthBuffer,
-    color_buffer: color_buffer::ColorBuffer,
+    render_pass: render_pass::RenderPass,
     swapchain_framebuffers: Vec<framebuffer::Framebuffer>,
+    // Only kept alive for the framebuffers
+    _depth_buffer: Option<depth_buffer::DepthBuffer>,
+    _color_buffer: Option<color_buffer::ColorBuffer>,
+    swapchain: swapchain::Swapchain,
     image_to_frame_idx: Vec<Option<u32>>,
-    render_pass: render_pass::RenderPass,
+}
+
+/// The color format of the target of a headless renderer
+pub const HEADLESS_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+// What frames are rendered into
+enum RenderTarget {
+    Swapchain(SwapchainAndCo),
+    // A single image, see Renderer::new_headless
+    Headless(offscreen::OffscreenTarget),
+}
+
+impl RenderTarget {
+    fn render_pass(&self) -> &render_pass::RenderPass {
+        match self {
+            Self::Swapchain(sc) => &sc.render_pass,
+            Self::Headless(target) => target.render_pass(),
+        }
+    }
+
+    fn render_pass_mut(&mut self) -> &mut render_pass::RenderPass {
+        match self {
+            Self::Swapchain(sc) => &mut sc.render_pass,
+            Self::Headless(target) => target.render_pass_mut(),
+        }
+    }
+
+    fn extent(&self) -> util::Extent2D {
+        match self {
+            Self::Swapchain(sc) => sc.swapchain.info().extent,
+            Self::Headless(target) => target.extent(),
+        }
+    }
+
+    fn format(&self) -> vk::Format {
+        match self {
+            Self::Swapchain(sc) => sc.swapchain.info().format,
+            Self::Headless(target) => target.format(),
+        }
+    }
+
+    fn image_count(&self) -> usize {
+        match self {
+            Self::Swapchain(sc) => sc.swapchain.num_images(),
+            Self::Headless(_) => 1,
+        }
+    }
+
+    fn framebuffer(&self, image_idx: u32) -> &framebuffer::Framebuffer {
+        match self {
+            Self::Swapchain(sc) => &sc.swapchain_framebuffers[image_idx as usize],
+            Self::Headless(target) => target.framebuffer(),
+        }
+    }
+}
+
+/// Identifies a window (or headless target) that frames are rendered into, see
+/// `Renderer::add_window`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WindowId(u32);
+
+impl WindowId {
+    /// The window (or offscreen image, when headless) that the renderer was created with
+    pub const MAIN: WindowId = WindowId(0);
+}
+
+// A copy of the image of a submitted frame, see Renderer::request_readback
+struct Readback {
+    buffer: mem::DeviceBuffer,
+    extent: util::Extent2D,
+    format: vk::Format,
+}
+
+fn readback_needs_swizzle(format: vk::Format) -> Result<bool, RenderError> {
+    match format {
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => Ok(true),
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => Ok(false),
+        _ => Err(RenderError::UnsupportedReadbackFormat(format)),
+    }
+}
+
+// Everything that is specific to one window, the device and resources are shared. Fields are
+// dropped in declaration order, the swapchain before its surface.
+struct SwapchainContext {
+    target: RenderTarget,
+    // Frames are rendered into this instead of the target if the render scale is not 1, and it is
+    // blitted to the target in submit. Only used for the main window.
+    scaled_target: Option<offscreen::OffscreenTarget>,
+    // The copy of the last frame that requested a readback
+    readback: Option<Readback>,
+    // If the image of the current frame was acquired from a suboptimal swapchain
+    acquired_sub_optimal: bool,
+    minimized: bool,
+    // The extent that was last passed to resize, the target might have been clamped to another
+    // one by the surface
+    requested_extent: util::Extent2D,
+    // None when headless
+    surface: Option<surface::Surface>,
+    // Makes the headless target return NeedsResize once, as a swapchain would
+    #[cfg(test)]
+    injected_resize: Option<InjectedResize>,
+}
+
+// Where the headless target returns NeedsResize, see SwapchainContext::injected_resize
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InjectedResize {
+    Acquire,
+    Present,
+}
+
+impl SwapchainContext {
+    fn new(target: RenderTarget, surface: Option<surface::Surface>) -> Self {
+        Self {
+            requested_extent: target.extent(),
+            target,
+            scaled_target: None,
+            readback: None,
+            acquired_sub_optimal: false,
+            minimized: false,
+            surface,
+            #[cfg(test)]
+            injected_resize: None,
+        }
+    }
+
+    // The image that frames end up in, and its layout at the end of a frame
+    fn target_image(&self, image_idx: u32) -> (&vk::Image, vk::ImageLayout) {
+        match &self.target {
+            RenderTarget::Swapchain(sc) => (
+                sc.swapchain.image(image_idx),
+                vk::ImageLayout::PRESENT_SRC_KHR,
+            ),
+            RenderTarget::Headless(target) => {
+                (target.vk_image(), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            }
+        }
+    }
+
+    // The render pass that frames are rendered with, see Renderer::set_render_scale
+    fn render_pass(&self) -> &render_pass::RenderPass {
+        match self.scaled_target.as_ref() {
+            Some(scaled_target) => scaled_target.render_pass(),
+            None => self.target.render_pass(),
+        }
+    }
+
+    fn render_pass_mut(&mut self) -> &mut render_pass::RenderPass {
+        match self.scaled_target.as_mut() {
+            Some(scaled_target) => scaled_target.render_pass_mut(),
+            None => self.target.render_pass_mut(),
+        }
+    }
+
+    fn render_extent(&self) -> util::Extent2D {
+        match self.scaled_target.as_ref() {
+            Some(scaled_target) => scaled_target.extent(),
+            None => self.target.extent(),
+        }
+    }
+
+    fn framebuffer(&self, image_idx: u32) -> &framebuffer::Framebuffer {
+        match self.scaled_target.as_ref() {
+            Some(scaled_target) => scaled_target.framebuffer(),
+            None => self.target.framebuffer(image_idx),
+        }
+    }
+}
+
+// The main window and the ones added with Renderer::add_window
+struct Windows {
+    main: SwapchainContext,
+    // Indexed by id - 1, None for removed windows so that ids are not reused
+    added: Vec<Option<SwapchainContext>>,
+}
+
+impl Windows {
+    fn new(main: SwapchainContext) -> Self {
+        Self {
+            main,
+            added: Vec::new(),
+        }
+    }
+
+    fn get(&self, id: WindowId) -> Result<&SwapchainContext, RenderError> {
+        if id == WindowId::MAIN {
+            return Ok(&self.main);
+        }
+
+        self.added
+            .get(id.0 as usize - 1)
+            .and_then(Option::as_ref)
+            .ok_or(RenderError::InvalidWindow(id))
+    }
+
+    fn get_mut(&mut self, id: WindowId) -> Result<&mut SwapchainContext, RenderError> {
+        if id == WindowId::MAIN {
+            return Ok(&mut self.main);
+        }
+
+        self.added
+            .get_mut(id.0 as usize - 1)
+            .and_then(Option::as_mut)
+            .ok_or(RenderError::InvalidWindow(id))
+    }
+
+    fn add(&mut self, context: SwapchainContext) -> WindowId {
+        self.added.push(Some(context));
+        WindowId(self.added.len() as u32)
+    }
+
+    // The main window can't be removed
+    fn remove(&mut self, id: WindowId) -> Option<SwapchainContext> {
+        if id == WindowId::MAIN {
+            return None;
+        }
+
+        self.added.get_mut(id.0 as usize - 1).and_then(Option::take)
+    }
+
+    fn ids(&self) -> Vec<WindowId> {
+        let added = self
+            .added
+            .iter()
+            .enumerate()
+            .filter(|(_, context)| context.is_some())
+            .map(|(i, _)| WindowId(i as u32 + 1));
+        std::iter::once(WindowId::MAIN).chain(added).collect()
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut SwapchainContext> {
+        std::iter::once(&mut self.main).chain(self.added.iter_mut().flatten())
+    }
+}
+
+fn main_depth_format(
+    device: &device::Device,
+    depth: bool,
+    stencil: bool,
+) -> Result<Option<vk::Format>, RenderError> {
+    if stencil {
+        device
+            .depth_stencil_format()
+            .map(Some)
+            .ok_or(RenderError::StencilUnsupported)
+    } else if depth {
+        Ok(Some(device.depth_buffer_format()))
+    } else {
+        Ok(None)
+    }
 }
 
 fn create_swapchain_and_co(
@@ -137,267 +468,1595 @@ fn create_swapchain_and_co(
     device: &device::Device,
     surface: &surface::Surface,
     extent: &util::Extent2D,
+    msaa_sample_count: vk::SampleCountFlags,
+    present_mode: vk::PresentModeKHR,
+    surface_formats: &[vk::SurfaceFormatKHR],
+    subpass_dependencies: &[vk::SubpassDependency],
+    depth_format: Option<vk::Format>,
     old: Option<&swapchain::Swapchain>,
 ) -> Result<SwapchainAndCo, RenderError> {
-    let msaa_sample_count = device.max_msaa_sample_count();
-    let swapchain = swapchain::Swapchain::new(&instance, &device, &surface, &extent, old)?;
-    let render_pass =
-        render_pass::RenderPass::new(&device, swapchain.info().format, msaa_sample_count)?;
+    let swapchain = swapchain::Swapchain::new(
+        &instance,
+        &device,
+        &surface,
+        &extent,
+        present_mode,
+        surface_formats,
+        old,
+    )?;
+    let render_pass = render_pass::RenderPassBuilder::forward(
+        swapchain.info().format,
+        depth_format,
+        msaa_sample_count,
+    )
+    .dependencies(subpass_dependencies)
+    .build(&device)?;
 
+    let (swapchain_framebuffers, depth_buffer, color_buffer) =
+        create_swapchain_attachments(device, &swapchain, &render_pass)?;
     let image_to_frame_idx: Vec<Option<u32>> = (0..swapchain.num_images()).map(|_| None).collect();
-    let depth_buffer = depth_buffer::DepthBuffer::new(device, extent, msaa_sample_count)?;
-    let color_buffer = color_buffer::ColorBuffer::new(
-        device,
-        swapchain.info().format.into(),
-        extent,
-        msaa_sample_count,
-    )?;
-    let swapchain_framebuffers =
-        swapchain.create_framebuffers_for(&render_pass, &depth_buffer, &color_buffer)?;
 
     Ok(SwapchainAndCo {
-        swapchain,
-        depth_buffer,
-        color_buffer,
+        render_pass,
         swapchain_framebuffers,
+        _depth_buffer: depth_buffer,
+        _color_buffer: color_buffer,
+        swapchain,
         image_to_frame_idx,
-        render_pass,
     })
 }
 
+// The resolution-dependent parts of SwapchainAndCo, see Renderer::resize
+fn create_swapchain_attachments(
+    device: &device::Device,
+    swapchain: &swapchain::Swapchain,
+    render_pass: &render_pass::RenderPass,
+) -> Result<
+    (
+        Vec<framebuffer::Framebuffer>,
+        Option<depth_buffer::DepthBuffer>,
+        Option<color_buffer::ColorBuffer>,
+    ),
+    RenderError,
+> {
+    let extent = &swapchain.info().extent;
+    let msaa_sample_count = render_pass.msaa_sample_count();
+    // No explicit layout transition is needed for the depth buffer, the render pass transitions
+    // it from UNDEFINED to DEPTH_STENCIL_ATTACHMENT_OPTIMAL when it is loaded (cleared).
+    let depth_buffer = match render_pass.subpass_depth_format(0) {
+        Some(depth_format) => Some(depth_buffer::DepthBuffer::new(
+            device,
+            extent,
+            msaa_sample_count,
+            depth_format,
+        )?),
+        None => None,
+    };
+    // Only needed as the multisampled target that is resolved into the swapchain image
+    let color_buffer = if msaa_sample_count != vk::SampleCountFlags::TYPE_1 {
+        Some(color_buffer::ColorBuffer::new(
+            device,
+            swapchain.info().format.into(),
+            extent,
+            msaa_sample_count,
+        )?)
+    } else {
+        None
+    };
+    let swapchain_framebuffers = swapchain.create_framebuffers_for(
+        render_pass,
+        depth_buffer.as_ref(),
+        color_buffer.as_ref(),
+    )?;
+
+    Ok((swapchain_framebuffers, depth_buffer, color_buffer))
+}
+
 impl Renderer {
     pub fn new<W>(window: &W) -> Result<Self, RenderError>
+    where
+        W: raw_window_handle::HasRawWindowHandle + window::Window,
+    {
+        Self::builder(window).build()
+    }
+
+    pub fn builder<W>(window: &W) -> RendererBuilder<'_, W>
+    where
+        W: raw_window_handle::HasRawWindowHandle + window::Window,
+    {
+        RendererBuilder::new(window)
+    }
+
+    pub fn with_config<W>(window: &W, config: RendererConfig) -> Result<Self, RenderError>
     where
         W: raw_window_handle::HasRawWindowHandle + window::Window,
     {
         let extensions = window.required_instance_extensions();
 
-        let instance = instance::Instance::new(&extensions)?;
-        let _debug_utils = util::vk_debug::DebugUtils::new(&instance)?;
+        let instance = instance::Instance::new(&extensions, config.validation, &config.instance)?;
+        let debug_utils = if instance.validation_enabled() {
+            Some(util::vk_debug::DebugUtils::new(
+                &instance,
+                config.validation_errors,
+            )?)
+        } else {
+            None
+        };
         let surface = surface::Surface::new(&instance, window)?;
-        let device = device::Device::new(&instance, &surface)?;
+        let device = device::Device::new(
+            &instance,
+            Some(&surface),
+            config.device_preference.as_ref(),
+            config.pipeline_cache_path.as_deref(),
+        )?;
 
         let extent = window.extents();
-        let SwapchainAndCo {
-            swapchain,
-            swapchain_framebuffers,
-            depth_buffer,
-            color_buffer,
-            image_to_frame_idx,
-            render_pass,
-        } = create_swapchain_and_co(&instance, &device, &surface, &extent, None)?;
+        let msaa_sample_count = match config.msaa {
+            Some(requested) => device.clamp_msaa_sample_count(requested),
+            None => device.max_usable_sample_count(),
+        };
+        let depth_format = main_depth_format(&device, config.depth, config.stencil)?;
+        let swapchain_and_co = create_swapchain_and_co(
+            &instance,
+            &device,
+            &surface,
+            &extent,
+            msaa_sample_count,
+            config.present_mode,
+            &config.surface_formats,
+            &config.subpass_dependencies,
+            depth_format,
+            None,
+        )?;
 
-        let frames = [None, None];
-        let frame_synchronization = [
-            FrameSynchronization::new(&device)?,
-            FrameSynchronization::new(&device)?,
-        ];
+        if config.frames_in_flight > swapchain_and_co.swapchain.num_images() {
+            return Err(RenderError::InvalidFramesInFlight(config.frames_in_flight));
+        }
+
+        Self::with_target(
+            instance,
+            debug_utils,
+            Some(surface),
+            device,
+            RenderTarget::Swapchain(swapchain_and_co),
+            msaa_sample_count,
+            depth_format,
+            config,
+        )
+    }
+
+    /// Create a renderer without a window, that renders into a single offscreen image of `extent`
+    /// with format `HEADLESS_FORMAT`, e.g. for tests on machines without a display. Use
+    /// `request_readback` and `read_framebuffer` to get the result of a frame.
+    ///
+    /// `next_frame` and `submit` work as with a window, but nothing is presented and each frame
+    /// waits for the earlier ones as they share the image. The present mode, surface formats and
+    /// subpass dependencies of `config` are not used. Unlike with a window, there is no msaa
+    /// unless `config.msaa` is set.
+    pub fn new_headless(
+        extent: util::Extent2D,
+        config: RendererConfig,
+    ) -> Result<Self, RenderError> {
+        let instance =
+            instance::Instance::new(&Vec::<String>::new(), config.validation, &config.instance)?;
+        let debug_utils = if instance.validation_enabled() {
+            Some(util::vk_debug::DebugUtils::new(
+                &instance,
+                config.validation_errors,
+            )?)
+        } else {
+            None
+        };
+        let device = device::Device::new(
+            &instance,
+            None,
+            config.device_preference.as_ref(),
+            config.pipeline_cache_path.as_deref(),
+        )?;
+        let msaa_sample_count = config
+            .msaa
+            .map(|requested| device.clamp_msaa_sample_count(requested))
+            .unwrap_or(vk::SampleCountFlags::TYPE_1);
+        let depth_format = main_depth_format(&device, config.depth, config.stencil)?;
+        let target = offscreen::OffscreenTarget::with_depth_format(
+            &device,
+            extent,
+            HEADLESS_FORMAT,
+            depth_format,
+            msaa_sample_count,
+        )?;
+
+        Self::with_target(
+            instance,
+            debug_utils,
+            None,
+            device,
+            RenderTarget::Headless(target),
+            msaa_sample_count,
+            depth_format,
+            config,
+        )
+    }
+
+    fn with_target(
+        instance: instance::Instance,
+        debug_utils: Option<util::vk_debug::DebugUtils>,
+        surface: Option<surface::Surface>,
+        device: device::Device,
+        target: RenderTarget,
+        msaa_sample_count: vk::SampleCountFlags,
+        depth_format: Option<vk::Format>,
+        config: RendererConfig,
+    ) -> Result<Self, RenderError> {
+        let RendererConfig {
+            present_mode,
+            surface_formats,
+            frames_in_flight,
+            msaa: _,
+            validation: _,
+            validation_errors: _,
+            pipeline_cache_path: _,
+            shader_dir,
+            subpass_dependencies,
+            depth: _,
+            stencil: _,
+            device_preference: _,
+            instance: _,
+        } = config;
+
+        if frames_in_flight == 0 {
+            return Err(RenderError::InvalidFramesInFlight(frames_in_flight));
+        }
+
+        let frames = (0..frames_in_flight).map(|_| None).collect();
+        let frame_synchronization = (0..frames_in_flight)
+            .map(|_| FrameSynchronization::new(&device))
+            .collect::<Result<Vec<_>, sync::SyncError>>()?;
 
         let util_command_pool = command::CommandPool::util(&device)?;
-        let descriptor_sets = descriptor::DescriptorSets::new(&device)?;
+        let transfer_command_pool = command::CommandPool::transfer(&device)?;
+        let gfx_command_pools = (0..frames_in_flight)
+            .map(|_| command::CommandPool::graphics(&device).map(Rc::new))
+            .collect::<Result<Vec<_>, command::CommandError>>()?;
+        let descriptor_sets = descriptor::DescriptorSets::new(&device, frames_in_flight)?;
+        let timestamp_queries =
+            query::TimestampQueryPool::new(&device, 2 * frames_in_flight as u32)?;
 
         Ok(Self {
             instance,
-            surface,
             device,
-            swapchain,
-            image_to_frame_idx,
-            render_pass,
-            swapchain_framebuffers,
-            depth_buffer,
-            color_buffer,
+            windows: Windows::new(SwapchainContext::new(target, surface)),
+            render_scale: 1.0,
+            msaa_sample_count,
+            present_mode,
+            surface_formats,
+            subpass_dependencies,
+            depth_format,
+            clear_color: None,
+            frame_pacer: pacing::FramePacer::new(),
             frame_synchronization,
             frame_idx: 0,
             frames,
-            swapchain_image_idx: 0,
-            _debug_utils,
-            graphics_pipelines: Default::default(),
+            submitted_frames: 0,
+            submit_scratch: Default::default(),
+            frames_in_flight,
+            timestamp_queries,
+            last_frame_gpu_time_ms: None,
+            debug_utils,
+            graphics_pipelines: pipeline::GraphicsPipelines::new(shader_dir)?,
             vertex_buffers: Default::default(),
             index_buffers: Default::default(),
-            uniform_buffers: Default::default(),
+            meshes: Default::default(),
+            storage_buffers: Default::default(),
+            uniform_buffers: uniform::UniformBuffers::new(frames_in_flight),
             textures: Default::default(),
+            deletion_queue: resource::DeletionQueue::new(),
             descriptor_sets,
             util_command_pool,
+            transfer_command_pool,
+            staging_pool: mem::StagingPool::new(),
+            gfx_command_pools,
         })
     }
 
+    /// Returns `RenderError::NeedsResize` if the swapchain is out of date. If it is only
+    /// suboptimal, the frame can still be rendered and `submit` returns the error after presenting
+    /// it.
     pub fn next_frame(&mut self) -> Result<Frame, RenderError> {
+        self.next_frame_for(WindowId::MAIN)
+    }
+
+    /// As `next_frame`, but for rendering into `window`. The frames in flight are shared by all
+    /// windows, so a frame has to be submitted before the next one (for any window) is started.
+    /// Only frames of the main window are paced, see `set_target_frame_time`.
+    pub fn next_frame_for(&mut self, window: WindowId) -> Result<Frame, RenderError> {
+        self.check_validation_errors();
+        if self.windows.get(window)?.minimized {
+            return Err(RenderError::Minimized);
+        }
+
+        if window == WindowId::MAIN {
+            self.frame_pacer.begin_frame();
+        }
+
         let frame_sync = &self.frame_synchronization[self.frame_idx as usize];
         frame_sync.in_flight.blocking_wait()?;
 
-        self.swapchain_image_idx = self
-            .swapchain
-            .acquire_next_image(Some(&frame_sync.image_available))?;
+        let ctx = self.windows.get_mut(window)?;
+        let swapchain_image_idx = match &ctx.target {
+            RenderTarget::Swapchain(sc) => {
+                let (image_idx, status) = sc
+                    .swapchain
+                    .acquire_next_image(Some(&frame_sync.image_available))?;
+                // The image is acquired either way, so the resize has to wait until it has been
+                // presented in submit
+                ctx.acquired_sub_optimal = status == swapchain::SwapchainStatus::SubOptimal;
+
+                // This means that we received an image that might be in the process of rendering
+                if let Some(frame_idx) = sc.image_to_frame_idx[image_idx as usize] {
+                    self.frame_synchronization[frame_idx as usize]
+                        .in_flight
+                        .blocking_wait()?;
+                }
+                image_idx
+            }
+            RenderTarget::Headless(_) => {
+                #[cfg(test)]
+                if ctx.injected_resize == Some(InjectedResize::Acquire) {
+                    ctx.injected_resize = None;
+                    return Err(RenderError::NeedsResize(ResizeReason::OutOfDate));
+                }
+                // All frames render into the same image
+                for sync in self.frame_synchronization.iter() {
+                    sync.in_flight.blocking_wait()?;
+                }
+                0
+            }
+        };
+
+        // The fence has signaled, so the timestamps of the last submission in this slot are written
+        if let (Some(pool), Some(_)) = (
+            self.timestamp_queries.as_ref(),
+            self.frames[self.frame_idx as usize].as_ref(),
+        ) {
+            let start_query = 2 * self.frame_idx;
+            if let Some(ms) = pool.elapsed_ms(start_query, start_query + 1)? {
+                self.last_frame_gpu_time_ms = Some(ms);
+            }
+        }
 
-        // This means that we received an image that might be in the process of rendering
-        if let Some(frame_idx) = self.image_to_frame_idx[self.swapchain_image_idx as usize] {
-            self.frame_synchronization[frame_idx as usize]
-                .in_flight
-                .blocking_wait()?;
+        if let Some(frame) = self.frames[self.frame_idx as usize].as_ref() {
+            self.deletion_queue.frame_completed(frame.number);
         }
 
         // This will drop the frame that resided here previously
         let _ = std::mem::replace(&mut self.frames[self.frame_idx as usize], None);
 
-        let gfx_command_pool = command::CommandPool::graphics(&self.device)?;
+        // The in-flight fence has been waited on, so none of the command buffers are pending
+        let gfx_command_pool = Rc::clone(&self.gfx_command_pools[self.frame_idx as usize]);
+        gfx_command_pool.reset()?;
 
-        self.image_to_frame_idx[self.swapchain_image_idx as usize] = Some(self.frame_idx);
+        if let RenderTarget::Swapchain(sc) = &mut self.windows.get_mut(window)?.target {
+            sc.image_to_frame_idx[swapchain_image_idx as usize] = Some(self.frame_idx);
+        }
 
         Ok(Frame {
+            number: self.submitted_frames,
             frame_idx: self.frame_idx,
-            swapchain_image_idx: self.swapchain_image_idx,
+            window,
+            swapchain_image_idx,
             recorded_command_buffers: Vec::new(),
             gfx_command_pool,
+            read_back: false,
         })
     }
 
     pub fn submit(&mut self, frame: Frame) -> Result<(), RenderError> {
-        assert_eq!(frame.frame_idx, self.frame_idx, "Mismatching frame indexes");
+        self.check_current_frame(&frame)?;
+        self.check_validation_errors();
+        let (window, image_idx) = (frame.window, frame.swapchain_image_idx);
 
         // Make sure that this is captured before any early returns. If this function returns
         // without having extended the lifetime of frame, it might be dropped while it's command
         // buffers are still in use.
         self.frames[self.frame_idx as usize] = Some(frame);
-        let frame = self.frames[self.frame_idx as usize].as_ref().unwrap();
+        let frame = self.frames[self.frame_idx as usize].as_mut().unwrap();
+        let ctx = self.windows.get_mut(window)?;
+
+        if let Some(scaled_target) = ctx.scaled_target.as_ref() {
+            let (dst, dst_final_layout) = ctx.target_image(image_idx);
+            let blit = scaled_target
+                .blit_to(
+                    frame.new_command_buffer()?,
+                    dst,
+                    ctx.target.extent(),
+                    dst_final_layout,
+                )
+                .end()?;
+            frame.add_command_buffer(blit)?;
+        }
 
-        let frame_sync = &self.frame_synchronization[self.frame_idx as usize];
-        let vk_wait_sems = [*frame_sync.image_available.vk_semaphore()];
-        let wait_dst_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let vk_sig_sems = [*frame_sync.render_done.vk_semaphore()];
+        // After everything else that writes the image, in the same submission so that it is
+        // copied before it is presented
+        if frame.read_back {
+            let (vk_image, layout) = ctx.target_image(image_idx);
+            let format = ctx.target.format();
+            let extent = ctx.target.extent();
+            let size = (extent.width * extent.height * 4) as usize;
+            let buffer = mem::DeviceBuffer::readback_empty(&self.device, size)
+                .map_err(RenderError::Readback)?;
+            let host_read = mem::queue_ownership_barrier(
+                buffer.vk_buffer(),
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::HOST_READ,
+            );
+            let cmd_buf = mem::transition_image_layout(
+                frame.new_command_buffer()?,
+                vk_image,
+                1,
+                format,
+                layout,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+            .copy_image_to_buffer(vk_image, buffer.vk_buffer(), &extent);
+            let cmd_buf = mem::transition_image_layout(
+                cmd_buf,
+                vk_image,
+                1,
+                format,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                layout,
+            )
+            .buffer_barrier(
+                &host_read,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+            )
+            .end()?;
+            frame.add_command_buffer(cmd_buf)?;
+
+            let replaced = ctx.readback.replace(Readback {
+                buffer,
+                extent,
+                format,
+            });
+            // Might still be written by an earlier frame
+            if let Some(replaced) = replaced {
+                self.deletion_queue
+                    .push(replaced.buffer, self.submitted_frames);
+            }
+        }
 
-        let info = vk::SubmitInfo::builder()
-            .wait_semaphores(&vk_wait_sems)
-            .wait_dst_stage_mask(&wait_dst_mask)
-            .signal_semaphores(&vk_sig_sems)
-            .command_buffers(&frame.recorded_command_buffers);
+        if let Some(pool) = self.timestamp_queries.as_ref() {
+            let start_query = 2 * frame.frame_idx;
+            let start = frame
+                .new_command_buffer()?
+                .reset_query_pool(pool, start_query, 2)
+                .write_timestamp(pool, vk::PipelineStageFlags::TOP_OF_PIPE, start_query)
+                .end()?;
+            let end = frame
+                .new_command_buffer()?
+                .write_timestamp(
+                    pool,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    start_query + 1,
+                )
+                .end()?;
+            frame.recorded_command_buffers.insert(0, start);
+            frame.add_command_buffer(end)?;
+        }
 
+        // With a render scale, the image is first written by the blit
+        let wait_stage = if ctx.scaled_target.is_some() {
+            vk::PipelineStageFlags::TRANSFER
+        } else {
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        };
+        let frame_sync = &self.frame_synchronization[self.frame_idx as usize];
+        let wait = [(&frame_sync.image_available, wait_stage)];
+        let signal = [&frame_sync.render_done];
+        // Without a swapchain, there is no image to wait for and nothing to present
+        let n_semaphores = match ctx.target {
+            RenderTarget::Swapchain(_) => 1,
+            RenderTarget::Headless(_) => 0,
+        };
         let gfx_queue = self.device.graphics_queue();
         frame_sync.in_flight.reset()?;
 
-        gfx_queue.submit(&info, &frame_sync.in_flight)?;
+        gfx_queue.submit_batch_with(
+            &mut self.submit_scratch,
+            frame.recorded_command_buffers.iter(),
+            &wait[..n_semaphores],
+            &signal[..n_semaphores],
+            Some(&frame_sync.in_flight),
+        )?;
+        self.submitted_frames += 1;
+
+        let sc = match &ctx.target {
+            RenderTarget::Swapchain(sc) => sc,
+            RenderTarget::Headless(_) => {
+                // As for a swapchain, the frame index is not advanced
+                #[cfg(test)]
+                if ctx.injected_resize == Some(InjectedResize::Present) {
+                    ctx.injected_resize = None;
+                    return Err(RenderError::NeedsResize(ResizeReason::SubOptimal));
+                }
+                self.frame_idx = (self.frame_idx + 1) % self.frames_in_flight as u32;
+                return Ok(());
+            }
+        };
 
-        let swapchains = [*self.swapchain.vk_swapchain()];
-        let indices = [self.swapchain_image_idx];
+        let frame_sync = &self.frame_synchronization[self.frame_idx as usize];
+        let vk_sig_sems = [*frame_sync.render_done.vk_semaphore()];
+        let swapchains = [*sc.swapchain.vk_swapchain()];
+        let indices = [image_idx];
         let present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(&vk_sig_sems)
             .swapchains(&swapchains)
             .image_indices(&indices);
 
-        let status = self
+        let status = sc
             .swapchain
             .enqueue_present(self.device.present_queue(), present_info.build())?;
 
-        if let swapchain::SwapchainStatus::SubOptimal = status {
+        if status == swapchain::SwapchainStatus::SubOptimal || ctx.acquired_sub_optimal {
             return Err(RenderError::NeedsResize(ResizeReason::SubOptimal));
         }
 
-        self.frame_idx = (self.frame_idx + 1) % MAX_FRAMES_IN_FLIGHT as u32;
+        self.frame_idx = (self.frame_idx + 1) % self.frames_in_flight as u32;
 
         Ok(())
     }
 
-    pub fn render_pass(&self) -> &render_pass::RenderPass {
-        &self.render_pass
+    /// Acquire a frame, let `record` add its command buffers to it and submit it. The swapchain is
+    /// resized to the extent of `window` when that changes, or when acquiring or presenting
+    /// returns `RenderError::NeedsResize`, after which acquiring is retried once. Returns
+    /// `RenderError::Minimized` without calling `record` while the window is minimized. If
+    /// `record` returns an error, the acquired image is still submitted and presented, but only
+    /// cleared, and the error is returned. Use `next_frame` and `submit` directly for more
+    /// control, e.g. over other windows.
+    pub fn render_frame<W, F>(&mut self, window: &W, record: F) -> Result<(), RenderError>
+    where
+        W: window::Window,
+        F: FnOnce(&mut Frame, &mut Renderer) -> Result<(), RenderError>,
+    {
+        let extent = window.extents();
+        if extent != self.windows.main.requested_extent {
+            self.resize(extent)?;
+        }
+
+        let mut frame = match self.next_frame() {
+            Err(RenderError::NeedsResize(reason)) => {
+                log::debug!("Resizing before rendering the frame, {}", reason);
+                self.resize(window.extents())?;
+                self.next_frame()
+            }
+            x => x,
+        }?;
+
+        if let Err(e) = record(&mut frame, self) {
+            log::debug!(
+                "Failed to record the frame, presenting a cleared image: {}",
+                e
+            );
+            self.submit_cleared(window, frame)?;
+            return Err(e);
+        }
+
+        self.submit_or_resize(window, frame)
     }
 
-    pub fn swapchain_extent(&self) -> util::Extent2D {
-        self.swapchain.info().extent
+    // The image of `frame` has been acquired and image_available will be signaled, so the frame
+    // has to be submitted and presented even if it couldn't be recorded. What was recorded is
+    // discarded and the target is only cleared, which also moves it to the layout for presenting.
+    fn submit_cleared<W: window::Window>(
+        &mut self,
+        window: &W,
+        mut frame: Frame,
+    ) -> Result<(), RenderError> {
+        frame.recorded_command_buffers.clear();
+        frame.read_back = false;
+        let cmd_buf = frame
+            .new_command_buffer()?
+            .begin_render_pass(
+                self.render_pass(),
+                self.framebuffer(&frame),
+                self.render_extent(),
+            )
+            .end_render_pass()
+            .end()?;
+        frame.add_command_buffer(cmd_buf)?;
+        self.submit_or_resize(window, frame)
     }
 
-    pub fn framebuffer(&self, frame: &Frame) -> &framebuffer::Framebuffer {
-        &self.swapchain_framebuffers[frame.swapchain_image_idx as usize]
+    fn submit_or_resize<W: window::Window>(
+        &mut self,
+        window: &W,
+        frame: Frame,
+    ) -> Result<(), RenderError> {
+        match self.submit(frame) {
+            Err(RenderError::NeedsResize(reason)) => {
+                log::debug!("Resizing after presenting the frame, {}", reason);
+                self.resize(window.extents())
+            }
+            x => x,
+        }
     }
 
-    fn recreate_pipelines(&mut self) -> Result<(), RenderError> {
-        log::trace!("Recreating pipelines with {}", self.swapchain_extent());
-        self.graphics_pipelines.recreate_all(
-            &self.device,
-            self.swapchain_extent(),
-            &self.render_pass,
-        )?;
-        Ok(())
+    /// Limit the frame rate by having `next_frame` wait until `target` has passed since the
+    /// previous call started. This is done on the CPU and works with any present mode. `None`
+    /// disables the limit.
+    pub fn set_target_frame_time(&mut self, target: Option<std::time::Duration>) {
+        self.frame_pacer.set_target_frame_time(target);
     }
 
-    pub fn resize(&mut self, new_extent: util::Extent2D) -> Result<(), RenderError> {
-        log::trace!(
-            "Resizing from {} to {}",
-            self.swapchain_extent(),
-            new_extent
-        );
-        self.device.wait_idle()?;
-
-        let SwapchainAndCo {
-            swapchain,
-            swapchain_framebuffers,
-            depth_buffer,
-            color_buffer,
-            image_to_frame_idx,
-            render_pass,
-        } = create_swapchain_and_co(
-            &self.instance,
-            &self.device,
-            &self.surface,
-            &new_extent,
-            Some(&self.swapchain),
-        )?;
+    pub fn target_frame_time(&self) -> Option<std::time::Duration> {
+        self.frame_pacer.target_frame_time()
+    }
 
-        self.swapchain = swapchain;
-        self.swapchain_framebuffers = swapchain_framebuffers;
-        self.depth_buffer = depth_buffer;
-        self.color_buffer = color_buffer;
-        self.image_to_frame_idx = image_to_frame_idx;
-        self.render_pass = render_pass;
+    /// The CPU time between the starts of the two most recent frames, including the time spent
+    /// waiting for the target frame time
+    pub fn frame_time(&self) -> Option<std::time::Duration> {
+        self.frame_pacer.last_frame_time()
+    }
 
-        self.recreate_pipelines()?;
+    pub fn memory_stats(&self) -> Result<device::MemoryStats, RenderError> {
+        Ok(self.device.memory_stats()?)
+    }
 
-        Ok(())
+    /// A summary of the GPU memory allocated by the renderer, per memory heap
+    pub fn memory_report(&self) -> Result<String, RenderError> {
+        Ok(self.memory_stats()?.to_string())
     }
 
-    pub fn update_uniform<T>(
-        &mut self,
-        h: &Handle<uniform::UniformBuffer>,
-        data: &T,
-    ) -> Result<(), RenderError> {
-        let ubuf = self
-            .uniform_buffers
-            .get_mut(h, self.frame_idx as usize)
-            .ok_or_else(|| RenderError::InvalidHandle(h.id()))?;
+    /// GPU time of the most recent frame that is known to have finished, measured from the start
+    /// of its first command buffer to the end of its last one. `None` until such a frame exists,
+    /// or if the graphics queue doesn't support timestamps (`timestampValidBits` is zero).
+    pub fn last_frame_gpu_time_ms(&self) -> Option<f32> {
+        self.last_frame_gpu_time_ms
+    }
 
-        ubuf.update_with(data).map_err(RenderError::UniformBuffer)
+    /// Validation messages at or above the min severity of `RendererConfig::validation_errors`
+    /// that were reported since the last call. Empty if validation is disabled or if the action
+    /// is `Panic`.
+    pub fn take_validation_errors(&self) -> Vec<ValidationMessage> {
+        self.debug_utils
+            .as_ref()
+            .map(|d| d.take_validation_errors())
+            .unwrap_or_default()
     }
 
-    pub fn create_descriptor_set(
-        &mut self,
-        gfx_pipeline_handle: &Handle<pipeline::GraphicsPipeline>,
-        uniform_buffer_handle: &Handle<uniform::UniformBuffer>,
-        texture_handle: &Handle<texture::Texture>,
-    ) -> Result<Handle<descriptor::DescriptorSet>, RenderError> {
-        let gfx_pipeline = self
-            .get_resource(gfx_pipeline_handle)
-            .ok_or_else(|| RenderError::InvalidHandle(gfx_pipeline_handle.id()))?;
-        assert_eq!(gfx_pipeline.vk_descriptor_set_layouts().len(), 1);
+    fn check_validation_errors(&self) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            debug_utils.check_validation_errors();
+        }
+    }
 
-        let uniform_buffers = self
-            .uniform_buffers
-            .get_all(uniform_buffer_handle)
-            .ok_or_else(|| RenderError::InvalidHandle(uniform_buffer_handle.id()))?;
+    pub fn render_pass(&self) -> &render_pass::RenderPass {
+        self.windows.main.render_pass()
+    }
 
-        let texture = self
-            .textures
-            .get(texture_handle)
-            .ok_or_else(|| RenderError::InvalidHandle(texture_handle.id()))?;
-        let descriptor = descriptor::DescriptorSetDescriptor {
-            layout: gfx_pipeline.vk_descriptor_set_layouts()[0],
-            uniform_buffers,
-            texture,
-        };
+    /// The render pass that frames for `window` are rendered with
+    pub fn render_pass_for(
+        &self,
+        window: WindowId,
+    ) -> Result<&render_pass::RenderPass, RenderError> {
+        Ok(self.windows.get(window)?.render_pass())
+    }
 
-        self.descriptor_sets
-            .create(descriptor)
-            .map_err(RenderError::Descriptor)
+    /// The extent of the offscreen image when headless
+    pub fn swapchain_extent(&self) -> util::Extent2D {
+        self.windows.main.target.extent()
     }
 
-    pub fn get_descriptor_set(
+    pub fn swapchain_extent_for(&self, window: WindowId) -> Result<util::Extent2D, RenderError> {
+        Ok(self.windows.get(window)?.target.extent())
+    }
+
+    /// The extent of the framebuffers that frames are rendered into. This is the swapchain extent
+    /// scaled by the render scale, see `set_render_scale`.
+    pub fn render_extent(&self) -> util::Extent2D {
+        self.windows.main.render_extent()
+    }
+
+    /// The format that was chosen for the swapchain images (and thus the render pass color
+    /// attachment) from the preferred surface formats. `HEADLESS_FORMAT` when headless.
+    pub fn swapchain_format(&self) -> vk::Format {
+        self.windows.main.target.format()
+    }
+
+    /// The framebuffer to render `frame` into, for the window it was started for. Panics if the
+    /// window has been removed since.
+    pub fn framebuffer(&self, frame: &Frame) -> &framebuffer::Framebuffer {
+        self.windows
+            .get(frame.window)
+            .expect("The window of the frame has been removed")
+            .framebuffer(frame.swapchain_image_idx)
+    }
+
+    /// The number of swapchain images, 1 when headless. This can change when the swapchain is
+    /// recreated, e.g. by `resize`, so resources that are sized by it have to be recreated then.
+    pub fn swapchain_image_count(&self) -> usize {
+        self.windows.main.target.image_count()
+    }
+
+    /// The index of the swapchain image that `frame` renders into, in
+    /// `0..swapchain_image_count()`
+    pub fn current_image_index(&self, frame: &Frame) -> u32 {
+        frame.swapchain_image_idx
+    }
+
+    /// E.g. to warn about running on an integrated GPU
+    pub fn device_type(&self) -> vk::PhysicalDeviceType {
+        self.device.device_type()
+    }
+
+    pub fn device_name(&self) -> String {
+        self.device.name()
+    }
+
+    /// The Vulkan version of the instance, see `InstanceConfig::api_version`
+    pub fn api_version(&self) -> (u32, u32, u32) {
+        let version = self.instance.api_version();
+        (
+            vk::version_major(version),
+            vk::version_minor(version),
+            vk::version_patch(version),
+        )
+    }
+
+    pub fn is_headless(&self) -> bool {
+        matches!(self.windows.main.target, RenderTarget::Headless(_))
+    }
+
+    /// Copy the image of `frame` (the swapchain image, or the offscreen image when headless) to
+    /// host memory when the frame is submitted, for `read_framebuffer` to return. The copy is
+    /// recorded after everything else in the frame's submission, so the image is read before it
+    /// is presented. Fails if the image can't be read back.
+    pub fn request_readback(&self, frame: &mut Frame) -> Result<(), RenderError> {
+        let ctx = self.windows.get(frame.window)?;
+        if let RenderTarget::Swapchain(sc) = &ctx.target {
+            let usage = sc.swapchain.info().usage;
+            if !usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+                return Err(RenderError::ReadbackUnsupported);
+            }
+        }
+        readback_needs_swizzle(ctx.target.format())?;
+
+        frame.read_back = true;
+        Ok(())
+    }
+
+    /// The image of the last submitted frame of the main window that requested a readback, see
+    /// `request_readback`, as RGBA8. Waits for the device to be idle, so this is meant for
+    /// screenshots and tests, not for use every frame.
+    pub fn read_framebuffer(&self) -> Result<ImageData, RenderError> {
+        self.read_framebuffer_for(WindowId::MAIN)
+    }
+
+    /// As `read_framebuffer`, for a frame of `window`
+    pub fn read_framebuffer_for(&self, window: WindowId) -> Result<ImageData, RenderError> {
+        let readback = self
+            .windows
+            .get(window)?
+            .readback
+            .as_ref()
+            .ok_or(RenderError::NoReadback)?;
+
+        self.wait_idle()?;
+
+        let mut data = readback.buffer.read_data().map_err(RenderError::Readback)?;
+        if readback_needs_swizzle(readback.format)? {
+            util::swizzle_bgra_to_rgba(&mut data);
+        }
+
+        Ok(ImageData {
+            width: readback.extent.width,
+            height: readback.extent.height,
+            data,
+        })
+    }
+
+    fn recreate_pipelines(&mut self) -> Result<(), RenderError> {
+        log::trace!("Recreating pipelines with {}", self.render_extent());
+        let extent = self.render_extent();
+        self.graphics_pipelines.recreate_all(
+            &self.device,
+            extent,
+            self.windows.main.render_pass(),
+        )?;
+        Ok(())
+    }
+
+    /// Rebuild the pipelines whose shader files have been modified on disk since they were
+    /// built, returns the number of rebuilt pipelines. Waits for the GPU to be idle if there is
+    /// anything to rebuild. On error, the pipelines that failed keep their old version.
+    pub fn reload_changed_shaders(&mut self) -> Result<usize, RenderError> {
+        if !self.graphics_pipelines.shaders_changed() {
+            return Ok(0);
+        }
+
+        self.wait_idle()?;
+        let extent = self.render_extent();
+        Ok(self.graphics_pipelines.reload_changed(
+            &self.device,
+            extent,
+            self.windows.main.render_pass(),
+        )?)
+    }
+
+    fn recreate_swapchain_and_co(
+        &mut self,
+        window: WindowId,
+        extent: util::Extent2D,
+    ) -> Result<(), RenderError> {
+        self.wait_idle()?;
+
+        // The old swapchain is passed as oldSwapchain and is only dropped (with its framebuffers)
+        // when it is replaced, after the new one has been created.
+        let ctx = self.windows.get_mut(window)?;
+        ctx.target = match &ctx.target {
+            RenderTarget::Swapchain(sc) => RenderTarget::Swapchain(create_swapchain_and_co(
+                &self.instance,
+                &self.device,
+                ctx.surface.as_ref().expect("Swapchain without surface"),
+                &extent,
+                self.msaa_sample_count,
+                self.present_mode,
+                &self.surface_formats,
+                &self.subpass_dependencies,
+                self.depth_format,
+                Some(&sc.swapchain),
+            )?),
+            RenderTarget::Headless(target) => {
+                RenderTarget::Headless(offscreen::OffscreenTarget::with_depth_format(
+                    &self.device,
+                    extent,
+                    target.format(),
+                    self.depth_format,
+                    self.msaa_sample_count,
+                )?)
+            }
+        };
+        if window == WindowId::MAIN {
+            self.windows.main.scaled_target = self.create_scaled_target()?;
+        }
+        let ctx = self.windows.get_mut(window)?;
+        if let Some([r, g, b, a]) = self.clear_color {
+            ctx.render_pass_mut().set_clear_color(r, g, b, a);
+        }
+
+        // The pipelines are created for the main render pass, the others are compatible with it
+        if window == WindowId::MAIN {
+            self.recreate_pipelines()?;
+        }
+
+        Ok(())
+    }
+
+    // Like recreate_swapchain_and_co, but keeps the render passes (and thus the pipelines) unless
+    // they no longer match, e.g. if the surface format or the msaa sample count changed.
+    fn resize_swapchain_and_co(
+        &mut self,
+        window: WindowId,
+        extent: util::Extent2D,
+    ) -> Result<(), RenderError> {
+        self.wait_idle()?;
+
+        let mut render_pass_kept = true;
+        let ctx = self.windows.get_mut(window)?;
+        match &mut ctx.target {
+            RenderTarget::Swapchain(sc) => {
+                let swapchain = swapchain::Swapchain::new(
+                    &self.instance,
+                    &self.device,
+                    ctx.surface.as_ref().expect("Swapchain without surface"),
+                    &extent,
+                    self.present_mode,
+                    &self.surface_formats,
+                    Some(&sc.swapchain),
+                )?;
+                if swapchain.info().format == sc.swapchain.info().format
+                    && sc.render_pass.msaa_sample_count() == self.msaa_sample_count
+                {
+                    let (framebuffers, depth_buffer, color_buffer) =
+                        create_swapchain_attachments(&self.device, &swapchain, &sc.render_pass)?;
+                    // The old framebuffers refer to the old swapchain images, so they go first
+                    sc.swapchain_framebuffers = framebuffers;
+                    sc._depth_buffer = depth_buffer;
+                    sc._color_buffer = color_buffer;
+                    sc.image_to_frame_idx = (0..swapchain.num_images()).map(|_| None).collect();
+                    sc.swapchain = swapchain;
+                } else {
+                    log::trace!("Surface format or msaa changed, recreating the render pass");
+                    render_pass_kept = false;
+                    let render_pass = render_pass::RenderPassBuilder::forward(
+                        swapchain.info().format,
+                        self.depth_format,
+                        self.msaa_sample_count,
+                    )
+                    .dependencies(&self.subpass_dependencies)
+                    .build(&self.device)?;
+                    let (swapchain_framebuffers, depth_buffer, color_buffer) =
+                        create_swapchain_attachments(&self.device, &swapchain, &render_pass)?;
+                    *sc = SwapchainAndCo {
+                        render_pass,
+                        swapchain_framebuffers,
+                        _depth_buffer: depth_buffer,
+                        _color_buffer: color_buffer,
+                        image_to_frame_idx: (0..swapchain.num_images()).map(|_| None).collect(),
+                        swapchain,
+                    };
+                }
+            }
+            RenderTarget::Headless(target) => {
+                if target.render_pass().msaa_sample_count() == self.msaa_sample_count {
+                    target.resize(&self.device, extent)?;
+                } else {
+                    render_pass_kept = false;
+                    *target = offscreen::OffscreenTarget::with_depth_format(
+                        &self.device,
+                        extent,
+                        target.format(),
+                        self.depth_format,
+                        self.msaa_sample_count,
+                    )?;
+                }
+            }
+        }
+
+        if window != WindowId::MAIN {
+            if !render_pass_kept {
+                if let Some([r, g, b, a]) = self.clear_color {
+                    ctx.render_pass_mut().set_clear_color(r, g, b, a);
+                }
+            }
+            return Ok(());
+        }
+
+        let format = self.windows.main.target.format();
+        let msaa_sample_count = self.msaa_sample_count;
+        let scaled_extent = extent.scaled(self.render_scale);
+        match &mut self.windows.main.scaled_target {
+            Some(scaled_target)
+                if self.render_scale != 1.0
+                    && scaled_target.format() == format
+                    && scaled_target.render_pass().msaa_sample_count() == msaa_sample_count =>
+            {
+                scaled_target.resize(&self.device, scaled_extent)?;
+            }
+            _ => {
+                render_pass_kept &=
+                    self.windows.main.scaled_target.is_none() && self.render_scale == 1.0;
+                self.windows.main.scaled_target = self.create_scaled_target()?;
+            }
+        }
+
+        let extent = self.render_extent();
+        if render_pass_kept {
+            log::trace!("Recreating pipelines with a static viewport for {}", extent);
+            self.graphics_pipelines.recreate_for_extent(
+                &self.device,
+                extent,
+                self.windows.main.render_pass(),
+            )?;
+        } else {
+            if let Some([r, g, b, a]) = self.clear_color {
+                self.windows
+                    .main
+                    .render_pass_mut()
+                    .set_clear_color(r, g, b, a);
+            }
+            self.recreate_pipelines()?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the clear color of the render passes of all windows. See
+    /// `RenderPass::set_clear_color` for how this differs from `set_clear_color_srgb`.
+    pub fn set_clear_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.clear_color = Some([r, g, b, a]);
+        for ctx in self.windows.iter_mut() {
+            ctx.render_pass_mut().set_clear_color(r, g, b, a);
+        }
+    }
+
+    /// Set the clear color of all windows, with sRGB-encoded `r`, `g` and `b`
+    pub fn set_clear_color_srgb(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        let [r, g, b] = util::srgb_to_linear([r, g, b]);
+        self.set_clear_color(r, g, b, a);
+    }
+
+    /// Recreate the swapchain and everything that depends on its extent for `new_extent`. The
+    /// render pass is kept, as are the pipelines that use a dynamic viewport, unless the surface
+    /// format changed. A zero-sized extent (e.g. a minimized window) pauses rendering,
+    /// `next_frame` returns `RenderError::Minimized` until this is called again with a non-zero
+    /// extent.
+    pub fn resize(&mut self, new_extent: util::Extent2D) -> Result<(), RenderError> {
+        self.resize_window(WindowId::MAIN, new_extent)
+    }
+
+    /// As `resize`, for `window`
+    pub fn resize_window(
+        &mut self,
+        window: WindowId,
+        new_extent: util::Extent2D,
+    ) -> Result<(), RenderError> {
+        let ctx = self.windows.get_mut(window)?;
+        ctx.requested_extent = new_extent;
+        if new_extent.is_empty() {
+            log::trace!("Resizing {:?} to {}, pausing rendering", window, new_extent);
+            ctx.minimized = true;
+            return Ok(());
+        }
+
+        log::trace!(
+            "Resizing {:?} from {} to {}",
+            window,
+            ctx.target.extent(),
+            new_extent
+        );
+        ctx.minimized = false;
+        self.resize_swapchain_and_co(window, new_extent)
+    }
+
+    /// Add a window with its own surface and swapchain, which shares the device, resources and
+    /// pipelines with the main window. Its render pass has the same depth buffer and msaa sample
+    /// count as the main one, so pipelines can be used for both as long as the surface formats
+    /// match. Pipelines that are used for windows of different sizes need a dynamic viewport. The
+    /// render scale only applies to the main window.
+    pub fn add_window<W>(&mut self, window: &W) -> Result<WindowId, RenderError>
+    where
+        W: raw_window_handle::HasRawWindowHandle + window::Window,
+    {
+        // Without a main surface, the device was created without the swapchain extension
+        if self.windows.main.surface.is_none() {
+            return Err(RenderError::SurfaceUnsupported);
+        }
+
+        let surface = surface::Surface::new(&self.instance, window)?;
+        let present_family = self.device.present_queue_family().index;
+        if !surface.is_supported_by(self.device.vk_phys_device(), present_family)? {
+            return Err(RenderError::SurfaceUnsupported);
+        }
+
+        let swapchain_and_co = create_swapchain_and_co(
+            &self.instance,
+            &self.device,
+            &surface,
+            &window.extents(),
+            self.msaa_sample_count,
+            self.present_mode,
+            &self.surface_formats,
+            &self.subpass_dependencies,
+            self.depth_format,
+            None,
+        )?;
+        if self.frames_in_flight > swapchain_and_co.swapchain.num_images() {
+            return Err(RenderError::InvalidFramesInFlight(self.frames_in_flight));
+        }
+        if swapchain_and_co.swapchain.info().format != self.swapchain_format() {
+            log::warn!(
+                "The new window uses {:?} instead of {:?}, the pipelines can't be used for it",
+                swapchain_and_co.swapchain.info().format,
+                self.swapchain_format()
+            );
+        }
+
+        Ok(self.add_context(SwapchainContext::new(
+            RenderTarget::Swapchain(swapchain_and_co),
+            Some(surface),
+        )))
+    }
+
+    /// Add an offscreen image of `extent` that frames can be rendered into like a window, e.g.
+    /// for previews. It has the format of the main window, see `add_window`.
+    pub fn add_headless_window(&mut self, extent: util::Extent2D) -> Result<WindowId, RenderError> {
+        let target = offscreen::OffscreenTarget::with_depth_format(
+            &self.device,
+            extent,
+            self.swapchain_format(),
+            self.depth_format,
+            self.msaa_sample_count,
+        )?;
+
+        Ok(self.add_context(SwapchainContext::new(RenderTarget::Headless(target), None)))
+    }
+
+    fn add_context(&mut self, mut ctx: SwapchainContext) -> WindowId {
+        if let Some([r, g, b, a]) = self.clear_color {
+            ctx.render_pass_mut().set_clear_color(r, g, b, a);
+        }
+        self.windows.add(ctx)
+    }
+
+    /// Waits for the device to be idle before destroying the swapchain of `window`. The main
+    /// window can't be removed.
+    pub fn remove_window(&mut self, window: WindowId) -> Result<(), RenderError> {
+        if window == WindowId::MAIN {
+            return Err(RenderError::InvalidWindow(window));
+        }
+
+        self.windows.get(window)?;
+        self.wait_idle()?;
+        self.windows.remove(window);
+        Ok(())
+    }
+
+    /// Block until the GPU has finished all submitted work. Resources created through
+    /// `create_resource` may still be used by frames in flight, so this has to be called before
+    /// destroying them (e.g. when switching scenes).
+    pub fn wait_idle(&self) -> Result<(), RenderError> {
+        Ok(self.device.wait_idle()?)
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.windows.main.minimized
+    }
+
+    /// Set the msaa sample count, which has to be one of `supported_msaa_sample_counts`.
+    /// Recreates the render pass, framebuffers and pipelines.
+    pub fn set_msaa(&mut self, sample_count: vk::SampleCountFlags) -> Result<(), RenderError> {
+        if !self
+            .device
+            .supported_sample_counts()
+            .contains(&sample_count)
+        {
+            return Err(RenderError::UnsupportedSampleCount(sample_count));
+        }
+        log::trace!(
+            "Changing msaa sample count from {:?} to {:?}",
+            self.msaa_sample_count,
+            sample_count
+        );
+        if sample_count == self.msaa_sample_count {
+            return Ok(());
+        }
+
+        self.msaa_sample_count = sample_count;
+        for window in self.windows.ids() {
+            let ctx = self.windows.get(window)?;
+            // Minimized windows pick it up when resized to a non-zero extent
+            if !ctx.minimized {
+                let extent = ctx.target.extent();
+                self.recreate_swapchain_and_co(window, extent)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn msaa_sample_count(&self) -> vk::SampleCountFlags {
+        self.msaa_sample_count
+    }
+
+    /// The sample counts that can be passed to `set_msaa`, lowest first. TYPE_1 (no msaa) is
+    /// always supported.
+    pub fn supported_msaa_sample_counts(&self) -> Vec<vk::SampleCountFlags> {
+        self.device.supported_sample_counts()
+    }
+
+    /// Render at `scale` times the swapchain extent, e.g. 0.75 to trade quality for performance,
+    /// and scale the result to the swapchain image with a linear blit in `submit`. Recreates the
+    /// render pass, framebuffers and pipelines, so frames have to be rendered with the current
+    /// `render_pass()`, `framebuffer()` and `render_extent()`.
+    pub fn set_render_scale(&mut self, scale: f32) -> Result<(), RenderError> {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(RenderError::InvalidRenderScale(scale));
+        }
+
+        if let RenderTarget::Swapchain(sc) = &self.windows.main.target {
+            let usage = sc.swapchain.info().usage;
+            if scale != 1.0 && !usage.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+                return Err(RenderError::RenderScaleUnsupported);
+            }
+        }
+
+        log::trace!(
+            "Changing render scale from {} to {}",
+            self.render_scale,
+            scale
+        );
+        if scale == self.render_scale {
+            return Ok(());
+        }
+
+        self.render_scale = scale;
+        if self.windows.main.minimized {
+            // Will be picked up when resized to a non-zero extent
+            return Ok(());
+        }
+
+        self.recreate_swapchain_and_co(WindowId::MAIN, self.swapchain_extent())
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    fn create_scaled_target(&self) -> Result<Option<offscreen::OffscreenTarget>, RenderError> {
+        if self.render_scale == 1.0 {
+            return Ok(None);
+        }
+
+        Ok(Some(offscreen::OffscreenTarget::with_depth_format(
+            &self.device,
+            self.swapchain_extent().scaled(self.render_scale),
+            self.swapchain_format(),
+            self.depth_format,
+            self.msaa_sample_count,
+        )?))
+    }
+
+    /// The pipeline cache contents, to be passed to `RendererBuilder::pipeline_cache` in a later run
+    pub fn pipeline_cache_data(&self) -> Result<Vec<u8>, RenderError> {
+        Ok(self.device.pipeline_cache().data()?)
+    }
+
+    pub fn save_pipeline_cache(&self, path: &std::path::Path) -> Result<(), RenderError> {
+        Ok(self.device.pipeline_cache().save(path)?)
+    }
+
+    pub fn create_timeline_semaphore(
+        &self,
+        initial_value: u64,
+    ) -> Result<sync::TimelineSemaphore, RenderError> {
+        Ok(sync::TimelineSemaphore::new(&self.device, initial_value)?)
+    }
+
+    // The copy of the uniform buffer that is used by frame, which has to be the one that is
+    // being recorded. The copies of the other frames in flight may still be read by the GPU.
+    fn check_current_frame(&self, frame: &Frame) -> Result<(), RenderError> {
+        if frame.frame_idx != self.frame_idx {
+            return Err(RenderError::FrameOutOfOrder {
+                expected: self.frame_idx,
+                got: frame.frame_idx,
+            });
+        }
+
+        // With as many frames submitted as there are frames in flight, the index wraps around
+        if frame.number != self.submitted_frames {
+            return Err(RenderError::StaleFrame {
+                expected: self.submitted_frames,
+                got: frame.number,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn frame_uniform_buffer(
+        &mut self,
+        frame: &Frame,
+        h: &Handle<uniform::UniformBuffer>,
+    ) -> Result<&mut uniform::UniformBuffer, RenderError> {
+        self.check_current_frame(frame)?;
+
+        self.uniform_buffers
+            .get_mut(h, frame.frame_idx as usize)
+            .ok_or_else(|| RenderError::InvalidHandle(h.id()))
+    }
+
+    /// Write `data` to the copy of the uniform buffer that belongs to `frame`. Each frame in
+    /// flight has its own copy, so the update is only seen by `frame`. The other copies keep their
+    /// contents until they are updated in their own frames.
+    pub fn update_uniform<T>(
+        &mut self,
+        frame: &Frame,
+        h: &Handle<uniform::UniformBuffer>,
+        data: &T,
+    ) -> Result<(), RenderError> {
+        let ubuf = self.frame_uniform_buffer(frame, h)?;
+
+        ubuf.update_with(data).map_err(RenderError::UniformBuffer)
+    }
+
+    /// As `update_uniform`, for element `idx`
+    pub fn update_uniform_element<T>(
+        &mut self,
+        frame: &Frame,
+        h: &Handle<uniform::UniformBuffer>,
+        idx: usize,
+        data: &T,
+    ) -> Result<(), RenderError> {
+        let ubuf = self.frame_uniform_buffer(frame, h)?;
+
+        ubuf.update_element(idx, data)
+            .map_err(RenderError::UniformBuffer)
+    }
+
+    /// Replace the vertices of the buffer with `data`. A dynamic buffer has one copy per frame in
+    /// flight and only the copy of `frame` is written, the other copies keep their contents until
+    /// they are updated in their own frames. If the buffer has to grow, the old allocation is
+    /// destroyed once the frames that may use it are done.
+    pub fn update_vertex_buffer<T>(
+        &mut self,
+        frame: &Frame,
+        h: &Handle<mesh::VertexBuffer>,
+        data: &[T],
+    ) -> Result<(), RenderError> {
+        self.check_current_frame(frame)?;
+
+        let vbuf = self
+            .vertex_buffers
+            .get_mut(h, frame.frame_idx as usize)
+            .ok_or_else(|| RenderError::InvalidHandle(h.id()))?;
+
+        let replaced = vbuf
+            .update(
+                &self.device,
+                self.device.util_queue(),
+                &self.util_command_pool,
+                util::as_byte_slice(data),
+            )
+            .map_err(RenderError::VertexBuffer)?;
+        self.schedule_destroy_removed(replaced);
+
+        Ok(())
+    }
+
+    /// As `update_vertex_buffer`, for indices
+    pub fn update_index_buffer<T>(
+        &mut self,
+        frame: &Frame,
+        h: &Handle<mesh::IndexBuffer>,
+        data: &[T],
+    ) -> Result<(), RenderError> {
+        self.check_current_frame(frame)?;
+
+        let ibuf = self
+            .index_buffers
+            .get_mut(h, frame.frame_idx as usize)
+            .ok_or_else(|| RenderError::InvalidHandle(h.id()))?;
+
+        let replaced = ibuf
+            .update(
+                &self.device,
+                self.device.util_queue(),
+                &self.util_command_pool,
+                util::as_byte_slice(data),
+            )
+            .map_err(RenderError::IndexBuffer)?;
+        self.schedule_destroy_removed(replaced);
+
+        Ok(())
+    }
+
+    pub fn create_descriptor_set(
+        &mut self,
+        gfx_pipeline_handle: &Handle<pipeline::GraphicsPipeline>,
+        uniform_buffer_handle: &Handle<uniform::UniformBuffer>,
+        texture_handle: &Handle<texture::Texture>,
+    ) -> Result<Handle<descriptor::DescriptorSet>, RenderError> {
+        let gfx_pipeline = self
+            .get_resource(gfx_pipeline_handle)
+            .ok_or_else(|| RenderError::InvalidHandle(gfx_pipeline_handle.id()))?;
+        assert_eq!(gfx_pipeline.vk_descriptor_set_layouts().len(), 1);
+
+        let uniform_buffers = self
+            .uniform_buffers
+            .get_all(uniform_buffer_handle)
+            .ok_or_else(|| RenderError::InvalidHandle(uniform_buffer_handle.id()))?;
+
+        let texture = self
+            .textures
+            .get(texture_handle)
+            .ok_or_else(|| RenderError::InvalidHandle(texture_handle.id()))?;
+        let descriptor = descriptor::DescriptorSetDescriptor {
+            layout: gfx_pipeline.vk_descriptor_set_layouts()[0],
+            uniform_buffers,
+            texture,
+        };
+
+        self.descriptor_sets
+            .create(&self.device, descriptor)
+            .map_err(RenderError::Descriptor)
+    }
+
+    /// Create a render target that can be sampled in later passes. Its image is bound to
+    /// descriptor sets with `bind_offscreen_target`.
+    pub fn create_offscreen_target(
+        &self,
+        extent: util::Extent2D,
+        format: vk::Format,
+        with_depth: bool,
+    ) -> Result<offscreen::OffscreenTarget, RenderError> {
+        Ok(offscreen::OffscreenTarget::new(
+            &self.device,
+            extent,
+            format,
+            with_depth,
+            vk::SampleCountFlags::TYPE_1,
+        )?)
+    }
+
+    /// Create several textures with one upload submission instead of one per texture, e.g. when
+    /// loading a scene. Textures that have already been created from an equal descriptor are
+    /// reused, as with `create_resource`.
+    pub fn create_textures(
+        &mut self,
+        descriptors: Vec<texture::TextureDescriptor>,
+    ) -> Result<Vec<Handle<texture::Texture>>, texture::TextureError> {
+        let queue = self.device.util_queue();
+        let handles = self.textures.create_batch(
+            &self.device,
+            queue,
+            &self.util_command_pool,
+            Some(&mut self.staging_pool),
+            descriptors,
+        )?;
+        self.evict_textures();
+        Ok(handles)
+    }
+
+    /// Replace the pixels of a texture, see `Texture::update`. Waits for the device to be idle
+    /// first, as frames in flight might be sampling the texture. Textures created from the same
+    /// descriptor afterwards get a new texture instead of this one.
+    pub fn update_texture(
+        &mut self,
+        handle: &Handle<texture::Texture>,
+        data: &[u8],
+    ) -> Result<(), RenderError> {
+        self.wait_idle()?;
+        let texture = self
+            .textures
+            .get_mut(handle)
+            .ok_or_else(|| RenderError::InvalidHandle(handle.id()))?;
+        texture.update(
+            &self.device,
+            self.device.util_queue(),
+            &self.util_command_pool,
+            data,
+        )?;
+        Ok(())
+    }
+
+    /// Limit the device memory used by cached textures. Textures that have been released are
+    /// evicted, least recently used first, when the budget is exceeded.
+    pub fn set_texture_budget(&mut self, bytes: u64) {
+        self.textures.set_budget(bytes);
+        self.evict_textures();
+    }
+
+    /// Drop a reference to a texture returned by `create_resource` or `create_textures`. The
+    /// texture stays cached until it is evicted, and creating it again before that reuses it.
+    pub fn release_texture(&mut self, handle: Handle<texture::Texture>) -> bool {
+        let released = self.textures.release(handle);
+        self.evict_textures();
+        released
+    }
+
+    /// The descriptor a texture was created from, e.g. to find its source file. None after its
+    /// contents have been updated with `update_texture`.
+    pub fn texture_descriptor(
+        &self,
+        handle: &Handle<texture::Texture>,
+    ) -> Option<&texture::TextureDescriptor> {
+        self.textures.descriptor(handle)
+    }
+
+    fn evict_textures(&mut self) {
+        for texture in self.textures.evict() {
+            self.deletion_queue.push(texture, self.submitted_frames);
+        }
+    }
+
+    /// Create a render pass with custom attachments and subpasses, e.g. for deferred shading
+    pub fn create_render_pass(
+        &self,
+        builder: render_pass::RenderPassBuilder,
+    ) -> Result<render_pass::RenderPass, RenderError> {
+        Ok(builder.build(&self.device)?)
+    }
+
+    /// Create a pipeline that renders into `target` instead of the swapchain
+    pub fn create_offscreen_pipeline(
+        &mut self,
+        descriptor: &pipeline::GraphicsPipelineDescriptor,
+        target: &offscreen::OffscreenTarget,
+    ) -> Result<Handle<pipeline::GraphicsPipeline>, RenderError> {
+        Ok(self.graphics_pipelines.create_fixed(
+            &self.device,
+            descriptor,
+            target.extent(),
+            target.render_pass(),
+        )?)
+    }
+
+    /// Bind a storage buffer in the descriptor sets of all frames. The sets must not be in use by
+    /// a frame in flight.
+    pub fn bind_storage_buffer(
+        &self,
+        handle: &Handle<descriptor::DescriptorSet>,
+        binding: u32,
+        buffer_handle: &Handle<storage_buffer::StorageBuffer>,
+    ) -> Result<(), RenderError> {
+        let buffer = self
+            .storage_buffers
+            .get(buffer_handle)
+            .ok_or_else(|| RenderError::InvalidHandle(buffer_handle.id()))?;
+        self.descriptor_sets
+            .bind_storage_buffer(handle, binding, buffer)
+            .ok_or_else(|| RenderError::InvalidHandle(handle.id()))
+    }
+
+    /// Bind the color image of `target` as a combined image sampler in the descriptor sets of
+    /// all frames. The sets must not be in use by a frame in flight.
+    pub fn bind_offscreen_target(
+        &self,
+        handle: &Handle<descriptor::DescriptorSet>,
+        binding: u32,
+        target: &offscreen::OffscreenTarget,
+    ) -> Result<(), RenderError> {
+        self.descriptor_sets
+            .bind_offscreen_target(handle, binding, target)
+            .ok_or_else(|| RenderError::InvalidHandle(handle.id()))
+    }
+
+    /// Bind an attachment of a render pass with several subpasses in the descriptor sets of all
+    /// frames, for a later subpass to read what an earlier one wrote. The sets must not be in use
+    /// by a frame in flight.
+    pub fn bind_input_attachment(
+        &self,
+        handle: &Handle<descriptor::DescriptorSet>,
+        binding: u32,
+        image_view: &image::ImageView,
+    ) -> Result<(), RenderError> {
+        self.descriptor_sets
+            .bind_input_attachment(handle, binding, image_view)
+            .ok_or_else(|| RenderError::InvalidHandle(handle.id()))
+    }
+
+    /// Bind the image of a texture, without its sampler, in the descriptor sets of all frames.
+    /// The shader samples it with a separate sampler, see `bind_sampler`. The sets must not be in
+    /// use by a frame in flight.
+    pub fn bind_sampled_image(
+        &self,
+        handle: &Handle<descriptor::DescriptorSet>,
+        binding: u32,
+        texture_handle: &Handle<texture::Texture>,
+    ) -> Result<(), RenderError> {
+        let texture = self
+            .textures
+            .get(texture_handle)
+            .ok_or_else(|| RenderError::InvalidHandle(texture_handle.id()))?;
+        self.descriptor_sets
+            .bind_sampled_image(handle, binding, texture)
+            .ok_or_else(|| RenderError::InvalidHandle(handle.id()))
+    }
+
+    /// Bind a sampler on its own in the descriptor sets of all frames, e.g. one from
+    /// `create_sampler` that is shared by many sampled images. The sampler has to outlive the
+    /// use of the sets.
+    pub fn bind_sampler(
+        &self,
+        handle: &Handle<descriptor::DescriptorSet>,
+        binding: u32,
+        sampler: &texture::Sampler,
+    ) -> Result<(), RenderError> {
+        self.descriptor_sets
+            .bind_sampler(handle, binding, sampler)
+            .ok_or_else(|| RenderError::InvalidHandle(handle.id()))
+    }
+
+    /// Create a sampler that is not tied to a texture, to bind with `bind_sampler`. Without a max
+    /// lod in `descriptor`, it can sample all mip levels of any image.
+    pub fn create_sampler(
+        &self,
+        descriptor: &texture::SamplerDescriptor,
+    ) -> Result<texture::Sampler, RenderError> {
+        let max_dim = self.device.limits().max_image_dimension2_d;
+        let mip_levels = util::mip_levels_for(util::Extent2D {
+            width: max_dim,
+            height: max_dim,
+        });
+        Ok(texture::Sampler::new(&self.device, mip_levels, descriptor)?)
+    }
+
+    pub fn get_descriptor_set(
         &self,
         handle: &Handle<descriptor::DescriptorSet>,
     ) -> Option<&descriptor::DescriptorSet> {
@@ -429,11 +2088,12 @@ impl
         &mut self,
         descriptor: pipeline::GraphicsPipelineDescriptor,
     ) -> Result<Handle<pipeline::GraphicsPipeline>, pipeline::PipelineError> {
+        let extent = self.render_extent();
         self.graphics_pipelines.create(
             &self.device,
             descriptor,
-            self.swapchain_extent(),
-            &self.render_pass,
+            extent,
+            self.windows.main.render_pass(),
         )
     }
 }
@@ -446,18 +2106,32 @@ impl<'a>
     > for Renderer
 {
     fn get_resource(&self, handle: &Handle<mesh::VertexBuffer>) -> Option<&mesh::VertexBuffer> {
-        self.vertex_buffers.get(handle)
+        self.vertex_buffers.get(handle, self.frame_idx as usize)
     }
 
+    /// Dynamic vertex buffers get one copy per frame in flight, see `update_vertex_buffer`
     fn create_resource(
         &mut self,
         descriptor: mesh::VertexBufferDescriptor<'a>,
     ) -> Result<Handle<mesh::VertexBuffer>, mem::MemoryError> {
-        let queue = self.device.util_queue();
-        let new =
-            mesh::VertexBuffer::create(&self.device, queue, &self.util_command_pool, &descriptor)?;
+        let queue = self.device.transfer_queue();
+        let n_copies = if descriptor.is_dynamic() {
+            self.frames_in_flight
+        } else {
+            1
+        };
+        let mut copies = Vec::with_capacity(n_copies);
+        for _ in 0..n_copies {
+            copies.push(mesh::VertexBuffer::create(
+                &self.device,
+                queue,
+                &self.transfer_command_pool,
+                Some(&mut self.staging_pool),
+                &descriptor,
+            )?);
+        }
 
-        Ok(self.vertex_buffers.add(new))
+        Ok(self.vertex_buffers.add(copies))
     }
 }
 
@@ -466,18 +2140,56 @@ impl<'a>
     for Renderer
 {
     fn get_resource(&self, handle: &Handle<mesh::IndexBuffer>) -> Option<&mesh::IndexBuffer> {
-        self.index_buffers.get(handle)
+        self.index_buffers.get(handle, self.frame_idx as usize)
+    }
+
+    /// Dynamic index buffers get one copy per frame in flight, see `update_index_buffer`
+    fn create_resource(
+        &mut self,
+        descriptor: mesh::IndexBufferDescriptor<'a>,
+    ) -> Result<Handle<mesh::IndexBuffer>, mem::MemoryError> {
+        let queue = self.device.transfer_queue();
+        let n_copies = if descriptor.is_dynamic() {
+            self.frames_in_flight
+        } else {
+            1
+        };
+        let mut copies = Vec::with_capacity(n_copies);
+        for _ in 0..n_copies {
+            copies.push(mesh::IndexBuffer::create(
+                &self.device,
+                queue,
+                &self.transfer_command_pool,
+                Some(&mut self.staging_pool),
+                &descriptor,
+            )?);
+        }
+
+        Ok(self.index_buffers.add(copies))
+    }
+}
+
+impl<'a> resource::ResourceManager<mesh::MeshDescriptor<'a>, mesh::Mesh, mem::MemoryError>
+    for Renderer
+{
+    fn get_resource(&self, handle: &Handle<mesh::Mesh>) -> Option<&mesh::Mesh> {
+        self.meshes.get(handle)
     }
 
     fn create_resource(
         &mut self,
-        descriptor: mesh::IndexBufferDescriptor<'a>,
-    ) -> Result<Handle<mesh::IndexBuffer>, mem::MemoryError> {
-        let queue = self.device.util_queue();
-        let new =
-            mesh::IndexBuffer::create(&self.device, queue, &self.util_command_pool, &descriptor)?;
+        descriptor: mesh::MeshDescriptor<'a>,
+    ) -> Result<Handle<mesh::Mesh>, mem::MemoryError> {
+        let queue = self.device.transfer_queue();
+        let new = mesh::Mesh::create(
+            &self.device,
+            queue,
+            &self.transfer_command_pool,
+            Some(&mut self.staging_pool),
+            &descriptor,
+        )?;
 
-        Ok(self.index_buffers.add(new))
+        Ok(self.meshes.add(new))
     }
 }
 
@@ -499,9 +2211,14 @@ impl<'a>
         &mut self,
         descriptor: uniform::UniformBufferDescriptor<'a>,
     ) -> Result<Handle<uniform::UniformBuffer>, mem::MemoryError> {
-        let queue = self.device.util_queue();
-        self.uniform_buffers
-            .create(&self.device, queue, &self.util_command_pool, &descriptor)
+        let queue = self.device.transfer_queue();
+        self.uniform_buffers.create(
+            &self.device,
+            queue,
+            &self.transfer_command_pool,
+            Some(&mut self.staging_pool),
+            &descriptor,
+        )
     }
 }
 
@@ -518,7 +2235,1764 @@ impl<'a>
         descriptor: texture::TextureDescriptor,
     ) -> Result<Handle<texture::Texture>, texture::TextureError> {
         let queue = self.device.util_queue();
-        self.textures
-            .create(&self.device, queue, &self.util_command_pool, descriptor)
+        let handle = self.textures.create(
+            &self.device,
+            queue,
+            &self.util_command_pool,
+            Some(&mut self.staging_pool),
+            descriptor,
+        )?;
+        self.evict_textures();
+        Ok(handle)
+    }
+}
+
+impl<'a>
+    resource::ResourceManager<
+        storage_buffer::StorageBufferDescriptor<'a>,
+        storage_buffer::StorageBuffer,
+        mem::MemoryError,
+    > for Renderer
+{
+    fn get_resource(
+        &self,
+        handle: &Handle<storage_buffer::StorageBuffer>,
+    ) -> Option<&storage_buffer::StorageBuffer> {
+        self.storage_buffers.get(handle)
+    }
+
+    fn create_resource(
+        &mut self,
+        descriptor: storage_buffer::StorageBufferDescriptor<'a>,
+    ) -> Result<Handle<storage_buffer::StorageBuffer>, mem::MemoryError> {
+        let queue = self.device.transfer_queue();
+        let new = storage_buffer::StorageBuffer::create(
+            &self.device,
+            queue,
+            &self.transfer_command_pool,
+            Some(&mut self.staging_pool),
+            &descriptor,
+        )?;
+
+        Ok(self.storage_buffers.add(new))
+    }
+}
+
+impl Renderer {
+    // The resource might be used by the frame that is being recorded, or any submitted before it
+    fn schedule_destroy_removed<T: 'static>(&mut self, removed: Option<T>) -> bool {
+        match removed {
+            Some(resource) => {
+                self.deletion_queue.push(resource, self.submitted_frames);
+                log::trace!(
+                    "{} resources pending destruction",
+                    self.deletion_queue.len()
+                );
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl resource::DestroyResource<mesh::VertexBuffer> for Renderer {
+    fn schedule_destroy(&mut self, handle: Handle<mesh::VertexBuffer>) -> bool {
+        let removed = self.vertex_buffers.remove(handle);
+        self.schedule_destroy_removed(removed)
+    }
+}
+
+impl resource::DestroyResource<mesh::IndexBuffer> for Renderer {
+    fn schedule_destroy(&mut self, handle: Handle<mesh::IndexBuffer>) -> bool {
+        let removed = self.index_buffers.remove(handle);
+        self.schedule_destroy_removed(removed)
+    }
+}
+
+impl resource::DestroyResource<mesh::Mesh> for Renderer {
+    fn schedule_destroy(&mut self, handle: Handle<mesh::Mesh>) -> bool {
+        let removed = self.meshes.remove(handle);
+        self.schedule_destroy_removed(removed)
+    }
+}
+
+impl resource::DestroyResource<storage_buffer::StorageBuffer> for Renderer {
+    fn schedule_destroy(&mut self, handle: Handle<storage_buffer::StorageBuffer>) -> bool {
+        let removed = self.storage_buffers.remove(handle);
+        self.schedule_destroy_removed(removed)
+    }
+}
+
+impl resource::DestroyResource<uniform::UniformBuffer> for Renderer {
+    fn schedule_destroy(&mut self, handle: Handle<uniform::UniformBuffer>) -> bool {
+        let removed = self.uniform_buffers.remove(handle);
+        self.schedule_destroy_removed(removed)
+    }
+}
+
+impl resource::DestroyResource<texture::Texture> for Renderer {
+    fn schedule_destroy(&mut self, handle: Handle<texture::Texture>) -> bool {
+        let removed = self.textures.remove(handle);
+        self.schedule_destroy_removed(removed)
+    }
+}
+
+impl resource::IterResources<pipeline::GraphicsPipeline> for Renderer {
+    fn iter_resources(
+        &self,
+    ) -> Box<
+        dyn Iterator<
+                Item = (
+                    Handle<pipeline::GraphicsPipeline>,
+                    &pipeline::GraphicsPipeline,
+                ),
+            > + '_,
+    > {
+        Box::new(self.graphics_pipelines.iter())
+    }
+}
+
+impl resource::IterResources<mesh::VertexBuffer> for Renderer {
+    fn iter_resources(
+        &self,
+    ) -> Box<dyn Iterator<Item = (Handle<mesh::VertexBuffer>, &mesh::VertexBuffer)> + '_> {
+        Box::new(self.vertex_buffers.iter(self.frame_idx as usize))
+    }
+}
+
+impl resource::IterResources<mesh::IndexBuffer> for Renderer {
+    fn iter_resources(
+        &self,
+    ) -> Box<dyn Iterator<Item = (Handle<mesh::IndexBuffer>, &mesh::IndexBuffer)> + '_> {
+        Box::new(self.index_buffers.iter(self.frame_idx as usize))
+    }
+}
+
+impl resource::IterResources<mesh::Mesh> for Renderer {
+    fn iter_resources(&self) -> Box<dyn Iterator<Item = (Handle<mesh::Mesh>, &mesh::Mesh)> + '_> {
+        Box::new(self.meshes.iter_with_handles())
+    }
+}
+
+impl resource::IterResources<storage_buffer::StorageBuffer> for Renderer {
+    fn iter_resources(
+        &self,
+    ) -> Box<
+        dyn Iterator<
+                Item = (
+                    Handle<storage_buffer::StorageBuffer>,
+                    &storage_buffer::StorageBuffer,
+                ),
+            > + '_,
+    > {
+        Box::new(self.storage_buffers.iter_with_handles())
+    }
+}
+
+/// The buffers of the current frame
+impl resource::IterResources<uniform::UniformBuffer> for Renderer {
+    fn iter_resources(
+        &self,
+    ) -> Box<dyn Iterator<Item = (Handle<uniform::UniformBuffer>, &uniform::UniformBuffer)> + '_>
+    {
+        Box::new(self.uniform_buffers.iter(self.frame_idx as usize))
+    }
+}
+
+impl resource::IterResources<texture::Texture> for Renderer {
+    fn iter_resources(
+        &self,
+    ) -> Box<dyn Iterator<Item = (Handle<texture::Texture>, &texture::Texture)> + '_> {
+        Box::new(self.textures.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{headless_renderer, write_spirv, Position, POSITION_SPV_VERT};
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn headless_clear() {
+        let mut renderer = headless_renderer();
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        assert_eq!(renderer.swapchain_image_count(), 1);
+        assert_eq!(renderer.current_image_index(&frame), 0);
+        let cmd_buf = frame
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.swapchain_extent(),
+            )
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        assert_eq!((image.width, image.height), (4, 4));
+        // The default clear color
+        assert!(image.data.chunks(4).all(|px| px == [0, 0, 0, 255]));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn readback_of_requested_frame() {
+        let mut renderer = headless_renderer();
+        let clear = |renderer: &mut Renderer, color: f32, read_back: bool| {
+            renderer.set_clear_color(color, color, color, 1.0);
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            if read_back {
+                renderer
+                    .request_readback(&mut frame)
+                    .expect("Failed to request readback");
+            }
+            renderer.submit(frame).expect("Failed to submit");
+        };
+
+        clear(&mut renderer, 1.0, false);
+        assert!(matches!(
+            renderer.read_framebuffer(),
+            Err(RenderError::NoReadback)
+        ));
+
+        // Later frames without a request don't replace the copy
+        clear(&mut renderer, 1.0, true);
+        clear(&mut renderer, 0.0, false);
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        assert!(image.data.chunks(4).all(|px| px == [255, 255, 255, 255]));
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn render_scale() {
+        let extent = util::Extent2D {
+            width: 8,
+            height: 4,
+        };
+        let mut renderer = Renderer::new_headless(extent, RendererConfig::default())
+            .expect("Failed to create headless renderer");
+        assert!(matches!(
+            renderer.set_render_scale(0.0),
+            Err(RenderError::InvalidRenderScale(_))
+        ));
+        assert_eq!(renderer.render_extent(), extent);
+
+        renderer.set_render_scale(0.5).expect("Failed to set scale");
+        let scaled = util::Extent2D {
+            width: 4,
+            height: 2,
+        };
+        assert_eq!(renderer.render_extent(), scaled);
+        assert_eq!(renderer.swapchain_extent(), extent);
+
+        // The internal target follows resizes
+        let resized = util::Extent2D {
+            width: 16,
+            height: 8,
+        };
+        renderer.resize(resized).expect("Failed to resize");
+        assert_eq!(renderer.render_extent(), resized.scaled(0.5));
+
+        renderer.set_clear_color(1.0, 0.0, 0.0, 1.0);
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let cmd_buf = frame
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.render_extent(),
+            )
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+
+        // The blit fills the whole, unscaled image
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        assert_eq!((image.width, image.height), (16, 8));
+        assert!(image.data.chunks(4).all(|px| px == [255, 0, 0, 255]));
+
+        renderer.set_render_scale(1.0).expect("Failed to set scale");
+        assert_eq!(renderer.render_extent(), resized);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn resize_keeps_render_pass() {
+        let extent = util::Extent2D {
+            width: 8,
+            height: 4,
+        };
+        let mut renderer = Renderer::new_headless(extent, RendererConfig::default())
+            .expect("Failed to create headless renderer");
+        let render_pass = *renderer.render_pass().vk_render_pass();
+
+        let resized = util::Extent2D {
+            width: 16,
+            height: 8,
+        };
+        renderer.resize(resized).expect("Failed to resize");
+        assert_eq!(renderer.swapchain_extent(), resized);
+        assert_eq!(*renderer.render_pass().vk_render_pass(), render_pass);
+
+        // Changing the sample count needs a new render pass
+        let msaa = renderer.msaa_sample_count();
+        let max = *renderer.supported_msaa_sample_counts().last().unwrap();
+        renderer.set_msaa(max).expect("Failed to set msaa");
+        if max != msaa {
+            assert_ne!(*renderer.render_pass().vk_render_pass(), render_pass);
+        }
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn minimized_until_resized() {
+        let mut renderer = headless_renderer();
+        let extent = renderer.swapchain_extent();
+
+        renderer
+            .resize(util::Extent2D {
+                width: 0,
+                height: 0,
+            })
+            .expect("Failed to resize");
+        assert!(matches!(renderer.next_frame(), Err(RenderError::Minimized)));
+        // Still minimized
+        assert!(matches!(renderer.next_frame(), Err(RenderError::Minimized)));
+
+        renderer.resize(extent).expect("Failed to resize");
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        renderer.submit(frame).expect("Failed to submit");
+        assert_eq!(renderer.swapchain_extent(), extent);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn msaa_sample_counts() {
+        let mut renderer = headless_renderer();
+        let supported = renderer.supported_msaa_sample_counts();
+        assert!(supported.contains(&vk::SampleCountFlags::TYPE_1));
+        assert!(supported.contains(&renderer.device.max_usable_sample_count()));
+
+        for &count in &supported {
+            renderer.set_msaa(count).expect("Failed to set msaa");
+            assert_eq!(renderer.msaa_sample_count(), count);
+        }
+        // Not a single sample count
+        let invalid = vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_4;
+        assert!(matches!(
+            renderer.set_msaa(invalid),
+            Err(RenderError::UnsupportedSampleCount(c)) if c == invalid
+        ));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn stale_frame() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            frames_in_flight: 3,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+
+        // Frame index 0 is handed out twice, as nothing is submitted in between
+        let stale = renderer.next_frame().expect("Failed to get frame");
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        renderer.submit(frame).expect("Failed to submit");
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let foreign = stale
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .end()
+            .expect("Failed to end command buffer");
+        assert!(matches!(
+            frame.add_command_buffer(foreign),
+            Err(RenderError::ForeignCommandBuffer)
+        ));
+        renderer.submit(frame).expect("Failed to submit");
+
+        assert!(matches!(
+            renderer.submit(stale),
+            Err(RenderError::FrameOutOfOrder {
+                expected: 2,
+                got: 0
+            })
+        ));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn stale_frame_with_current_index() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            frames_in_flight: 2,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        let handle: Handle<uniform::UniformBuffer> = renderer
+            .create_resource(uniform::UniformBufferDescriptor::uninitialized::<[f32; 4]>(
+                1,
+            ))
+            .expect("Failed to create uniform buffer");
+
+        // Frame 0 is handed out twice, and the index is back at 0 after two more submits
+        let stale = renderer.next_frame().expect("Failed to get frame");
+        for _ in 0..2 {
+            let frame = renderer.next_frame().expect("Failed to get frame");
+            renderer.submit(frame).expect("Failed to submit");
+        }
+
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        assert!(matches!(
+            renderer.update_uniform(&stale, &handle, &[1.0f32; 4]),
+            Err(RenderError::StaleFrame {
+                expected: 2,
+                got: 0
+            })
+        ));
+        assert!(matches!(
+            renderer.submit(stale),
+            Err(RenderError::StaleFrame {
+                expected: 2,
+                got: 0
+            })
+        ));
+        renderer.submit(frame).expect("Failed to submit");
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn secondary_command_buffer_is_rejected() {
+        let mut renderer = headless_renderer();
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let secondary = frame
+            .new_secondary_command_buffer(renderer.render_pass(), renderer.framebuffer(&frame))
+            .expect("Failed to create command buffer")
+            .end()
+            .expect("Failed to end command buffer");
+        assert!(matches!(
+            frame.add_command_buffer(secondary),
+            Err(RenderError::SecondaryCommandBuffer)
+        ));
+        renderer.submit(frame).expect("Failed to submit");
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn memory_stats_track_allocations() {
+        let renderer = headless_renderer();
+
+        let size = 4 * 1024 * 1024;
+        let before = renderer.memory_stats().expect("Failed to get stats");
+        let _buffer = mem::DeviceBuffer::empty(
+            &renderer.device,
+            size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk_mem::MemoryUsage::GpuOnly,
+        )
+        .expect("Failed to create buffer");
+        let after = renderer.memory_stats().expect("Failed to get stats");
+
+        // Alignment might add a bit
+        let used = after.total.used_bytes - before.total.used_bytes;
+        assert!(used >= size as u64 && used < 2 * size as u64, "{}", used);
+        assert_eq!(
+            after.total.allocation_count,
+            before.total.allocation_count + 1
+        );
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn deferred_destruction() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            frames_in_flight: 2,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        let data = [0u32; 64];
+        let handle: Handle<storage_buffer::StorageBuffer> = renderer
+            .create_resource(storage_buffer::StorageBufferDescriptor::from_slice(&data))
+            .expect("Failed to create buffer");
+
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        assert!(renderer.schedule_destroy(handle));
+        assert!(renderer.get_resource(&handle).is_none());
+        renderer.submit(frame).expect("Failed to submit");
+
+        // The second frame doesn't wait for the first one
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        assert_eq!(renderer.deletion_queue.len(), 1);
+        renderer.submit(frame).expect("Failed to submit");
+
+        // Waits for the fence of the first frame
+        let _frame = renderer.next_frame().expect("Failed to get frame");
+        assert_eq!(renderer.deletion_queue.len(), 0);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn validation_disabled() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        // The env var is covered by the instance tests, setting it here would race with the other
+        // tests that create renderers
+        let config = RendererConfig {
+            validation: false,
+            ..Default::default()
+        };
+        let renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        assert!(!renderer.instance.validation_enabled());
+        assert!(renderer.debug_utils.is_none());
+        assert!(renderer.take_validation_errors().is_empty());
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn device_name() {
+        let renderer = headless_renderer();
+        let extent = renderer.swapchain_extent();
+        assert!(!renderer.device_name().is_empty());
+
+        // Any device matches the empty string
+        let config = RendererConfig {
+            device_preference: Some(DevicePreference::Name(String::new())),
+            ..Default::default()
+        };
+        let renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        assert!(!renderer.device_name().is_empty());
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn api_version() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        // Downgraded to what the loader supports, e.g. 1.2, instead of failing
+        let config = RendererConfig {
+            instance: InstanceConfig {
+                api_version: (1, 3, 0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        let (major, minor, _) = renderer.api_version();
+        assert_eq!(major, 1);
+        assert!(minor <= 3);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn dependent_submits() {
+        let renderer = headless_renderer();
+        let device = &renderer.device;
+        let pool = &renderer.util_command_pool;
+        let queue = device.util_queue();
+
+        let data = (0..64u8).collect::<Vec<_>>();
+        let src = mem::DeviceBuffer::staging_with_data(device, &data).expect("Failed to create");
+        let mid = mem::DeviceBuffer::empty(
+            device,
+            data.len(),
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuOnly,
+        )
+        .expect("Failed to create");
+        let dst = mem::DeviceBuffer::readback_empty(device, data.len()).expect("Failed to create");
+
+        let first = pool
+            .begin_single_submit()
+            .expect("Failed to begin")
+            .copy_buffer(src.vk_buffer(), mid.vk_buffer(), data.len())
+            .end()
+            .expect("Failed to end");
+        let second = pool
+            .begin_single_submit()
+            .expect("Failed to begin")
+            .copy_buffer(mid.vk_buffer(), dst.vk_buffer(), data.len())
+            .end()
+            .expect("Failed to end");
+
+        let copied = sync::Semaphore::new(device).expect("Failed to create semaphore");
+        let done = sync::Fence::unsignaled(device).expect("Failed to create fence");
+        queue
+            .submit_batch(&[&first], &[], &[&copied], None)
+            .expect("Failed to submit");
+        queue
+            .submit_batch(
+                &[&second],
+                &[(&copied, vk::PipelineStageFlags::TRANSFER)],
+                &[],
+                Some(&done),
+            )
+            .expect("Failed to submit");
+        done.blocking_wait().expect("Failed to wait");
+
+        assert_eq!(dst.read_data().expect("Failed to read"), data);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn iter_textures() {
+        let mut renderer = headless_renderer();
+        let handles = (0..3u8)
+            .map(|i| {
+                let descriptor = texture::TextureDescriptor::from_raw(
+                    vec![i; 4],
+                    1,
+                    1,
+                    vk::Format::R8G8B8A8_UNORM,
+                    false,
+                );
+                renderer
+                    .create_resource(descriptor)
+                    .expect("Failed to create texture")
+            })
+            .collect::<Vec<Handle<texture::Texture>>>();
+
+        let textures = IterResources::<texture::Texture>::iter_resources(&renderer)
+            .map(|(h, texture)| {
+                assert!(texture.size_in_bytes() >= 4);
+                assert!(renderer.texture_descriptor(&h).is_some());
+                h
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(textures.len(), 3);
+        assert!(handles.iter().all(|h| textures.contains(h)));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn rgba_image_texture() {
+        let mut renderer = headless_renderer();
+        let gradient = ::image::RgbaImage::from_fn(2, 2, |x, y| {
+            ::image::Rgba([(x * 255) as u8, (y * 255) as u8, 0, 255])
+        });
+
+        let texture = texture::Texture::from_rgba_image(
+            &renderer.device,
+            renderer.device.util_queue(),
+            &renderer.util_command_pool,
+            &gradient,
+            vk::Format::R8G8B8A8_UNORM,
+        )
+        .expect("Failed to create texture");
+        assert_eq!(texture.extent().width, 2);
+        assert_eq!(texture.extent().height, 2);
+        assert_eq!(texture.format(), vk::Format::R8G8B8A8_UNORM);
+
+        let descriptor = || {
+            texture::TextureDescriptor::from_rgba_image(&gradient, vk::Format::R8G8B8A8_UNORM, true)
+        };
+        let handle: Handle<texture::Texture> = renderer
+            .create_resource(descriptor())
+            .expect("Failed to create texture");
+        let cached: Handle<texture::Texture> = renderer
+            .create_resource(descriptor())
+            .expect("Failed to create texture");
+        assert!(handle == cached);
+        assert_eq!(renderer.get_resource(&handle).unwrap().mip_levels(), 2);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn mapped_buffer_flush() {
+        let renderer = headless_renderer();
+        let mut buffer = mem::DeviceBuffer::persistent_mapped(
+            &renderer.device,
+            256,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+        )
+        .expect("Failed to create buffer");
+
+        // Ranges that don't line up with nonCoherentAtomSize are fine, vk-mem aligns them
+        buffer
+            .update_data_at(&[1, 2, 3], 7)
+            .expect("Failed to write");
+        buffer.flush_range(0, 256).expect("Failed to flush");
+        buffer
+            .invalidate_range(5, 11)
+            .expect("Failed to invalidate");
+        let data = buffer.read_data().expect("Failed to read");
+        assert_eq!(&data[7..10], &[1, 2, 3]);
+    }
+
+    // Records the error logs of each thread, to check for the ones about objects being destroyed
+    // out of order. The errors of tests that run at the same time are kept apart.
+    struct ErrorLogger;
+
+    thread_local! {
+        static LOGGED_ERRORS: std::cell::RefCell<Vec<String>> = std::cell::RefCell::new(Vec::new());
+    }
+
+    impl log::Log for ErrorLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() == log::Level::Error
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                LOGGED_ERRORS.with(|errors| errors.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static ERROR_LOGGER: ErrorLogger = ErrorLogger;
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn drop_order() {
+        // There can only be one logger per process. If another one was set first, the errors
+        // can't be seen here.
+        if log::set_logger(&ERROR_LOGGER).is_err() {
+            eprintln!("Another logger is set, drop_order can't check the logged errors");
+            return;
+        }
+        log::set_max_level(log::LevelFilter::Error);
+
+        let mut renderer = headless_renderer();
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        renderer.submit(frame).expect("Failed to submit");
+        std::mem::drop(renderer);
+
+        let errors = LOGGED_ERRORS.with(|errors| errors.borrow().clone());
+        assert!(
+            !errors
+                .iter()
+                .any(|e| e.contains("still children alive") || e.contains("still existing")),
+            "{:?}",
+            errors
+        );
+    }
+
+    static WHITE_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = vec4(1.0);
+        }
+    ",
+        frag
+    );
+
+    static RED_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = vec4(1.0, 0.0, 0.0, 1.0);
+        }
+    ",
+        frag
+    );
+
+    static HLSL_SPV_VERT: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        float4 VSMain(float2 pos : POSITION) : SV_POSITION {
+            return float4(pos, 0.0, 1.0);
+        }
+    ",
+        vert,
+        hlsl,
+        entry = "VSMain"
+    );
+
+    static HLSL_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        float4 PSMain() : SV_TARGET {
+            return float4(1.0, 1.0, 1.0, 1.0);
+        }
+    ",
+        frag,
+        hlsl,
+        entry = "PSMain"
+    );
+
+    // Needs a Vulkan device with validation layers, run with --ignored
+    #[test]
+    #[ignore]
+    fn msaa_resolve_blends_edges() {
+        let dir = std::env::temp_dir().join("trekanten_msaa_resolve_blends_edges");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, WHITE_SPV_FRAG);
+
+        let extent = util::Extent2D {
+            width: 16,
+            height: 16,
+        };
+        let config = RendererConfig {
+            msaa: Some(vk::SampleCountFlags::TYPE_4),
+            validation_errors: Some(ValidationErrors {
+                min_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+                action: ValidationErrorAction::Collect,
+            }),
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        assert_eq!(renderer.msaa_sample_count(), vk::SampleCountFlags::TYPE_4);
+        renderer.set_clear_color(0.0, 0.0, 0.0, 1.0);
+
+        let descriptor = pipeline::GraphicsPipelineDescriptor::builder()
+            .vertex_shader(&vert)
+            .fragment_shader(&frag)
+            .vertex_type::<Position>()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .build()
+            .expect("Failed to create pipeline descriptor");
+        let pipeline_handle = renderer
+            .create_resource(descriptor)
+            .expect("Failed to create pipeline");
+        // The long edge goes diagonally through the pixels
+        let vertices = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+        ];
+        let indices = [0u32, 1, 2];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(&vertices, &indices))
+            .expect("Failed to create mesh");
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let pipeline = renderer.get_resource(&pipeline_handle).unwrap();
+        let mesh = renderer.get_resource(&mesh_handle).unwrap();
+        let cmd_buf = frame
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.swapchain_extent(),
+            )
+            .bind_graphics_pipeline(pipeline)
+            .draw_mesh(mesh)
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        let reds = image.data.chunks(4).map(|px| px[0]).collect::<Vec<_>>();
+        assert!(reds.contains(&0));
+        assert!(reds.contains(&255));
+        assert!(reds.iter().any(|&r| r > 0 && r < 255), "{:?}", reds);
+
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn shader_entry_points() {
+        let dir = std::env::temp_dir().join("trekanten_shader_entry_points");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, HLSL_SPV_VERT);
+        write_spirv(&frag, HLSL_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        let descriptor = |vert_entry| {
+            pipeline::GraphicsPipelineDescriptor::builder()
+                .vertex_shader(&vert)
+                .fragment_shader(&frag)
+                .vertex_entry(vert_entry)
+                .fragment_entry("PSMain")
+                .vertex_type::<Position>()
+                .build()
+                .expect("Failed to create pipeline descriptor")
+        };
+
+        renderer
+            .create_resource(descriptor("VSMain"))
+            .expect("Failed to create pipeline");
+        assert!(matches!(
+            renderer.create_resource(descriptor("main")),
+            Err(pipeline::PipelineError::MissingEntryPoint(_))
+        ));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn stencil_masks_draw() {
+        let dir = std::env::temp_dir().join("trekanten_stencil_masks_draw");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, red, white) = (
+            dir.join("vert.spv"),
+            dir.join("red.spv"),
+            dir.join("white.spv"),
+        );
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&red, RED_SPV_FRAG);
+        write_spirv(&white, WHITE_SPV_FRAG);
+
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            stencil: true,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        assert!(renderer.render_pass().subpass_has_stencil(0));
+        renderer.set_clear_color(0.0, 0.0, 0.0, 1.0);
+
+        let pipeline = |frag: &std::path::Path, mode| {
+            pipeline::GraphicsPipelineDescriptor::builder()
+                .vertex_shader(&vert)
+                .fragment_shader(frag)
+                .vertex_type::<Position>()
+                .cull_mode(vk::CullModeFlags::NONE)
+                .depth_test_enable(false)
+                .stencil_mode(mode)
+                .build()
+                .expect("Failed to create pipeline descriptor")
+        };
+        let write_handle = renderer
+            .create_resource(pipeline(&red, pipeline::StencilMode::Write))
+            .expect("Failed to create pipeline");
+        let masked_handle = renderer
+            .create_resource(pipeline(&white, pipeline::StencilMode::NotEqual))
+            .expect("Failed to create pipeline");
+
+        let quad = |x0, x1| {
+            [
+                Position([x0, -1.0]),
+                Position([x1, -1.0]),
+                Position([x0, 1.0]),
+                Position([x1, 1.0]),
+            ]
+        };
+        let indices = [0u32, 1, 2, 2, 1, 3];
+        let left_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad(-1.0, 0.0),
+                &indices,
+            ))
+            .expect("Failed to create mesh");
+        let full_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad(-1.0, 1.0),
+                &indices,
+            ))
+            .expect("Failed to create mesh");
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let cmd_buf = frame
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.swapchain_extent(),
+            )
+            .bind_graphics_pipeline(renderer.get_resource(&write_handle).unwrap())
+            .set_stencil_reference(1)
+            .draw_mesh(renderer.get_resource(&left_handle).unwrap())
+            .bind_graphics_pipeline(renderer.get_resource(&masked_handle).unwrap())
+            .set_stencil_reference(1)
+            .draw_mesh(renderer.get_resource(&full_handle).unwrap())
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        for (i, px) in image.data.chunks(4).enumerate() {
+            let x = i as u32 % image.width;
+            let expected = if x < image.width / 2 {
+                [255, 0, 0, 255]
+            } else {
+                [255, 255, 255, 255]
+            };
+            assert_eq!(px, &expected[..], "pixel {}", i);
+        }
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn uniform_updates_per_frame() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            frames_in_flight: 2,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        let handle: Handle<uniform::UniformBuffer> = renderer
+            .create_resource(uniform::UniformBufferDescriptor::uninitialized::<[f32; 4]>(
+                1,
+            ))
+            .expect("Failed to create uniform buffer");
+
+        for &value in &[1.0f32, 2.0] {
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            renderer
+                .update_uniform(&frame, &handle, &[value; 4])
+                .expect("Failed to update uniform buffer");
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer.submit(frame).expect("Failed to submit");
+        }
+        renderer.wait_idle().expect("Failed to wait");
+
+        let values = renderer
+            .uniform_buffers
+            .get_all(&handle)
+            .unwrap()
+            .iter()
+            .map(|ubuf| {
+                let data = ubuf.read_data().expect("Failed to read uniform buffer");
+                f32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![1.0, 2.0]);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn two_windows() {
+        let dir = std::env::temp_dir().join("trekanten_two_windows");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, WHITE_SPV_FRAG);
+
+        let main_extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let preview_extent = util::Extent2D {
+            width: 8,
+            height: 2,
+        };
+        let mut renderer = Renderer::new_headless(main_extent, RendererConfig::default())
+            .expect("Failed to create headless renderer");
+        let preview = renderer
+            .add_headless_window(preview_extent)
+            .expect("Failed to add window");
+        renderer.set_clear_color(0.0, 0.0, 0.0, 1.0);
+
+        // Shared by both windows
+        let descriptor = pipeline::GraphicsPipelineDescriptor::builder()
+            .vertex_shader(&vert)
+            .fragment_shader(&frag)
+            .vertex_type::<Position>()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .dynamic_viewport(true)
+            .build()
+            .expect("Failed to create pipeline descriptor");
+        let pipeline_handle = renderer
+            .create_resource(descriptor)
+            .expect("Failed to create pipeline");
+        let vertices = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let indices = [0u32, 1, 2, 2, 1, 3];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(&vertices, &indices))
+            .expect("Failed to create mesh");
+
+        // One loop iteration, the quad is only drawn in the preview
+        for &(window, draw) in &[(WindowId::MAIN, false), (preview, true)] {
+            let mut frame = renderer
+                .next_frame_for(window)
+                .expect("Failed to get frame");
+            assert_eq!(frame.window(), window);
+            let extent = renderer.swapchain_extent_for(window).unwrap();
+            let mut cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass_for(window).unwrap(),
+                    renderer.framebuffer(&frame),
+                    extent,
+                );
+            if draw {
+                cmd_buf = cmd_buf
+                    .bind_graphics_pipeline(renderer.get_resource(&pipeline_handle).unwrap())
+                    .set_viewport(util::Viewport::from_extent(extent))
+                    .set_scissor(util::Rect2D::from_extent(extent))
+                    .draw_mesh(renderer.get_resource(&mesh_handle).unwrap());
+            }
+            let cmd_buf = cmd_buf
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer
+                .request_readback(&mut frame)
+                .expect("Failed to request readback");
+            renderer.submit(frame).expect("Failed to submit");
+        }
+
+        let main = renderer.read_framebuffer().expect("Failed to read back");
+        assert_eq!((main.width, main.height), (4, 4));
+        assert!(main.data.chunks(4).all(|px| px == [0, 0, 0, 255]));
+        let image = renderer
+            .read_framebuffer_for(preview)
+            .expect("Failed to read back");
+        assert_eq!((image.width, image.height), (8, 2));
+        assert!(image.data.chunks(4).all(|px| px == [255, 255, 255, 255]));
+
+        renderer
+            .remove_window(preview)
+            .expect("Failed to remove window");
+        assert!(matches!(
+            renderer.next_frame_for(preview),
+            Err(RenderError::InvalidWindow(_))
+        ));
+        assert!(matches!(
+            renderer.remove_window(WindowId::MAIN),
+            Err(RenderError::InvalidWindow(_))
+        ));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn missing_shader_file() {
+        let dir = std::env::temp_dir().join("trekanten_missing_shader_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("missing_frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        let _ = std::fs::remove_file(&frag);
+
+        let mut renderer = headless_renderer();
+        let descriptor = pipeline::GraphicsPipelineDescriptor::builder()
+            .vertex_shader(&vert)
+            .fragment_shader(&frag)
+            .vertex_type::<Position>()
+            .build()
+            .expect("Failed to create pipeline descriptor");
+
+        match renderer.create_resource(descriptor) {
+            Err(pipeline::PipelineError::ShaderFileNotFound { path, stage }) => {
+                assert_eq!(path, frag);
+                assert_eq!(stage, vk::ShaderStageFlags::FRAGMENT);
+            }
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Created a pipeline without a fragment shader"),
+        }
+    }
+
+    // Needs a Vulkan device with validation layers, run with --ignored
+    #[test]
+    #[ignore]
+    fn no_depth_is_validation_clean() {
+        let dir = std::env::temp_dir().join("trekanten_no_depth_is_validation_clean");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, RED_SPV_FRAG);
+
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            depth: false,
+            validation_errors: Some(ValidationErrors {
+                min_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+                action: ValidationErrorAction::Collect,
+            }),
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        assert!(!renderer.render_pass().subpass_has_depth(0));
+
+        let pipeline_handle = renderer
+            .create_resource(
+                pipeline::GraphicsPipelineDescriptor::builder()
+                    .vertex_shader(&vert)
+                    .fragment_shader(&frag)
+                    .vertex_type::<Position>()
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .depth_test_enable(false)
+                    .depth_write_enable(false)
+                    .build()
+                    .expect("Failed to create pipeline descriptor"),
+            )
+            .expect("Failed to create pipeline");
+        // The left half of the image
+        let quad = [
+            Position([-1.0, -1.0]),
+            Position([0.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([0.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad,
+                &[0u32, 1, 2, 2, 1, 3],
+            ))
+            .expect("Failed to create mesh");
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let cmd_buf = frame
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.swapchain_extent(),
+            )
+            .bind_graphics_pipeline(renderer.get_resource(&pipeline_handle).unwrap())
+            .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        let width = image.width as usize;
+        for (i, px) in image.data.chunks(4).enumerate() {
+            let red = if i % width < width / 2 { 255 } else { 0 };
+            assert_eq!(px, [red, 0, 0, 255], "pixel {}", i);
+        }
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device with validation layers, run with --ignored
+    #[test]
+    #[ignore]
+    fn overlapping_depth_writes_are_validation_clean() {
+        let dir = std::env::temp_dir().join("trekanten_overlapping_depth_writes");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, red, white) = (
+            dir.join("vert.spv"),
+            dir.join("red.spv"),
+            dir.join("white.spv"),
+        );
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&red, RED_SPV_FRAG);
+        write_spirv(&white, WHITE_SPV_FRAG);
+
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            depth: true,
+            validation_errors: Some(ValidationErrors {
+                min_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+                action: ValidationErrorAction::Collect,
+            }),
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        assert!(renderer.render_pass().subpass_has_depth(0));
+
+        let mut pipeline = |frag: &std::path::Path| {
+            renderer
+                .create_resource(
+                    pipeline::GraphicsPipelineDescriptor::builder()
+                        .vertex_shader(&vert)
+                        .fragment_shader(frag)
+                        .vertex_type::<Position>()
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .build()
+                        .expect("Failed to create pipeline descriptor"),
+                )
+                .expect("Failed to create pipeline")
+        };
+        let red_pipeline = pipeline(&red);
+        let white_pipeline = pipeline(&white);
+
+        // The left and right three quarters, at the same depth. With the default LESS compare op,
+        // the quad that is drawn first is kept where they overlap.
+        let quads = [
+            Position([-1.0, -1.0]),
+            Position([0.5, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([0.5, 1.0]),
+            Position([-0.5, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-0.5, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let indices = [0u32, 1, 2, 2, 1, 3];
+        let left = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(&quads[..4], &indices))
+            .expect("Failed to create mesh");
+        let right = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(&quads[4..], &indices))
+            .expect("Failed to create mesh");
+
+        // The second frame writes depth after the first one has, see external_dependency
+        for _ in 0..2 {
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .bind_graphics_pipeline(renderer.get_resource(&red_pipeline).unwrap())
+                .draw_mesh(renderer.get_resource(&left).unwrap())
+                .bind_graphics_pipeline(renderer.get_resource(&white_pipeline).unwrap())
+                .draw_mesh(renderer.get_resource(&right).unwrap())
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer
+                .request_readback(&mut frame)
+                .expect("Failed to request readback");
+            renderer.submit(frame).expect("Failed to submit");
+        }
+
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        let width = image.width as usize;
+        for (i, px) in image.data.chunks(4).enumerate() {
+            let expected = if i % width == width - 1 {
+                [255, 255, 255, 255]
+            } else {
+                [255, 0, 0, 255]
+            };
+            assert_eq!(px, expected, "pixel {}", i);
+        }
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn name_buffer() {
+        let renderer = headless_renderer();
+        let buffer = mem::DeviceBuffer::empty(
+            &renderer.device,
+            64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk_mem::MemoryUsage::GpuOnly,
+        )
+        .expect("Failed to create buffer");
+        renderer
+            .device
+            .set_object_name(*buffer.vk_buffer(), "my vertex buffer")
+            .expect("Failed to name buffer");
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // A window that can be resized between frames
+    struct TestWindow {
+        extent: std::cell::Cell<util::Extent2D>,
+    }
+
+    impl window::Window for TestWindow {
+        fn required_instance_extensions(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn extents(&self) -> util::Extent2D {
+            self.extent.get()
+        }
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn render_frame_resizes() {
+        let extent = util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let resized = util::Extent2D {
+            width: 8,
+            height: 2,
+        };
+        let window = TestWindow {
+            extent: std::cell::Cell::new(extent),
+        };
+        let mut renderer = Renderer::new_headless(extent, RendererConfig::default())
+            .expect("Failed to create headless renderer");
+
+        let mut recorded = 0;
+        for i in 0..4 {
+            if i == 2 {
+                window.extent.set(resized);
+            }
+            renderer
+                .render_frame(&window, |frame, renderer| {
+                    let cmd_buf = frame
+                        .new_command_buffer()?
+                        .begin_render_pass(
+                            renderer.render_pass(),
+                            renderer.framebuffer(frame),
+                            renderer.render_extent(),
+                        )
+                        .end_render_pass()
+                        .end()?;
+                    frame.add_command_buffer(cmd_buf)?;
+                    renderer.request_readback(frame)?;
+                    recorded += 1;
+                    Ok(())
+                })
+                .expect("Failed to render frame");
+
+            let expected = if i < 2 { extent } else { resized };
+            assert_eq!(renderer.swapchain_extent(), expected);
+            let image = renderer.read_framebuffer().expect("Failed to read back");
+            assert_eq!(
+                (image.width, image.height),
+                (expected.width, expected.height)
+            );
+        }
+        assert_eq!(recorded, 4);
+    }
+
+    fn clear_frame(frame: &mut Frame, renderer: &mut Renderer) -> Result<(), RenderError> {
+        let cmd_buf = frame
+            .new_command_buffer()?
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(frame),
+                renderer.render_extent(),
+            )
+            .end_render_pass()
+            .end()?;
+        frame.add_command_buffer(cmd_buf)
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn render_frame_handles_needs_resize() {
+        let mut renderer = headless_renderer();
+        let window = TestWindow {
+            extent: std::cell::Cell::new(renderer.swapchain_extent()),
+        };
+
+        // Acquiring is retried after the resize, and the frame is resized after presenting
+        for injected in [InjectedResize::Acquire, InjectedResize::Present].iter() {
+            renderer.windows.main.injected_resize = Some(*injected);
+            let submitted = renderer.submitted_frames;
+            renderer
+                .render_frame(&window, clear_frame)
+                .expect("Failed to render frame");
+            assert_eq!(renderer.windows.main.injected_resize, None);
+            assert_eq!(renderer.submitted_frames, submitted + 1);
+        }
+
+        renderer
+            .render_frame(&window, clear_frame)
+            .expect("Failed to render frame");
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn render_frame_submits_after_record_error() {
+        let mut renderer = headless_renderer();
+        let window = TestWindow {
+            extent: std::cell::Cell::new(renderer.swapchain_extent()),
+        };
+
+        let submitted = renderer.submitted_frames;
+        let result = renderer.render_frame(&window, |frame, renderer| {
+            clear_frame(frame, renderer)?;
+            renderer.request_readback(frame)?;
+            Err(RenderError::NoReadback)
+        });
+        assert!(matches!(result, Err(RenderError::NoReadback)));
+        // The acquired image was submitted, without what was recorded
+        assert_eq!(renderer.submitted_frames, submitted + 1);
+        assert!(matches!(
+            renderer.read_framebuffer(),
+            Err(RenderError::NoReadback)
+        ));
+
+        // Rendering continues with the next frame
+        renderer
+            .render_frame(&window, |frame, renderer| {
+                clear_frame(frame, renderer)?;
+                renderer.request_readback(frame)
+            })
+            .expect("Failed to render frame");
+        assert_eq!(renderer.submitted_frames, submitted + 2);
+        renderer.read_framebuffer().expect("Failed to read back");
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // The uniform buffer is what create_descriptor_set binds, it doesn't change the color
+    static SAMPLE_CENTRE_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0) uniform Params {
+            vec4 scale;
+        } params;
+        layout(set = 0, binding = 1) uniform sampler2D offscreen;
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = texture(offscreen, vec2(0.5)) * params.scale;
+        }
+    ",
+        frag
+    );
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn offscreen_target_is_sampled() {
+        let dir = std::env::temp_dir().join("trekanten_offscreen_target_is_sampled");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, red, sample) = (
+            dir.join("vert.spv"),
+            dir.join("red.spv"),
+            dir.join("sample.spv"),
+        );
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&red, RED_SPV_FRAG);
+        write_spirv(&sample, SAMPLE_CENTRE_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        let target = renderer
+            .create_offscreen_target(
+                renderer.swapchain_extent(),
+                vk::Format::R8G8B8A8_UNORM,
+                true,
+            )
+            .expect("Failed to create offscreen target");
+
+        let descriptor = |frag: &std::path::Path, depth_test| {
+            pipeline::GraphicsPipelineDescriptor::builder()
+                .vertex_shader(&vert)
+                .fragment_shader(frag)
+                .vertex_type::<Position>()
+                .cull_mode(vk::CullModeFlags::NONE)
+                .depth_test_enable(depth_test)
+                .build()
+                .expect("Failed to create pipeline descriptor")
+        };
+        // Against the depth buffer of the target
+        let offscreen_pipeline = renderer
+            .create_offscreen_pipeline(&descriptor(&red, true), &target)
+            .expect("Failed to create offscreen pipeline");
+        let sample_pipeline = renderer
+            .create_resource(descriptor(&sample, false))
+            .expect("Failed to create pipeline");
+
+        let params = [[1.0f32; 4]];
+        let params_handle = renderer
+            .create_resource(uniform::UniformBufferDescriptor::from_slice(&params))
+            .expect("Failed to create uniform buffer");
+        let black = ::image::RgbaImage::from_pixel(2, 2, ::image::Rgba([0, 0, 0, 255]));
+        let texture_handle = renderer
+            .create_resource(texture::TextureDescriptor::from_rgba_image(
+                &black,
+                vk::Format::R8G8B8A8_UNORM,
+                false,
+            ))
+            .expect("Failed to create texture");
+        let desc_set_handle = renderer
+            .create_descriptor_set(&sample_pipeline, &params_handle, &texture_handle)
+            .expect("Failed to create descriptor set");
+        renderer
+            .bind_offscreen_target(&desc_set_handle, 1, &target)
+            .expect("Failed to bind offscreen target");
+
+        // A red quad over the middle of the target, the rest is cleared to black. The second
+        // pass covers the whole image with the colour at the centre of the target.
+        let quads = [
+            Position([-0.5, -0.5]),
+            Position([0.5, -0.5]),
+            Position([-0.5, 0.5]),
+            Position([0.5, 0.5]),
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let indices = [0u32, 1, 2, 2, 1, 3];
+        let centre_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(&quads[..4], &indices))
+            .expect("Failed to create mesh");
+        let full_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(&quads[4..], &indices))
+            .expect("Failed to create mesh");
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let cmd_buf = target
+            .begin(
+                frame
+                    .new_command_buffer()
+                    .expect("Failed to create command buffer"),
+            )
+            .bind_graphics_pipeline(renderer.get_resource(&offscreen_pipeline).unwrap())
+            .draw_mesh(renderer.get_resource(&centre_handle).unwrap());
+        let sample_pipeline = renderer.get_resource(&sample_pipeline).unwrap();
+        let cmd_buf = target
+            .end(cmd_buf)
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.swapchain_extent(),
+            )
+            .bind_graphics_pipeline(sample_pipeline)
+            .bind_descriptor_set(
+                renderer.get_descriptor_set(&desc_set_handle).unwrap(),
+                sample_pipeline,
+            )
+            .draw_mesh(renderer.get_resource(&full_handle).unwrap())
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+        assert!(
+            image.data.chunks(4).all(|px| px == [255, 0, 0, 255]),
+            "{:?}",
+            image.data
+        );
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
     }
 }
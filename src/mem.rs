@@ -1,6 +1,8 @@
 use ash::vk;
 
-use vk_mem::{Allocation, AllocationCreateInfo, AllocationInfo, MemoryUsage};
+use vk_mem::{
+    Allocation, AllocationCreateFlags, AllocationCreateInfo, AllocationInfo, MemoryUsage,
+};
 
 use thiserror::Error;
 
@@ -25,6 +27,16 @@ pub enum MemoryError {
     CopySubmit(#[from] QueueError),
     #[error("memory mapping failed {0}")]
     MemoryMapping(vk_mem::Error),
+    #[error("size mismatch, expected {expected} bytes but got {got}")]
+    SizeMismatch { expected: usize, got: usize },
+    #[error("element {index} is out of range for a buffer with {n_elems} elements")]
+    ElementOutOfRange { index: usize, n_elems: usize },
+    #[error("buffer is neither host visible nor a transfer source and can't be read back")]
+    ReadbackUnsupported,
+    #[error("{got} bytes don't fit in a buffer of {capacity} bytes")]
+    CapacityExceeded { capacity: usize, got: usize },
+    #[error("{got} bytes is not a whole number of elements of {elem_size} bytes")]
+    PartialElement { elem_size: usize, got: usize },
 }
 
 pub struct DeviceBuffer {
@@ -32,20 +44,24 @@ pub struct DeviceBuffer {
     vk_buffer: vk::Buffer,
     allocation: Allocation,
     size: usize,
+    usage: vk::BufferUsageFlags,
     _allocation_info: AllocationInfo,
+    mapped: Option<*mut u8>,
+    host_visible: bool,
+    coherent: bool,
 }
 
 impl DeviceBuffer {
-    pub fn empty(
+    fn create(
         device: &Device,
         size: usize,
         buffer_usage_flags: vk::BufferUsageFlags,
-        mem_usage: MemoryUsage,
+        allocation_create_info: &AllocationCreateInfo,
     ) -> Result<Self, MemoryError> {
-        log::trace!("Creating empty DeviceBuffer with:");
+        log::trace!("Creating DeviceBuffer with:");
         log::trace!("\tsize: {}", size);
         log::trace!("\tusage: {:?}", buffer_usage_flags);
-        log::trace!("\tmemory usage: {:?}", mem_usage);
+        log::trace!("\tallocation: {:?}", allocation_create_info);
         let buffer_info = vk::BufferCreateInfo {
             size: size as u64,
             usage: buffer_usage_flags,
@@ -53,26 +69,79 @@ impl DeviceBuffer {
             ..Default::default()
         };
 
-        let allocation_create_info = AllocationCreateInfo {
-            usage: mem_usage,
-            ..Default::default()
-        };
         let allocator = device.allocator();
 
-        let (vk_buffer, allocation, _allocation_info) = allocator
-            .create_buffer(&buffer_info, &allocation_create_info)
+        let (vk_buffer, allocation, allocation_info) = allocator
+            .create_buffer(&buffer_info, allocation_create_info)
             .map_err(MemoryError::BufferCreation)?;
-        log::trace!("Allocation succeeded: {:?}", &_allocation_info);
+        log::trace!("Allocation succeeded: {:?}", &allocation_info);
+        device.name_object(
+            vk_buffer,
+            &format!("{:?} buffer ({} bytes)", buffer_usage_flags, size),
+        );
+
+        let memory_type = allocation_info.get_memory_type() as usize;
+        let property_flags = device.memory_properties().memory_types[memory_type].property_flags;
+        let host_visible = property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let coherent = property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+
+        let mapped = if allocation_create_info
+            .flags
+            .contains(AllocationCreateFlags::MAPPED)
+        {
+            let ptr = allocation_info.get_mapped_data();
+            assert!(
+                !ptr.is_null(),
+                "Persistently mapped allocation has no mapping"
+            );
+            Some(ptr)
+        } else {
+            None
+        };
 
         Ok(Self {
             allocator,
             vk_buffer,
             allocation,
-            _allocation_info,
+            _allocation_info: allocation_info,
             size,
+            usage: buffer_usage_flags,
+            mapped,
+            host_visible,
+            coherent,
         })
     }
 
+    pub fn empty(
+        device: &Device,
+        size: usize,
+        buffer_usage_flags: vk::BufferUsageFlags,
+        mem_usage: MemoryUsage,
+    ) -> Result<Self, MemoryError> {
+        let allocation_create_info = AllocationCreateInfo {
+            usage: mem_usage,
+            ..Default::default()
+        };
+
+        Self::create(device, size, buffer_usage_flags, &allocation_create_info)
+    }
+
+    /// Create a host-visible buffer that stays mapped for its whole lifetime. Updates through
+    /// `update_data_at` write directly into the mapping instead of mapping/unmapping each call.
+    pub fn persistent_mapped(
+        device: &Device,
+        size: usize,
+        buffer_usage_flags: vk::BufferUsageFlags,
+    ) -> Result<Self, MemoryError> {
+        let allocation_create_info = AllocationCreateInfo {
+            usage: MemoryUsage::CpuToGpu,
+            flags: AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+
+        Self::create(device, size, buffer_usage_flags, &allocation_create_info)
+    }
+
     pub fn staging_empty(device: &Device, size: usize) -> Result<Self, MemoryError> {
         DeviceBuffer::empty(
             device,
@@ -95,37 +164,77 @@ impl DeviceBuffer {
             let src = data.as_ptr() as *const u8;
             std::ptr::copy_nonoverlapping::<u8>(src, dst, size);
         }
+        // Unmap even if the flush failed
+        let flushed = staging.flush_range(0, size);
         allocator
             .unmap_memory(&staging.allocation)
             .map_err(MemoryError::MemoryMapping)?;
+        flushed?;
 
         Ok(staging)
     }
 
+    fn staging_mapped(device: &Device, size: usize) -> Result<Self, MemoryError> {
+        let allocation_create_info = AllocationCreateInfo {
+            usage: MemoryUsage::CpuOnly,
+            flags: AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+
+        Self::create(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            &allocation_create_info,
+        )
+    }
+
+    /// A staging buffer with `data` at offset 0. It is taken from `staging_pool` if there is one,
+    /// and then needs to be returned with `unstage` once the copy has finished.
+    fn stage(
+        device: &Device,
+        data: &[u8],
+        staging_pool: Option<&mut StagingPool>,
+    ) -> Result<Self, MemoryError> {
+        match staging_pool {
+            Some(pool) => pool.acquire_with_data(device, data),
+            None => Self::staging_with_data(device, data),
+        }
+    }
+
+    fn unstage(self, staging_pool: Option<&mut StagingPool>) {
+        if let Some(pool) = staging_pool {
+            pool.release(self);
+        }
+    }
+
+    /// A host-visible buffer to copy data from the device into, see `read_data`
+    pub fn readback_empty(device: &Device, size: usize) -> Result<Self, MemoryError> {
+        DeviceBuffer::empty(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuToCpu,
+        )
+    }
+
+    /// `queue` and `command_pool` are expected to be for the same queue family, e.g. the transfer
+    /// queue. If that is not the graphics queue family, ownership of the buffer is transferred to
+    /// the graphics queue family before returning. The staging buffer is taken from
+    /// `staging_pool`, if given, instead of being allocated for this upload only.
     pub fn device_local_by_staging(
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
         usage: vk::BufferUsageFlags,
         data: &[u8],
     ) -> Result<Self, MemoryError> {
-        let staging = Self::staging_with_data(device, data)?;
+        let mut uploader = Uploader::begin(device, queue, command_pool, staging_pool)?;
+        let buffer = uploader.upload_buffer(usage, data)?;
+        uploader.submit_and_wait()?;
 
-        let dst_buffer = Self::empty(
-            device,
-            staging.size(),
-            vk::BufferUsageFlags::TRANSFER_DST | usage,
-            MemoryUsage::GpuOnly,
-        )?;
-
-        let cmd_buf = command_pool
-            .begin_single_submit()?
-            .copy_buffer(staging.vk_buffer(), dst_buffer.vk_buffer(), staging.size())
-            .end()?;
-
-        queue.submit_and_wait(&cmd_buf)?;
-
-        Ok(dst_buffer)
+        Ok(buffer)
     }
 
     pub fn vk_buffer(&self) -> &vk::Buffer {
@@ -134,24 +243,172 @@ impl DeviceBuffer {
 
     pub fn update_data_at(&mut self, data: &[u8], offset: usize) -> Result<(), MemoryError> {
         let size = data.len();
+        assert!(offset + size <= self.size());
 
-        let dst_base = self
-            .allocator
-            .map_memory(&self.allocation)
-            .map_err(MemoryError::MemoryMapping)?;
+        let dst_base = match self.mapped {
+            Some(ptr) => ptr,
+            None => self
+                .allocator
+                .map_memory(&self.allocation)
+                .map_err(MemoryError::MemoryMapping)?,
+        };
 
         let src = data.as_ptr() as *const u8;
         unsafe {
-            assert!(offset + size <= self.size());
             let dst = dst_base.add(offset);
             std::ptr::copy_nonoverlapping::<u8>(src, dst, size);
         }
 
+        // Has to happen while the memory is mapped. Unmap even if it fails.
+        let flushed = self.flush_range(offset, size);
+
+        if self.mapped.is_none() {
+            self.allocator
+                .unmap_memory(&self.allocation)
+                .map_err(MemoryError::MemoryMapping)?;
+        }
+
+        flushed
+    }
+
+    /// Write `data` to the start of the buffer. Host-visible buffers are written directly, others
+    /// through a staging buffer, which blocks until the copy has finished. The buffer must not be
+    /// in use on the device. `queue` has to be of the queue family that owns the buffer, e.g. the
+    /// graphics queue, and `command_pool` for the same family.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        data: &[u8],
+    ) -> Result<(), MemoryError> {
+        if data.len() > self.size {
+            return Err(MemoryError::CapacityExceeded {
+                capacity: self.size,
+                got: data.len(),
+            });
+        }
+
+        if self.host_visible {
+            return self.update_data_at(data, 0);
+        }
+
+        let mut uploader = Uploader::begin(device, queue, command_pool, None)?;
+        uploader.update_buffer(self, data)?;
+        uploader.submit_and_wait()
+    }
+
+    /// Make host writes to `offset..offset + size` visible to the device. Only needed if the
+    /// memory is not HOST_COHERENT, otherwise this does nothing. The buffer has to be mapped,
+    /// e.g. one from `persistent_mapped`.
+    pub fn flush_range(&self, offset: usize, size: usize) -> Result<(), MemoryError> {
+        if self.coherent {
+            return Ok(());
+        }
+
         self.allocator
-            .unmap_memory(&self.allocation)
-            .map_err(MemoryError::MemoryMapping)?;
+            .flush_allocation(&self.allocation, offset, size)
+            .map_err(MemoryError::MemoryMapping)
+    }
 
-        Ok(())
+    /// Make device writes to `offset..offset + size` visible to the host, the counterpart of
+    /// `flush_range`. The device writes need to have completed.
+    pub fn invalidate_range(&self, offset: usize, size: usize) -> Result<(), MemoryError> {
+        if self.coherent {
+            return Ok(());
+        }
+
+        self.allocator
+            .invalidate_allocation(&self.allocation, offset, size)
+            .map_err(MemoryError::MemoryMapping)
+    }
+
+    /// Copy the contents of a host-visible buffer to a Vec. Any device writes to it need to have
+    /// completed.
+    pub fn read_data(&self) -> Result<Vec<u8>, MemoryError> {
+        let size = self.size();
+        let mut data = vec![0u8; size];
+
+        let src = match self.mapped {
+            Some(ptr) => ptr,
+            None => self
+                .allocator
+                .map_memory(&self.allocation)
+                .map_err(MemoryError::MemoryMapping)?,
+        };
+
+        // Unmap even if the invalidation failed
+        let invalidated = self.invalidate_range(0, size).map(|()| unsafe {
+            std::ptr::copy_nonoverlapping::<u8>(src, data.as_mut_ptr(), size);
+        });
+
+        if self.mapped.is_none() {
+            self.allocator
+                .unmap_memory(&self.allocation)
+                .map_err(MemoryError::MemoryMapping)?;
+        }
+
+        invalidated.map(|()| data)
+    }
+
+    /// Copy the contents of the buffer to a Vec, through a staging buffer unless it is host
+    /// visible. Device-local buffers need TRANSFER_SRC usage, which buffers from
+    /// `Uploader::upload_buffer` have.
+    ///
+    /// Blocks until the copy has finished. Any device writes to the buffer need to have completed
+    /// for host-visible buffers, and to have been submitted for others. `queue` has to be of the
+    /// queue family that owns the buffer, e.g. the graphics queue, and `command_pool` for the
+    /// same family.
+    pub fn read_back(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+    ) -> Result<Vec<u8>, MemoryError> {
+        if self.host_visible {
+            return self.read_data();
+        }
+
+        if !self.usage.contains(vk::BufferUsageFlags::TRANSFER_SRC) {
+            return Err(MemoryError::ReadbackUnsupported);
+        }
+
+        let staging = DeviceBuffer::readback_empty(device, self.size)?;
+        let barrier = |vk_buffer, src_access_mask, dst_access_mask| vk::BufferMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: vk_buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        let cmd_buf = command_pool
+            .begin_single_submit()?
+            .buffer_barrier(
+                &barrier(
+                    self.vk_buffer,
+                    vk::AccessFlags::MEMORY_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                ),
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+            .copy_buffer(&self.vk_buffer, staging.vk_buffer(), self.size)
+            .buffer_barrier(
+                &barrier(
+                    *staging.vk_buffer(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::HOST_READ,
+                ),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+            )
+            .end()?;
+        queue.submit_and_wait(&cmd_buf)?;
+
+        staging.read_data()
     }
 
     pub fn size(&self) -> usize {
@@ -170,7 +427,130 @@ impl std::ops::Drop for DeviceBuffer {
     }
 }
 
-fn transition_image_layout(
+/// Staging buffers are at least this big so that small uploads can share them
+const MIN_STAGING_SIZE: usize = 64 * 1024;
+
+/// Rounded up to a power of two, so that an upload can reuse a buffer from an earlier, slightly
+/// smaller one
+fn staging_capacity(size: usize) -> usize {
+    size.max(MIN_STAGING_SIZE).next_power_of_two()
+}
+
+// The bookkeeping of `StagingPool`, separate from the buffers so that it can be tested without a
+// device.
+struct ReusePool<B> {
+    // (capacity, buffer)
+    free: Vec<(usize, B)>,
+    n_allocations: usize,
+}
+
+impl<B> ReusePool<B> {
+    fn new() -> Self {
+        Self {
+            free: Vec::new(),
+            n_allocations: 0,
+        }
+    }
+
+    /// The smallest free buffer that fits `size`, or a new one from `alloc`
+    fn take<E>(
+        &mut self,
+        size: usize,
+        alloc: impl FnOnce(usize) -> Result<B, E>,
+    ) -> Result<(usize, B), E> {
+        let best_fit = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, (capacity, _))| *capacity >= size)
+            .min_by_key(|(_, (capacity, _))| *capacity)
+            .map(|(i, _)| i);
+
+        if let Some(i) = best_fit {
+            return Ok(self.free.swap_remove(i));
+        }
+
+        let capacity = staging_capacity(size);
+        let buffer = alloc(capacity)?;
+        self.n_allocations += 1;
+        Ok((capacity, buffer))
+    }
+
+    fn give_back(&mut self, capacity: usize, buffer: B) {
+        self.free.push((capacity, buffer));
+    }
+}
+
+/// Persistently mapped staging buffers that are reused between uploads instead of being allocated
+/// and freed for each one. Buffer sizes are powers of two, at least `MIN_STAGING_SIZE`.
+pub struct StagingPool {
+    buffers: ReusePool<DeviceBuffer>,
+}
+
+impl StagingPool {
+    pub fn new() -> Self {
+        Self {
+            buffers: ReusePool::new(),
+        }
+    }
+
+    fn acquire_with_data(
+        &mut self,
+        device: &Device,
+        data: &[u8],
+    ) -> Result<DeviceBuffer, MemoryError> {
+        let (_, mut buffer) = self.buffers.take(data.len(), |capacity| {
+            DeviceBuffer::staging_mapped(device, capacity)
+        })?;
+
+        if let Err(e) = buffer.update_data_at(data, 0) {
+            self.release(buffer);
+            return Err(e);
+        }
+
+        Ok(buffer)
+    }
+
+    /// The copy from `buffer` has to have finished
+    fn release(&mut self, buffer: DeviceBuffer) {
+        self.buffers.give_back(buffer.size(), buffer);
+    }
+
+    /// How many staging buffers have been allocated in total
+    pub fn allocation_count(&self) -> usize {
+        self.buffers.n_allocations
+    }
+}
+
+impl Default for StagingPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Release (on `src_family`) or acquire (on `dst_family`) half of a queue family ownership
+/// transfer of a whole buffer. The same barrier, apart from the access masks, needs to be recorded
+/// on both queues.
+pub fn queue_ownership_barrier(
+    vk_buffer: &vk::Buffer,
+    src_family: u32,
+    dst_family: u32,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::BufferMemoryBarrier {
+    vk::BufferMemoryBarrier {
+        src_access_mask,
+        dst_access_mask,
+        src_queue_family_index: src_family,
+        dst_queue_family_index: dst_family,
+        buffer: *vk_buffer,
+        offset: 0,
+        size: vk::WHOLE_SIZE,
+        ..Default::default()
+    }
+}
+
+pub fn transition_image_layout(
     cmd_buf: CommandBuffer,
     vk_image: &vk::Image,
     mip_levels: u32,
@@ -192,12 +572,48 @@ fn transition_image_layout(
             vk::AccessFlags::TRANSFER_WRITE,
             vk::PipelineStageFlags::TRANSFER,
         ),
+        // Updating the contents of a sampled image
+        (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
         (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
             vk::AccessFlags::TRANSFER_WRITE,
             vk::PipelineStageFlags::TRANSFER,
             vk::AccessFlags::SHADER_READ,
             vk::PipelineStageFlags::FRAGMENT_SHADER,
         ),
+        // For reading back the swapchain image of a frame in its own submission, before it is
+        // presented. Waits for the render pass, or the blit of a scaled frame, to write it. All
+        // commands, as the final layout transition of a render pass happens in its implicit
+        // dependency on BOTTOM_OF_PIPE.
+        (vk::ImageLayout::PRESENT_SRC_KHR, vk::ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR) => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        // Same as above, for reading back an offscreen target
+        (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
         _ => unimplemented!(),
     };
 
@@ -230,7 +646,6 @@ fn generate_mipmaps(
     extent: &util::Extent2D,
     mip_levels: u32,
 ) -> CommandBuffer {
-    assert!(cmd_buf.is_started());
     let aspect_mask = vk::ImageAspectFlags::COLOR;
 
     let mut barrier = vk::ImageMemoryBarrier {
@@ -402,23 +817,228 @@ impl DeviceImage {
         })
     }
 
+    /// Create a device local image with a single mip level
+    pub fn device_local_by_staging(
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
+        extent: util::Extent2D,
+        format: util::Format,
+        data: &[u8],
+    ) -> Result<Self, MemoryError> {
+        Self::device_local_mipmapped(
+            device,
+            queue,
+            command_pool,
+            staging_pool,
+            extent,
+            format,
+            1,
+            data,
+        )
+    }
+
     /// Create a device local image, generating mipmaps in the process
     pub fn device_local_mipmapped(
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
         extent: util::Extent2D,
         format: util::Format,
         mip_levels: u32,
         data: &[u8],
     ) -> Result<Self, MemoryError> {
-        let staging = DeviceBuffer::staging_with_data(device, data)?;
-        // Both src & dst as we use one mip level to create the next
-        let usage = vk::ImageUsageFlags::TRANSFER_SRC
-            | vk::ImageUsageFlags::TRANSFER_DST
-            | vk::ImageUsageFlags::SAMPLED;
-        let dst_image = Self::empty_2d(
+        let mut uploader = Uploader::begin(device, queue, command_pool, staging_pool)?;
+        let image = uploader.upload_image(extent, format, mip_levels, data)?;
+        uploader.submit_and_wait()?;
+
+        Ok(image)
+    }
+
+    pub fn vk_image(&self) -> &vk::Image {
+        &self.vk_image
+    }
+
+    /// Size of the allocation backing the image, in bytes
+    pub fn size(&self) -> usize {
+        self._allocation_info.get_size()
+    }
+}
+
+impl std::ops::Drop for DeviceImage {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .allocator
+            .destroy_image(self.vk_image, &self.allocation)
+        {
+            log::error!("Failed to destroy image: {}", e);
+        }
+    }
+}
+
+/// Records the copies and layout transitions of several uploads into one command buffer that is
+/// submitted once, instead of submitting and waiting for each resource. The returned buffers and
+/// images must not be used before `submit_and_wait` has returned.
+///
+/// `queue` and `command_pool` are expected to be for the same queue family. Images can only be
+/// uploaded on a graphics queue, as mipmaps are generated with blits.
+pub struct Uploader<'a> {
+    device: &'a Device,
+    queue: &'a Queue,
+    command_pool: &'a CommandPool,
+    staging_pool: Option<&'a mut StagingPool>,
+    // Only None while a command is being recorded
+    cmd_buf: Option<CommandBuffer>,
+    // Kept alive until the copies have finished
+    staging: Vec<DeviceBuffer>,
+    // Buffers released by the queue family of `command_pool`, to be acquired by the graphics one
+    acquire_barriers: Vec<vk::BufferMemoryBarrier>,
+}
+
+impl<'a> Uploader<'a> {
+    pub fn begin(
+        device: &'a Device,
+        queue: &'a Queue,
+        command_pool: &'a CommandPool,
+        staging_pool: Option<&'a mut StagingPool>,
+    ) -> Result<Self, MemoryError> {
+        let cmd_buf = command_pool.begin_single_submit()?;
+
+        Ok(Self {
             device,
+            queue,
+            command_pool,
+            staging_pool,
+            cmd_buf: Some(cmd_buf),
+            staging: Vec::new(),
+            acquire_barriers: Vec::new(),
+        })
+    }
+
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    fn record(&mut self, f: impl FnOnce(CommandBuffer) -> CommandBuffer) {
+        let cmd_buf = self.cmd_buf.take().expect("Missing upload command buffer");
+        self.cmd_buf = Some(f(cmd_buf));
+    }
+
+    fn stage(&mut self, data: &[u8]) -> Result<vk::Buffer, MemoryError> {
+        let staging = DeviceBuffer::stage(self.device, data, self.staging_pool.as_deref_mut())?;
+        let vk_buffer = *staging.vk_buffer();
+        self.staging.push(staging);
+        Ok(vk_buffer)
+    }
+
+    /// A device local buffer with `usage` (and TRANSFER_DST) that will contain `data`. It is
+    /// also a transfer source, see `DeviceBuffer::read_back`.
+    pub fn upload_buffer(
+        &mut self,
+        usage: vk::BufferUsageFlags,
+        data: &[u8],
+    ) -> Result<DeviceBuffer, MemoryError> {
+        let staging = self.stage(data)?;
+        let dst_buffer = DeviceBuffer::empty(
+            self.device,
+            data.len(),
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC | usage,
+            MemoryUsage::GpuOnly,
+        )?;
+
+        let src_family = self.command_pool.queue_family().index;
+        let dst_family = self.device.graphics_queue_family().index;
+        let vk_buffer = *dst_buffer.vk_buffer();
+        self.record(|cmd_buf| cmd_buf.copy_buffer(&staging, &vk_buffer, data.len()));
+
+        if src_family != dst_family {
+            let release = queue_ownership_barrier(
+                &vk_buffer,
+                src_family,
+                dst_family,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::empty(),
+            );
+            self.record(|cmd_buf| {
+                cmd_buf.buffer_barrier(
+                    &release,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                )
+            });
+            self.acquire_barriers.push(queue_ownership_barrier(
+                &vk_buffer,
+                src_family,
+                dst_family,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::MEMORY_READ,
+            ));
+        }
+
+        Ok(dst_buffer)
+    }
+
+    /// Copy `data` to the start of a buffer created by `upload_buffer`. The copy waits for the
+    /// commands submitted before on the same queue, e.g. frames that read the buffer, and the
+    /// ones submitted after it see the new data.
+    pub fn update_buffer(&mut self, buffer: &DeviceBuffer, data: &[u8]) -> Result<(), MemoryError> {
+        assert!(data.len() <= buffer.size());
+        assert!(buffer.usage.contains(vk::BufferUsageFlags::TRANSFER_DST));
+        let staging = self.stage(data)?;
+        let vk_buffer = *buffer.vk_buffer();
+        // Plain memory barriers, the buffer stays with the same queue family
+        let before = queue_ownership_barrier(
+            &vk_buffer,
+            vk::QUEUE_FAMILY_IGNORED,
+            vk::QUEUE_FAMILY_IGNORED,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+        let after = queue_ownership_barrier(
+            &vk_buffer,
+            vk::QUEUE_FAMILY_IGNORED,
+            vk::QUEUE_FAMILY_IGNORED,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::MEMORY_READ,
+        );
+        self.record(|cmd_buf| {
+            cmd_buf
+                .buffer_barrier(
+                    &before,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::PipelineStageFlags::TRANSFER,
+                )
+                .copy_buffer(&staging, &vk_buffer, data.len())
+                .buffer_barrier(
+                    &after,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                )
+        });
+
+        Ok(())
+    }
+
+    /// A sampled, device local image that will contain `data` in mip level 0. If `mip_levels` is
+    /// more than 1, the rest are generated from it. The image ends up in SHADER_READ_ONLY_OPTIMAL.
+    pub fn upload_image(
+        &mut self,
+        extent: util::Extent2D,
+        format: util::Format,
+        mip_levels: u32,
+        data: &[u8],
+    ) -> Result<DeviceImage, MemoryError> {
+        let staging = self.stage(data)?;
+        let mut usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        if mip_levels > 1 {
+            // One mip level is used to create the next
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
+        let dst_image = DeviceImage::empty_2d(
+            self.device,
             extent,
             format,
             usage,
@@ -427,38 +1047,293 @@ impl DeviceImage {
             vk::SampleCountFlags::TYPE_1,
         )?;
 
-        // Transitioned to SHADER_READ_ONLY_OPTIMAL during mipmap generation
-        let cmd_buf = command_pool.begin_single_submit()?;
-
-        let cmd_buf = transition_image_layout(
-            cmd_buf,
-            &dst_image.vk_image,
+        self.record_image_copy(
+            staging,
+            *dst_image.vk_image(),
+            extent,
+            format,
             mip_levels,
-            format.into(),
             vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        )
-        .copy_buffer_to_image(&staging.vk_buffer, dst_image.vk_image(), &extent);
+        );
 
-        let cmd_buf = generate_mipmaps(cmd_buf, dst_image.vk_image(), &extent, mip_levels).end()?;
+        Ok(dst_image)
+    }
 
-        queue.submit_and_wait(&cmd_buf)?;
+    /// A sampled, device local image with `data` already in a block-compressed `format`, so no
+    /// mipmaps are generated. Mip level `i` is copied from `levels[i]`, which is its extent and
+    /// offset in `data`. The image ends up in SHADER_READ_ONLY_OPTIMAL.
+    pub fn upload_compressed_image(
+        &mut self,
+        format: util::Format,
+        levels: &[(util::Extent2D, u64)],
+        data: &[u8],
+    ) -> Result<DeviceImage, MemoryError> {
+        assert!(!levels.is_empty());
+        let staging = self.stage(data)?;
+        let mip_levels = levels.len() as u32;
+        let dst_image = DeviceImage::empty_2d(
+            self.device,
+            levels[0].0,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            MemoryUsage::GpuOnly,
+            mip_levels,
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+
+        let vk_image = *dst_image.vk_image();
+        self.record(|cmd_buf| {
+            // Rows are tightly packed blocks, which the copy derives from the format
+            let cmd_buf = levels.iter().enumerate().fold(
+                transition_image_layout(
+                    cmd_buf,
+                    &vk_image,
+                    mip_levels,
+                    format.into(),
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                ),
+                |cmd_buf, (mip_level, (extent, offset))| {
+                    cmd_buf.copy_buffer_to_image(
+                        &staging,
+                        &vk_image,
+                        extent,
+                        *offset,
+                        mip_level as u32,
+                    )
+                },
+            );
+            transition_image_layout(
+                cmd_buf,
+                &vk_image,
+                mip_levels,
+                format.into(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        });
 
         Ok(dst_image)
     }
 
-    pub fn vk_image(&self) -> &vk::Image {
-        &self.vk_image
+    /// Replace the contents of an image created by `upload_image`, with the same parameters.
+    /// The mip levels are generated again. The image must not be in use on the device until the
+    /// uploader has been submitted.
+    pub fn update_image(
+        &mut self,
+        image: &DeviceImage,
+        extent: util::Extent2D,
+        format: util::Format,
+        mip_levels: u32,
+        data: &[u8],
+    ) -> Result<(), MemoryError> {
+        let staging = self.stage(data)?;
+        self.record_image_copy(
+            staging,
+            *image.vk_image(),
+            extent,
+            format,
+            mip_levels,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        Ok(())
+    }
+
+    // Copy to mip level 0 and leave the image in SHADER_READ_ONLY_OPTIMAL
+    fn record_image_copy(
+        &mut self,
+        staging: vk::Buffer,
+        vk_image: vk::Image,
+        extent: util::Extent2D,
+        format: util::Format,
+        mip_levels: u32,
+        old_layout: vk::ImageLayout,
+    ) {
+        self.record(|cmd_buf| {
+            let cmd_buf = transition_image_layout(
+                cmd_buf,
+                &vk_image,
+                mip_levels,
+                format.into(),
+                old_layout,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            )
+            .copy_buffer_to_image(&staging, &vk_image, &extent, 0, 0);
+
+            // Transitioned to SHADER_READ_ONLY_OPTIMAL during mipmap generation
+            if mip_levels > 1 {
+                generate_mipmaps(cmd_buf, &vk_image, &extent, mip_levels)
+            } else {
+                transition_image_layout(
+                    cmd_buf,
+                    &vk_image,
+                    mip_levels,
+                    format.into(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+            }
+        });
+    }
+
+    /// Submit all recorded uploads at once and wait for them to finish
+    pub fn submit_and_wait(mut self) -> Result<(), MemoryError> {
+        let cmd_buf = self
+            .cmd_buf
+            .take()
+            .expect("Missing upload command buffer")
+            .end()?;
+        self.queue.submit_and_wait(&cmd_buf)?;
+
+        for staging in self.staging.drain(..) {
+            staging.unstage(self.staging_pool.as_deref_mut());
+        }
+
+        if !self.acquire_barriers.is_empty() {
+            // The wait above covers the execution dependency, the acquire only needs to happen
+            // before any use on the graphics queue.
+            let gfx_command_pool = CommandPool::graphics(self.device)?;
+            let cmd_buf = self
+                .acquire_barriers
+                .iter()
+                .fold(gfx_command_pool.begin_single_submit()?, |cmd_buf, b| {
+                    cmd_buf.buffer_barrier(
+                        b,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        vk::PipelineStageFlags::ALL_COMMANDS,
+                    )
+                })
+                .end()?;
+            self.device.graphics_queue().submit_and_wait(&cmd_buf)?;
+        }
+
+        Ok(())
     }
 }
 
-impl std::ops::Drop for DeviceImage {
-    fn drop(&mut self) {
-        if let Err(e) = self
-            .allocator
-            .destroy_image(self.vk_image, &self.allocation)
-        {
-            log::error!("Failed to destroy image: {}", e);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command;
+    use crate::test_util::headless_renderer;
+
+    #[test]
+    fn staging_capacities() {
+        assert_eq!(staging_capacity(1), MIN_STAGING_SIZE);
+        assert_eq!(staging_capacity(MIN_STAGING_SIZE), MIN_STAGING_SIZE);
+        assert_eq!(staging_capacity(MIN_STAGING_SIZE + 1), 2 * MIN_STAGING_SIZE);
+    }
+
+    #[test]
+    fn small_uploads_reuse_staging() {
+        let mut pool = ReusePool::<Vec<u8>>::new();
+        let alloc = |capacity: usize| -> Result<Vec<u8>, ()> { Ok(vec![0; capacity]) };
+
+        for i in 0..100 {
+            let data = vec![i as u8; 256 + i * 16];
+            let (capacity, mut buffer) = pool.take(data.len(), alloc).unwrap();
+            assert!(capacity >= data.len());
+            buffer[..data.len()].copy_from_slice(&data);
+            pool.give_back(capacity, buffer);
+        }
+
+        assert_eq!(pool.n_allocations, 1);
+
+        // Two uploads in flight at once and a bigger one need their own buffers
+        let a = pool.take(16, alloc).unwrap();
+        let b = pool.take(16, alloc).unwrap();
+        let c = pool.take(MIN_STAGING_SIZE * 3, alloc).unwrap();
+        assert_eq!(c.0, MIN_STAGING_SIZE * 4);
+        assert_eq!(pool.n_allocations, 3);
+        pool.give_back(c.0, c.1);
+        pool.give_back(b.0, b.1);
+        pool.give_back(a.0, a.1);
+
+        // The best fit is picked, the big buffer is kept for big uploads
+        let (capacity, _) = pool.take(16, alloc).unwrap();
+        assert_eq!(capacity, MIN_STAGING_SIZE);
+        assert_eq!(pool.n_allocations, 3);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn buffer_read_back() {
+        let renderer = headless_renderer();
+        let device = &renderer.device;
+        let pattern = (0..=255u8).cycle().take(1000).collect::<Vec<u8>>();
+
+        // Owned by the graphics queue family after the upload
+        let device_local = DeviceBuffer::device_local_by_staging(
+            device,
+            device.util_queue(),
+            &renderer.util_command_pool,
+            None,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &pattern,
+        )
+        .expect("Failed to create buffer");
+        let command_pool =
+            command::CommandPool::graphics(device).expect("Failed to create command pool");
+        let data = device_local
+            .read_back(device, device.graphics_queue(), &command_pool)
+            .expect("Failed to read back");
+        assert_eq!(data, pattern);
+
+        let mut host_visible = DeviceBuffer::persistent_mapped(
+            device,
+            pattern.len(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+        .expect("Failed to create buffer");
+        host_visible
+            .update_data_at(&pattern, 0)
+            .expect("Failed to write");
+        let data = host_visible
+            .read_back(device, device.graphics_queue(), &command_pool)
+            .expect("Failed to read back");
+        assert_eq!(data, pattern);
+    }
+
+    // Needs a Vulkan device, run with --ignored --nocapture to see the timings
+    #[test]
+    #[ignore]
+    fn map_per_update_vs_persistent_mapping() {
+        const UPDATES: usize = 1000;
+        const SIZE: usize = 256;
+
+        let renderer = headless_renderer();
+        let device = &renderer.device;
+        let usage = vk::BufferUsageFlags::UNIFORM_BUFFER;
+        let mut mapped_per_update =
+            DeviceBuffer::empty(device, UPDATES * SIZE, usage, MemoryUsage::CpuToGpu)
+                .expect("Failed to create buffer");
+        let mut persistent = DeviceBuffer::persistent_mapped(device, UPDATES * SIZE, usage)
+            .expect("Failed to create buffer");
+        assert!(mapped_per_update.mapped.is_none());
+        assert!(persistent.mapped.is_some());
+
+        let data = (0..SIZE).map(|i| i as u8).collect::<Vec<u8>>();
+        let time = |buffer: &mut DeviceBuffer| {
+            let start = std::time::Instant::now();
+            for i in 0..UPDATES {
+                buffer
+                    .update_data_at(&data, i * SIZE)
+                    .expect("Failed to write");
+            }
+            start.elapsed()
+        };
+        let map_per_update_time = time(&mut mapped_per_update);
+        let persistent_time = time(&mut persistent);
+        println!(
+            "{} updates of {} bytes: {:?} mapping each update, {:?} persistently mapped",
+            UPDATES, SIZE, map_per_update_time, persistent_time
+        );
+
+        for buffer in &[mapped_per_update, persistent] {
+            let written = buffer.read_data().expect("Failed to read");
+            assert!(written.chunks(SIZE).all(|chunk| chunk == &data[..]));
         }
     }
 }
@@ -3,7 +3,9 @@ use ash::vk;
 use crate::command::CommandPool;
 use crate::device::Device;
 use crate::mem;
+use crate::mem::Uploader;
 use crate::queue::Queue;
+use crate::resource::{BufferedStorage, Handle};
 use crate::util::as_byte_slice;
 use crate::vertex::VertexDefinition;
 use crate::vertex::VertexFormat;
@@ -14,9 +16,20 @@ pub enum IndexSize {
     Size16,
 }
 
+impl IndexSize {
+    fn bytes(self) -> usize {
+        match self {
+            IndexSize::Size16 => 2,
+            IndexSize::Size32 => 4,
+        }
+    }
+}
+
 pub struct IndexBufferDescriptor<'a> {
     data: &'a [u8],
     index_size: IndexSize,
+    dynamic: bool,
+    fixed_capacity: bool,
 }
 
 impl<'a> IndexBufferDescriptor<'a> {
@@ -28,42 +41,248 @@ impl<'a> IndexBufferDescriptor<'a> {
             _ => unreachable!("Invalid index type, needs to be either 16 or 32 bits"),
         };
 
-        Self { data, index_size }
+        Self {
+            data,
+            index_size,
+            dynamic: false,
+            fixed_capacity: false,
+        }
+    }
+
+    /// Create a host-visible buffer, see `IndexBuffer::dynamic`. The renderer keeps one copy per
+    /// frame in flight and `Renderer::update_index_buffer` only writes the copy of the current
+    /// frame, so data that should stay the same has to be written in each of the frames.
+    pub fn dynamic(mut self) -> Self {
+        self.dynamic = true;
+        self
+    }
+
+    /// Make `IndexBuffer::update` fail instead of reallocating if the data doesn't fit
+    pub fn fixed_capacity(mut self) -> Self {
+        self.fixed_capacity = true;
+        self
+    }
+
+    /// The number of indices
+    pub fn len(&self) -> usize {
+        self.data.len() / self.index_size.bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub(crate) fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+}
+
+// The number of elements in data, which has to hold a whole number of them
+fn element_count(data: &[u8], elem_size: usize) -> Result<u32, mem::MemoryError> {
+    if data.len() % elem_size != 0 {
+        return Err(mem::MemoryError::PartialElement {
+            elem_size,
+            got: data.len(),
+        });
+    }
+
+    Ok((data.len() / elem_size) as u32)
+}
+
+// The allocation of a vertex or index buffer, shared by their update functions
+struct BufferAllocation {
+    usage: vk::BufferUsageFlags,
+    dynamic: bool,
+    fixed_capacity: bool,
+}
+
+impl BufferAllocation {
+    fn create(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        staging_pool: Option<&mut mem::StagingPool>,
+        data: &[u8],
+    ) -> Result<mem::DeviceBuffer, mem::MemoryError> {
+        if self.dynamic {
+            self.create_dynamic(device, data)
+        } else {
+            mem::DeviceBuffer::device_local_by_staging(
+                device,
+                queue,
+                command_pool,
+                staging_pool,
+                self.usage,
+                data,
+            )
+        }
+    }
+
+    fn upload(
+        &self,
+        uploader: &mut Uploader,
+        data: &[u8],
+    ) -> Result<mem::DeviceBuffer, mem::MemoryError> {
+        if self.dynamic {
+            self.create_dynamic(uploader.device(), data)
+        } else {
+            uploader.upload_buffer(self.usage, data)
+        }
+    }
+
+    fn create_dynamic(
+        &self,
+        device: &Device,
+        data: &[u8],
+    ) -> Result<mem::DeviceBuffer, mem::MemoryError> {
+        let mut buffer = mem::DeviceBuffer::persistent_mapped(device, data.len(), self.usage)?;
+        buffer.update_data_at(data, 0)?;
+        Ok(buffer)
+    }
+
+    // Returns the old buffer if a larger one was allocated. It might still be in use by frames
+    // in flight, so it is up to the caller when to destroy it.
+    fn update(
+        &self,
+        buffer: &mut mem::DeviceBuffer,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        data: &[u8],
+    ) -> Result<Option<mem::DeviceBuffer>, mem::MemoryError> {
+        if data.len() <= buffer.size() || self.fixed_capacity {
+            buffer.update(device, queue, command_pool, data)?;
+            return Ok(None);
+        }
+
+        log::trace!(
+            "Growing buffer from {} to {} bytes",
+            buffer.size(),
+            data.len()
+        );
+        let grown = self.create(device, queue, command_pool, None, data)?;
+        Ok(Some(std::mem::replace(buffer, grown)))
+    }
+}
+
+fn vk_index_type(index_size: IndexSize) -> vk::IndexType {
+    match index_size {
+        IndexSize::Size16 => vk::IndexType::UINT16,
+        IndexSize::Size32 => vk::IndexType::UINT32,
     }
 }
 
 pub struct IndexBuffer {
     pub buffer: mem::DeviceBuffer,
     pub index_type: vk::IndexType,
+    index_size: IndexSize,
+    index_count: u32,
+    allocation: BufferAllocation,
 }
 
 impl IndexBuffer {
+    fn allocation(descriptor: &IndexBufferDescriptor) -> BufferAllocation {
+        BufferAllocation {
+            usage: vk::BufferUsageFlags::INDEX_BUFFER,
+            dynamic: descriptor.dynamic,
+            fixed_capacity: descriptor.fixed_capacity,
+        }
+    }
+
     pub fn create<'a>(
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut mem::StagingPool>,
         descriptor: &IndexBufferDescriptor<'a>,
     ) -> Result<Self, mem::MemoryError> {
-        let buffer = mem::DeviceBuffer::device_local_by_staging(
-            device,
-            queue,
-            command_pool,
-            vk::BufferUsageFlags::INDEX_BUFFER,
-            descriptor.data,
-        )?;
-
-        let index_type = match descriptor.index_size {
-            IndexSize::Size16 => vk::IndexType::UINT16,
-            IndexSize::Size32 => vk::IndexType::UINT32,
-        };
+        let allocation = Self::allocation(descriptor);
+        let buffer =
+            allocation.create(device, queue, command_pool, staging_pool, descriptor.data)?;
+
+        Ok(Self {
+            buffer,
+            index_type: vk_index_type(descriptor.index_size),
+            index_size: descriptor.index_size,
+            index_count: descriptor.len() as u32,
+            allocation,
+        })
+    }
+
+    /// A host-visible buffer that is written to directly instead of through a staging buffer,
+    /// e.g. for indices that are updated every frame. Slower to read from on the device.
+    pub fn dynamic<'a>(
+        device: &Device,
+        descriptor: &IndexBufferDescriptor<'a>,
+    ) -> Result<Self, mem::MemoryError> {
+        let allocation = Self::allocation(descriptor);
+        let buffer = allocation.create_dynamic(device, descriptor.data)?;
+
+        Ok(Self {
+            buffer,
+            index_type: vk_index_type(descriptor.index_size),
+            index_size: descriptor.index_size,
+            index_count: descriptor.len() as u32,
+            allocation: BufferAllocation {
+                dynamic: true,
+                ..allocation
+            },
+        })
+    }
+
+    /// Record the upload of the buffer in `uploader`. It can't be used before the uploader has
+    /// been submitted.
+    pub fn upload<'a>(
+        uploader: &mut Uploader,
+        descriptor: &IndexBufferDescriptor<'a>,
+    ) -> Result<Self, mem::MemoryError> {
+        let allocation = Self::allocation(descriptor);
+        let buffer = allocation.upload(uploader, descriptor.data)?;
 
-        Ok(Self { buffer, index_type })
+        Ok(Self {
+            buffer,
+            index_type: vk_index_type(descriptor.index_size),
+            index_size: descriptor.index_size,
+            index_count: descriptor.len() as u32,
+            allocation,
+        })
+    }
+
+    /// Replace the indices with `data`, which has to be a whole number of indices of the index
+    /// type of the buffer. If it is larger than the buffer, a new one is allocated unless the
+    /// buffer has a fixed capacity, and the old one is returned. Neither may be in use by any
+    /// frame in flight, see `Renderer::update_index_buffer` for updates between frames. `queue`
+    /// has to be of the graphics queue family, see `DeviceBuffer::update`.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        data: &[u8],
+    ) -> Result<Option<mem::DeviceBuffer>, mem::MemoryError> {
+        let index_count = element_count(data, self.index_size.bytes())?;
+        let replaced =
+            self.allocation
+                .update(&mut self.buffer, device, queue, command_pool, data)?;
+        self.index_count = index_count;
+        Ok(replaced)
     }
 
     pub fn vk_buffer(&self) -> &vk::Buffer {
         &self.buffer.vk_buffer()
     }
 
+    /// The capacity in bytes, which can be larger than the indices after an update
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// The number of indices from the last update, or creation
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
     pub fn vk_index_type(&self) -> vk::IndexType {
         self.index_type
     }
@@ -72,6 +291,9 @@ impl IndexBuffer {
 pub struct VertexBufferDescriptor<'a> {
     data: &'a [u8],
     format: VertexFormat,
+    vertex_size: usize,
+    dynamic: bool,
+    fixed_capacity: bool,
 }
 
 impl<'a> VertexBufferDescriptor<'a> {
@@ -83,37 +305,445 @@ impl<'a> VertexBufferDescriptor<'a> {
             attribute_description: V::attribute_description(),
         };
 
-        Self { data, format }
+        Self {
+            data,
+            format,
+            vertex_size: std::mem::size_of::<V>(),
+            dynamic: false,
+            fixed_capacity: false,
+        }
+    }
+
+    /// Create a host-visible buffer, see `VertexBuffer::dynamic`. The renderer keeps one copy per
+    /// frame in flight and `Renderer::update_vertex_buffer` only writes the copy of the current
+    /// frame, so data that should stay the same has to be written in each of the frames.
+    pub fn dynamic(mut self) -> Self {
+        self.dynamic = true;
+        self
+    }
+
+    /// Make `VertexBuffer::update` fail instead of reallocating if the data doesn't fit
+    pub fn fixed_capacity(mut self) -> Self {
+        self.fixed_capacity = true;
+        self
+    }
+
+    /// The number of vertices
+    pub fn len(&self) -> usize {
+        self.data.len() / self.vertex_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub(crate) fn is_dynamic(&self) -> bool {
+        self.dynamic
     }
 }
 
 pub struct VertexBuffer {
     pub buffer: mem::DeviceBuffer,
     pub _format: VertexFormat,
+    vertex_size: usize,
+    vertex_count: u32,
+    allocation: BufferAllocation,
 }
 
 impl VertexBuffer {
+    fn allocation(descriptor: &VertexBufferDescriptor) -> BufferAllocation {
+        BufferAllocation {
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+            dynamic: descriptor.dynamic,
+            fixed_capacity: descriptor.fixed_capacity,
+        }
+    }
+
     pub fn create<'a>(
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut mem::StagingPool>,
         descriptor: &VertexBufferDescriptor<'a>,
     ) -> Result<Self, mem::MemoryError> {
-        let buffer = mem::DeviceBuffer::device_local_by_staging(
-            device,
-            queue,
-            command_pool,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            descriptor.data,
-        )?;
+        let allocation = Self::allocation(descriptor);
+        let buffer =
+            allocation.create(device, queue, command_pool, staging_pool, descriptor.data)?;
 
         Ok(Self {
             buffer,
             _format: descriptor.format.clone(),
+            vertex_size: descriptor.vertex_size,
+            vertex_count: descriptor.len() as u32,
+            allocation,
         })
     }
 
+    /// A host-visible buffer that is written to directly instead of through a staging buffer,
+    /// e.g. for vertices that are updated every frame. Slower to read from on the device.
+    pub fn dynamic<'a>(
+        device: &Device,
+        descriptor: &VertexBufferDescriptor<'a>,
+    ) -> Result<Self, mem::MemoryError> {
+        let allocation = Self::allocation(descriptor);
+        let buffer = allocation.create_dynamic(device, descriptor.data)?;
+
+        Ok(Self {
+            buffer,
+            _format: descriptor.format.clone(),
+            vertex_size: descriptor.vertex_size,
+            vertex_count: descriptor.len() as u32,
+            allocation: BufferAllocation {
+                dynamic: true,
+                ..allocation
+            },
+        })
+    }
+
+    /// Record the upload of the buffer in `uploader`. It can't be used before the uploader has
+    /// been submitted.
+    pub fn upload<'a>(
+        uploader: &mut Uploader,
+        descriptor: &VertexBufferDescriptor<'a>,
+    ) -> Result<Self, mem::MemoryError> {
+        let allocation = Self::allocation(descriptor);
+        let buffer = allocation.upload(uploader, descriptor.data)?;
+
+        Ok(Self {
+            buffer,
+            _format: descriptor.format.clone(),
+            vertex_size: descriptor.vertex_size,
+            vertex_count: descriptor.len() as u32,
+            allocation,
+        })
+    }
+
+    /// Replace the vertices with `data`, which has to be a whole number of vertices of the type
+    /// the buffer was created with. If it is larger than the buffer, a new one is allocated unless
+    /// the buffer has a fixed capacity, and the old one is returned. Neither may be in use by any
+    /// frame in flight, see `Renderer::update_vertex_buffer` for updates between frames. `queue`
+    /// has to be of the graphics queue family, see `DeviceBuffer::update`.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        data: &[u8],
+    ) -> Result<Option<mem::DeviceBuffer>, mem::MemoryError> {
+        let vertex_count = element_count(data, self.vertex_size)?;
+        let replaced =
+            self.allocation
+                .update(&mut self.buffer, device, queue, command_pool, data)?;
+        self.vertex_count = vertex_count;
+        Ok(replaced)
+    }
+
     pub fn vk_buffer(&self) -> &vk::Buffer {
         &self.buffer.vk_buffer()
     }
+
+    /// The capacity in bytes, which can be larger than the vertices after an update
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// The number of vertices from the last update, or creation
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+}
+
+/// The vertex or index buffers of the renderer. Dynamic buffers are written by the host, so they
+/// have one copy per frame in flight like `UniformBuffers`. The other ones are only written on
+/// the device and have a single copy that is shared by all frames.
+pub struct MeshBuffers<T> {
+    storage: BufferedStorage<T>,
+}
+
+impl<T> MeshBuffers<T> {
+    /// `copies` has either one buffer or one per frame in flight
+    pub fn add(&mut self, copies: Vec<T>) -> Handle<T> {
+        self.storage.add(copies)
+    }
+
+    /// Removes the copies for all frames in flight
+    pub fn remove(&mut self, h: Handle<T>) -> Option<Vec<T>> {
+        self.storage.remove(h)
+    }
+
+    /// The copy used by frame `frame_idx`
+    pub fn get(&self, h: &Handle<T>, frame_idx: usize) -> Option<&T> {
+        self.storage
+            .get_all(h)
+            .map(|copies| &copies[frame_idx % copies.len()])
+    }
+
+    pub fn get_mut(&mut self, h: &Handle<T>, frame_idx: usize) -> Option<&mut T> {
+        self.storage
+            .get_all_mut(h)
+            .map(|copies| &mut copies[frame_idx % copies.len()])
+    }
+
+    /// The buffers used by frame `frame_idx`
+    pub fn iter(&self, frame_idx: usize) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.storage
+            .iter_all_with_handles()
+            .map(move |(h, copies)| (h, &copies[frame_idx % copies.len()]))
+    }
+}
+
+impl<T> Default for MeshBuffers<T> {
+    fn default() -> Self {
+        Self {
+            storage: Default::default(),
+        }
+    }
+}
+
+pub struct MeshDescriptor<'a> {
+    pub vertices: VertexBufferDescriptor<'a>,
+    pub indices: IndexBufferDescriptor<'a>,
+}
+
+impl<'a> MeshDescriptor<'a> {
+    pub fn from_slices<V: VertexDefinition, I>(vertices: &'a [V], indices: &'a [I]) -> Self {
+        Self {
+            vertices: VertexBufferDescriptor::from_slice(vertices),
+            indices: IndexBufferDescriptor::from_slice(indices),
+        }
+    }
+}
+
+/// A vertex buffer and the index buffer that is used to draw it, see `CommandBuffer::draw_mesh`
+pub struct Mesh {
+    vertex_buffer: VertexBuffer,
+    index_buffer: IndexBuffer,
+    index_count: u32,
+}
+
+impl Mesh {
+    /// Both buffers are uploaded with a single submit
+    pub fn create<'a>(
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        staging_pool: Option<&mut mem::StagingPool>,
+        descriptor: &MeshDescriptor<'a>,
+    ) -> Result<Self, mem::MemoryError> {
+        let mut uploader = Uploader::begin(device, queue, command_pool, staging_pool)?;
+        let vertex_buffer = VertexBuffer::upload(&mut uploader, &descriptor.vertices)?;
+        let index_buffer = IndexBuffer::upload(&mut uploader, &descriptor.indices)?;
+        uploader.submit_and_wait()?;
+
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: descriptor.indices.len() as u32,
+        })
+    }
+
+    pub fn vertex_buffer(&self) -> &VertexBuffer {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &IndexBuffer {
+        &self.index_buffer
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    pub fn vk_index_type(&self) -> vk::IndexType {
+        self.index_buffer.vk_index_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{headless_renderer, Position};
+    use crate::{RenderError, Renderer, RendererConfig, ResourceManager};
+
+    #[test]
+    fn index_count() {
+        let indices16 = [0u16, 1, 2, 2, 3, 0];
+        let desc = IndexBufferDescriptor::from_slice(&indices16);
+        assert_eq!(desc.len(), 6);
+        assert_eq!(vk_index_type(desc.index_size), vk::IndexType::UINT16);
+
+        let indices32 = [0u32, 1, 2];
+        let desc = IndexBufferDescriptor::from_slice(&indices32);
+        assert_eq!(desc.len(), 3);
+        assert_eq!(vk_index_type(desc.index_size), vk::IndexType::UINT32);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn vertex_buffer_update() {
+        let renderer = headless_renderer();
+        let device = &renderer.device;
+        let queue = device.graphics_queue();
+        let command_pool = CommandPool::graphics(device).expect("Failed to create command pool");
+        let triangle = [
+            Position([0.0, 0.0]),
+            Position([1.0, 0.0]),
+            Position([0.0, 1.0]),
+        ];
+        let moved = [
+            Position([0.5, 0.5]),
+            Position([1.0, 0.5]),
+            Position([0.5, 1.0]),
+        ];
+        let quad = [
+            Position([0.0, 0.0]),
+            Position([1.0, 0.0]),
+            Position([0.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let read_back = |vbuf: &VertexBuffer| {
+            vbuf.buffer
+                .read_back(device, queue, &command_pool)
+                .expect("Failed to read back")
+        };
+        let create = |descriptor| {
+            VertexBuffer::create(device, queue, &command_pool, None, &descriptor)
+                .expect("Failed to create vertex buffer")
+        };
+
+        let mut vbuf = create(VertexBufferDescriptor::from_slice(&triangle));
+        vbuf.update(device, queue, &command_pool, as_byte_slice(&moved))
+            .expect("Failed to update");
+        assert_eq!(read_back(&vbuf), as_byte_slice(&moved));
+        // Grows
+        vbuf.update(device, queue, &command_pool, as_byte_slice(&quad))
+            .expect("Failed to update");
+        assert_eq!(read_back(&vbuf), as_byte_slice(&quad));
+        assert_eq!(vbuf.vertex_count(), 4);
+        // Half a vertex
+        assert!(matches!(
+            vbuf.update(device, queue, &command_pool, &as_byte_slice(&moved)[..12]),
+            Err(mem::MemoryError::PartialElement {
+                elem_size: 8,
+                got: 12
+            })
+        ));
+        assert_eq!(vbuf.vertex_count(), 4);
+
+        let mut fixed = create(VertexBufferDescriptor::from_slice(&triangle).fixed_capacity());
+        assert!(matches!(
+            fixed.update(device, queue, &command_pool, as_byte_slice(&quad)),
+            Err(mem::MemoryError::CapacityExceeded {
+                capacity: 24,
+                got: 32
+            })
+        ));
+
+        let mut dynamic =
+            VertexBuffer::dynamic(device, &VertexBufferDescriptor::from_slice(&triangle))
+                .expect("Failed to create vertex buffer");
+        dynamic
+            .update(device, queue, &command_pool, as_byte_slice(&moved))
+            .expect("Failed to update");
+        assert_eq!(read_back(&dynamic), as_byte_slice(&moved));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn renderer_buffer_updates() {
+        let extent = crate::util::Extent2D {
+            width: 4,
+            height: 4,
+        };
+        let config = RendererConfig {
+            frames_in_flight: 2,
+            ..Default::default()
+        };
+        let mut renderer =
+            Renderer::new_headless(extent, config).expect("Failed to create headless renderer");
+        let triangle = [
+            Position([0.0, 0.0]),
+            Position([1.0, 0.0]),
+            Position([0.0, 1.0]),
+        ];
+        let moved = [
+            Position([0.5, 0.5]),
+            Position([1.0, 0.5]),
+            Position([0.5, 1.0]),
+        ];
+        let quad = [
+            Position([0.0, 0.0]),
+            Position([1.0, 0.0]),
+            Position([0.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let vh = renderer
+            .create_resource(VertexBufferDescriptor::from_slice(&triangle).dynamic())
+            .expect("Failed to create vertex buffer");
+        let ih = renderer
+            .create_resource(IndexBufferDescriptor::from_slice(&[0u16, 1, 2]))
+            .expect("Failed to create index buffer");
+        assert_eq!(
+            renderer.vertex_buffers.storage.get_all(&vh).unwrap().len(),
+            2
+        );
+        assert_eq!(
+            renderer.index_buffers.storage.get_all(&ih).unwrap().len(),
+            1
+        );
+
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        let updated_idx = frame.frame_idx as usize;
+        renderer
+            .update_vertex_buffer(&frame, &vh, &moved)
+            .expect("Failed to update vertex buffer");
+        renderer.submit(frame).expect("Failed to submit");
+        renderer.wait_idle().expect("Failed to wait");
+
+        // Only the copy of the updated frame changes
+        let command_pool =
+            CommandPool::graphics(&renderer.device).expect("Failed to create command pool");
+        let contents = renderer
+            .vertex_buffers
+            .storage
+            .get_all(&vh)
+            .unwrap()
+            .iter()
+            .map(|vbuf| {
+                vbuf.buffer
+                    .read_back(
+                        &renderer.device,
+                        renderer.device.graphics_queue(),
+                        &command_pool,
+                    )
+                    .expect("Failed to read back")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(contents[updated_idx], as_byte_slice(&moved));
+        assert_eq!(contents[1 - updated_idx], as_byte_slice(&triangle));
+
+        let frame = renderer.next_frame().expect("Failed to get frame");
+        renderer
+            .update_vertex_buffer(&frame, &vh, &quad)
+            .expect("Failed to update vertex buffer");
+        assert_eq!(renderer.get_resource(&vh).unwrap().vertex_count(), 4);
+        // The outgrown buffer is destroyed once the frame is done with it
+        assert_eq!(renderer.deletion_queue.len(), 1);
+
+        renderer
+            .update_index_buffer(&frame, &ih, &[0u16, 1, 2, 2, 1, 3])
+            .expect("Failed to update index buffer");
+        assert_eq!(renderer.get_resource(&ih).unwrap().index_count(), 6);
+        assert_eq!(renderer.deletion_queue.len(), 2);
+        assert!(matches!(
+            renderer.update_index_buffer(&frame, &ih, &[0u8; 3]),
+            Err(RenderError::IndexBuffer(mem::MemoryError::PartialElement {
+                elem_size: 2,
+                got: 3
+            }))
+        ));
+        renderer.submit(frame).expect("Failed to submit");
+    }
 }
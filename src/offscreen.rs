@@ -0,0 +1,382 @@
+use ash::vk;
+
+use thiserror::Error;
+
+use crate::color_buffer::{ColorBuffer, ColorBufferError};
+use crate::command::{CommandBuffer, InRenderPass};
+use crate::depth_buffer::{DepthBuffer, DepthBufferError};
+use crate::device::Device;
+use crate::framebuffer::{Framebuffer, FramebufferError};
+use crate::image::{ImageView, ImageViewError};
+use crate::mem::{DeviceImage, MemoryError};
+use crate::render_pass::{RenderPass, RenderPassBuilder, RenderPassError};
+use crate::texture::{Sampler, SamplerDescriptor, TextureError};
+use crate::util;
+
+#[derive(Debug, Error)]
+pub enum OffscreenError {
+    #[error("Offscreen target memory error: {0}")]
+    Memory(#[from] MemoryError),
+    #[error("Offscreen target image view error: {0}")]
+    ImageView(#[from] ImageViewError),
+    #[error("Offscreen target color buffer error: {0}")]
+    ColorBuffer(#[from] ColorBufferError),
+    #[error("Offscreen target depth buffer error: {0}")]
+    DepthBuffer(#[from] DepthBufferError),
+    #[error("Offscreen target render pass error: {0}")]
+    RenderPass(#[from] RenderPassError),
+    #[error("Offscreen target framebuffer error: {0}")]
+    Framebuffer(#[from] FramebufferError),
+    #[error("Offscreen target sampler error: {0}")]
+    Sampler(#[from] TextureError),
+}
+
+/// A color image (with an optional depth buffer) that can be rendered to and then sampled in a
+/// later render pass, e.g. for post-processing. With msaa, a multisampled color buffer is
+/// rendered to and resolved into the image.
+pub struct OffscreenTarget {
+    framebuffer: Framebuffer,
+    render_pass: RenderPass,
+    sampler: Sampler,
+    _depth_buffer: Option<DepthBuffer>,
+    _color_buffer: Option<ColorBuffer>,
+    image_view: ImageView,
+    image: DeviceImage,
+    extent: util::Extent2D,
+    format: vk::Format,
+    depth_format: Option<vk::Format>,
+    msaa_sample_count: vk::SampleCountFlags,
+}
+
+// Everything that depends on the extent
+struct Attachments {
+    framebuffer: Framebuffer,
+    depth_buffer: Option<DepthBuffer>,
+    color_buffer: Option<ColorBuffer>,
+    image_view: ImageView,
+    image: DeviceImage,
+}
+
+impl Attachments {
+    fn new(
+        device: &Device,
+        render_pass: &RenderPass,
+        extent: util::Extent2D,
+        format: vk::Format,
+        depth_format: Option<vk::Format>,
+        msaa_sample_count: vk::SampleCountFlags,
+    ) -> Result<Self, OffscreenError> {
+        let util_format = util::Format::from(format);
+        // Transfer src for reading it back, e.g. in tests, or blitting it to another image.
+        // Transfer dst for being blitted to, see Renderer::set_render_scale.
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST;
+        let mip_levels = 1; // No mip maps
+        let image = DeviceImage::empty_2d(
+            device,
+            extent,
+            util_format,
+            usage,
+            vk_mem::MemoryUsage::GpuOnly,
+            mip_levels,
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+        let image_view = ImageView::new(
+            device,
+            image.vk_image(),
+            util_format,
+            vk::ImageAspectFlags::COLOR,
+            mip_levels,
+        )?;
+
+        let depth_buffer = match depth_format {
+            Some(depth_format) => Some(DepthBuffer::new(
+                device,
+                &extent,
+                msaa_sample_count,
+                depth_format,
+            )?),
+            None => None,
+        };
+        let color_buffer = if msaa_sample_count != vk::SampleCountFlags::TYPE_1 {
+            Some(ColorBuffer::new(
+                device,
+                util_format,
+                &extent,
+                msaa_sample_count,
+            )?)
+        } else {
+            None
+        };
+
+        // Attachment order has to match the render pass
+        let mut attachments = match color_buffer.as_ref() {
+            Some(cb) => vec![cb.image_view()],
+            None => vec![&image_view],
+        };
+        if let Some(depth_buffer) = depth_buffer.as_ref() {
+            attachments.push(depth_buffer.image_view());
+        }
+        if color_buffer.is_some() {
+            attachments.push(&image_view);
+        }
+        let framebuffer = Framebuffer::new(device, &attachments, render_pass, &extent)?;
+
+        Ok(Self {
+            framebuffer,
+            depth_buffer,
+            color_buffer,
+            image_view,
+            image,
+        })
+    }
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        device: &Device,
+        extent: util::Extent2D,
+        format: vk::Format,
+        with_depth: bool,
+        msaa_sample_count: vk::SampleCountFlags,
+    ) -> Result<Self, OffscreenError> {
+        let depth_format = if with_depth {
+            Some(device.depth_buffer_format())
+        } else {
+            None
+        };
+        Self::with_depth_format(device, extent, format, depth_format, msaa_sample_count)
+    }
+
+    /// As `new`, but with an explicit depth buffer format, e.g. one with a stencil component
+    pub fn with_depth_format(
+        device: &Device,
+        extent: util::Extent2D,
+        format: vk::Format,
+        depth_format: Option<vk::Format>,
+        msaa_sample_count: vk::SampleCountFlags,
+    ) -> Result<Self, OffscreenError> {
+        let render_pass =
+            RenderPassBuilder::offscreen(format, depth_format, msaa_sample_count).build(device)?;
+        let Attachments {
+            framebuffer,
+            depth_buffer,
+            color_buffer,
+            image_view,
+            image,
+        } = Attachments::new(
+            device,
+            &render_pass,
+            extent,
+            format,
+            depth_format,
+            msaa_sample_count,
+        )?;
+        let mip_levels = 1; // No mip maps
+        let sampler = Sampler::new(device, mip_levels, &SamplerDescriptor::default())?;
+
+        Ok(Self {
+            framebuffer,
+            render_pass,
+            sampler,
+            _depth_buffer: depth_buffer,
+            _color_buffer: color_buffer,
+            image_view,
+            image,
+            extent,
+            format,
+            depth_format,
+            msaa_sample_count,
+        })
+    }
+
+    /// Recreate the images and the framebuffer for `extent`. The render pass (and thus the
+    /// pipelines created for it) is kept. The target must not be in use by the device.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        extent: util::Extent2D,
+    ) -> Result<(), OffscreenError> {
+        let attachments = Attachments::new(
+            device,
+            &self.render_pass,
+            extent,
+            self.format,
+            self.depth_format,
+            self.msaa_sample_count,
+        )?;
+
+        // The framebuffer refers to the image views, so it goes first
+        self.framebuffer = attachments.framebuffer;
+        self._depth_buffer = attachments.depth_buffer;
+        self._color_buffer = attachments.color_buffer;
+        self.image_view = attachments.image_view;
+        self.image = attachments.image;
+        self.extent = extent;
+
+        Ok(())
+    }
+
+    /// Begin the render pass of this target. Pipelines used within it need to be created for
+    /// `render_pass()`.
+    pub fn begin(&self, cmd_buffer: CommandBuffer) -> CommandBuffer<InRenderPass> {
+        cmd_buffer.begin_render_pass(&self.render_pass, &self.framebuffer, self.extent)
+    }
+
+    /// After this, the color image is in SHADER_READ_ONLY_OPTIMAL and can be sampled
+    pub fn end(&self, cmd_buffer: CommandBuffer<InRenderPass>) -> CommandBuffer {
+        cmd_buffer.end_render_pass()
+    }
+
+    /// Scale the color image to all of `dst` with a linear blit. Has to be recorded after `end`.
+    /// The contents of `dst` are discarded and it is transitioned to `dst_final_layout`.
+    pub fn blit_to(
+        &self,
+        cmd_buffer: CommandBuffer,
+        dst: &vk::Image,
+        dst_extent: util::Extent2D,
+        dst_final_layout: vk::ImageLayout,
+    ) -> CommandBuffer {
+        let src = self.image.vk_image();
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let blit = vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: [vk::Offset3D::default(), far_corner(self.extent)],
+            dst_subresource: subresource,
+            dst_offsets: [vk::Offset3D::default(), far_corner(dst_extent)],
+        };
+
+        // The render pass makes the color writes available to fragment shaders, see
+        // RenderPass::offscreen
+        cmd_buffer
+            .pipeline_barrier(
+                &color_barrier(
+                    src,
+                    (
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    ),
+                    (
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                ),
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+            .pipeline_barrier(
+                &color_barrier(
+                    dst,
+                    (vk::ImageLayout::UNDEFINED, vk::AccessFlags::TRANSFER_WRITE),
+                    (
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+            .blit_image(src, dst, &blit)
+            // Back to the final layout of the render pass, before it is rendered to again
+            .pipeline_barrier(
+                &color_barrier(
+                    src,
+                    (
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                    ),
+                    (
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                ),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .pipeline_barrier(
+                &color_barrier(
+                    dst,
+                    (
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                    (dst_final_layout, vk::AccessFlags::empty()),
+                ),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            )
+    }
+
+    pub fn render_pass(&self) -> &RenderPass {
+        &self.render_pass
+    }
+
+    /// E.g. to set the clear color
+    pub fn render_pass_mut(&mut self) -> &mut RenderPass {
+        &mut self.render_pass
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn image_view(&self) -> &ImageView {
+        &self.image_view
+    }
+
+    pub fn sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+
+    pub fn extent(&self) -> util::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn vk_image(&self) -> &vk::Image {
+        self.image.vk_image()
+    }
+}
+
+fn far_corner(extent: util::Extent2D) -> vk::Offset3D {
+    vk::Offset3D {
+        x: extent.width as i32,
+        y: extent.height as i32,
+        z: 1,
+    }
+}
+
+fn color_barrier(
+    image: &vk::Image,
+    (old_layout, src_access_mask): (vk::ImageLayout, vk::AccessFlags),
+    (new_layout, dst_access_mask): (vk::ImageLayout, vk::AccessFlags),
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier {
+        old_layout,
+        new_layout,
+        src_access_mask,
+        dst_access_mask,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image: *image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        ..Default::default()
+    }
+}
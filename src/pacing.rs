@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+// Sleeping can overshoot by more than this, so the last part of the wait is spent spinning
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// CPU-side frame rate limiting, independent of the present mode. `begin_frame` waits until at
+/// least the target frame time has passed since the previous frame started.
+#[derive(Debug, Default)]
+pub struct FramePacer {
+    target_frame_time: Option<Duration>,
+    last_frame_start: Option<Instant>,
+    last_frame_time: Option<Duration>,
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_target_frame_time(&mut self, target: Option<Duration>) {
+        self.target_frame_time = target;
+    }
+
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        self.target_frame_time
+    }
+
+    /// The time between the starts of the two most recent frames, including any waiting
+    pub fn last_frame_time(&self) -> Option<Duration> {
+        self.last_frame_time
+    }
+
+    pub fn begin_frame(&mut self) {
+        if let (Some(target), Some(last)) = (self.target_frame_time, self.last_frame_start) {
+            let deadline = last + target;
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+
+                let remaining = deadline - now;
+                if remaining > SPIN_THRESHOLD {
+                    std::thread::sleep(remaining - SPIN_THRESHOLD);
+                } else {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+
+        let now = Instant::now();
+        self.last_frame_time = self.last_frame_start.map(|last| now - last);
+        self.last_frame_start = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_starts_are_spaced_by_target() {
+        let target = Duration::from_millis(16);
+        let mut pacer = FramePacer::new();
+        pacer.set_target_frame_time(Some(target));
+
+        pacer.begin_frame();
+        assert_eq!(pacer.last_frame_time(), None);
+
+        let mut starts = vec![Instant::now()];
+        for _ in 0..5 {
+            pacer.begin_frame();
+            starts.push(Instant::now());
+            let frame_time = pacer.last_frame_time().expect("Missing frame time");
+            assert!(frame_time >= target);
+            // Generous, the machine might be busy
+            assert!(frame_time < target * 3, "{:?}", frame_time);
+        }
+
+        let total = *starts.last().unwrap() - starts[0];
+        assert!(total >= target * 5 - Duration::from_millis(1));
+    }
+
+    #[test]
+    fn no_target_doesnt_wait() {
+        let mut pacer = FramePacer::new();
+        pacer.begin_frame();
+        let start = Instant::now();
+        pacer.begin_frame();
+        assert!(start.elapsed() < Duration::from_millis(16));
+    }
+}
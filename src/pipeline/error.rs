@@ -1,5 +1,7 @@
 use ash::vk;
 
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 use crate::spirv::SpirvError;
@@ -8,10 +10,23 @@ use crate::spirv::SpirvError;
 pub enum PipelineError {
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
+    #[error("{stage:?} shader {} not found", path.display())]
+    ShaderFileNotFound {
+        path: PathBuf,
+        stage: vk::ShaderStageFlags,
+    },
     #[error("Could not create {1}: {0}")]
     VulkanObjectCreation(vk::Result, &'static str),
     #[error("Missing required arg: {0}")]
     MissingArg(&'static str),
     #[error("Spirv reflection failed: {0}")]
     Reflection(#[from] SpirvError),
+    #[error("Missing device feature: {0}")]
+    MissingDeviceFeature(&'static str),
+    #[error("Shader compilation failed: {0}")]
+    ShaderCompilation(String),
+    #[error("Shader has no entry point named {0}")]
+    MissingEntryPoint(String),
+    #[error("Stencil test enabled but subpass {0} has no stencil attachment")]
+    MissingStencilAttachment(u32),
 }
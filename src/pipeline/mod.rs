@@ -6,15 +6,19 @@ use std::fs::File;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 use crate::device::Device;
 use crate::device::HasVkDevice;
 use crate::device::VkDeviceHandle;
 use crate::render_pass::RenderPass;
 use crate::resource::{Handle, Storage};
-use crate::spirv::{parse_descriptor_sets, DescriptorSetLayouts};
+use crate::spirv::{
+    parse_descriptor_sets, parse_entry_point_names, parse_output_location_count,
+    DescriptorSetLayouts,
+};
 use crate::util;
-use crate::vertex::VertexDefinition;
+use crate::vertex::{VertexDefinition, VertexFormat};
 
 mod error;
 pub use error::PipelineError;
@@ -31,11 +35,54 @@ fn read_shader_abs<P: AsRef<Path>>(path: P) -> io::Result<RawShader> {
     Ok(RawShader { data: words })
 }
 
-fn read_shader_rel<N: AsRef<Path>>(name: N) -> io::Result<RawShader> {
-    let cd = std::env::current_dir()?;
-    let path = cd.join("src").join("pipeline").join("shaders").join(name);
+/// The entry point of shaders unless something else is set, see
+/// `GraphicsPipelineBuilder::vertex_entry`. GLSL shaders always use this.
+pub const DEFAULT_ENTRY_POINT: &str = "main";
+
+// The error message contains "<name>:<line>: error: ..." for each error
+#[cfg(feature = "shaderc")]
+fn compile_glsl(
+    source: &str,
+    kind: shaderc::ShaderKind,
+    name: &str,
+) -> Result<RawShader, PipelineError> {
+    let mut compiler = shaderc::Compiler::new().ok_or_else(|| {
+        PipelineError::ShaderCompilation("Failed to initialize shaderc".to_owned())
+    })?;
+    let artifact = compiler
+        .compile_into_spirv(source, kind, name, DEFAULT_ENTRY_POINT, None)
+        .map_err(|e| PipelineError::ShaderCompilation(e.to_string()))?;
+
+    Ok(RawShader {
+        data: artifact.as_binary().to_vec(),
+    })
+}
+
+pub const SHADER_DIR_ENV_VAR: &str = "TREK_SHADER_DIR";
+
+/// Where relative shader paths are looked up if no shader dir is configured: `TREK_SHADER_DIR`
+/// if set, otherwise `<current dir>/src/pipeline/shaders`. Always absolute, a relative
+/// `TREK_SHADER_DIR` is relative to the current dir.
+fn default_shader_dir() -> io::Result<PathBuf> {
+    let current_dir = std::env::current_dir()?;
+    match std::env::var_os(SHADER_DIR_ENV_VAR) {
+        Some(dir) => Ok(current_dir.join(dir)),
+        None => Ok(current_dir.join("src").join("pipeline").join("shaders")),
+    }
+}
+
+/// Absolute paths are used as is, relative ones are joined onto `shader_dir` (or the default
+/// shader dir if it is None). The result is only absolute if `shader_dir` is, as it is for
+/// `GraphicsPipelines`.
+pub fn resolve_shader_path(path: &Path, shader_dir: Option<&Path>) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
 
-    read_shader_abs(path)
+    match shader_dir {
+        Some(dir) => Ok(dir.join(path)),
+        None => Ok(default_shader_dir()?.join(path)),
+    }
 }
 
 struct ShaderModule {
@@ -122,8 +169,24 @@ impl GraphicsPipeline {
 }
 
 struct PipelineCreationInfo {
-    create_info: vk::PipelineShaderStageCreateInfo,
-    _shader_module: ShaderModule,
+    stage: vk::ShaderStageFlags,
+    shader_module: ShaderModule,
+    entry_point_names: Vec<String>,
+    // For fragment shaders, the number of color attachments that are written
+    output_location_count: u32,
+    // The shader path, used to name the pipeline for debugging
+    name: String,
+}
+
+impl PipelineCreationInfo {
+    // The entry name is checked here as Vulkan does not validate it
+    fn entry_name(&self, name: &str) -> Result<CString, PipelineError> {
+        if !self.entry_point_names.iter().any(|n| n == name) {
+            return Err(PipelineError::MissingEntryPoint(name.to_owned()));
+        }
+
+        Ok(CString::new(name).expect("Entry point names are nul-terminated in SPIR-V"))
+    }
 }
 
 struct VertexInputDescription<'a> {
@@ -131,29 +194,246 @@ struct VertexInputDescription<'a> {
     _attribute_description: &'a [vk::VertexInputAttributeDescription],
     create_info: vk::PipelineVertexInputStateCreateInfo,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    /// Blend with `src.a * src + (1 - src.a) * dst`
+    AlphaBlend,
+    /// Blend with `src.a * src + dst`
+    Additive,
+    /// Blend with `src + (1 - src.a) * dst`, for colors that are already multiplied by alpha
+    PremultipliedAlpha,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Opaque
+    }
+}
+
+/// How a binding declared with `GraphicsPipelineBuilder::descriptor_binding` is incompatible with
+/// the one reflected from the shaders, if it is
+fn binding_mismatch(
+    reflected: &vk::DescriptorSetLayoutBinding,
+    declared: &vk::DescriptorSetLayoutBinding,
+) -> Option<&'static str> {
+    if reflected.descriptor_type != declared.descriptor_type {
+        Some("descriptor type")
+    } else if !declared.stage_flags.contains(reflected.stage_flags) {
+        Some("stages that use it")
+    } else if declared.descriptor_count < reflected.descriptor_count {
+        // Runtime-sized arrays are reflected with a count of 1
+        Some("descriptor count")
+    } else {
+        None
+    }
+}
+
+fn warn_binding_mismatch(
+    set: u32,
+    reflected: &vk::DescriptorSetLayoutBinding,
+    declared: &vk::DescriptorSetLayoutBinding,
+) {
+    if let Some(what) = binding_mismatch(reflected, declared) {
+        log::warn!(
+            "Set {} binding {} doesn't match the {} in the shaders: declared {:?} but reflected {:?}",
+            set,
+            declared.binding,
+            what,
+            declared,
+            reflected
+        );
+    }
+}
+
+fn viewport(extent: util::Extent2D, flip_y: bool) -> vk::Viewport {
+    let (width, height) = (extent.width as f32, extent.height as f32);
+    let (y, height) = if flip_y {
+        (height, -height)
+    } else {
+        (0.0, height)
+    };
+
+    vk::Viewport {
+        x: 0.0,
+        y,
+        width,
+        height,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
+fn color_blend_attachment_state(mode: BlendMode) -> vk::PipelineColorBlendAttachmentState {
+    let builder = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .color_blend_op(vk::BlendOp::ADD)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let (src_color, dst_color, src_alpha, dst_alpha) = match mode {
+        BlendMode::Opaque => return builder.blend_enable(false).build(),
+        BlendMode::AlphaBlend => (
+            vk::BlendFactor::SRC_ALPHA,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            vk::BlendFactor::ONE,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        ),
+        BlendMode::Additive => (
+            vk::BlendFactor::SRC_ALPHA,
+            vk::BlendFactor::ONE,
+            vk::BlendFactor::ONE,
+            vk::BlendFactor::ONE,
+        ),
+        BlendMode::PremultipliedAlpha => (
+            vk::BlendFactor::ONE,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            vk::BlendFactor::ONE,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        ),
+    };
+
+    builder
+        .blend_enable(true)
+        .src_color_blend_factor(src_color)
+        .dst_color_blend_factor(dst_color)
+        .src_alpha_blend_factor(src_alpha)
+        .dst_alpha_blend_factor(dst_alpha)
+        .build()
+}
+
+/// Common uses of the stencil buffer. The reference value is always dynamic, see
+/// `CommandBuffer::set_stencil_reference`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StencilMode {
+    Disabled,
+    /// Write the reference value wherever a fragment passes the depth test
+    Write,
+    /// Only draw where the stencil buffer is equal to the reference value
+    Equal,
+    /// Only draw where the stencil buffer is not equal to the reference value
+    NotEqual,
+}
+
+impl Default for StencilMode {
+    fn default() -> Self {
+        StencilMode::Disabled
+    }
+}
+
+fn stencil_op_state(mode: StencilMode) -> Option<vk::StencilOpState> {
+    let (compare_op, pass_op, write_mask) = match mode {
+        StencilMode::Disabled => return None,
+        StencilMode::Write => (vk::CompareOp::ALWAYS, vk::StencilOp::REPLACE, 0xff),
+        StencilMode::Equal => (vk::CompareOp::EQUAL, vk::StencilOp::KEEP, 0),
+        StencilMode::NotEqual => (vk::CompareOp::NOT_EQUAL, vk::StencilOp::KEEP, 0),
+    };
+
+    Some(vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op,
+        compare_mask: 0xff,
+        write_mask,
+        reference: 0,
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+struct DepthState {
+    test_enable: bool,
+    write_enable: bool,
+    compare_op: vk::CompareOp,
+    // Front and back, None if the stencil test is disabled
+    stencil: Option<[vk::StencilOpState; 2]>,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            test_enable: true,
+            write_enable: true,
+            compare_op: vk::CompareOp::LESS,
+            stencil: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RasterizerState {
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    polygon_mode: vk::PolygonMode,
+    flip_viewport_y: bool,
+    dynamic_viewport: bool,
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            flip_viewport_y: false,
+            dynamic_viewport: false,
+        }
+    }
+}
+
+// What the pipeline renders into, see GraphicsPipelineBuilder::render_pass and rendering_formats
+struct Target {
+    vk_render_pass: vk::RenderPass,
+    sample_count: vk::SampleCountFlags,
+    color_attachment_count: u32,
+    has_depth: bool,
+    has_stencil: bool,
+}
+
 pub struct GraphicsPipelineBuilder<'a> {
     device: &'a Device,
-    entry_name: CString,
+    vert_entry: String,
+    frag_entry: String,
     vert: Option<PipelineCreationInfo>,
     frag: Option<PipelineCreationInfo>,
     vertex_input: Option<VertexInputDescription<'a>>,
     viewport_extent: Option<util::Extent2D>,
     render_pass: Option<&'a RenderPass>,
+    subpass: u32,
+    // Color formats and depth format, instead of a render pass
+    #[cfg(feature = "dynamic_rendering")]
+    rendering_formats: Option<(Vec<vk::Format>, Option<vk::Format>)>,
     refl_descriptor_set_layouts: DescriptorSetLayouts,
+    // Applied on top of the reflected bindings in build(), with their set index
+    descriptor_bindings: Vec<(u32, vk::DescriptorSetLayoutBinding)>,
+    bindless: bool,
+    dynamic_uniform_buffers: bool,
+    rasterizer_state: RasterizerState,
+    blend_mode: BlendMode,
+    depth_state: DepthState,
 }
 
 impl<'a> GraphicsPipelineBuilder<'a> {
     pub fn new(device: &'a Device) -> Self {
-        let entry_name = CString::new("main").expect("CString failed!");
         Self {
             device,
-            entry_name,
+            vert_entry: DEFAULT_ENTRY_POINT.to_owned(),
+            frag_entry: DEFAULT_ENTRY_POINT.to_owned(),
             vert: None,
             frag: None,
             vertex_input: None,
             render_pass: None,
+            subpass: 0,
+            #[cfg(feature = "dynamic_rendering")]
+            rendering_formats: None,
             viewport_extent: None,
             refl_descriptor_set_layouts: DescriptorSetLayouts::new(),
+            descriptor_bindings: Vec::new(),
+            bindless: false,
+            dynamic_uniform_buffers: false,
+            rasterizer_state: RasterizerState::default(),
+            blend_mode: BlendMode::default(),
+            depth_state: DepthState::default(),
         }
     }
 
@@ -162,21 +442,42 @@ impl<'a> GraphicsPipelineBuilder<'a> {
         path: P,
         stage: vk::ShaderStageFlags,
     ) -> Result<PipelineCreationInfo, PipelineError> {
-        let raw = read_shader_rel(path)?;
+        // Paths from GraphicsPipelines are already absolute and used as is
+        let path = resolve_shader_path(path.as_ref(), None)?;
+        let name = path.display().to_string();
+        let raw = read_shader_abs(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                log::error!("{:?} shader {} not found", stage, path.display());
+                PipelineError::ShaderFileNotFound { path, stage }
+            }
+            _ => PipelineError::IO(e),
+        })?;
+        self.shader_from_spirv(raw, stage, name)
+    }
+
+    fn shader_from_spirv(
+        &mut self,
+        raw: RawShader,
+        stage: vk::ShaderStageFlags,
+        name: String,
+    ) -> Result<PipelineCreationInfo, PipelineError> {
         let shader_module = ShaderModule::new(self.device, &raw)?;
-        let create_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(stage)
-            .module(shader_module.vk_shader_module)
-            .name(&self.entry_name)
-            .build();
+        let entry_point_names = parse_entry_point_names(&raw.data)?;
+        let output_location_count =
+            parse_output_location_count(&raw.data).map_err(PipelineError::Reflection)?;
 
         let new_desc_sets = parse_descriptor_sets(&raw.data).map_err(PipelineError::Reflection)?;
 
-        self.refl_descriptor_set_layouts.append(new_desc_sets);
+        self.refl_descriptor_set_layouts
+            .append(new_desc_sets)
+            .map_err(PipelineError::Reflection)?;
 
         Ok(PipelineCreationInfo {
-            create_info,
-            _shader_module: shader_module,
+            stage,
+            shader_module,
+            entry_point_names,
+            output_location_count,
+            name,
         })
     }
 
@@ -190,6 +491,43 @@ impl<'a> GraphicsPipelineBuilder<'a> {
         Ok(self)
     }
 
+    /// Compile `source` to SPIR-V at runtime and use it as the vertex shader
+    #[cfg(feature = "shaderc")]
+    pub fn vertex_shader_glsl(mut self, source: &str) -> Result<Self, PipelineError> {
+        let raw = compile_glsl(source, shaderc::ShaderKind::Vertex, "vertex")?;
+        self.vert = Some(self.shader_from_spirv(
+            raw,
+            vk::ShaderStageFlags::VERTEX,
+            String::from("<glsl vertex>"),
+        )?);
+        Ok(self)
+    }
+
+    /// Compile `source` to SPIR-V at runtime and use it as the fragment shader
+    #[cfg(feature = "shaderc")]
+    pub fn fragment_shader_glsl(mut self, source: &str) -> Result<Self, PipelineError> {
+        let raw = compile_glsl(source, shaderc::ShaderKind::Fragment, "fragment")?;
+        self.frag = Some(self.shader_from_spirv(
+            raw,
+            vk::ShaderStageFlags::FRAGMENT,
+            String::from("<glsl fragment>"),
+        )?);
+        Ok(self)
+    }
+
+    /// The name of the entry point in the vertex shader, e.g. for shaders compiled from HLSL.
+    /// Defaults to "main".
+    pub fn vertex_entry(mut self, name: &str) -> Self {
+        self.vert_entry = name.to_owned();
+        self
+    }
+
+    /// The name of the entry point in the fragment shader. Defaults to "main".
+    pub fn fragment_entry(mut self, name: &str) -> Self {
+        self.frag_entry = name.to_owned();
+        self
+    }
+
     pub fn vertex_input(
         mut self,
         attribute_description: &'a [vk::VertexInputAttributeDescription],
@@ -219,7 +557,189 @@ impl<'a> GraphicsPipelineBuilder<'a> {
         self
     }
 
+    /// The subpass of `render_pass` that the pipeline is used in. Defaults to 0.
+    pub fn subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
+
+    /// Create the pipeline for `CommandBuffer::begin_rendering` with attachments of these formats,
+    /// instead of for a render pass. Needs `Device::dynamic_rendering_enabled`.
+    #[cfg(feature = "dynamic_rendering")]
+    pub fn rendering_formats(
+        mut self,
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+    ) -> Self {
+        self.rendering_formats = Some((color_formats.to_vec(), depth_format));
+        self
+    }
+
+    #[cfg(feature = "dynamic_rendering")]
+    fn target(&self) -> Result<Target, PipelineError> {
+        match &self.rendering_formats {
+            Some((color_formats, depth_format)) => {
+                if !self.device.dynamic_rendering_enabled() {
+                    return Err(PipelineError::MissingDeviceFeature(
+                        "VK_KHR_dynamic_rendering",
+                    ));
+                }
+                // Only the depth aspect is used, see CommandBuffer::begin_rendering
+                Ok(Target {
+                    vk_render_pass: vk::RenderPass::null(),
+                    sample_count: vk::SampleCountFlags::TYPE_1,
+                    color_attachment_count: color_formats.len() as u32,
+                    has_depth: depth_format.is_some(),
+                    has_stencil: false,
+                })
+            }
+            None => self.render_pass_target(),
+        }
+    }
+
+    #[cfg(not(feature = "dynamic_rendering"))]
+    fn target(&self) -> Result<Target, PipelineError> {
+        self.render_pass_target()
+    }
+
+    fn render_pass_target(&self) -> Result<Target, PipelineError> {
+        let render_pass = self
+            .render_pass
+            .ok_or(PipelineError::MissingArg("render pass"))?;
+        Ok(Target {
+            vk_render_pass: *render_pass.vk_render_pass(),
+            sample_count: render_pass.subpass_sample_count(self.subpass),
+            color_attachment_count: render_pass.subpass_color_attachment_count(self.subpass),
+            has_depth: render_pass.subpass_has_depth(self.subpass),
+            has_stencil: render_pass.subpass_has_stencil(self.subpass),
+        })
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.rasterizer_state.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.rasterizer_state.front_face = front_face;
+        self
+    }
+
+    /// Anything but FILL requires the `fillModeNonSolid` device feature
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.rasterizer_state.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Flip the viewport vertically (with a negative height) so that shaders and projection
+    /// matrices written for OpenGL's Y-up clip space render upright, without negating the Y scale
+    /// of the projection matrix. Facing is determined in framebuffer space, so the flip reverses
+    /// the winding of what the shader outputs. Triangles that are counter-clockwise with GL
+    /// conventions are counter-clockwise after the flip too, and `front_face` does not have to
+    /// change. Needs Vulkan 1.1 or `VK_KHR_maintenance1`, `build` returns an error if the device
+    /// has neither. Defaults to false.
+    pub fn flip_viewport_y(mut self, flip: bool) -> Self {
+        self.rasterizer_state.flip_viewport_y = flip;
+        self
+    }
+
+    /// Set the viewport and scissor with `CommandBuffer::set_viewport` and `set_scissor` instead
+    /// of using the viewport extent, e.g. for split-screen. Defaults to false.
+    pub fn dynamic_viewport(mut self, dynamic: bool) -> Self {
+        self.rasterizer_state.dynamic_viewport = dynamic;
+        self
+    }
+
+    pub fn blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn depth_test_enable(mut self, enable: bool) -> Self {
+        self.depth_state.test_enable = enable;
+        self
+    }
+
+    pub fn depth_write_enable(mut self, enable: bool) -> Self {
+        self.depth_state.write_enable = enable;
+        self
+    }
+
+    pub fn depth_compare_op(mut self, op: vk::CompareOp) -> Self {
+        self.depth_state.compare_op = op;
+        self
+    }
+
+    /// Requires a subpass with a stencil attachment, see `RenderPass::subpass_has_stencil`
+    pub fn stencil_mode(mut self, mode: StencilMode) -> Self {
+        self.depth_state.stencil = stencil_op_state(mode).map(|op| [op, op]);
+        self
+    }
+
+    /// As `stencil_mode`, but with full control over the front and back face operations. Their
+    /// `reference` is ignored.
+    pub fn stencil_ops(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.depth_state.stencil = Some([front, back]);
+        self
+    }
+
+    fn depth_state(mut self, state: DepthState) -> Self {
+        self.depth_state = state;
+        self
+    }
+
+    fn declared_bindings(mut self, bindings: &[(u32, vk::DescriptorSetLayoutBinding)]) -> Self {
+        self.descriptor_bindings.extend_from_slice(bindings);
+        self
+    }
+
+    fn rasterizer_state(mut self, state: RasterizerState) -> Self {
+        self.rasterizer_state = state;
+        self
+    }
+
+    /// Declare the uniform buffers found in the shaders as dynamic, see
+    /// `UniformBufferDescriptor::Dynamic`.
+    pub fn dynamic_uniform_buffers(mut self, dynamic: bool) -> Self {
+        self.dynamic_uniform_buffers = dynamic;
+        self
+    }
+
+    /// Declare a descriptor binding instead of relying on the one reflected from the shaders,
+    /// e.g. to give a runtime-sized array a size. Replaces the reflected binding, if any, and a
+    /// warning is logged if they don't match.
+    pub fn descriptor_binding(
+        mut self,
+        set: u32,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        self.descriptor_bindings.push((
+            set,
+            vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type,
+                descriptor_count: count,
+                stage_flags,
+                ..Default::default()
+            },
+        ));
+        self
+    }
+
+    /// Make the bindings declared with `descriptor_binding` PARTIALLY_BOUND and
+    /// UPDATE_AFTER_BIND, for bindless texture and buffer arrays. Requires descriptor indexing,
+    /// see `Device::descriptor_indexing_enabled`. Defaults to false.
+    pub fn bindless(mut self, bindless: bool) -> Self {
+        self.bindless = bindless;
+        self
+    }
+
     pub fn build(self) -> Result<GraphicsPipeline, PipelineError> {
+        // Before the fields are moved out of self
+        let target = self.target()?;
         let vert = self
             .vert
             .ok_or(PipelineError::MissingArg("vertex shader"))?;
@@ -232,12 +752,57 @@ impl<'a> GraphicsPipelineBuilder<'a> {
         let viewport_extent = self
             .viewport_extent
             .ok_or(PipelineError::MissingArg("viewport extent"))?;
-        let render_pass = self
-            .render_pass
-            .ok_or(PipelineError::MissingArg("render pass"))?;
+
+        let RasterizerState {
+            cull_mode,
+            front_face,
+            polygon_mode,
+            flip_viewport_y,
+            dynamic_viewport,
+        } = self.rasterizer_state;
+
+        if polygon_mode != vk::PolygonMode::FILL
+            && self.device.features().fill_mode_non_solid != vk::TRUE
+        {
+            return Err(PipelineError::MissingDeviceFeature("fillModeNonSolid"));
+        }
+
+        if self.depth_state.stencil.is_some() && !target.has_stencil {
+            return Err(PipelineError::MissingStencilAttachment(self.subpass));
+        }
+
+        if self.bindless && !self.device.descriptor_indexing_enabled() {
+            return Err(PipelineError::MissingDeviceFeature("descriptorIndexing"));
+        }
+
+        if flip_viewport_y && !self.device.negative_viewport_height_enabled() {
+            return Err(PipelineError::MissingDeviceFeature("VK_KHR_maintenance1"));
+        }
+
+        let mut set_layouts = self.refl_descriptor_set_layouts;
+        for &(set, binding) in self.descriptor_bindings.iter() {
+            if let Some(reflected) = set_layouts.override_binding(set as usize, binding) {
+                warn_binding_mismatch(set, &reflected, &binding);
+            }
+        }
 
         let vk_device = self.device.vk_device();
-        let stages = [vert.create_info, frag.create_info];
+        let entry_names = [
+            vert.entry_name(&self.vert_entry)?,
+            frag.entry_name(&self.frag_entry)?,
+        ];
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vert.stage)
+                .module(vert.shader_module.vk_shader_module)
+                .name(&entry_names[0])
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(frag.stage)
+                .module(frag.shader_module.vk_shader_module)
+                .name(&entry_names[1])
+                .build(),
+        ];
 
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
@@ -246,28 +811,68 @@ impl<'a> GraphicsPipelineBuilder<'a> {
         let raster_state_info = vk::PipelineRasterizationStateCreateInfo::builder()
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
+            .polygon_mode(polygon_mode)
             .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .cull_mode(cull_mode)
+            .front_face(front_face)
             .depth_bias_enable(false);
 
         let msaa_info = vk::PipelineMultisampleStateCreateInfo::builder()
             .sample_shading_enable(false)
-            .rasterization_samples(render_pass.msaa_sample_count());
-
-        let color_blend_attach_info = vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::all())
-            .blend_enable(false);
-
-        let attachments = [*color_blend_attach_info];
+            .rasterization_samples(target.sample_count);
+
+        // One blend state per color attachment of the subpass, they all use the same blend mode
+        let color_attachment_count = target.color_attachment_count;
+        if frag.output_location_count != color_attachment_count {
+            log::warn!(
+                "Fragment shader {} writes {} outputs but subpass {} has {} color attachments",
+                frag.name,
+                frag.output_location_count,
+                self.subpass,
+                color_attachment_count
+            );
+        }
+        let attachments =
+            vec![color_blend_attachment_state(self.blend_mode); color_attachment_count as usize];
         let color_blend_state_info = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
             .attachments(&attachments);
 
-        let mut descriptor_set_layouts = Vec::with_capacity(self.refl_descriptor_set_layouts.len());
-        for dset in self.refl_descriptor_set_layouts.layouts() {
-            let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&dset.bindings);
+        let (declared_bindings, bindless) = (&self.descriptor_bindings, self.bindless);
+        let mut descriptor_set_layouts = Vec::with_capacity(set_layouts.len());
+        for dset in set_layouts.layouts() {
+            let mut bindings = dset.bindings.clone();
+            if self.dynamic_uniform_buffers {
+                for b in bindings.iter_mut() {
+                    if b.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER {
+                        b.descriptor_type = vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC;
+                    }
+                }
+            }
+
+            let binding_flags = bindings
+                .iter()
+                .map(|b| {
+                    let declared = declared_bindings
+                        .iter()
+                        .any(|(set, d)| *set as usize == dset.set_idx && d.binding == b.binding);
+                    if bindless && declared {
+                        vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                    } else {
+                        vk::DescriptorBindingFlags::empty()
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+                .binding_flags(&binding_flags);
+
+            let mut info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            if binding_flags.iter().any(|f| !f.is_empty()) {
+                info = info
+                    .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                    .push_next(&mut binding_flags_info);
+            }
 
             let dset_layout = unsafe {
                 vk_device
@@ -287,20 +892,20 @@ impl<'a> GraphicsPipelineBuilder<'a> {
                 .map_err(|e| PipelineError::VulkanObjectCreation(e, "Pipeline layout"))?
         };
 
-        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS)
+        let mut depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_state.test_enable)
+            .depth_write_enable(self.depth_state.write_enable)
+            .depth_compare_op(self.depth_state.compare_op)
             .depth_bounds_test_enable(false)
             .stencil_test_enable(false);
+        if let Some([front, back]) = self.depth_state.stencil {
+            depth_stencil = depth_stencil
+                .stencil_test_enable(true)
+                .front(front)
+                .back(back);
+        }
 
-        let viewport = vk::Viewport::builder()
-            .x(0.0)
-            .y(0.0)
-            .width(viewport_extent.width as f32)
-            .height(viewport_extent.height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0);
+        let viewport = viewport(viewport_extent, flip_viewport_y);
 
         let scissor_extent: vk::Extent2D = viewport_extent.into();
 
@@ -309,13 +914,24 @@ impl<'a> GraphicsPipelineBuilder<'a> {
             extent: scissor_extent,
         };
 
-        let viewports = [*viewport];
+        let viewports = [viewport];
         let scissors = [scissor];
         let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
             .viewports(&viewports)
             .scissors(&scissors);
+        let mut dynamic_states = Vec::new();
+        if dynamic_viewport {
+            // The static viewport and scissor are ignored then, only their count is used
+            dynamic_states
+                .extend_from_slice(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        }
+        if self.depth_state.stencil.is_some() {
+            dynamic_states.push(vk::DynamicState::STENCIL_REFERENCE);
+        }
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
 
-        let g_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        let mut g_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&stages)
             .vertex_input_state(&vertex_input.create_info)
             .input_assembly_state(&input_assembly_info)
@@ -323,16 +939,43 @@ impl<'a> GraphicsPipelineBuilder<'a> {
             .rasterization_state(&raster_state_info)
             .multisample_state(&msaa_info)
             .color_blend_state(&color_blend_state_info)
-            .depth_stencil_state(&depth_stencil)
             .layout(pipeline_layout)
-            .render_pass(*render_pass.vk_render_pass())
-            .subpass(0);
+            .render_pass(target.vk_render_pass)
+            .subpass(self.subpass);
+
+        // Without a depth attachment, there is nothing to test against
+        if target.has_depth {
+            g_pipeline_info = g_pipeline_info.depth_stencil_state(&depth_stencil);
+        }
+
+        #[cfg(feature = "dynamic_rendering")]
+        let mut rendering_info =
+            self.rendering_formats
+                .as_ref()
+                .map(|(color_formats, depth_format)| {
+                    crate::dynamic_rendering::PipelineRenderingCreateInfo::new(
+                        color_formats,
+                        depth_format.unwrap_or(vk::Format::UNDEFINED),
+                        vk::Format::UNDEFINED,
+                    )
+                });
+        #[cfg(feature = "dynamic_rendering")]
+        if let Some(rendering_info) = rendering_info.as_mut() {
+            g_pipeline_info = g_pipeline_info.push_next(rendering_info);
+        }
+
+        if !dynamic_states.is_empty() {
+            g_pipeline_info = g_pipeline_info.dynamic_state(&dynamic_state_info);
+        }
 
         let create_infos = [*g_pipeline_info];
 
-        // TODO: Use the cache
         let vk_pipelines_result = unsafe {
-            vk_device.create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
+            vk_device.create_graphics_pipelines(
+                *self.device.pipeline_cache().vk_pipeline_cache(),
+                &create_infos,
+                None,
+            )
         };
         // According to: https://renderdoc.org/vkspec_chunked/chap10.html#pipelines-multiple
         // Implementations will attempt to create as many pipelines as possible, but if any fail, we really want to exit anyway.
@@ -343,6 +986,9 @@ impl<'a> GraphicsPipelineBuilder<'a> {
         assert_eq!(pipelines.len(), 1, "Expected single pipeline");
 
         let vk_pipeline = pipelines[0];
+        let name = format!("{} | {}", vert.name, frag.name);
+        self.device.name_object(vk_pipeline, &name);
+        self.device.name_object(pipeline_layout, &name);
 
         Ok(GraphicsPipeline {
             vk_device,
@@ -357,8 +1003,16 @@ impl<'a> GraphicsPipelineBuilder<'a> {
 pub struct GraphicsPipelineDescriptor {
     vert: PathBuf,
     frag: PathBuf,
-    vert_binding_description: Vec<vk::VertexInputBindingDescription>,
-    vert_attribute_description: Vec<vk::VertexInputAttributeDescription>,
+    vert_entry: String,
+    frag_entry: String,
+    vertex_format: VertexFormat,
+    dynamic_uniform_buffers: bool,
+    descriptor_bindings: Vec<(u32, vk::DescriptorSetLayoutBinding)>,
+    bindless: bool,
+    rasterizer_state: RasterizerState,
+    blend_mode: BlendMode,
+    depth_state: DepthState,
+    subpass: u32,
 }
 
 impl GraphicsPipelineDescriptor {
@@ -366,8 +1020,17 @@ impl GraphicsPipelineDescriptor {
         GraphicsPipelineDescriptorBuilder {
             vert: None,
             frag: None,
-            vert_attribute_description: Vec::new(),
-            vert_binding_description: Vec::new(),
+            vert_entry: DEFAULT_ENTRY_POINT.to_owned(),
+            frag_entry: DEFAULT_ENTRY_POINT.to_owned(),
+            vertex_format: None,
+            instance_format: None,
+            dynamic_uniform_buffers: false,
+            descriptor_bindings: Vec::new(),
+            bindless: false,
+            rasterizer_state: RasterizerState::default(),
+            blend_mode: BlendMode::default(),
+            depth_state: DepthState::default(),
+            subpass: 0,
         }
     }
 }
@@ -375,8 +1038,17 @@ impl GraphicsPipelineDescriptor {
 pub struct GraphicsPipelineDescriptorBuilder {
     vert: Option<PathBuf>,
     frag: Option<PathBuf>,
-    vert_binding_description: Vec<vk::VertexInputBindingDescription>,
-    vert_attribute_description: Vec<vk::VertexInputAttributeDescription>,
+    vert_entry: String,
+    frag_entry: String,
+    vertex_format: Option<VertexFormat>,
+    instance_format: Option<VertexFormat>,
+    dynamic_uniform_buffers: bool,
+    descriptor_bindings: Vec<(u32, vk::DescriptorSetLayoutBinding)>,
+    bindless: bool,
+    rasterizer_state: RasterizerState,
+    blend_mode: BlendMode,
+    depth_state: DepthState,
+    subpass: u32,
 }
 
 impl GraphicsPipelineDescriptorBuilder {
@@ -390,12 +1062,148 @@ impl GraphicsPipelineDescriptorBuilder {
         self
     }
 
+    /// Defaults to "main", see `GraphicsPipelineBuilder::vertex_entry`
+    pub fn vertex_entry(mut self, name: &str) -> Self {
+        self.vert_entry = name.to_owned();
+        self
+    }
+
+    /// Defaults to "main"
+    pub fn fragment_entry(mut self, name: &str) -> Self {
+        self.frag_entry = name.to_owned();
+        self
+    }
+
     pub fn vertex_type<V>(mut self) -> Self
     where
         V: VertexDefinition,
     {
-        self.vert_binding_description = V::binding_description();
-        self.vert_attribute_description = V::attribute_description();
+        self.vertex_format = Some(V::format());
+        self
+    }
+
+    /// Use an explicit vertex input description, e.g. when attributes are split over several
+    /// vertex buffer bindings. See `CommandBuffer::bind_vertex_buffers`.
+    pub fn vertex_format(mut self, format: VertexFormat) -> Self {
+        self.vertex_format = Some(format);
+        self
+    }
+
+    /// Per-instance attributes, read from the vertex buffer bound after the vertex buffer(s) of
+    /// `vertex_type`/`vertex_format`. The bindings of `I` are renumbered to come after those and
+    /// always use `vk::VertexInputRate::INSTANCE`.
+    pub fn instance_type<I>(mut self) -> Self
+    where
+        I: VertexDefinition,
+    {
+        self.instance_format = Some(I::format());
+        self
+    }
+
+    pub fn instance_format(mut self, format: VertexFormat) -> Self {
+        self.instance_format = Some(format);
+        self
+    }
+
+    pub fn dynamic_uniform_buffers(mut self, dynamic: bool) -> Self {
+        self.dynamic_uniform_buffers = dynamic;
+        self
+    }
+
+    /// See `GraphicsPipelineBuilder::descriptor_binding`
+    pub fn descriptor_binding(
+        mut self,
+        set: u32,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        self.descriptor_bindings.push((
+            set,
+            vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type,
+                descriptor_count: count,
+                stage_flags,
+                ..Default::default()
+            },
+        ));
+        self
+    }
+
+    /// Defaults to false, see `GraphicsPipelineBuilder::bindless`
+    pub fn bindless(mut self, bindless: bool) -> Self {
+        self.bindless = bindless;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.rasterizer_state.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.rasterizer_state.front_face = front_face;
+        self
+    }
+
+    /// Anything but FILL requires the `fillModeNonSolid` device feature
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.rasterizer_state.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// See `GraphicsPipelineBuilder::flip_viewport_y`
+    pub fn flip_viewport_y(mut self, flip: bool) -> Self {
+        self.rasterizer_state.flip_viewport_y = flip;
+        self
+    }
+
+    /// Set the viewport and scissor with `CommandBuffer::set_viewport` and `set_scissor` instead
+    /// of using the viewport extent, e.g. for split-screen. Defaults to false.
+    pub fn dynamic_viewport(mut self, dynamic: bool) -> Self {
+        self.rasterizer_state.dynamic_viewport = dynamic;
+        self
+    }
+
+    pub fn blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    /// Defaults to true
+    pub fn depth_test_enable(mut self, enable: bool) -> Self {
+        self.depth_state.test_enable = enable;
+        self
+    }
+
+    /// Defaults to true
+    pub fn depth_write_enable(mut self, enable: bool) -> Self {
+        self.depth_state.write_enable = enable;
+        self
+    }
+
+    /// Defaults to LESS
+    pub fn depth_compare_op(mut self, op: vk::CompareOp) -> Self {
+        self.depth_state.compare_op = op;
+        self
+    }
+
+    /// Defaults to `StencilMode::Disabled`, see `GraphicsPipelineBuilder::stencil_mode`
+    pub fn stencil_mode(mut self, mode: StencilMode) -> Self {
+        self.depth_state.stencil = stencil_op_state(mode).map(|op| [op, op]);
+        self
+    }
+
+    pub fn stencil_ops(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.depth_state.stencil = Some([front, back]);
+        self
+    }
+
+    /// Defaults to 0
+    pub fn subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
         self
     }
 
@@ -406,53 +1214,125 @@ impl GraphicsPipelineDescriptorBuilder {
         let frag = self
             .frag
             .ok_or(PipelineError::MissingArg("fragment shader"))?;
-        if self.vert_binding_description.is_empty() || self.vert_attribute_description.is_empty() {
-            return Err(PipelineError::MissingArg("vertex description"));
-        }
-
-        let vert_binding_description = self.vert_binding_description;
-        let vert_attribute_description = self.vert_attribute_description;
+        let vertex_format = self
+            .vertex_format
+            .filter(|f| !f.binding_description.is_empty() && !f.attribute_description.is_empty())
+            .ok_or(PipelineError::MissingArg("vertex description"))?;
+        let vertex_format = match self.instance_format {
+            Some(instance_format) => vertex_format.with_instance_format(instance_format),
+            None => vertex_format,
+        };
 
         Ok(GraphicsPipelineDescriptor {
             vert,
             frag,
-            vert_binding_description,
-            vert_attribute_description,
+            vert_entry: self.vert_entry,
+            frag_entry: self.frag_entry,
+            vertex_format,
+            dynamic_uniform_buffers: self.dynamic_uniform_buffers,
+            descriptor_bindings: self.descriptor_bindings,
+            bindless: self.bindless,
+            rasterizer_state: self.rasterizer_state,
+            blend_mode: self.blend_mode,
+            depth_state: self.depth_state,
+            subpass: self.subpass,
         })
     }
 }
 
-#[derive(Default)]
-pub struct GraphicsPipelines {
-    desc_storage: Storage<GraphicsPipelineDescriptor>,
-    mat_storage: Storage<GraphicsPipeline>,
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
-impl GraphicsPipelines {
-    pub fn new() -> Self {
-        Self {
-            desc_storage: Default::default(),
-            mat_storage: Default::default(),
-        }
+// What is needed to rebuild a pipeline on resize or when its shaders have been modified
+struct PipelineSource {
+    descriptor: GraphicsPipelineDescriptor,
+    // Resolved vertex and fragment shader paths
+    shader_paths: [PathBuf; 2],
+    shader_modification_times: [Option<SystemTime>; 2],
+}
+
+impl PipelineSource {
+    fn new(
+        descriptor: GraphicsPipelineDescriptor,
+        shader_dir: Option<&Path>,
+    ) -> Result<Self, PipelineError> {
+        let shader_paths = [
+            resolve_shader_path(&descriptor.vert, shader_dir)?,
+            resolve_shader_path(&descriptor.frag, shader_dir)?,
+        ];
+        let mut src = Self {
+            descriptor,
+            shader_paths,
+            shader_modification_times: [None, None],
+        };
+        src.shader_modification_times = src.current_modification_times();
+        Ok(src)
     }
 
-    fn create_pipeline(
+    fn current_modification_times(&self) -> [Option<SystemTime>; 2] {
+        [
+            modification_time(&self.shader_paths[0]),
+            modification_time(&self.shader_paths[1]),
+        ]
+    }
+
+    fn shaders_changed(&self) -> bool {
+        self.current_modification_times() != self.shader_modification_times
+    }
+
+    fn build(
+        &self,
         device: &Device,
         viewport_extent: util::Extent2D,
         render_pass: &RenderPass,
-        descriptor: &GraphicsPipelineDescriptor,
     ) -> Result<GraphicsPipeline, PipelineError> {
+        let descriptor = &self.descriptor;
         GraphicsPipeline::builder(device)
-            .vertex_shader(&descriptor.vert)?
-            .fragment_shader(&descriptor.frag)?
+            .vertex_shader(&self.shader_paths[0])?
+            .fragment_shader(&self.shader_paths[1])?
+            .vertex_entry(&descriptor.vert_entry)
+            .fragment_entry(&descriptor.frag_entry)
             .vertex_input(
-                &descriptor.vert_attribute_description,
-                &descriptor.vert_binding_description,
+                &descriptor.vertex_format.attribute_description,
+                &descriptor.vertex_format.binding_description,
             )
             .viewport_extent(viewport_extent)
             .render_pass(render_pass)
+            .subpass(descriptor.subpass)
+            .dynamic_uniform_buffers(descriptor.dynamic_uniform_buffers)
+            .declared_bindings(&descriptor.descriptor_bindings)
+            .bindless(descriptor.bindless)
+            .rasterizer_state(descriptor.rasterizer_state)
+            .blend_mode(descriptor.blend_mode)
+            .depth_state(descriptor.depth_state)
             .build()
     }
+}
+
+#[derive(Default)]
+pub struct GraphicsPipelines {
+    // None for pipelines that don't target the swapchain, they are not recreated on resize
+    desc_storage: Storage<Option<PipelineSource>>,
+    mat_storage: Storage<GraphicsPipeline>,
+    shader_dir: Option<PathBuf>,
+}
+
+impl GraphicsPipelines {
+    /// Relative shader paths in descriptors are resolved against `shader_dir`, see
+    /// `resolve_shader_path`. A relative `shader_dir` is relative to the current dir at the time
+    /// of this call.
+    pub fn new(shader_dir: Option<PathBuf>) -> Result<Self, PipelineError> {
+        let shader_dir = match shader_dir {
+            Some(dir) => Some(std::env::current_dir()?.join(dir)),
+            None => None,
+        };
+        Ok(Self {
+            desc_storage: Default::default(),
+            mat_storage: Default::default(),
+            shader_dir,
+        })
+    }
 
     pub fn recreate_all(
         &mut self,
@@ -460,13 +1340,96 @@ impl GraphicsPipelines {
         viewport_extent: util::Extent2D,
         render_pass: &RenderPass,
     ) -> Result<(), PipelineError> {
-        for (pipe, desc) in self.mat_storage.iter_mut().zip(self.desc_storage.iter()) {
-            *pipe = Self::create_pipeline(device, viewport_extent, render_pass, &desc)?;
+        self.recreate_if(device, viewport_extent, render_pass, |_| true)
+    }
+
+    /// Recreate the pipelines that have the viewport extent baked in, i.e. those without a
+    /// dynamic viewport. For when only the extent changed and `render_pass` is the same as before.
+    pub fn recreate_for_extent(
+        &mut self,
+        device: &Device,
+        viewport_extent: util::Extent2D,
+        render_pass: &RenderPass,
+    ) -> Result<(), PipelineError> {
+        self.recreate_if(device, viewport_extent, render_pass, |src| {
+            !src.descriptor.rasterizer_state.dynamic_viewport
+        })
+    }
+
+    fn recreate_if(
+        &mut self,
+        device: &Device,
+        viewport_extent: util::Extent2D,
+        render_pass: &RenderPass,
+        pred: impl Fn(&PipelineSource) -> bool,
+    ) -> Result<(), PipelineError> {
+        for (pipe, src) in self.mat_storage.iter_mut().zip(self.desc_storage.iter()) {
+            match src {
+                Some(src) if pred(src) => {
+                    *pipe = src.build(device, viewport_extent, render_pass)?
+                }
+                _ => (),
+            }
         }
 
         Ok(())
     }
 
+    /// True if any of the shader files have been modified since their pipeline was built
+    pub fn shaders_changed(&self) -> bool {
+        self.desc_storage
+            .iter()
+            .any(|src| src.as_ref().map_or(false, PipelineSource::shaders_changed))
+    }
+
+    /// Rebuild the pipelines whose shader files have been modified and return how many were
+    /// rebuilt. The old pipelines are destroyed, so none of them may be in use by the GPU.
+    ///
+    /// If a pipeline fails to build (e.g. the new SPIR-V is invalid), the old one is kept and the
+    /// first error is returned after the rest have been rebuilt. It is retried when the shader
+    /// files are modified again.
+    pub fn reload_changed(
+        &mut self,
+        device: &Device,
+        viewport_extent: util::Extent2D,
+        render_pass: &RenderPass,
+    ) -> Result<usize, PipelineError> {
+        let mut n_reloaded = 0;
+        let mut first_err = None;
+        for (pipe, src) in self
+            .mat_storage
+            .iter_mut()
+            .zip(self.desc_storage.iter_mut())
+        {
+            let src = match src {
+                Some(src) if src.shaders_changed() => src,
+                _ => continue,
+            };
+
+            src.shader_modification_times = src.current_modification_times();
+            match src.build(device, viewport_extent, render_pass) {
+                Ok(new) => {
+                    *pipe = new;
+                    n_reloaded += 1;
+                }
+                Err(e) => {
+                    log::error!(
+                        "Failed to reload pipeline ({}, {}): {}",
+                        src.shader_paths[0].display(),
+                        src.shader_paths[1].display(),
+                        e
+                    );
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(n_reloaded),
+        }
+    }
+
     pub fn create(
         &mut self,
         device: &Device,
@@ -474,12 +1437,464 @@ impl GraphicsPipelines {
         viewport_extent: util::Extent2D,
         render_pass: &RenderPass,
     ) -> Result<Handle<GraphicsPipeline>, PipelineError> {
-        let pipeline = Self::create_pipeline(device, viewport_extent, render_pass, &descriptor)?;
-        self.desc_storage.add(descriptor);
+        let src = PipelineSource::new(descriptor, self.shader_dir.as_deref())?;
+        let pipeline = src.build(device, viewport_extent, render_pass)?;
+        self.desc_storage.add(Some(src));
+        Ok(self.mat_storage.add(pipeline))
+    }
+
+    /// Create a pipeline for a render pass that is not recreated with the swapchain, e.g. the
+    /// one of an `OffscreenTarget`.
+    pub fn create_fixed(
+        &mut self,
+        device: &Device,
+        descriptor: &GraphicsPipelineDescriptor,
+        viewport_extent: util::Extent2D,
+        render_pass: &RenderPass,
+    ) -> Result<Handle<GraphicsPipeline>, PipelineError> {
+        let src = PipelineSource::new(descriptor.clone(), self.shader_dir.as_deref())?;
+        let pipeline = src.build(device, viewport_extent, render_pass)?;
+        self.desc_storage.add(None);
         Ok(self.mat_storage.add(pipeline))
     }
 
     pub fn get(&self, h: &Handle<GraphicsPipeline>) -> Option<&GraphicsPipeline> {
         self.mat_storage.get(h)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<GraphicsPipeline>, &GraphicsPipeline)> {
+        self.mat_storage.iter_with_handles()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+    use crate::test_util::{headless_renderer, write_spirv, Position, POSITION_SPV_VERT};
+    use crate::ResourceManager;
+
+    #[test]
+    fn descriptor_binding_mismatch() {
+        let textures = |descriptor_count, stage_flags| vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count,
+            stage_flags,
+            ..Default::default()
+        };
+        let reflected = textures(4, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(
+            binding_mismatch(&reflected, &textures(64, vk::ShaderStageFlags::FRAGMENT)),
+            None
+        );
+        assert_eq!(
+            binding_mismatch(&reflected, &textures(2, vk::ShaderStageFlags::FRAGMENT)),
+            Some("descriptor count")
+        );
+        assert_eq!(
+            binding_mismatch(&reflected, &textures(4, vk::ShaderStageFlags::VERTEX)),
+            Some("stages that use it")
+        );
+        let storage = vk::DescriptorSetLayoutBinding {
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            ..reflected
+        };
+        assert_eq!(
+            binding_mismatch(&reflected, &storage),
+            Some("descriptor type")
+        );
+    }
+
+    #[test]
+    fn stencil_modes() {
+        assert!(stencil_op_state(StencilMode::Disabled).is_none());
+
+        let write = stencil_op_state(StencilMode::Write).unwrap();
+        assert_eq!(write.compare_op, vk::CompareOp::ALWAYS);
+        assert_eq!(write.pass_op, vk::StencilOp::REPLACE);
+        assert_eq!(write.write_mask, 0xff);
+
+        let equal = stencil_op_state(StencilMode::Equal).unwrap();
+        assert_eq!(equal.compare_op, vk::CompareOp::EQUAL);
+        assert_eq!(equal.pass_op, vk::StencilOp::KEEP);
+        assert_eq!(equal.write_mask, 0);
+
+        let not_equal = stencil_op_state(StencilMode::NotEqual).unwrap();
+        assert_eq!(not_equal.compare_op, vk::CompareOp::NOT_EQUAL);
+        assert_eq!(not_equal.write_mask, 0);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let opaque = color_blend_attachment_state(BlendMode::Opaque);
+        assert_eq!(opaque.blend_enable, vk::FALSE);
+        assert_eq!(opaque.color_write_mask, vk::ColorComponentFlags::all());
+
+        let alpha = color_blend_attachment_state(BlendMode::AlphaBlend);
+        assert_eq!(alpha.blend_enable, vk::TRUE);
+        assert_eq!(alpha.src_color_blend_factor, vk::BlendFactor::SRC_ALPHA);
+        assert_eq!(
+            alpha.dst_color_blend_factor,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+        );
+        assert_eq!(alpha.color_blend_op, vk::BlendOp::ADD);
+
+        let additive = color_blend_attachment_state(BlendMode::Additive);
+        assert_eq!(additive.blend_enable, vk::TRUE);
+        assert_eq!(additive.dst_color_blend_factor, vk::BlendFactor::ONE);
+
+        let premul = color_blend_attachment_state(BlendMode::PremultipliedAlpha);
+        assert_eq!(premul.blend_enable, vk::TRUE);
+        assert_eq!(premul.src_color_blend_factor, vk::BlendFactor::ONE);
+        assert_eq!(
+            premul.dst_color_blend_factor,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+        );
+    }
+
+    #[test]
+    fn shader_modification_detection() {
+        let dir = std::env::temp_dir().join("trekanten_shader_modification_detection");
+        std::fs::create_dir_all(&dir).unwrap();
+        let vert = dir.join("vert.spv");
+        let frag = dir.join("frag.spv");
+        std::fs::write(&vert, b"vert").unwrap();
+        std::fs::write(&frag, b"frag").unwrap();
+
+        let descriptor = GraphicsPipelineDescriptor::builder()
+            .vertex_shader(&vert)
+            .fragment_shader(&frag)
+            .vertex_format(VertexFormat {
+                binding_description: vec![vk::VertexInputBindingDescription::default()],
+                attribute_description: vec![vk::VertexInputAttributeDescription::default()],
+            })
+            .build()
+            .unwrap();
+        let mut src = PipelineSource::new(descriptor, None).unwrap();
+        assert!(!src.shaders_changed());
+
+        // Pretend the pipeline was built before the last modification
+        src.shader_modification_times[1] = Some(SystemTime::UNIX_EPOCH);
+        assert!(src.shaders_changed());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(src.shaders_changed());
+    }
+
+    #[test]
+    fn relative_shader_dir() {
+        let dir = std::env::temp_dir().join("trekanten_relative_shader_dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("vert.spv"), b"vert").unwrap();
+        std::fs::write(dir.join("frag.spv"), b"frag").unwrap();
+
+        // The temp dir, relative to the current dir
+        let current_dir = std::env::current_dir().unwrap();
+        let relative = current_dir
+            .components()
+            .skip(1)
+            .map(|_| Path::new(".."))
+            .collect::<PathBuf>()
+            .join(dir.strip_prefix("/").unwrap());
+        assert!(relative.is_relative());
+
+        let pipelines = GraphicsPipelines::new(Some(relative)).unwrap();
+        let shader_dir = pipelines.shader_dir.as_deref().unwrap();
+        assert!(shader_dir.is_absolute());
+
+        let descriptor = GraphicsPipelineDescriptor::builder()
+            .vertex_shader("vert.spv")
+            .fragment_shader("frag.spv")
+            .vertex_format(VertexFormat {
+                binding_description: vec![vk::VertexInputBindingDescription::default()],
+                attribute_description: vec![vk::VertexInputAttributeDescription::default()],
+            })
+            .build()
+            .unwrap();
+        let src = PipelineSource::new(descriptor, Some(shader_dir)).unwrap();
+        for path in &src.shader_paths {
+            assert!(path.is_file(), "{}", path.display());
+            // Resolving again, as the pipeline builder does, keeps the path
+            assert_eq!(&resolve_shader_path(path, None).unwrap(), path);
+        }
+    }
+
+    #[test]
+    fn shader_path_resolution() {
+        let dir = std::env::temp_dir();
+        let abs = dir.join("shader.spv");
+        assert_eq!(resolve_shader_path(&abs, None).unwrap(), abs);
+        assert_eq!(
+            resolve_shader_path(&abs, Some(Path::new("/other"))).unwrap(),
+            abs
+        );
+        assert_eq!(
+            resolve_shader_path(Path::new("vert.spv"), Some(&dir)).unwrap(),
+            dir.join("vert.spv")
+        );
+    }
+
+    #[test]
+    fn instance_input_rate() {
+        let binding = |binding| vk::VertexInputBindingDescription {
+            binding,
+            stride: 12,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let attribute = |binding, location| vk::VertexInputAttributeDescription {
+            binding,
+            location,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: 0,
+        };
+
+        let descriptor = GraphicsPipelineDescriptor::builder()
+            .vertex_shader("vert.spv")
+            .fragment_shader("frag.spv")
+            .vertex_format(VertexFormat {
+                binding_description: vec![binding(0)],
+                attribute_description: vec![attribute(0, 0)],
+            })
+            .instance_format(VertexFormat {
+                binding_description: vec![binding(0)],
+                attribute_description: vec![attribute(0, 1)],
+            })
+            .build()
+            .unwrap();
+
+        let bindings = &descriptor.vertex_format.binding_description;
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[0].binding, 0);
+        assert_eq!(bindings[0].input_rate, vk::VertexInputRate::VERTEX);
+        assert_eq!(bindings[1].binding, 1);
+        assert_eq!(bindings[1].input_rate, vk::VertexInputRate::INSTANCE);
+
+        let attributes = &descriptor.vertex_format.attribute_description;
+        assert_eq!(attributes[1].binding, 1);
+        assert_eq!(attributes[1].location, 1);
+    }
+
+    static HALF_RED_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = vec4(1.0, 0.0, 0.0, 0.5);
+        }
+    ",
+        frag
+    );
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn alpha_blended_quads() {
+        let dir = std::env::temp_dir().join("trekanten_alpha_blended_quads");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, HALF_RED_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        let pipeline_handle = renderer
+            .create_resource(
+                GraphicsPipelineDescriptor::builder()
+                    .vertex_shader(&vert)
+                    .fragment_shader(&frag)
+                    .vertex_type::<Position>()
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .depth_test_enable(false)
+                    .blend_mode(BlendMode::AlphaBlend)
+                    .build()
+                    .expect("Failed to create pipeline descriptor"),
+            )
+            .expect("Failed to create pipeline");
+        // Two quads covering the left and right three quarters, they overlap in the middle half
+        let quads = [
+            Position([-1.0, -1.0]),
+            Position([0.5, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([0.5, 1.0]),
+            Position([-0.5, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-0.5, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quads,
+                &[0u32, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7],
+            ))
+            .expect("Failed to create mesh");
+
+        let mut frame = renderer.next_frame().expect("Failed to get frame");
+        let cmd_buf = frame
+            .new_command_buffer()
+            .expect("Failed to create command buffer")
+            .begin_render_pass(
+                renderer.render_pass(),
+                renderer.framebuffer(&frame),
+                renderer.swapchain_extent(),
+            )
+            .bind_graphics_pipeline(renderer.get_resource(&pipeline_handle).unwrap())
+            .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+            .end_render_pass()
+            .end()
+            .expect("Failed to end command buffer");
+        frame
+            .add_command_buffer(cmd_buf)
+            .expect("Failed to add command buffer");
+        renderer
+            .request_readback(&mut frame)
+            .expect("Failed to request readback");
+        renderer.submit(frame).expect("Failed to submit");
+        let image = renderer.read_framebuffer().expect("Failed to read back");
+
+        // Blending happens in linear space, the sRGB target stores the encoded values. Over the
+        // black clear color one quad gives 0.5 red and two give 0.5 + 0.5 * 0.5 = 0.75.
+        let to_srgb = |linear: f32| (1.055 * linear.powf(1.0 / 2.4) - 0.055) * 255.0;
+        let (single, double) = (to_srgb(0.5), to_srgb(0.75));
+        let width = image.width as usize;
+        for (i, px) in image.data.chunks(4).enumerate() {
+            let red = if i % width == 0 || i % width == width - 1 {
+                single
+            } else {
+                double
+            };
+            assert!((px[0] as f32 - red).abs() <= 2.0, "pixel {}: {:?}", i, px);
+            assert_eq!(&px[1..], &[0, 0, 255], "pixel {}", i);
+        }
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn viewport_flip() {
+        let dir = std::env::temp_dir().join("trekanten_viewport_flip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, HALF_RED_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        // The upper half of clip space with GL's Y-up convention, counter-clockwise there
+        let quad = [
+            Position([-1.0, 0.0]),
+            Position([1.0, 0.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad,
+                &[0u32, 1, 2, 2, 1, 3],
+            ))
+            .expect("Failed to create mesh");
+
+        let mut render = |flip: bool, cull_mode: vk::CullModeFlags| {
+            let pipeline_handle = renderer
+                .create_resource(
+                    GraphicsPipelineDescriptor::builder()
+                        .vertex_shader(&vert)
+                        .fragment_shader(&frag)
+                        .vertex_type::<Position>()
+                        .cull_mode(cull_mode)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .flip_viewport_y(flip)
+                        .depth_test_enable(false)
+                        .build()
+                        .expect("Failed to create pipeline descriptor"),
+                )
+                .expect("Failed to create pipeline");
+
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .bind_graphics_pipeline(renderer.get_resource(&pipeline_handle).unwrap())
+                .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer
+                .request_readback(&mut frame)
+                .expect("Failed to request readback");
+            renderer.submit(frame).expect("Failed to submit");
+            renderer.read_framebuffer().expect("Failed to read back")
+        };
+
+        let red_rows = |image: &crate::ImageData| -> Vec<bool> {
+            let row_size = image.width as usize * 4;
+            image
+                .data
+                .chunks(row_size)
+                .map(|row| row.chunks(4).all(|px| px[0] > 0))
+                .collect()
+        };
+
+        // With the flip, GL's top ends up in the first rows and back faces are still culled
+        // with the GL winding
+        let flipped = render(true, vk::CullModeFlags::BACK);
+        assert_eq!(red_rows(&flipped), [true, true, false, false]);
+        // Without it, the quad ends up in the last rows with its winding reversed
+        let normal = render(false, vk::CullModeFlags::NONE);
+        assert_eq!(red_rows(&normal), [false, false, true, true]);
+        let culled = render(false, vk::CullModeFlags::BACK);
+        assert_eq!(red_rows(&culled), [false, false, false, false]);
+
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn recreate_all_replaces_stored_pipelines() {
+        let dir = std::env::temp_dir().join("trekanten_recreate_all_replaces_stored_pipelines");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, HALF_RED_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        let handle = renderer
+            .create_resource(
+                GraphicsPipelineDescriptor::builder()
+                    .vertex_shader(&vert)
+                    .fragment_shader(&frag)
+                    .vertex_type::<Position>()
+                    .build()
+                    .expect("Failed to create pipeline descriptor"),
+            )
+            .expect("Failed to create pipeline");
+        let vk_pipeline =
+            |renderer: &crate::Renderer| *renderer.get_resource(&handle).unwrap().vk_pipeline();
+        let created = vk_pipeline(&renderer);
+
+        renderer
+            .resize(util::Extent2D {
+                width: 8,
+                height: 8,
+            })
+            .expect("Failed to resize");
+        let resized = vk_pipeline(&renderer);
+        assert_ne!(resized, created);
+
+        renderer.recreate_pipelines().expect("Failed to recreate");
+        assert_ne!(vk_pipeline(&renderer), resized);
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
 }
@@ -0,0 +1,83 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use thiserror::Error;
+
+use std::path::Path;
+
+use crate::device::VkDeviceHandle;
+
+#[derive(Debug, Error)]
+pub enum PipelineCacheError {
+    #[error("Pipeline cache creation failed: {0}")]
+    Creation(vk::Result),
+    #[error("Failed to get pipeline cache data: {0}")]
+    Data(vk::Result),
+    #[error("Pipeline cache IO error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// Shared by all pipeline builds so that recreating pipelines (e.g. on resize) doesn't recompile
+/// the shaders. The contents can be persisted between runs with `data`/`save`.
+pub struct PipelineCache {
+    vk_device: VkDeviceHandle,
+    vk_pipeline_cache: vk::PipelineCache,
+}
+
+impl std::ops::Drop for PipelineCache {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device
+                .destroy_pipeline_cache(self.vk_pipeline_cache, None);
+        }
+    }
+}
+
+impl PipelineCache {
+    /// `initial_data` is the output of a previous `data` call, or empty. Data from an
+    /// incompatible driver or device is ignored by the implementation.
+    pub fn new(vk_device: VkDeviceHandle, initial_data: &[u8]) -> Result<Self, PipelineCacheError> {
+        let info = vk::PipelineCacheCreateInfo::builder().initial_data(initial_data);
+
+        let vk_pipeline_cache = unsafe {
+            vk_device
+                .create_pipeline_cache(&info, None)
+                .map_err(PipelineCacheError::Creation)?
+        };
+
+        Ok(Self {
+            vk_device,
+            vk_pipeline_cache,
+        })
+    }
+
+    /// Read the initial data from `path`. A missing or unreadable file gives an empty cache.
+    pub fn load(vk_device: VkDeviceHandle, path: &Path) -> Result<Self, PipelineCacheError> {
+        let initial_data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                log::trace!("Not loading pipeline cache from {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+
+        Self::new(vk_device, &initial_data)
+    }
+
+    pub fn data(&self) -> Result<Vec<u8>, PipelineCacheError> {
+        unsafe {
+            self.vk_device
+                .get_pipeline_cache_data(self.vk_pipeline_cache)
+                .map_err(PipelineCacheError::Data)
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PipelineCacheError> {
+        std::fs::write(path, self.data()?)?;
+        Ok(())
+    }
+
+    pub fn vk_pipeline_cache(&self) -> &vk::PipelineCache {
+        &self.vk_pipeline_cache
+    }
+}
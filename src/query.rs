@@ -0,0 +1,132 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use thiserror::Error;
+
+use crate::device::Device;
+use crate::device::HasVkDevice;
+use crate::device::VkDeviceHandle;
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum QueryError {
+    #[error("Query pool creation failed {0}")]
+    Creation(vk::Result),
+    #[error("Couldn't get query pool results {0}")]
+    Results(vk::Result),
+}
+
+/// Ticks between two timestamps converted to milliseconds. Only the low `valid_bits` bits of a
+/// timestamp are written, so the difference wraps around at that width.
+fn timestamp_delta_ms(start: u64, end: u64, valid_bits: u32, timestamp_period: f32) -> f32 {
+    let mask = if valid_bits >= 64 {
+        !0
+    } else {
+        (1u64 << valid_bits) - 1
+    };
+    let ticks = end.wrapping_sub(start) & mask;
+    // timestamp_period is in nanoseconds per tick
+    (ticks as f64 * timestamp_period as f64 / 1_000_000.0) as f32
+}
+
+/// A query pool of type TIMESTAMP, for measuring GPU time between two points in a command buffer.
+///
+/// Timestamps can only be written on queues whose family has a nonzero `timestampValidBits`.
+/// If `timestampComputeAndGraphics` is set in the device limits, this holds for all graphics and
+/// compute queues. `new` returns `None` when the graphics queue doesn't support timestamps.
+pub struct TimestampQueryPool {
+    vk_device: VkDeviceHandle,
+    vk_query_pool: vk::QueryPool,
+    query_count: u32,
+    valid_bits: u32,
+    timestamp_period: f32,
+}
+
+impl std::ops::Drop for TimestampQueryPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device.destroy_query_pool(self.vk_query_pool, None);
+        }
+    }
+}
+
+impl TimestampQueryPool {
+    pub fn new(device: &Device, query_count: u32) -> Result<Option<Self>, QueryError> {
+        let valid_bits = device.graphics_queue_family().props.timestamp_valid_bits;
+        if valid_bits == 0 {
+            return Ok(None);
+        }
+
+        let info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(query_count);
+
+        let vk_device = device.vk_device();
+        let vk_query_pool = unsafe {
+            vk_device
+                .create_query_pool(&info, None)
+                .map_err(QueryError::Creation)?
+        };
+
+        Ok(Some(Self {
+            vk_device,
+            vk_query_pool,
+            query_count,
+            valid_bits,
+            timestamp_period: device.limits().timestamp_period,
+        }))
+    }
+
+    /// Doesn't block, `None` is returned if the query has not been written yet
+    fn timestamp(&self, query: u32) -> Result<Option<u64>, QueryError> {
+        assert!(query < self.query_count);
+        let mut data = [0u64];
+        let result = unsafe {
+            self.vk_device.get_query_pool_results(
+                self.vk_query_pool,
+                query,
+                1,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+
+        match result {
+            Ok(()) => Ok(Some(data[0])),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(e) => Err(QueryError::Results(e)),
+        }
+    }
+
+    /// Milliseconds between the timestamps in `start_query` and `end_query`, if both have been
+    /// written
+    pub fn elapsed_ms(&self, start_query: u32, end_query: u32) -> Result<Option<f32>, QueryError> {
+        let start = self.timestamp(start_query)?;
+        let end = self.timestamp(end_query)?;
+
+        Ok(start.and_then(|start| {
+            end.map(|end| timestamp_delta_ms(start, end, self.valid_bits, self.timestamp_period))
+        }))
+    }
+
+    pub fn query_count(&self) -> u32 {
+        self.query_count
+    }
+
+    pub fn vk_query_pool(&self) -> &vk::QueryPool {
+        &self.vk_query_pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_delta() {
+        assert_eq!(timestamp_delta_ms(1_000, 2_001_000, 64, 1.0), 2.0);
+        assert_eq!(timestamp_delta_ms(0, 1_000_000, 64, 2.5), 2.5);
+        // The counter wrapped around at 36 bits between the two timestamps
+        let max = (1u64 << 36) - 1;
+        assert_eq!(timestamp_delta_ms(max - 499_999, 500_000, 36, 1.0), 1.0);
+    }
+}
@@ -5,9 +5,10 @@ use thiserror::Error;
 
 use crate::device::VkDeviceHandle;
 
-use crate::command::CommandBuffer;
+use crate::command::{CommandBuffer, Finished};
 use crate::device::HasVkDevice;
 use crate::sync::Fence;
+use crate::sync::Semaphore;
 use crate::sync::SyncError;
 
 #[derive(Debug, Copy, Clone, Error)]
@@ -28,6 +29,38 @@ pub struct QueueFamily {
 pub struct QueueFamilies {
     pub graphics: QueueFamily,
     pub present: QueueFamily,
+    /// Same as graphics if the device has no separate transfer queue family
+    pub transfer: QueueFamily,
+}
+
+/// Reusable storage for the Vulkan handles of a submission. Submitting through the same scratch
+/// every frame only allocates when it has to grow, see `Queue::submit_batch_with`.
+#[derive(Default)]
+pub struct SubmitScratch {
+    cmd_buffers: Vec<vk::CommandBuffer>,
+    wait_semaphores: Vec<vk::Semaphore>,
+    wait_stages: Vec<vk::PipelineStageFlags>,
+    signal_semaphores: Vec<vk::Semaphore>,
+}
+
+impl SubmitScratch {
+    fn fill(
+        &mut self,
+        cmd_buffers: impl Iterator<Item = vk::CommandBuffer>,
+        wait: impl Iterator<Item = (vk::Semaphore, vk::PipelineStageFlags)>,
+        signal: impl Iterator<Item = vk::Semaphore>,
+    ) {
+        self.cmd_buffers.clear();
+        self.cmd_buffers.extend(cmd_buffers);
+        self.wait_semaphores.clear();
+        self.wait_stages.clear();
+        for (semaphore, stage) in wait {
+            self.wait_semaphores.push(semaphore);
+            self.wait_stages.push(stage);
+        }
+        self.signal_semaphores.clear();
+        self.signal_semaphores.extend(signal);
+    }
 }
 
 #[derive(Clone)]
@@ -55,12 +88,62 @@ impl Queue {
         Ok(())
     }
 
-    pub fn submit_and_wait(&self, cmd_buf: &CommandBuffer) -> Result<(), QueueError> {
-        let bufs = [*cmd_buf.vk_command_buffer()];
-        let submit_info = vk::SubmitInfo::builder().command_buffers(&bufs);
+    /// Submit `cmd_buffers` in order. They start executing once all of `wait` are signaled,
+    /// blocking only the given pipeline stages, and `signal` and `fence` are signaled when they
+    /// have completed.
+    pub fn submit_batch(
+        &self,
+        cmd_buffers: &[&CommandBuffer<Finished>],
+        wait: &[(&Semaphore, vk::PipelineStageFlags)],
+        signal: &[&Semaphore],
+        fence: Option<&Fence>,
+    ) -> Result<(), QueueError> {
+        self.submit_batch_with(
+            &mut SubmitScratch::default(),
+            cmd_buffers.iter().copied(),
+            wait,
+            signal,
+            fence,
+        )
+    }
+
+    /// Same as `submit_batch`, but the handles are gathered in `scratch`, which avoids allocating
+    /// when it is reused, e.g. for every frame
+    pub fn submit_batch_with<'a>(
+        &self,
+        scratch: &mut SubmitScratch,
+        cmd_buffers: impl Iterator<Item = &'a CommandBuffer<Finished>>,
+        wait: &[(&Semaphore, vk::PipelineStageFlags)],
+        signal: &[&Semaphore],
+        fence: Option<&Fence>,
+    ) -> Result<(), QueueError> {
+        scratch.fill(
+            cmd_buffers.map(|b| *b.vk_command_buffer()),
+            wait.iter()
+                .map(|(sem, stage)| (*sem.vk_semaphore(), *stage)),
+            signal.iter().map(|sem| *sem.vk_semaphore()),
+        );
 
+        let info = vk::SubmitInfo::builder()
+            .wait_semaphores(&scratch.wait_semaphores)
+            .wait_dst_stage_mask(&scratch.wait_stages)
+            .signal_semaphores(&scratch.signal_semaphores)
+            .command_buffers(&scratch.cmd_buffers);
+        let infos = [*info];
+        let vk_fence = fence.map(|f| *f.vk_fence()).unwrap_or_else(vk::Fence::null);
+
+        unsafe {
+            self.vk_device
+                .queue_submit(self.vk_queue, &infos, vk_fence)
+                .map_err(QueueError::Submit)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn submit_and_wait(&self, cmd_buf: &CommandBuffer<Finished>) -> Result<(), QueueError> {
         let copied = Fence::unsignaled(&self.vk_device)?;
-        self.submit(&submit_info, &copied)?;
+        self.submit_batch(&[cmd_buf], &[], &[], Some(&copied))?;
 
         // TODO: Async
         copied.blocking_wait()?;
@@ -72,3 +155,82 @@ impl Queue {
         &self.vk_queue
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    // Counts per thread, as the tests run in parallel
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = Cell::new(0);
+    }
+
+    fn count_allocation() {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_allocation();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_allocation();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations_in(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(Cell::get);
+        f();
+        ALLOCATIONS.with(Cell::get) - before
+    }
+
+    // What a frame submits: a few command buffers, waiting for the swapchain image and signaling
+    // that rendering is done
+    fn fill_frame(scratch: &mut SubmitScratch) {
+        scratch.fill(
+            (0..3).map(|_| vk::CommandBuffer::null()),
+            std::iter::once((
+                vk::Semaphore::null(),
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )),
+            std::iter::once(vk::Semaphore::null()),
+        );
+    }
+
+    #[test]
+    fn submit_scratch_reuse() {
+        // A new scratch for each submit, like submit_batch
+        let fresh = allocations_in(|| {
+            for _ in 0..10 {
+                fill_frame(&mut SubmitScratch::default());
+            }
+        });
+        assert_eq!(fresh, 10 * 4);
+
+        let mut scratch = SubmitScratch::default();
+        assert_eq!(allocations_in(|| fill_frame(&mut scratch)), 4);
+        let reused = allocations_in(|| {
+            for _ in 0..10 {
+                fill_frame(&mut scratch);
+            }
+        });
+        assert_eq!(reused, 0);
+        assert_eq!(scratch.cmd_buffers.len(), 3);
+        assert_eq!(scratch.wait_stages.len(), 1);
+    }
+}
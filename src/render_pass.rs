@@ -7,18 +7,72 @@ use crate::device::VkDeviceHandle;
 
 use crate::device::Device;
 use crate::device::HasVkDevice;
+use crate::util;
 
 #[derive(Clone, Error, Debug)]
 pub enum RenderPassError {
     #[error("Render pass creation failed")]
     Creation(vk::Result),
+    #[error("Render pass has no subpasses")]
+    NoSubpasses,
+    #[error("Subpass {subpass} refers to attachment {attachment}, which does not exist")]
+    InvalidAttachment { subpass: u32, attachment: u32 },
+    #[error("Subpass {0} needs one resolve attachment per color attachment")]
+    ResolveMismatch(u32),
+    #[error("Dependency on subpass {0}, which does not exist")]
+    InvalidSubpass(u32),
+    #[error("Attachment {0} does not exist")]
+    NoSuchAttachment(u32),
+    #[error("The clear value of attachment {0} does not match its format")]
+    ClearValueMismatch(u32),
+}
+
+/// The value an attachment is cleared to, see `RenderPassBuilder::attachment`. Color values are
+/// for color attachments and depth/stencil values for depth/stencil attachments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClearValue {
+    Color([f32; 4]),
+    /// The stencil value is ignored unless the attachment format has a stencil component
+    DepthStencil {
+        depth: f32,
+        stencil: u32,
+    },
+}
+
+impl ClearValue {
+    /// Whether this can be used for an attachment of `format`
+    pub fn matches_format(&self, format: vk::Format) -> bool {
+        let depth_stencil = util::Format::from(format).is_depth_stencil();
+        match self {
+            Self::Color(_) => !depth_stencil,
+            Self::DepthStencil { .. } => depth_stencil,
+        }
+    }
+}
+
+impl From<ClearValue> for vk::ClearValue {
+    fn from(value: ClearValue) -> Self {
+        match value {
+            ClearValue::Color(float32) => vk::ClearValue {
+                color: vk::ClearColorValue { float32 },
+            },
+            ClearValue::DepthStencil { depth, stencil } => vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
+        }
+    }
 }
 
 pub struct RenderPass {
     vk_device: VkDeviceHandle,
     vk_render_pass: vk::RenderPass,
-    vk_clear_values: [vk::ClearValue; 2],
-    msaa_sample_count: vk::SampleCountFlags,
+    vk_clear_values: Vec<vk::ClearValue>,
+    attachment_formats: Vec<vk::Format>,
+    // The attachments that are used as color attachments in any subpass
+    color_attachments: Vec<u32>,
+    subpass_sample_counts: Vec<vk::SampleCountFlags>,
+    subpass_color_attachment_counts: Vec<u32>,
+    subpass_depth_formats: Vec<Option<vk::Format>>,
 }
 
 impl std::ops::Drop for RenderPass {
@@ -30,83 +84,411 @@ impl std::ops::Drop for RenderPass {
     }
 }
 
-impl RenderPass {
-    pub fn new(
-        device: &Device,
+pub(crate) fn default_clear_values() -> [ClearValue; 2] {
+    [
+        ClearValue::Color([0.0, 0.0, 0.0, 1.0]),
+        ClearValue::DepthStencil {
+            depth: 1.0,
+            stencil: 0,
+        },
+    ]
+}
+
+/// Waits for both color and depth attachment writes from earlier submissions. There is only one
+/// depth buffer, shared by all frames in flight, so the depth writes of the previous frame have to
+/// finish before it is cleared again.
+fn external_dependency() -> vk::SubpassDependency {
+    let stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+
+    vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(stages)
+        .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        .dst_stage_mask(stages)
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+        .build()
+}
+
+/// Makes the color output of `src_subpass` readable as an input attachment in `dst_subpass`
+pub fn input_attachment_dependency(src_subpass: u32, dst_subpass: u32) -> vk::SubpassDependency {
+    vk::SubpassDependency::builder()
+        .src_subpass(src_subpass)
+        .dst_subpass(dst_subpass)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ)
+        .dependency_flags(vk::DependencyFlags::BY_REGION)
+        .build()
+}
+
+fn attachment_ref(attachment: u32, layout: vk::ImageLayout) -> vk::AttachmentReference {
+    vk::AttachmentReference { attachment, layout }
+}
+
+/// The attachments used by one subpass, referred to by their index in `RenderPassBuilder`
+#[derive(Clone, Debug, Default)]
+pub struct Subpass {
+    color: Vec<vk::AttachmentReference>,
+    resolve: Vec<vk::AttachmentReference>,
+    input: Vec<vk::AttachmentReference>,
+    depth: Option<vk::AttachmentReference>,
+}
+
+impl Subpass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn color(mut self, attachment: u32) -> Self {
+        self.color.push(attachment_ref(
+            attachment,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        ));
+        self
+    }
+
+    /// Resolve target of the color attachment that was added at the same position. Either none
+    /// or all color attachments need to be resolved.
+    pub fn resolve(mut self, attachment: u32) -> Self {
+        self.resolve.push(attachment_ref(
+            attachment,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        ));
+        self
+    }
+
+    /// Read in the fragment shader with `subpassInput`. The attachment has to be written in an
+    /// earlier subpass, see `input_attachment_dependency`.
+    pub fn input(mut self, attachment: u32) -> Self {
+        self.input.push(attachment_ref(
+            attachment,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        ));
+        self
+    }
+
+    pub fn depth(mut self, attachment: u32) -> Self {
+        self.depth = Some(attachment_ref(
+            attachment,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ));
+        self
+    }
+
+    fn attachment_refs(&self) -> impl Iterator<Item = &vk::AttachmentReference> {
+        self.color
+            .iter()
+            .chain(self.resolve.iter())
+            .chain(self.input.iter())
+            .chain(self.depth.iter())
+    }
+
+    // The returned description points into self
+    fn vk_description(&self) -> vk::SubpassDescription {
+        let mut desc = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&self.color)
+            .input_attachments(&self.input);
+
+        if !self.resolve.is_empty() {
+            desc = desc.resolve_attachments(&self.resolve);
+        }
+
+        if let Some(depth) = self.depth.as_ref() {
+            desc = desc.depth_stencil_attachment(depth);
+        }
+
+        desc.build()
+    }
+}
+
+/// Declares the attachments, subpasses and dependencies of a render pass. `forward` creates the
+/// single-subpass render pass with a color and a depth attachment that is used for the swapchain.
+#[derive(Clone, Default)]
+pub struct RenderPassBuilder {
+    attachments: Vec<vk::AttachmentDescription>,
+    clear_values: Vec<ClearValue>,
+    subpasses: Vec<Subpass>,
+    dependencies: Vec<vk::SubpassDependency>,
+}
+
+impl RenderPassBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// With msaa, the multisampled color attachment (0) is resolved into the presentable one,
+    /// which is the last attachment. Without it, we render directly into the presentable
+    /// attachment. The depth attachment, if `depth_format` is given, is 1.
+    pub fn forward(
         format: vk::Format,
+        depth_format: Option<vk::Format>,
         msaa_sample_count: vk::SampleCountFlags,
-    ) -> Result<Self, RenderPassError> {
-        let msaa_color_attach = vk::AttachmentDescription::builder()
+    ) -> Self {
+        Self::single_subpass(
+            format,
+            depth_format,
+            msaa_sample_count,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+        .dependency(external_dependency())
+    }
+
+    /// As `forward`, but the color attachment (or the resolve attachment, with msaa) is left
+    /// ready to be sampled in a fragment shader
+    pub fn offscreen(
+        format: vk::Format,
+        depth_format: Option<vk::Format>,
+        msaa_sample_count: vk::SampleCountFlags,
+    ) -> Self {
+        // The first dependency also orders the depth writes against those of the previous use
+        // of the target. The second makes the writes visible to fragment shaders in later passes
+        // that sample the color attachment.
+        let (mut src_stages, mut dst_stages) = (
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        );
+        let (mut src_access, mut dst_access) = (
+            vk::AccessFlags::SHADER_READ,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        if depth_format.is_some() {
+            let depth_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+            src_stages |= depth_stages;
+            dst_stages |= depth_stages;
+            src_access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+            dst_access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        }
+
+        Self::single_subpass(
+            format,
+            depth_format,
+            msaa_sample_count,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .dependency(
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(src_stages)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stages)
+                .dst_access_mask(dst_access)
+                .build(),
+        )
+        .dependency(
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        )
+    }
+
+    // The single-sampled color attachment ends up in `final_layout`
+    fn single_subpass(
+        format: vk::Format,
+        depth_format: Option<vk::Format>,
+        msaa_sample_count: vk::SampleCountFlags,
+        final_layout: vk::ImageLayout,
+    ) -> Self {
+        let use_msaa = msaa_sample_count != vk::SampleCountFlags::TYPE_1;
+        let [color_clear, depth_clear] = default_clear_values();
+
+        // With msaa, the samples are only needed for the resolve at the end of the subpass
+        let color_attach = vk::AttachmentDescription::builder()
             .format(format)
             .samples(msaa_sample_count)
             .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
+            .store_op(if use_msaa {
+                vk::AttachmentStoreOp::DONT_CARE
+            } else {
+                vk::AttachmentStoreOp::STORE
+            })
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+            .final_layout(if use_msaa {
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            } else {
+                final_layout
+            })
+            .build();
 
-        let resolve_color_attach = vk::AttachmentDescription::builder()
-            .format(format)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        let mut subpass = Subpass::new().color(0);
+        let mut builder = Self::new().attachment(color_attach, color_clear);
 
-        let msaa_color_attach_ref = vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        };
+        if let Some(depth_format) = depth_format {
+            // The stencil is cleared along with the depth, if the format has one
+            let stencil_load_op = if util::Format::from(depth_format).has_stencil() {
+                vk::AttachmentLoadOp::CLEAR
+            } else {
+                vk::AttachmentLoadOp::DONT_CARE
+            };
+            let depth_attach = vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(msaa_sample_count)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(stencil_load_op)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build();
+            builder = builder.attachment(depth_attach, depth_clear);
+            subpass = subpass.depth(1);
+        }
 
-        let resolve_color_attach_ref = vk::AttachmentReference {
-            attachment: 2,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        };
+        if use_msaa {
+            let resolve_color_attach = vk::AttachmentDescription::builder()
+                .format(format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(final_layout)
+                .build();
+            // Never cleared, it is overwritten by the resolve
+            let resolve_idx = builder.attachments.len() as u32;
+            builder = builder.attachment(resolve_color_attach, color_clear);
+            subpass = subpass.resolve(resolve_idx);
+        }
 
-        let depth_attach = vk::AttachmentDescription::builder()
-            .format(device.depth_buffer_format())
-            .samples(msaa_sample_count)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        builder.subpass(subpass)
+    }
 
-        let depth_attach_ref = vk::AttachmentReference {
-            attachment: 1,
-            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-        };
+    /// Attachments are numbered in the order they are added. `clear_value` is used if the load
+    /// op is CLEAR, and has to match the format of the attachment.
+    pub fn attachment(
+        mut self,
+        description: vk::AttachmentDescription,
+        clear_value: ClearValue,
+    ) -> Self {
+        self.attachments.push(description);
+        self.clear_values.push(clear_value);
+        self
+    }
 
-        let color_attach_refs = [msaa_color_attach_ref];
-        let resolve_attach_refs = [resolve_color_attach_ref];
+    /// Subpasses are numbered in the order they are added
+    pub fn subpass(mut self, subpass: Subpass) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
 
-        let subpass = vk::SubpassDescription::builder()
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .color_attachments(&color_attach_refs)
-            .resolve_attachments(&resolve_attach_refs)
-            .depth_stencil_attachment(&depth_attach_ref);
+    pub fn dependency(mut self, dependency: vk::SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    pub fn dependencies(mut self, dependencies: &[vk::SubpassDependency]) -> Self {
+        self.dependencies.extend_from_slice(dependencies);
+        self
+    }
+
+    fn validate(&self) -> Result<(), RenderPassError> {
+        if self.subpasses.is_empty() {
+            return Err(RenderPassError::NoSubpasses);
+        }
+
+        if let Some(idx) = self
+            .attachments
+            .iter()
+            .zip(self.clear_values.iter())
+            .position(|(a, c)| !c.matches_format(a.format))
+        {
+            return Err(RenderPassError::ClearValueMismatch(idx as u32));
+        }
+
+        let n_attachments = self.attachments.len() as u32;
+        let n_subpasses = self.subpasses.len() as u32;
+        for (idx, subpass) in self.subpasses.iter().enumerate() {
+            let subpass_idx = idx as u32;
+            if let Some(r) = subpass
+                .attachment_refs()
+                .find(|r| r.attachment >= n_attachments)
+            {
+                return Err(RenderPassError::InvalidAttachment {
+                    subpass: subpass_idx,
+                    attachment: r.attachment,
+                });
+            }
+
+            if !subpass.resolve.is_empty() && subpass.resolve.len() != subpass.color.len() {
+                return Err(RenderPassError::ResolveMismatch(subpass_idx));
+            }
+        }
+
+        let valid_subpass = |s: u32| s == vk::SUBPASS_EXTERNAL || s < n_subpasses;
+        if let Some(dep) = self
+            .dependencies
+            .iter()
+            .find(|d| !valid_subpass(d.src_subpass) || !valid_subpass(d.dst_subpass))
+        {
+            let invalid = if valid_subpass(dep.src_subpass) {
+                dep.dst_subpass
+            } else {
+                dep.src_subpass
+            };
+            return Err(RenderPassError::InvalidSubpass(invalid));
+        }
 
-        let attachments = [*msaa_color_attach, *depth_attach, *resolve_color_attach];
-        let subpasses = [*subpass];
+        Ok(())
+    }
+
+    // Pipelines need to match the sample count of the subpass they are used in
+    fn subpass_sample_counts(&self) -> Vec<vk::SampleCountFlags> {
+        self.subpasses
+            .iter()
+            .map(|s| {
+                s.color
+                    .iter()
+                    .chain(s.depth.iter())
+                    .next()
+                    .map(|r| self.attachments[r.attachment as usize].samples)
+                    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+            })
+            .collect()
+    }
 
-        let subpass_dependency = vk::SubpassDependency::builder()
-            .src_subpass(vk::SUBPASS_EXTERNAL)
-            .dst_subpass(0)
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+    fn color_attachments(&self) -> Vec<u32> {
+        let mut attachments = self
+            .subpasses
+            .iter()
+            .flat_map(|s| s.color.iter().map(|r| r.attachment))
+            .collect::<Vec<_>>();
+        attachments.sort_unstable();
+        attachments.dedup();
+        attachments
+    }
 
-        let dependencies = [subpass_dependency.build()];
+    pub fn build(self, device: &Device) -> Result<RenderPass, RenderPassError> {
+        self.validate()?;
+
+        let subpasses = self
+            .subpasses
+            .iter()
+            .map(Subpass::vk_description)
+            .collect::<Vec<_>>();
 
         let render_pass_info = vk::RenderPassCreateInfo::builder()
-            .attachments(&attachments)
+            .attachments(&self.attachments)
             .subpasses(&subpasses)
-            .dependencies(&dependencies);
+            .dependencies(&self.dependencies);
 
         let vk_device = device.vk_device();
 
@@ -116,27 +498,98 @@ impl RenderPass {
                 .map_err(RenderPassError::Creation)?
         };
 
-        let vk_clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
-                },
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                },
-            },
-        ];
-
-        Ok(Self {
+        let subpass_sample_counts = self.subpass_sample_counts();
+        let color_attachments = self.color_attachments();
+        let subpass_color_attachment_counts = self
+            .subpasses
+            .iter()
+            .map(|s| s.color.len() as u32)
+            .collect();
+        let subpass_depth_formats = self
+            .subpasses
+            .iter()
+            .map(|s| {
+                s.depth
+                    .map(|r| self.attachments[r.attachment as usize].format)
+            })
+            .collect();
+        Ok(RenderPass {
             vk_device,
             vk_render_pass,
-            vk_clear_values,
-            msaa_sample_count,
+            vk_clear_values: self.clear_values.into_iter().map(Into::into).collect(),
+            attachment_formats: self.attachments.iter().map(|a| a.format).collect(),
+            color_attachments,
+            subpass_sample_counts,
+            subpass_color_attachment_counts,
+            subpass_depth_formats,
         })
     }
+}
+
+impl RenderPass {
+    pub fn new(
+        device: &Device,
+        format: vk::Format,
+        msaa_sample_count: vk::SampleCountFlags,
+    ) -> Result<Self, RenderPassError> {
+        RenderPassBuilder::forward(
+            format,
+            Some(device.depth_buffer_format()),
+            msaa_sample_count,
+        )
+        .build(device)
+    }
+
+    /// Render pass that leaves the color attachment ready to be sampled in a fragment shader. The
+    /// depth attachment (attachment 1) is optional. See `RenderPassBuilder::offscreen`.
+    pub fn offscreen(
+        device: &Device,
+        format: vk::Format,
+        with_depth: bool,
+        msaa_sample_count: vk::SampleCountFlags,
+    ) -> Result<Self, RenderPassError> {
+        let depth_format = if with_depth {
+            Some(device.depth_buffer_format())
+        } else {
+            None
+        };
+        RenderPassBuilder::offscreen(format, depth_format, msaa_sample_count).build(device)
+    }
+
+    /// Set the clear value of all color attachments. The values are written to the attachments
+    /// as is, so for an sRGB format like the ones of the swapchain, they are linear and encoded
+    /// when written. Use `set_clear_color_srgb` for a color that is picked in sRGB.
+    pub fn set_clear_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        for &i in self.color_attachments.iter() {
+            self.vk_clear_values[i as usize] = ClearValue::Color([r, g, b, a]).into();
+        }
+    }
+
+    /// Set the clear value of a single attachment, e.g. the depth attachment for reversed-z
+    pub fn set_clear_value(
+        &mut self,
+        attachment: u32,
+        value: ClearValue,
+    ) -> Result<(), RenderPassError> {
+        let format = *self
+            .attachment_formats
+            .get(attachment as usize)
+            .ok_or(RenderPassError::NoSuchAttachment(attachment))?;
+        if !value.matches_format(format) {
+            return Err(RenderPassError::ClearValueMismatch(attachment));
+        }
+
+        self.vk_clear_values[attachment as usize] = value.into();
+        Ok(())
+    }
+
+    /// Like `set_clear_color`, but `r`, `g` and `b` are sRGB-encoded and converted to linear.
+    /// For an sRGB attachment format, this means that the cleared pixels end up with exactly
+    /// these values. Alpha is always linear.
+    pub fn set_clear_color_srgb(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        let [r, g, b] = util::srgb_to_linear([r, g, b]);
+        self.set_clear_color(r, g, b, a);
+    }
 
     pub fn vk_clear_values(&self) -> &[vk::ClearValue] {
         &self.vk_clear_values
@@ -146,7 +599,659 @@ impl RenderPass {
         &self.vk_render_pass
     }
 
+    /// Sample count of the first subpass
     pub fn msaa_sample_count(&self) -> vk::SampleCountFlags {
-        self.msaa_sample_count
+        self.subpass_sample_counts[0]
+    }
+
+    pub fn subpass_sample_count(&self, subpass: u32) -> vk::SampleCountFlags {
+        self.subpass_sample_counts[subpass as usize]
+    }
+
+    /// The number of color attachments written by `subpass`, i.e. the fragment shader outputs
+    /// of pipelines that are used in it
+    pub fn subpass_color_attachment_count(&self, subpass: u32) -> u32 {
+        self.subpass_color_attachment_counts[subpass as usize]
+    }
+
+    pub fn subpass_has_depth(&self, subpass: u32) -> bool {
+        self.subpass_depth_format(subpass).is_some()
+    }
+
+    pub fn subpass_depth_format(&self, subpass: u32) -> Option<vk::Format> {
+        self.subpass_depth_formats[subpass as usize]
+    }
+
+    /// If the depth attachment of `subpass` has a stencil component
+    pub fn subpass_has_stencil(&self, subpass: u32) -> bool {
+        self.subpass_depth_format(subpass)
+            .map_or(false, |f| util::Format::from(f).has_stencil())
+    }
+
+    pub fn subpass_count(&self) -> u32 {
+        self.subpass_sample_counts.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer;
+    use crate::image;
+    use crate::mem;
+    use crate::mesh;
+    use crate::pipeline;
+    use crate::test_util::{headless_renderer, write_spirv, Position, POSITION_SPV_VERT};
+    use crate::texture;
+    use crate::uniform;
+    use crate::ResourceManager;
+
+    #[test]
+    fn external_dependency_covers_depth() {
+        let dep = external_dependency();
+        assert_eq!(dep.src_subpass, vk::SUBPASS_EXTERNAL);
+        assert_eq!(dep.dst_subpass, 0);
+
+        let depth_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        assert!(dep.src_stage_mask.contains(depth_stages));
+        assert!(dep
+            .dst_stage_mask
+            .contains(depth_stages | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT));
+        assert!(dep
+            .src_access_mask
+            .contains(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE));
+        assert!(dep.dst_access_mask.contains(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        ));
+    }
+
+    fn color_attachment(format: vk::Format) -> vk::AttachmentDescription {
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .build()
+    }
+
+    fn clear_value() -> ClearValue {
+        default_clear_values()[0]
+    }
+
+    #[test]
+    fn clear_value_conversion() {
+        let color = vk::ClearValue::from(ClearValue::Color([0.25, 0.5, 0.75, 1.0]));
+        assert_eq!(unsafe { color.color.float32 }, [0.25, 0.5, 0.75, 1.0]);
+
+        let depth = vk::ClearValue::from(ClearValue::DepthStencil {
+            depth: 0.5,
+            stencil: 3,
+        });
+        let depth_stencil = unsafe { depth.depth_stencil };
+        assert_eq!((depth_stencil.depth, depth_stencil.stencil), (0.5, 3));
+
+        assert!(ClearValue::Color([0.0; 4]).matches_format(vk::Format::B8G8R8A8_SRGB));
+        assert!(!ClearValue::Color([0.0; 4]).matches_format(vk::Format::D32_SFLOAT));
+        assert!(default_clear_values()[1].matches_format(vk::Format::D24_UNORM_S8_UINT));
+        assert!(!default_clear_values()[1].matches_format(vk::Format::R8G8B8A8_UNORM));
+    }
+
+    #[test]
+    fn clear_value_mismatch() {
+        let attach = color_attachment(vk::Format::B8G8R8A8_SRGB);
+        assert!(matches!(
+            RenderPassBuilder::new()
+                .attachment(attach, clear_value())
+                .attachment(attach, default_clear_values()[1])
+                .subpass(Subpass::new().color(0).color(1))
+                .validate(),
+            Err(RenderPassError::ClearValueMismatch(1))
+        ));
+    }
+
+    #[test]
+    fn forward_is_default_single_subpass() {
+        let builder = RenderPassBuilder::forward(
+            vk::Format::B8G8R8A8_SRGB,
+            Some(vk::Format::D32_SFLOAT),
+            vk::SampleCountFlags::TYPE_4,
+        );
+        assert!(builder.validate().is_ok());
+        assert_eq!(builder.attachments.len(), 3);
+        assert_eq!(builder.clear_values.len(), 3);
+        // The resolve attachment isn't cleared
+        assert_eq!(builder.color_attachments(), [0]);
+        assert_eq!(
+            builder.subpass_sample_counts(),
+            [vk::SampleCountFlags::TYPE_4]
+        );
+
+        let desc = builder.subpasses[0].vk_description();
+        assert_eq!(desc.color_attachment_count, 1);
+        assert!(!desc.p_resolve_attachments.is_null());
+        assert!(!desc.p_depth_stencil_attachment.is_null());
+
+        let no_msaa = RenderPassBuilder::forward(
+            vk::Format::B8G8R8A8_SRGB,
+            Some(vk::Format::D32_SFLOAT),
+            vk::SampleCountFlags::TYPE_1,
+        );
+        assert_eq!(no_msaa.attachments.len(), 2);
+        assert!(no_msaa.subpasses[0]
+            .vk_description()
+            .p_resolve_attachments
+            .is_null());
+    }
+
+    #[test]
+    fn stencil_is_cleared() {
+        let builder = RenderPassBuilder::forward(
+            vk::Format::B8G8R8A8_SRGB,
+            Some(vk::Format::D24_UNORM_S8_UINT),
+            vk::SampleCountFlags::TYPE_1,
+        );
+        assert_eq!(
+            builder.attachments[1].stencil_load_op,
+            vk::AttachmentLoadOp::CLEAR
+        );
+
+        let no_stencil = RenderPassBuilder::forward(
+            vk::Format::B8G8R8A8_SRGB,
+            Some(vk::Format::D32_SFLOAT),
+            vk::SampleCountFlags::TYPE_1,
+        );
+        assert_eq!(
+            no_stencil.attachments[1].stencil_load_op,
+            vk::AttachmentLoadOp::DONT_CARE
+        );
+    }
+
+    #[test]
+    fn msaa_resolve() {
+        let builder = RenderPassBuilder::offscreen(
+            vk::Format::R8G8B8A8_SRGB,
+            None,
+            vk::SampleCountFlags::TYPE_4,
+        );
+        assert!(builder.validate().is_ok());
+        let [color, resolve] = [builder.attachments[0], builder.attachments[1]];
+        // Only the resolved image is kept, in the same format
+        assert_eq!(color.store_op, vk::AttachmentStoreOp::DONT_CARE);
+        assert_eq!(resolve.store_op, vk::AttachmentStoreOp::STORE);
+        assert_eq!(resolve.samples, vk::SampleCountFlags::TYPE_1);
+        assert_eq!(resolve.format, color.format);
+        assert_eq!(
+            resolve.final_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(builder.subpasses[0].resolve[0].attachment, 1);
+
+        let no_msaa = RenderPassBuilder::offscreen(
+            vk::Format::R8G8B8A8_SRGB,
+            None,
+            vk::SampleCountFlags::TYPE_1,
+        );
+        assert_eq!(no_msaa.attachments.len(), 1);
+        assert_eq!(
+            no_msaa.attachments[0].store_op,
+            vk::AttachmentStoreOp::STORE
+        );
+    }
+
+    #[test]
+    fn offscreen_depth_dependency() {
+        let depth_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let with_depth = RenderPassBuilder::offscreen(
+            vk::Format::R8G8B8A8_SRGB,
+            Some(vk::Format::D32_SFLOAT),
+            vk::SampleCountFlags::TYPE_1,
+        );
+        let dep = with_depth.dependencies[0];
+        assert!(dep.src_stage_mask.contains(depth_stages));
+        assert!(dep.dst_stage_mask.contains(depth_stages));
+        assert!(dep
+            .src_access_mask
+            .contains(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE));
+        assert!(dep
+            .dst_access_mask
+            .contains(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE));
+
+        let without_depth = RenderPassBuilder::offscreen(
+            vk::Format::R8G8B8A8_SRGB,
+            None,
+            vk::SampleCountFlags::TYPE_1,
+        );
+        assert!(!without_depth.dependencies[0]
+            .dst_stage_mask
+            .intersects(depth_stages));
+    }
+
+    #[test]
+    fn forward_without_depth() {
+        let builder = RenderPassBuilder::forward(
+            vk::Format::B8G8R8A8_SRGB,
+            None,
+            vk::SampleCountFlags::TYPE_4,
+        );
+        assert!(builder.validate().is_ok());
+        assert_eq!(builder.attachments.len(), 2);
+        assert_eq!(builder.clear_values.len(), 2);
+        // The resolve attachment takes the place of the depth attachment
+        assert_eq!(builder.subpasses[0].resolve[0].attachment, 1);
+        assert!(builder.subpasses[0].depth.is_none());
+
+        let no_msaa = RenderPassBuilder::forward(
+            vk::Format::B8G8R8A8_SRGB,
+            None,
+            vk::SampleCountFlags::TYPE_1,
+        );
+        assert_eq!(no_msaa.attachments.len(), 1);
+        assert!(no_msaa.subpasses[0]
+            .vk_description()
+            .p_depth_stencil_attachment
+            .is_null());
+    }
+
+    #[test]
+    fn second_subpass_reads_first_as_input() {
+        let mut gbuffer = color_attachment(vk::Format::R16G16B16A16_SFLOAT);
+        gbuffer.final_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        gbuffer.store_op = vk::AttachmentStoreOp::DONT_CARE;
+
+        let builder = RenderPassBuilder::new()
+            .attachment(gbuffer, clear_value())
+            .attachment(color_attachment(vk::Format::B8G8R8A8_SRGB), clear_value())
+            .subpass(Subpass::new().color(0))
+            .subpass(Subpass::new().input(0).color(1))
+            .dependency(external_dependency())
+            .dependency(input_attachment_dependency(0, 1));
+        assert!(builder.validate().is_ok());
+
+        let first = builder.subpasses[0].vk_description();
+        assert_eq!(first.input_attachment_count, 0);
+        let first_color = unsafe { *first.p_color_attachments };
+        assert_eq!(first_color.attachment, 0);
+
+        let second = builder.subpasses[1].vk_description();
+        assert_eq!(second.input_attachment_count, 1);
+        let input = unsafe { *second.p_input_attachments };
+        assert_eq!(input.attachment, 0);
+        assert_eq!(input.layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let second_color = unsafe { *second.p_color_attachments };
+        assert_eq!(second_color.attachment, 1);
+
+        let dep = &builder.dependencies[1];
+        assert_eq!((dep.src_subpass, dep.dst_subpass), (0, 1));
+        assert!(dep
+            .dst_access_mask
+            .contains(vk::AccessFlags::INPUT_ATTACHMENT_READ));
+    }
+
+    #[test]
+    fn invalid_references() {
+        let attach = color_attachment(vk::Format::B8G8R8A8_SRGB);
+        assert!(matches!(
+            RenderPassBuilder::new()
+                .attachment(attach, clear_value())
+                .validate(),
+            Err(RenderPassError::NoSubpasses)
+        ));
+        assert!(matches!(
+            RenderPassBuilder::new()
+                .attachment(attach, clear_value())
+                .subpass(Subpass::new().color(0).input(1))
+                .validate(),
+            Err(RenderPassError::InvalidAttachment {
+                subpass: 0,
+                attachment: 1
+            })
+        ));
+        assert!(matches!(
+            RenderPassBuilder::new()
+                .attachment(attach, clear_value())
+                .subpass(Subpass::new().color(0))
+                .dependency(input_attachment_dependency(0, 1))
+                .validate(),
+            Err(RenderPassError::InvalidSubpass(1))
+        ));
+    }
+
+    static MRT_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 albedo;
+        layout(location = 1) out vec4 normal;
+
+        void main() {
+            albedo = vec4(1.0, 0.0, 0.0, 1.0);
+            normal = vec4(0.0, 0.0, 1.0, 1.0);
+        }
+    ",
+        frag
+    );
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn multiple_render_targets() {
+        let dir = std::env::temp_dir().join("trekanten_multiple_render_targets");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, MRT_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        let extent = renderer.swapchain_extent();
+        let format = vk::Format::R8G8B8A8_UNORM;
+
+        let targets = (0..2)
+            .map(|_| {
+                let image = mem::DeviceImage::empty_2d(
+                    &renderer.device,
+                    extent,
+                    format.into(),
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                    vk_mem::MemoryUsage::GpuOnly,
+                    1,
+                    vk::SampleCountFlags::TYPE_1,
+                )
+                .expect("Failed to create image");
+                let view = image::ImageView::new(
+                    &renderer.device,
+                    image.vk_image(),
+                    format.into(),
+                    vk::ImageAspectFlags::COLOR,
+                    1,
+                )
+                .expect("Failed to create image view");
+                (image, view)
+            })
+            .collect::<Vec<_>>();
+
+        let attachment = vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ..Default::default()
+        };
+        let black = ClearValue::Color([0.0, 0.0, 0.0, 1.0]);
+        let render_pass = renderer
+            .create_render_pass(
+                RenderPassBuilder::new()
+                    .attachment(attachment, black)
+                    .attachment(attachment, black)
+                    .subpass(Subpass::new().color(0).color(1)),
+            )
+            .expect("Failed to create render pass");
+        assert_eq!(render_pass.subpass_color_attachment_count(0), 2);
+        let framebuffer = framebuffer::Framebuffer::new(
+            &renderer.device,
+            &[&targets[0].1, &targets[1].1],
+            &render_pass,
+            &extent,
+        )
+        .expect("Failed to create framebuffer");
+
+        let descriptor = pipeline::GraphicsPipelineDescriptor::builder()
+            .vertex_shader(&vert)
+            .fragment_shader(&frag)
+            .vertex_type::<Position>()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .depth_test_enable(false)
+            .build()
+            .expect("Failed to create pipeline descriptor");
+        let pipeline_handle = renderer
+            .graphics_pipelines
+            .create_fixed(&renderer.device, &descriptor, extent, &render_pass)
+            .expect("Failed to create pipeline");
+        let quad = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad,
+                &[0u32, 1, 2, 2, 1, 3],
+            ))
+            .expect("Failed to create mesh");
+
+        let size = (extent.width * extent.height * 4) as usize;
+        let readbacks = (0..2)
+            .map(|_| mem::DeviceBuffer::readback_empty(&renderer.device, size))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Failed to create readback buffers");
+        let cmd_buf = renderer
+            .util_command_pool
+            .begin_single_submit()
+            .expect("Failed to begin")
+            .begin_render_pass(&render_pass, &framebuffer, extent)
+            .bind_graphics_pipeline(renderer.get_resource(&pipeline_handle).unwrap())
+            .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+            .end_render_pass()
+            .copy_image_to_buffer(targets[0].0.vk_image(), readbacks[0].vk_buffer(), &extent)
+            .copy_image_to_buffer(targets[1].0.vk_image(), readbacks[1].vk_buffer(), &extent)
+            .end()
+            .expect("Failed to end");
+        renderer
+            .device
+            .util_queue()
+            .submit_and_wait(&cmd_buf)
+            .expect("Failed to submit");
+
+        let pixels = |readback: &mem::DeviceBuffer| readback.read_data().expect("Failed to read");
+        assert!(pixels(&readbacks[0])
+            .chunks(4)
+            .all(|p| p == [255, 0, 0, 255]));
+        assert!(pixels(&readbacks[1])
+            .chunks(4)
+            .all(|p| p == [0, 0, 255, 255]));
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    static RED_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = vec4(1.0, 0.0, 0.0, 1.0);
+        }
+    ",
+        frag
+    );
+
+    // Swaps red and blue of what the first subpass wrote. The uniform buffer and texture are
+    // what create_descriptor_set binds, they don't change the color.
+    static SUBPASS_INPUT_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0) uniform Params {
+            vec4 scale;
+        } params;
+        layout(set = 0, binding = 1) uniform sampler2D tint;
+        layout(input_attachment_index = 0, set = 0, binding = 2) uniform subpassInput gbuffer;
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = subpassLoad(gbuffer).bgra * params.scale * texture(tint, vec2(0.5));
+        }
+    ",
+        frag
+    );
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn read_input_attachment() {
+        let dir = std::env::temp_dir().join("trekanten_read_input_attachment");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, red, swap) = (
+            dir.join("vert.spv"),
+            dir.join("red.spv"),
+            dir.join("swap.spv"),
+        );
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&red, RED_SPV_FRAG);
+        write_spirv(&swap, SUBPASS_INPUT_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+        let extent = renderer.swapchain_extent();
+        let format = vk::Format::R8G8B8A8_UNORM;
+
+        let target = |usage| {
+            let image = mem::DeviceImage::empty_2d(
+                &renderer.device,
+                extent,
+                format.into(),
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | usage,
+                vk_mem::MemoryUsage::GpuOnly,
+                1,
+                vk::SampleCountFlags::TYPE_1,
+            )
+            .expect("Failed to create image");
+            let view = image::ImageView::new(
+                &renderer.device,
+                image.vk_image(),
+                format.into(),
+                vk::ImageAspectFlags::COLOR,
+                1,
+            )
+            .expect("Failed to create image view");
+            (image, view)
+        };
+        let gbuffer = target(vk::ImageUsageFlags::INPUT_ATTACHMENT);
+        let output = target(vk::ImageUsageFlags::TRANSFER_SRC);
+
+        let gbuffer_attachment = vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        };
+        let output_attachment = vk::AttachmentDescription {
+            store_op: vk::AttachmentStoreOp::STORE,
+            final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ..gbuffer_attachment
+        };
+        let black = ClearValue::Color([0.0, 0.0, 0.0, 1.0]);
+        let render_pass = renderer
+            .create_render_pass(
+                RenderPassBuilder::new()
+                    .attachment(gbuffer_attachment, black)
+                    .attachment(output_attachment, black)
+                    .subpass(Subpass::new().color(0))
+                    .subpass(Subpass::new().input(0).color(1))
+                    .dependency(external_dependency())
+                    .dependency(input_attachment_dependency(0, 1)),
+            )
+            .expect("Failed to create render pass");
+        let framebuffer = framebuffer::Framebuffer::new(
+            &renderer.device,
+            &[&gbuffer.1, &output.1],
+            &render_pass,
+            &extent,
+        )
+        .expect("Failed to create framebuffer");
+
+        let mut pipeline = |frag: &std::path::Path, subpass| {
+            let descriptor = pipeline::GraphicsPipelineDescriptor::builder()
+                .vertex_shader(&vert)
+                .fragment_shader(frag)
+                .vertex_type::<Position>()
+                .cull_mode(vk::CullModeFlags::NONE)
+                .depth_test_enable(false)
+                .subpass(subpass)
+                .build()
+                .expect("Failed to create pipeline descriptor");
+            renderer
+                .graphics_pipelines
+                .create_fixed(&renderer.device, &descriptor, extent, &render_pass)
+                .expect("Failed to create pipeline")
+        };
+        let first = pipeline(&red, 0);
+        let second = pipeline(&swap, 1);
+
+        let params = [[1.0f32; 4]];
+        let params_handle = renderer
+            .create_resource(uniform::UniformBufferDescriptor::from_slice(&params))
+            .expect("Failed to create uniform buffer");
+        let white = ::image::RgbaImage::from_pixel(2, 2, ::image::Rgba([255, 255, 255, 255]));
+        let texture_handle = renderer
+            .create_resource(texture::TextureDescriptor::from_rgba_image(
+                &white, format, false,
+            ))
+            .expect("Failed to create texture");
+        let desc_set_handle = renderer
+            .create_descriptor_set(&second, &params_handle, &texture_handle)
+            .expect("Failed to create descriptor set");
+        renderer
+            .bind_input_attachment(&desc_set_handle, 2, &gbuffer.1)
+            .expect("Failed to bind input attachment");
+
+        let quad = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad,
+                &[0u32, 1, 2, 2, 1, 3],
+            ))
+            .expect("Failed to create mesh");
+
+        let size = (extent.width * extent.height * 4) as usize;
+        let readback = mem::DeviceBuffer::readback_empty(&renderer.device, size)
+            .expect("Failed to create buffer");
+        let mesh = renderer.get_resource(&mesh_handle).unwrap();
+        let second_pipeline = renderer.get_resource(&second).unwrap();
+        let cmd_buf = renderer
+            .util_command_pool
+            .begin_single_submit()
+            .expect("Failed to begin")
+            .begin_render_pass(&render_pass, &framebuffer, extent)
+            .bind_graphics_pipeline(renderer.get_resource(&first).unwrap())
+            .draw_mesh(mesh)
+            .next_subpass()
+            .bind_graphics_pipeline(second_pipeline)
+            .bind_descriptor_set(
+                renderer.get_descriptor_set(&desc_set_handle).unwrap(),
+                second_pipeline,
+            )
+            .draw_mesh(mesh)
+            .end_render_pass()
+            .copy_image_to_buffer(output.0.vk_image(), readback.vk_buffer(), &extent)
+            .end()
+            .expect("Failed to end");
+        renderer
+            .device
+            .util_queue()
+            .submit_and_wait(&cmd_buf)
+            .expect("Failed to submit");
+
+        // The red from the first subpass, with red and blue swapped
+        let pixels = readback.read_data().expect("Failed to read");
+        assert!(pixels.chunks(4).all(|p| p == [0, 0, 255, 255]));
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
     }
 }
@@ -1,20 +1,16 @@
 pub use crate::resource::{Handle, Storage};
 
-pub const N_BUFFERS: usize = 2;
-
-// TODO: Static assert for idx > 2?
-
-/// Convenience type for double buffered storage of T
+/// Convenience type for buffered storage of T, e.g. one T per frame in flight
 pub struct BufferedStorage<T> {
-    storage: Storage<[T; N_BUFFERS]>,
+    storage: Storage<Vec<T>>,
 }
 
 impl<T> BufferedStorage<T> {
-    pub fn add(&mut self, t: [T; N_BUFFERS]) -> Handle<T> {
+    pub fn add(&mut self, t: Vec<T>) -> Handle<T> {
         self.storage.add(t).as_unbuffered()
     }
 
-    pub fn remove(&mut self, h: Handle<T>) -> Option<[T; N_BUFFERS]> {
+    pub fn remove(&mut self, h: Handle<T>) -> Option<Vec<T>> {
         self.storage.remove(h.as_buffered())
     }
 
@@ -30,8 +26,14 @@ impl<T> BufferedStorage<T> {
         self.storage.get_mut(&h.as_buffered()).map(|x| &mut x[idx])
     }
 
-    pub fn get_all(&self, h: &Handle<T>) -> Option<&[T; N_BUFFERS]> {
-        self.storage.get(&h.as_buffered())
+    pub fn get_all(&self, h: &Handle<T>) -> Option<&[T]> {
+        self.storage.get(&h.as_buffered()).map(|x| x.as_slice())
+    }
+
+    pub fn get_all_mut(&mut self, h: &Handle<T>) -> Option<&mut [T]> {
+        self.storage
+            .get_mut(&h.as_buffered())
+            .map(|x| x.as_mut_slice())
     }
 
     pub fn is_empty(&self) -> bool {
@@ -42,12 +44,24 @@ impl<T> BufferedStorage<T> {
         self.storage.len()
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &[T; N_BUFFERS]> {
-        self.storage.iter()
+    pub fn iter(&self) -> impl Iterator<Item = &[T]> {
+        self.storage.iter().map(|x| x.as_slice())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        self.storage.iter_mut().map(|x| x.as_mut_slice())
+    }
+
+    pub fn iter_with_handles(&self, idx: usize) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.storage
+            .iter_with_handles()
+            .map(move |(h, x)| (h.as_unbuffered(), &x[idx]))
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut [T; N_BUFFERS]> {
-        self.storage.iter_mut()
+    pub fn iter_all_with_handles(&self) -> impl Iterator<Item = (Handle<T>, &[T])> {
+        self.storage
+            .iter_with_handles()
+            .map(|(h, x)| (h.as_unbuffered(), x.as_slice()))
     }
 }
 
@@ -66,14 +80,14 @@ mod tests {
     #[test]
     fn add() {
         let mut s = BufferedStorage::default();
-        let ints = [3, 10];
+        let ints = vec![3, 10];
         let h = s.add(ints);
 
         assert_eq!(s.len(), 1);
         assert!(s.has(&h));
         assert_eq!(*s.get(&h, 0).unwrap(), 3);
         assert_eq!(*s.get(&h, 1).unwrap(), 10);
-        assert_eq!(*s.get_all(&h).unwrap(), [3, 10]);
+        assert_eq!(s.get_all(&h).unwrap(), &[3, 10]);
         assert_eq!(s.get(&h, 0).copied(), s.get_mut(&h, 0).copied());
         assert_eq!(s.get(&h, 1).copied(), s.get_mut(&h, 1).copied());
     }
@@ -81,10 +95,10 @@ mod tests {
     #[test]
     fn remove() {
         let mut s = BufferedStorage::default();
-        let ints0 = [3, 10];
-        let h0 = s.add(ints0);
+        let ints0 = vec![3, 10];
+        let h0 = s.add(ints0.clone());
 
-        let ints1 = [30, 100];
+        let ints1 = vec![30, 100];
         let h1 = s.add(ints1);
         assert_eq!(s.len(), 2);
 
@@ -93,6 +107,6 @@ mod tests {
         assert_eq!(s.len(), 1);
         assert_eq!(*s.get(&h1, 0).unwrap(), 30);
         assert_eq!(*s.get(&h1, 1).unwrap(), 100);
-        assert_eq!(*s.get_all(&h1).unwrap(), [30, 100]);
+        assert_eq!(s.get_all(&h1).unwrap(), &[30, 100]);
     }
 }
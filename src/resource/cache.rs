@@ -23,6 +23,18 @@ impl<D: Hash + Eq, T> Cache<D, T> {
     pub fn add(&mut self, desc: D, h: Handle<T>) {
         self.cache.insert(desc, h);
     }
+
+    /// The descriptor `h` is cached for, if any
+    pub fn descriptor(&self, h: Handle<T>) -> Option<&D> {
+        self.cache
+            .iter()
+            .find(|(_, cached)| **cached == h)
+            .map(|(desc, _)| desc)
+    }
+
+    pub fn remove_handle(&mut self, h: Handle<T>) {
+        self.cache.retain(|_, cached| *cached != h);
+    }
 }
 
 impl<D: Hash + Eq, T> std::default::Default for Cache<D, T> {
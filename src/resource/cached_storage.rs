@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::hash::Hash;
 
 use super::cache::*;
@@ -9,6 +10,13 @@ struct Stats {
     pub misses: usize,
 }
 
+/// Reference count and the tick of the last `create_or_add` for an entry
+#[derive(Clone, Copy, Debug)]
+struct Usage {
+    refs: usize,
+    last_used: u64,
+}
+
 pub struct CachedStorage<ResourceDescriptor, Resource>
 where
     ResourceDescriptor: Hash + Eq,
@@ -16,6 +24,8 @@ where
     cache: Cache<ResourceDescriptor, Resource>,
     storage: Storage<Resource>,
     stats: Stats,
+    usage: HashMap<Handle<Resource>, Usage>,
+    tick: u64,
 }
 
 impl<ResourceDescriptor, Resource> CachedStorage<ResourceDescriptor, Resource>
@@ -26,6 +36,7 @@ where
         Default::default()
     }
 
+    /// Each call adds a reference to the returned handle, which is dropped with `release`
     pub fn create_or_add<Create, Error>(
         &mut self,
         descriptor: ResourceDescriptor,
@@ -54,12 +65,97 @@ where
             self.stats.misses + self.stats.hits
         );
 
+        self.tick += 1;
+        let usage = self.usage.entry(h).or_insert(Usage {
+            refs: 0,
+            last_used: 0,
+        });
+        usage.refs += 1;
+        usage.last_used = self.tick;
+
         Ok(h)
     }
 
+    /// Drop a reference added by `create_or_add`. Entries without references are kept cached
+    /// until they are evicted. Returns false if the handle is not in the storage.
+    pub fn release(&mut self, h: Handle<Resource>) -> bool {
+        match self.usage.get_mut(&h) {
+            Some(usage) => {
+                usage.refs = usage.refs.saturating_sub(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove unreferenced entries, least recently used first, until the total size of the
+    /// stored resources is at most `budget`. Referenced entries are never evicted, so the
+    /// budget may still be exceeded afterwards.
+    pub fn evict<Size>(&mut self, budget: u64, size: Size) -> Vec<Resource>
+    where
+        Size: Fn(&Resource) -> u64,
+    {
+        let mut total: u64 = self.storage.iter().map(&size).sum();
+        if total <= budget {
+            return Vec::new();
+        }
+
+        let mut candidates = self
+            .usage
+            .iter()
+            .filter(|(_, usage)| usage.refs == 0)
+            .map(|(h, usage)| (usage.last_used, *h))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(last_used, _)| *last_used);
+
+        let mut evicted = Vec::new();
+        for (_, h) in candidates {
+            if total <= budget {
+                break;
+            }
+            if let Some(resource) = self.remove(h) {
+                total -= size(&resource);
+                evicted.push(resource);
+            }
+        }
+
+        log::debug!(
+            "Evicted {} cached resources, {} bytes remain",
+            evicted.len(),
+            total
+        );
+
+        evicted
+    }
+
     pub fn get(&self, h: &Handle<Resource>) -> Option<&Resource> {
         self.storage.get(h)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<Resource>, &Resource)> {
+        self.storage.iter_with_handles()
+    }
+
+    /// None if the resource has been modified through `get_mut_uncached`
+    pub fn descriptor(&self, h: &Handle<Resource>) -> Option<&ResourceDescriptor> {
+        self.cache.descriptor(*h)
+    }
+
+    /// For modifying the resource, after which it might not match its descriptor anymore. The
+    /// cache entries for it are removed, so it is not returned for the descriptor again.
+    pub fn get_mut_uncached(&mut self, h: &Handle<Resource>) -> Option<&mut Resource> {
+        let resource = self.storage.get_mut(h)?;
+        self.cache.remove_handle(*h);
+        Some(resource)
+    }
+
+    /// Also removes the cache entries for it, so the resource is created again if requested
+    pub fn remove(&mut self, h: Handle<Resource>) -> Option<Resource> {
+        let removed = self.storage.remove(h)?;
+        self.cache.remove_handle(h);
+        self.usage.remove(&h);
+        Some(removed)
+    }
 }
 
 impl<ResourceDescriptor, Resource> std::default::Default
@@ -72,6 +168,80 @@ where
             cache: Default::default(),
             storage: Default::default(),
             stats: Default::default(),
+            usage: HashMap::new(),
+            tick: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Bytes = CachedStorage<&'static str, Vec<u8>>;
+
+    fn add(storage: &mut Bytes, name: &'static str) -> Handle<Vec<u8>> {
+        storage
+            .create_or_add(name, |_| Ok::<_, ()>(vec![0; 4]))
+            .unwrap()
+    }
+
+    fn size(v: &Vec<u8>) -> u64 {
+        v.len() as u64
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut storage = Bytes::new();
+        let a = add(&mut storage, "a");
+        let b = add(&mut storage, "b");
+        let c = add(&mut storage, "c");
+
+        assert!(storage.release(a));
+        assert!(storage.release(b));
+        // Within budget, nothing is evicted
+        assert!(storage.evict(12, size).is_empty());
+
+        // Use b again so that a is the least recently used
+        assert_eq!(add(&mut storage, "b"), b);
+        assert!(storage.release(b));
+
+        assert_eq!(storage.evict(8, size).len(), 1);
+        assert!(storage.get(&a).is_none());
+        assert!(storage.get(&b).is_some());
+        assert!(storage.get(&c).is_some());
+
+        // c is still referenced, so only b can go
+        assert_eq!(storage.evict(0, size).len(), 1);
+        assert!(storage.get(&b).is_none());
+        assert!(storage.get(&c).is_some());
+
+        // Evicted entries are created again
+        let a2 = add(&mut storage, "a");
+        assert_ne!(a2, a);
+    }
+
+    #[test]
+    fn iter_entries() {
+        let mut storage = Bytes::new();
+        let a = add(&mut storage, "a");
+        let b = add(&mut storage, "b");
+        let c = add(&mut storage, "c");
+        assert_eq!(add(&mut storage, "b"), b);
+
+        let mut names = storage
+            .iter()
+            .map(|(h, _)| *storage.descriptor(&h).unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["a", "b", "c"]);
+
+        storage.remove(b);
+        assert_eq!(storage.iter().count(), 2);
+        assert!(storage.iter().all(|(h, _)| h == a || h == c));
+
+        storage.get_mut_uncached(&a);
+        assert!(storage.descriptor(&a).is_none());
+        assert_eq!(storage.descriptor(&c), Some(&"c"));
+    }
+}
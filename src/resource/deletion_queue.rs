@@ -0,0 +1,63 @@
+use std::any::Any;
+
+/// Keeps resources that were removed from their storage alive until no frame in flight can use
+/// them anymore, as they are freed when dropped. Frames are identified by a number that counts
+/// up with every submitted frame.
+#[derive(Default)]
+pub struct DeletionQueue {
+    // The last frame that might use the resource
+    pending: Vec<(u64, Box<dyn Any>)>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop `resource` once `last_frame` has completed
+    pub fn push<T: 'static>(&mut self, resource: T, last_frame: u64) {
+        self.pending.push((last_frame, Box::new(resource)));
+    }
+
+    /// `frame` and all frames before it have completed. A fence signaled by a queue submission
+    /// covers earlier submissions as well, so this is known once the fence of `frame` signaled.
+    pub fn frame_completed(&mut self, frame: u64) {
+        self.pending.retain(|(last_frame, _)| *last_frame > frame);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Resource(Rc<Cell<bool>>);
+
+    impl Drop for Resource {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    #[test]
+    fn dropped_after_last_frame_completes() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut queue = DeletionQueue::new();
+        queue.push(Resource(Rc::clone(&dropped)), 5);
+
+        queue.frame_completed(3);
+        queue.frame_completed(4);
+        assert!(!dropped.get());
+        assert_eq!(queue.len(), 1);
+
+        queue.frame_completed(5);
+        assert!(dropped.get());
+        assert_eq!(queue.len(), 0);
+    }
+}
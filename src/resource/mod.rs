@@ -1,10 +1,12 @@
 pub mod buffered_storage;
 pub mod cache;
 pub mod cached_storage;
+pub mod deletion_queue;
 pub mod storage;
 
 pub use buffered_storage::BufferedStorage;
 pub use cached_storage::CachedStorage;
+pub use deletion_queue::DeletionQueue;
 pub use storage::Handle;
 pub use storage::Storage;
 
@@ -12,3 +14,15 @@ pub trait ResourceManager<Descriptor, Resource, Error> {
     fn get_resource(&self, handle: &Handle<Resource>) -> Option<&Resource>;
     fn create_resource(&mut self, descriptor: Descriptor) -> Result<Handle<Resource>, Error>;
 }
+
+pub trait IterResources<Resource> {
+    /// All live resources with their handles, in no particular order
+    fn iter_resources(&self) -> Box<dyn Iterator<Item = (Handle<Resource>, &Resource)> + '_>;
+}
+
+pub trait DestroyResource<Resource> {
+    /// Invalidate `handle` and free the resource once no frame in flight can use it anymore.
+    /// Descriptor sets that refer to it must not be used in frames after this call. Returns false
+    /// if the handle was not valid.
+    fn schedule_destroy(&mut self, handle: Handle<Resource>) -> bool;
+}
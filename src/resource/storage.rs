@@ -39,8 +39,8 @@ impl<T> Handle<T> {
         self.id.index
     }
 
-    pub fn as_buffered(&self) -> Handle<[T; 2]> {
-        Handle::<[T; 2]>::new(self.id)
+    pub fn as_buffered(&self) -> Handle<Vec<T>> {
+        Handle::<Vec<T>>::new(self.id)
     }
 
     pub fn id(&self) -> ID {
@@ -56,8 +56,7 @@ impl<T> Clone for Handle<T> {
 impl<T> Copy for Handle<T> {}
 
 // For buffered storage
-// TODO: Make generic over array length
-impl<T> Handle<[T; 2]> {
+impl<T> Handle<Vec<T>> {
     pub fn as_unbuffered(self) -> Handle<T> {
         Handle::<T>::new(self.id)
     }
@@ -168,6 +167,14 @@ impl<T> Storage<T> {
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
         self.data.iter_mut()
     }
+
+    /// Only the live entries are visited, in no particular order
+    pub fn iter_with_handles(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.dense
+            .iter()
+            .map(|&id| Handle::new(id))
+            .zip(self.data.iter())
+    }
 }
 
 impl<T> Default for Storage<T> {
@@ -235,6 +242,24 @@ mod tests {
         assert!(m.has(&i2));
     }
 
+    #[test]
+    fn iter_with_handles_skips_removed() {
+        let mut m = Storage::new();
+        let handles = add_int_range(&mut m, 0, 5);
+        m.remove(handles[1]);
+        m.remove(handles[3]);
+
+        let mut entries = m
+            .iter_with_handles()
+            .map(|(h, v)| (h.index(), *v))
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, vec![(0, 0), (2, 2), (4, 4)]);
+        for (h, v) in m.iter_with_handles() {
+            assert_eq!(m.get(&h), Some(v));
+        }
+    }
+
     fn add_int_range(s: &mut Storage<u32>, start: u32, end: u32) -> Vec<Handle<u32>> {
         (start..end).map(|x| s.add(x)).collect::<Vec<_>>()
     }
@@ -3,7 +3,7 @@ use ash::vk;
 use thiserror::Error;
 
 use spirv_reflect::types::descriptor::ReflectDescriptorType;
-use spirv_reflect::types::variable::ReflectShaderStageFlags;
+use spirv_reflect::types::variable::{ReflectDecorationFlags, ReflectShaderStageFlags};
 use spirv_reflect::ShaderModule;
 
 #[derive(Debug, Error)]
@@ -12,6 +12,26 @@ pub enum SpirvError {
     Loading(&'static str),
     #[error("Couldn't parse spirv: {0}")]
     Parsing(&'static str),
+    #[error("Unsupported shader stage: {0}")]
+    UnsupportedShaderStage(String),
+    #[error("Unsupported descriptor type: {0}")]
+    UnsupportedDescriptorType(String),
+    #[error("Set {set} binding {binding} is a {first:?} in one stage and a {second:?} in another")]
+    DescriptorTypeMismatch {
+        set: usize,
+        binding: u32,
+        first: vk::DescriptorType,
+        second: vk::DescriptorType,
+    },
+    #[error(
+        "Set {set} binding {binding} has {first} descriptors in one stage and {second} in another"
+    )]
+    DescriptorCountMismatch {
+        set: usize,
+        binding: u32,
+        first: u32,
+        second: u32,
+    },
 }
 
 #[derive(Debug)]
@@ -20,6 +40,37 @@ pub struct DescriptorSetLayoutData {
     pub bindings: Vec<vk::DescriptorSetLayoutBinding>,
 }
 
+impl DescriptorSetLayoutData {
+    fn merge(&mut self, bindings: Vec<vk::DescriptorSetLayoutBinding>) -> Result<(), SpirvError> {
+        for b in bindings.into_iter() {
+            match self.bindings.iter_mut().find(|x| x.binding == b.binding) {
+                Some(existing) => {
+                    if existing.descriptor_type != b.descriptor_type {
+                        return Err(SpirvError::DescriptorTypeMismatch {
+                            set: self.set_idx,
+                            binding: b.binding,
+                            first: existing.descriptor_type,
+                            second: b.descriptor_type,
+                        });
+                    }
+                    if existing.descriptor_count != b.descriptor_count {
+                        return Err(SpirvError::DescriptorCountMismatch {
+                            set: self.set_idx,
+                            binding: b.binding,
+                            first: existing.descriptor_count,
+                            second: b.descriptor_count,
+                        });
+                    }
+                    existing.stage_flags |= b.stage_flags;
+                }
+                None => self.bindings.push(b),
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct DescriptorSetLayouts {
     layouts: Vec<DescriptorSetLayoutData>,
@@ -32,19 +83,56 @@ impl DescriptorSetLayouts {
         }
     }
 
-    pub fn append(&mut self, other: DescriptorSetLayouts) {
-        for mut ol in other.layouts.into_iter() {
-            let mut found = false;
-            for l in self.layouts.iter_mut() {
-                if ol.set_idx == l.set_idx {
-                    l.bindings.append(&mut ol.bindings);
-                    found = true;
-                    break;
-                }
+    /// Merge the sets of another shader stage into these. Bindings that are used by both stages
+    /// become a single binding with the stage flags of both. Fails if the stages disagree on
+    /// the type or count of a binding.
+    pub fn append(&mut self, other: DescriptorSetLayouts) -> Result<(), SpirvError> {
+        for ol in other.layouts.into_iter() {
+            match self.layouts.iter_mut().find(|l| l.set_idx == ol.set_idx) {
+                Some(l) => l.merge(ol.bindings)?,
+                None => self.layouts.push(ol),
             }
+        }
+
+        Ok(())
+    }
 
-            if !found {
-                self.layouts.push(ol);
+    /// Replace the binding with the same set and binding index as `binding`, or add it if the
+    /// shaders don't use it. Returns the replaced binding, e.g. to compare it with the new one.
+    pub fn override_binding(
+        &mut self,
+        set_idx: usize,
+        binding: vk::DescriptorSetLayoutBinding,
+    ) -> Option<vk::DescriptorSetLayoutBinding> {
+        let layout = match self.layouts.iter().position(|l| l.set_idx == set_idx) {
+            Some(i) => &mut self.layouts[i],
+            None => {
+                // Keep the sets in order, they are used in this order for the pipeline layout
+                let i = self
+                    .layouts
+                    .iter()
+                    .position(|l| l.set_idx > set_idx)
+                    .unwrap_or_else(|| self.layouts.len());
+                self.layouts.insert(
+                    i,
+                    DescriptorSetLayoutData {
+                        set_idx,
+                        bindings: Vec::new(),
+                    },
+                );
+                &mut self.layouts[i]
+            }
+        };
+
+        match layout
+            .bindings
+            .iter_mut()
+            .find(|b| b.binding == binding.binding)
+        {
+            Some(existing) => Some(std::mem::replace(existing, binding)),
+            None => {
+                layout.bindings.push(binding);
+                None
             }
         }
     }
@@ -58,42 +146,91 @@ impl DescriptorSetLayouts {
     }
 }
 
-fn map_shader_stage_flags(refl_stage: &ReflectShaderStageFlags) -> vk::ShaderStageFlags {
+fn map_shader_stage_flags(
+    refl_stage: &ReflectShaderStageFlags,
+) -> Result<vk::ShaderStageFlags, SpirvError> {
     match *refl_stage {
-        ReflectShaderStageFlags::VERTEX => vk::ShaderStageFlags::VERTEX,
-        ReflectShaderStageFlags::FRAGMENT => vk::ShaderStageFlags::FRAGMENT,
-        _ => unimplemented!("Unsupported shader stage: {:?}", refl_stage),
+        ReflectShaderStageFlags::VERTEX => Ok(vk::ShaderStageFlags::VERTEX),
+        ReflectShaderStageFlags::FRAGMENT => Ok(vk::ShaderStageFlags::FRAGMENT),
+        _ => Err(SpirvError::UnsupportedShaderStage(format!(
+            "{:?}",
+            refl_stage
+        ))),
     }
 }
 
-fn map_descriptor_type(refl_desc_ty: &ReflectDescriptorType) -> vk::DescriptorType {
+// Only the types that DescriptorPool reserves room for
+fn map_descriptor_type(
+    refl_desc_ty: &ReflectDescriptorType,
+) -> Result<vk::DescriptorType, SpirvError> {
     match *refl_desc_ty {
-        ReflectDescriptorType::UniformBuffer => vk::DescriptorType::UNIFORM_BUFFER,
-        ReflectDescriptorType::CombinedImageSampler => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-        _ => unimplemented!("Unsupported descriptor type: {:?}", refl_desc_ty),
+        ReflectDescriptorType::UniformBuffer => Ok(vk::DescriptorType::UNIFORM_BUFFER),
+        ReflectDescriptorType::CombinedImageSampler => {
+            Ok(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        }
+        ReflectDescriptorType::SampledImage => Ok(vk::DescriptorType::SAMPLED_IMAGE),
+        ReflectDescriptorType::Sampler => Ok(vk::DescriptorType::SAMPLER),
+        ReflectDescriptorType::StorageBuffer => Ok(vk::DescriptorType::STORAGE_BUFFER),
+        ReflectDescriptorType::InputAttachment => Ok(vk::DescriptorType::INPUT_ATTACHMENT),
+        _ => Err(SpirvError::UnsupportedDescriptorType(format!(
+            "{:?}",
+            refl_desc_ty
+        ))),
     }
 }
 
+/// The names of the entry points of a shader module, e.g. "main" for GLSL
+pub fn parse_entry_point_names(spv_data: &[u32]) -> Result<Vec<String>, SpirvError> {
+    let module = ShaderModule::load_u32_data(spv_data).map_err(SpirvError::Loading)?;
+    let entry_points = module
+        .enumerate_entry_points()
+        .map_err(SpirvError::Parsing)?;
+    Ok(entry_points.into_iter().map(|ep| ep.name).collect())
+}
+
+/// The number of output locations, e.g. the color attachments written by a fragment shader.
+/// Built-in outputs like `gl_Position` don't count.
+pub fn parse_output_location_count(spv_data: &[u32]) -> Result<u32, SpirvError> {
+    let module = ShaderModule::load_u32_data(spv_data).map_err(SpirvError::Loading)?;
+    let outputs = module
+        .enumerate_output_variables(None)
+        .map_err(SpirvError::Parsing)?;
+    let count = outputs
+        .iter()
+        .filter(|var| {
+            !var.decoration_flags
+                .contains(ReflectDecorationFlags::BUILT_IN)
+        })
+        // An array output, e.g. `out vec4 colors[2]`, uses one location per element
+        .map(|var| var.location + var.array.dims.iter().product::<u32>())
+        .max()
+        .unwrap_or(0);
+    Ok(count)
+}
+
 pub fn parse_descriptor_sets(spv_data: &[u32]) -> Result<DescriptorSetLayouts, SpirvError> {
     let module = ShaderModule::load_u32_data(spv_data).map_err(SpirvError::Loading)?;
     let desc_sets = module
         .enumerate_descriptor_sets(None)
         .map_err(SpirvError::Parsing)?;
-    let shader_stage = map_shader_stage_flags(&module.get_shader_stage());
+    let shader_stage = map_shader_stage_flags(&module.get_shader_stage())?;
     let mut ret = Vec::with_capacity(desc_sets.len());
     for refl_desc_set in desc_sets.iter() {
         let set_idx = refl_desc_set.set;
         let bindings: Vec<vk::DescriptorSetLayoutBinding> = refl_desc_set
             .bindings
             .iter()
-            .map(|refl_binding| vk::DescriptorSetLayoutBinding {
-                binding: refl_binding.binding,
-                descriptor_type: map_descriptor_type(&refl_binding.descriptor_type),
-                descriptor_count: 1,
-                stage_flags: shader_stage,
-                ..Default::default()
+            .map(|refl_binding| {
+                Ok(vk::DescriptorSetLayoutBinding {
+                    binding: refl_binding.binding,
+                    descriptor_type: map_descriptor_type(&refl_binding.descriptor_type)?,
+                    // Arrayed descriptors, e.g. sampler2D textures[4]
+                    descriptor_count: std::cmp::max(refl_binding.count, 1),
+                    stage_flags: shader_stage,
+                    ..Default::default()
+                })
             })
-            .collect();
+            .collect::<Result<_, SpirvError>>()?;
         log::trace!("Found descriptor set: {}", set_idx);
         log::trace!("With {} bindings", bindings.len());
         for b in &bindings {
@@ -143,6 +280,59 @@ mod tests {
     ",
         frag
     );
+
+    static SSBO_SPV_VERT: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450 core
+        layout(set = 0, binding = 2) readonly buffer Instances {
+            mat4 transforms[];
+        } instances;
+
+        void main() {
+            gl_Position = instances.transforms[gl_InstanceIndex] * vec4(0.0, 0.0, 0.0, 1.0);
+        }
+    ",
+        vert
+    );
+
+    static UBO_SAMPLERS_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0) uniform Material {
+            vec4 tint;
+        } material;
+
+        layout(set = 0, binding = 1) uniform sampler2D textures[4];
+
+        layout(location = 0) in vec2 fragTexCoord;
+
+        layout(location = 0) out vec4 outColor;
+
+        void main() {
+            outColor = material.tint * texture(textures[3], fragTexCoord);
+        }
+    ",
+        frag
+    );
+
+    static UBO_SPV_FRAG_SHARED: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450 core
+        layout(set = 0, binding = 0) uniform UniformBufferObject {
+            mat4 model;
+            mat4 view;
+            mat4 proj;
+        } ubo;
+
+        layout(location = 0) out vec4 outColor;
+
+        void main() {
+            outColor = ubo.model[0];
+        }
+    ",
+        frag
+    );
+
     use super::*;
 
     #[test]
@@ -185,8 +375,10 @@ mod tests {
     #[test]
     fn merge_descriptor_set_layout() {
         let mut res = DescriptorSetLayouts::new();
-        res.append(parse_descriptor_sets(UBO_SPV_VERT).expect("Failed to parse!"));
-        res.append(parse_descriptor_sets(UBO_SPV_FRAG).expect("Failed to parse!"));
+        res.append(parse_descriptor_sets(UBO_SPV_VERT).expect("Failed to parse!"))
+            .expect("Failed to merge");
+        res.append(parse_descriptor_sets(UBO_SPV_FRAG).expect("Failed to parse!"))
+            .expect("Failed to merge");
         let layouts = res.layouts;
         assert_eq!(layouts.len(), 1);
         let l = &layouts[0];
@@ -208,4 +400,310 @@ mod tests {
         assert_eq!(binding1.descriptor_count, 1);
         assert_eq!(binding1.stage_flags, vk::ShaderStageFlags::FRAGMENT);
     }
+
+    static SEPARATE_SAMPLER_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0) uniform texture2D image;
+        layout(set = 0, binding = 1) uniform sampler samp;
+
+        layout(location = 0) in vec2 fragTexCoord;
+
+        layout(location = 0) out vec4 outColor;
+
+        void main() {
+            outColor = texture(sampler2D(image, samp), fragTexCoord);
+        }
+    ",
+        frag
+    );
+
+    #[test]
+    fn parse_separate_image_and_sampler() {
+        let mut res = parse_descriptor_sets(SEPARATE_SAMPLER_SPV_FRAG)
+            .expect("Failed to parse!")
+            .layouts;
+        assert_eq!(res.len(), 1);
+        let bindings = &mut res[0].bindings;
+        bindings.sort_by_key(|b| b.binding);
+        assert_eq!(
+            bindings[0].descriptor_type,
+            vk::DescriptorType::SAMPLED_IMAGE
+        );
+        assert_eq!(bindings[1].descriptor_type, vk::DescriptorType::SAMPLER);
+    }
+
+    #[test]
+    fn parse_storage_buffer_descriptor_set_layout() {
+        let res = parse_descriptor_sets(SSBO_SPV_VERT)
+            .expect("Failed to parse!")
+            .layouts;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].bindings.len(), 1);
+        assert_eq!(res[0].set_idx, 0);
+
+        let binding: vk::DescriptorSetLayoutBinding = res[0].bindings[0];
+        assert_eq!(binding.descriptor_type, vk::DescriptorType::STORAGE_BUFFER);
+        assert_eq!(binding.binding, 2);
+        assert_eq!(binding.stage_flags, vk::ShaderStageFlags::VERTEX);
+    }
+
+    #[test]
+    fn parse_ubo_and_sampler_array() {
+        let res = parse_descriptor_sets(UBO_SAMPLERS_SPV_FRAG)
+            .expect("Failed to parse!")
+            .layouts;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].set_idx, 0);
+
+        let mut bindings = res[0].bindings.clone();
+        bindings.sort_by_key(|b| b.binding);
+        assert_eq!(bindings.len(), 2);
+
+        assert_eq!(bindings[0].binding, 0);
+        assert_eq!(
+            bindings[0].descriptor_type,
+            vk::DescriptorType::UNIFORM_BUFFER
+        );
+        assert_eq!(bindings[0].descriptor_count, 1);
+
+        assert_eq!(bindings[1].binding, 1);
+        assert_eq!(
+            bindings[1].descriptor_type,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+        );
+        assert_eq!(bindings[1].descriptor_count, 4);
+        assert_eq!(bindings[1].stage_flags, vk::ShaderStageFlags::FRAGMENT);
+    }
+
+    #[test]
+    fn override_binding() {
+        let mut res = parse_descriptor_sets(UBO_SAMPLERS_SPV_FRAG).expect("Failed to parse!");
+        let textures = vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 64,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+        let reflected = res
+            .override_binding(0, textures)
+            .expect("Binding 1 should have been reflected");
+        assert_eq!(reflected.descriptor_count, 4);
+
+        let storage = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            ..Default::default()
+        };
+        assert!(res.override_binding(2, storage).is_none());
+
+        let layouts = res.layouts;
+        assert_eq!(
+            layouts.iter().map(|l| l.set_idx).collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+        let overridden = layouts[0].bindings.iter().find(|b| b.binding == 1).unwrap();
+        assert_eq!(overridden.descriptor_count, 64);
+        assert_eq!(layouts[0].bindings.len(), 2);
+        assert_eq!(
+            layouts[1].bindings[0].descriptor_type,
+            vk::DescriptorType::STORAGE_BUFFER
+        );
+    }
+
+    #[test]
+    fn merge_shared_binding() {
+        let mut res = DescriptorSetLayouts::new();
+        res.append(parse_descriptor_sets(UBO_SPV_VERT).expect("Failed to parse!"))
+            .expect("Failed to merge");
+        res.append(parse_descriptor_sets(UBO_SPV_FRAG_SHARED).expect("Failed to parse!"))
+            .expect("Failed to merge");
+        let layouts = res.layouts;
+        assert_eq!(layouts.len(), 1);
+        assert_eq!(layouts[0].set_idx, 0);
+        assert_eq!(layouts[0].bindings.len(), 1);
+
+        let binding = layouts[0].bindings[0];
+        assert_eq!(binding.binding, 0);
+        assert_eq!(binding.descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+        assert_eq!(
+            binding.stage_flags,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+    }
+
+    #[test]
+    fn merge_mismatching_bindings() {
+        let layouts = |descriptor_type, descriptor_count, stage_flags| DescriptorSetLayouts {
+            layouts: vec![DescriptorSetLayoutData {
+                set_idx: 0,
+                bindings: vec![vk::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    descriptor_type,
+                    descriptor_count,
+                    stage_flags,
+                    ..Default::default()
+                }],
+            }],
+        };
+        let vert = || {
+            layouts(
+                vk::DescriptorType::UNIFORM_BUFFER,
+                1,
+                vk::ShaderStageFlags::VERTEX,
+            )
+        };
+
+        let mut res = vert();
+        assert!(matches!(
+            res.append(layouts(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+                vk::ShaderStageFlags::FRAGMENT
+            )),
+            Err(SpirvError::DescriptorTypeMismatch {
+                set: 0,
+                binding: 1,
+                first: vk::DescriptorType::UNIFORM_BUFFER,
+                second: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            })
+        ));
+
+        let mut res = vert();
+        assert!(matches!(
+            res.append(layouts(
+                vk::DescriptorType::UNIFORM_BUFFER,
+                2,
+                vk::ShaderStageFlags::FRAGMENT
+            )),
+            Err(SpirvError::DescriptorCountMismatch {
+                set: 0,
+                binding: 1,
+                first: 1,
+                second: 2,
+            })
+        ));
+    }
+
+    static HLSL_SPV_VERT: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        float4 VSMain(float2 pos : POSITION) : SV_POSITION {
+            return float4(pos, 0.0, 1.0);
+        }
+    ",
+        vert,
+        hlsl,
+        entry = "VSMain"
+    );
+
+    #[test]
+    fn parse_entry_point_name() {
+        assert_eq!(
+            parse_entry_point_names(UBO_SPV_VERT).expect("Failed to parse"),
+            vec!["main".to_owned()]
+        );
+        assert_eq!(
+            parse_entry_point_names(HLSL_SPV_VERT).expect("Failed to parse"),
+            vec!["VSMain".to_owned()]
+        );
+    }
+
+    static MRT_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(location = 0) out vec4 albedo;
+        layout(location = 1) out vec4 normal;
+
+        void main() {
+            albedo = vec4(1.0);
+            normal = vec4(0.0);
+        }
+    ",
+        frag
+    );
+
+    #[test]
+    fn parse_output_locations() {
+        assert_eq!(parse_output_location_count(UBO_SPV_FRAG).unwrap(), 1);
+        assert_eq!(parse_output_location_count(MRT_SPV_FRAG).unwrap(), 2);
+        // gl_Position is a built-in
+        assert_eq!(parse_output_location_count(SSBO_SPV_VERT).unwrap(), 0);
+    }
+
+    #[test]
+    fn merge_bindings_without_spirv() {
+        let binding = |binding, stage_flags| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+        };
+        let set = |bindings| DescriptorSetLayouts {
+            layouts: vec![DescriptorSetLayoutData {
+                set_idx: 0,
+                bindings,
+            }],
+        };
+
+        let mut res = set(vec![binding(0, vk::ShaderStageFlags::VERTEX)]);
+        res.append(set(vec![
+            binding(0, vk::ShaderStageFlags::FRAGMENT),
+            binding(1, vk::ShaderStageFlags::FRAGMENT),
+        ]))
+        .expect("Failed to merge");
+
+        let bindings = &res.layouts[0].bindings;
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(
+            bindings[0].stage_flags,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+        assert_eq!(bindings[1].stage_flags, vk::ShaderStageFlags::FRAGMENT);
+    }
+
+    static INPUT_ATTACHMENT_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput gbuffer;
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = subpassLoad(gbuffer);
+        }
+    ",
+        frag
+    );
+
+    static STORAGE_IMAGE_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0, rgba8) uniform readonly image2D image;
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = imageLoad(image, ivec2(0));
+        }
+    ",
+        frag
+    );
+
+    #[test]
+    fn parse_input_attachment() {
+        let res = parse_descriptor_sets(INPUT_ATTACHMENT_SPV_FRAG)
+            .expect("Failed to parse!")
+            .layouts;
+        assert_eq!(
+            res[0].bindings[0].descriptor_type,
+            vk::DescriptorType::INPUT_ATTACHMENT
+        );
+
+        assert!(matches!(
+            parse_descriptor_sets(STORAGE_IMAGE_SPV_FRAG),
+            Err(SpirvError::UnsupportedDescriptorType(_))
+        ));
+    }
 }
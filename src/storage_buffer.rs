@@ -0,0 +1,54 @@
+use ash::vk;
+
+use crate::command::CommandPool;
+use crate::device::Device;
+use crate::mem;
+use crate::queue::Queue;
+use crate::util::as_byte_slice;
+
+pub struct StorageBufferDescriptor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StorageBufferDescriptor<'a> {
+    pub fn from_slice<T>(slice: &'a [T]) -> Self {
+        Self {
+            data: as_byte_slice(slice),
+        }
+    }
+}
+
+/// Device local buffer that can be bound as a STORAGE_BUFFER descriptor, see
+/// `DescriptorSet::bind_storage_buffer`. Shaders may both read and write it.
+pub struct StorageBuffer {
+    pub buffer: mem::DeviceBuffer,
+}
+
+impl StorageBuffer {
+    pub fn create<'a>(
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        staging_pool: Option<&mut mem::StagingPool>,
+        descriptor: &StorageBufferDescriptor<'a>,
+    ) -> Result<Self, mem::MemoryError> {
+        let buffer = mem::DeviceBuffer::device_local_by_staging(
+            device,
+            queue,
+            command_pool,
+            staging_pool,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            descriptor.data,
+        )?;
+
+        Ok(Self { buffer })
+    }
+
+    pub fn vk_buffer(&self) -> &vk::Buffer {
+        &self.buffer.vk_buffer()
+    }
+
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+}
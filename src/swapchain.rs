@@ -32,19 +32,45 @@ pub enum SwapchainError {
     EnqueuePresent(vk::Result),
     #[error("Swapchain surface issue {0}")]
     Surface(#[from] SurfaceError),
-    #[error("Swapchain out of date")]
+    #[error("Swapchain is out of date, resize required")]
     OutOfDate,
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SwapchainStatus {
     Optimal,
     SubOptimal,
 }
 
+fn status(sub_optimal: bool) -> SwapchainStatus {
+    if sub_optimal {
+        SwapchainStatus::SubOptimal
+    } else {
+        SwapchainStatus::Optimal
+    }
+}
+
+// OutOfDate gets its own variant as it is recovered from by recreating the swapchain
+fn error(e: vk::Result, other: fn(vk::Result) -> SwapchainError) -> SwapchainError {
+    if e == vk::Result::ERROR_OUT_OF_DATE_KHR {
+        SwapchainError::OutOfDate
+    } else {
+        other(e)
+    }
+}
+
+fn acquire_result(
+    result: Result<(u32, bool), vk::Result>,
+) -> Result<(u32, SwapchainStatus), SwapchainError> {
+    result
+        .map(|(idx, sub_optimal)| (idx, status(sub_optimal)))
+        .map_err(|e| error(e, SwapchainError::AcquireNextImage))
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct SwapchainInfo {
     pub format: vk::Format,
     pub extent: util::Extent2D,
+    pub usage: vk::ImageUsageFlags,
 }
 
 pub struct Swapchain {
@@ -62,25 +88,78 @@ impl std::ops::Drop for Swapchain {
     }
 }
 
-fn choose_swapchain_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+/// Used if no preference is given, 8-bit sRGB in either channel order
+pub const DEFAULT_SURFACE_FORMATS: [vk::SurfaceFormatKHR; 2] = [
+    vk::SurfaceFormatKHR {
+        format: vk::Format::B8G8R8A8_SRGB,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    },
+    vk::SurfaceFormatKHR {
+        format: vk::Format::R8G8B8A8_SRGB,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    },
+];
+
+fn is_8bit_srgb(f: &vk::SurfaceFormatKHR) -> bool {
+    f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        && matches!(
+            f.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::B8G8R8A8_UNORM
+                | vk::Format::R8G8B8A8_UNORM
+        )
+}
+
+/// Pick the first of `preferred` that is available. Otherwise, fall back to any 8-bit sRGB
+/// format before picking whatever comes first (which might e.g. be a 10-bit HDR format).
+fn choose_swapchain_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    preferred: &[vk::SurfaceFormatKHR],
+) -> vk::SurfaceFormatKHR {
+    log::trace!("Candidate surface formats:");
     for f in formats.iter() {
-        if f.format == vk::Format::B8G8R8A8_SRGB
-            && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        {
-            return *f;
+        log::trace!("\t{:?}", f);
+    }
+
+    // A single UNDEFINED format means that the surface has no preference
+    if formats.len() == 1 && formats[0].format == vk::Format::UNDEFINED {
+        return preferred
+            .first()
+            .copied()
+            .unwrap_or(DEFAULT_SURFACE_FORMATS[0]);
+    }
+
+    for p in preferred.iter() {
+        if formats.contains(p) {
+            return *p;
         }
     }
 
-    formats[0]
+    let chosen = formats
+        .iter()
+        .find(|f| is_8bit_srgb(f))
+        .copied()
+        .unwrap_or(formats[0]);
+    log::trace!(
+        "None of the preferred surface formats are available, using {:?}",
+        chosen
+    );
+    chosen
 }
 
-fn choose_swapchain_surface_present_mode(pmodes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+fn choose_swapchain_surface_present_mode(
+    pmodes: &[vk::PresentModeKHR],
+    preferred: vk::PresentModeKHR,
+) -> vk::PresentModeKHR {
     for pm in pmodes.iter() {
-        if *pm == vk::PresentModeKHR::MAILBOX {
+        if *pm == preferred {
             return *pm;
         }
     }
 
+    log::trace!("Present mode {:?} is not supported, using FIFO", preferred);
+
     // Always available according to spec
     vk::PresentModeKHR::FIFO
 }
@@ -107,27 +186,37 @@ fn choose_swapchain_extent(
     }
 }
 
+// One more than the minimum, so that there is an image to render into while the driver holds on
+// to the others
+fn choose_image_count(capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+    let image_count = capabilities.min_image_count + 1;
+    // Zero means no max
+    if capabilities.max_image_count > 0 && image_count > capabilities.max_image_count {
+        capabilities.max_image_count
+    } else {
+        image_count
+    }
+}
+
 impl Swapchain {
     pub fn new(
         instance: &Instance,
         device: &Device,
         surface: &Surface,
         extent: &util::Extent2D,
+        present_mode: vk::PresentModeKHR,
+        surface_formats: &[vk::SurfaceFormatKHR],
         old: Option<&Self>,
     ) -> Result<Self, SwapchainError> {
         let query = surface.query_swapchain_support(device.vk_phys_device())?;
         log::trace!("Creating swapchain");
         log::trace!("Available: {:#?}", query);
-        let format = choose_swapchain_surface_format(&query.formats);
-        let present_mode = choose_swapchain_surface_present_mode(&query.present_modes);
+        let format = choose_swapchain_surface_format(&query.formats, surface_formats);
+        let present_mode =
+            choose_swapchain_surface_present_mode(&query.present_modes, present_mode);
         let extent = choose_swapchain_extent(&query.capabilites, extent);
 
-        let mut image_count = query.capabilites.min_image_count + 1;
-        // Zero means no max
-        if query.capabilites.max_image_count > 0 && image_count > query.capabilites.max_image_count
-        {
-            image_count = query.capabilites.max_image_count;
-        }
+        let image_count = choose_image_count(&query.capabilites);
 
         let mut builder = vk::SwapchainCreateInfoKHR::builder()
             .surface(*surface.vk_handle())
@@ -136,7 +225,13 @@ impl Swapchain {
             .image_color_space(format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            // TRANSFER_SRC is for reading back the presented image and TRANSFER_DST for blitting
+            // to it with a render scale, if supported
+            .image_usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | (query.capabilites.supported_usage_flags
+                        & (vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST)),
+            );
 
         let indices = [
             device.graphics_queue_family().index,
@@ -182,12 +277,14 @@ impl Swapchain {
         let vk::SwapchainCreateInfoKHR {
             image_format,
             image_extent,
+            image_usage,
             ..
         } = info;
 
         let light_info = SwapchainInfo {
             format: image_format,
             extent: image_extent.into(),
+            usage: image_usage,
         };
 
         let util_format = util::Format::from(image_format);
@@ -216,6 +313,10 @@ impl Swapchain {
         })
     }
 
+    pub fn image(&self, idx: u32) -> &vk::Image {
+        &self.images[idx as usize]
+    }
+
     pub fn info(&self) -> &SwapchainInfo {
         &self.info
     }
@@ -224,39 +325,42 @@ impl Swapchain {
     pub fn create_framebuffers_for(
         &self,
         render_pass: &RenderPass,
-        depth_buffer: &DepthBuffer,
-        color_buffer: &ColorBuffer,
+        depth_buffer: Option<&DepthBuffer>,
+        color_buffer: Option<&ColorBuffer>,
     ) -> Result<Vec<Framebuffer>, SwapchainError> {
         self.image_views
             .iter()
             .map(|iv| {
-                let views = [color_buffer.image_view(), depth_buffer.image_view(), iv];
+                // Attachment order has to match the render pass
+                let mut views = match color_buffer {
+                    Some(cb) => vec![cb.image_view()],
+                    None => vec![iv],
+                };
+                if let Some(db) = depth_buffer {
+                    views.push(db.image_view());
+                }
+                if color_buffer.is_some() {
+                    views.push(iv);
+                }
                 Framebuffer::new(&self.vk_device, &views, render_pass, &self.info.extent)
             })
             .collect::<Result<Vec<_>, FramebufferError>>()
             .map_err(SwapchainError::Framebuffer)
     }
 
-    pub fn acquire_next_image(&self, sem: Option<&Semaphore>) -> Result<u32, SwapchainError> {
+    /// A suboptimal image is still acquired, and `sem` will be signaled, so it has to be
+    /// rendered to and presented before the swapchain is recreated.
+    pub fn acquire_next_image(
+        &self,
+        sem: Option<&Semaphore>,
+    ) -> Result<(u32, SwapchainStatus), SwapchainError> {
         let s = sem
             .map(|x| *x.vk_semaphore())
             .unwrap_or_else(vk::Semaphore::null);
         let f = vk::Fence::null();
         let result = unsafe { self.loader.acquire_next_image(self.handle, u64::MAX, s, f) };
 
-        let (idx, sub_optimal) = result.map_err(|e| {
-            if e == vk::Result::ERROR_OUT_OF_DATE_KHR {
-                SwapchainError::OutOfDate
-            } else {
-                SwapchainError::AcquireNextImage(e)
-            }
-        })?;
-
-        if sub_optimal {
-            log::warn!("Suboptimal swapchain!");
-        }
-
-        Ok(idx)
+        acquire_result(result)
     }
 
     pub fn vk_swapchain(&self) -> &vk::SwapchainKHR {
@@ -270,23 +374,142 @@ impl Swapchain {
     ) -> Result<SwapchainStatus, SwapchainError> {
         let present_result = unsafe { self.loader.queue_present(*queue.vk_queue(), &info) };
 
-        let sub_optimal = present_result.map_err(|e| {
-            if e == vk::Result::ERROR_OUT_OF_DATE_KHR {
-                SwapchainError::OutOfDate
-            } else {
-                SwapchainError::EnqueuePresent(e)
-            }
-        })?;
-
-        if sub_optimal {
-            Ok(SwapchainStatus::SubOptimal)
-        } else {
-            Ok(SwapchainStatus::Optimal)
-        }
+        present_result
+            .map(status)
+            .map_err(|e| error(e, SwapchainError::EnqueuePresent))
     }
 
+    /// The number of images the implementation created, which may be more than requested
     pub fn num_images(&self) -> usize {
         assert_eq!(self.images.len(), self.image_views.len());
         self.images.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_format(format: vk::Format) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        }
+    }
+
+    #[test]
+    fn acquire_result_mapping() {
+        assert_eq!(
+            acquire_result(Ok((2, false))).unwrap(),
+            (2, SwapchainStatus::Optimal)
+        );
+        assert_eq!(
+            acquire_result(Ok((1, true))).unwrap(),
+            (1, SwapchainStatus::SubOptimal)
+        );
+        assert!(matches!(
+            acquire_result(Err(vk::Result::ERROR_OUT_OF_DATE_KHR)),
+            Err(SwapchainError::OutOfDate)
+        ));
+        assert!(matches!(
+            acquire_result(Err(vk::Result::ERROR_SURFACE_LOST_KHR)),
+            Err(SwapchainError::AcquireNextImage(
+                vk::Result::ERROR_SURFACE_LOST_KHR
+            ))
+        ));
+    }
+
+    #[test]
+    fn surface_format_preference() {
+        let available = [
+            surface_format(vk::Format::A2B10G10R10_UNORM_PACK32),
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+            surface_format(vk::Format::R8G8B8A8_SRGB),
+        ];
+
+        assert_eq!(
+            choose_swapchain_surface_format(&available, &DEFAULT_SURFACE_FORMATS),
+            surface_format(vk::Format::R8G8B8A8_SRGB)
+        );
+        assert_eq!(
+            choose_swapchain_surface_format(
+                &available,
+                &[surface_format(vk::Format::A2B10G10R10_UNORM_PACK32)]
+            ),
+            surface_format(vk::Format::A2B10G10R10_UNORM_PACK32)
+        );
+        // Falls back to 8-bit before taking the first one
+        assert_eq!(
+            choose_swapchain_surface_format(&available, &[]),
+            surface_format(vk::Format::B8G8R8A8_UNORM)
+        );
+        assert_eq!(
+            choose_swapchain_surface_format(&available[..1], &DEFAULT_SURFACE_FORMATS),
+            surface_format(vk::Format::A2B10G10R10_UNORM_PACK32)
+        );
+    }
+
+    #[test]
+    fn surface_format_undefined() {
+        let available = [surface_format(vk::Format::UNDEFINED)];
+        assert_eq!(
+            choose_swapchain_surface_format(&available, &[]),
+            DEFAULT_SURFACE_FORMATS[0]
+        );
+        assert_eq!(
+            choose_swapchain_surface_format(&available, &DEFAULT_SURFACE_FORMATS[1..]),
+            DEFAULT_SURFACE_FORMATS[1]
+        );
+    }
+
+    #[test]
+    fn image_count() {
+        let capabilities = |min_image_count, max_image_count| vk::SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        };
+        assert_eq!(choose_image_count(&capabilities(2, 8)), 3);
+        assert_eq!(choose_image_count(&capabilities(2, 0)), 3);
+        assert_eq!(choose_image_count(&capabilities(3, 3)), 3);
+        assert_eq!(choose_image_count(&capabilities(1, 2)), 2);
+    }
+
+    #[test]
+    fn extent_from_window_when_undefined() {
+        let capabilities = |current: vk::Extent2D| vk::SurfaceCapabilitiesKHR {
+            current_extent: current,
+            min_image_extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            max_image_extent: vk::Extent2D {
+                width: 4096,
+                height: 2048,
+            },
+            ..Default::default()
+        };
+        let undefined = capabilities(vk::Extent2D {
+            width: u32::MAX,
+            height: u32::MAX,
+        });
+        let e = |width, height| util::Extent2D { width, height };
+        let vk_e = |width, height| vk::Extent2D { width, height };
+
+        // E.g. on Wayland, the requested extent is used
+        assert_eq!(
+            choose_swapchain_extent(&undefined, &e(1920, 1080)),
+            vk_e(1920, 1080)
+        );
+        // Within the surface limits
+        assert_eq!(
+            choose_swapchain_extent(&undefined, &e(5000, 0)),
+            vk_e(4096, 1)
+        );
+        // Otherwise the surface decides
+        assert_eq!(
+            choose_swapchain_extent(&capabilities(vk_e(800, 600)), &e(1920, 1080)),
+            vk_e(800, 600)
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use ash::version::DeviceV1_0;
+use ash::version::DeviceV1_2;
 use ash::vk;
 
 use thiserror::Error;
 
+use crate::device::Device;
 use crate::device::VkDeviceHandle;
 
 use crate::device::HasVkDevice;
@@ -17,6 +19,16 @@ pub enum SyncError {
     FenceAwait(vk::Result),
     #[error("Couldn't reset fence {0}")]
     FenceReset(vk::Result),
+    #[error("Couldn't query fence status {0}")]
+    FenceStatus(vk::Result),
+    #[error("Timeline semaphores are not supported by the device")]
+    TimelineSemaphoreUnsupported,
+    #[error("Couldn't signal semaphore {0}")]
+    SemaphoreSignal(vk::Result),
+    #[error("Couldn't wait on semaphore {0}")]
+    SemaphoreAwait(vk::Result),
+    #[error("Couldn't query semaphore value {0}")]
+    SemaphoreQuery(vk::Result),
 }
 
 #[derive(Clone)]
@@ -55,6 +67,87 @@ impl Semaphore {
     }
 }
 
+/// A Vulkan 1.2 timeline semaphore, i.e. a semaphore with a monotonically increasing 64-bit value
+/// that can be both signaled and waited on from the host and in queue submissions.
+pub struct TimelineSemaphore {
+    vk_semaphore: vk::Semaphore,
+    vk_device: VkDeviceHandle,
+}
+
+impl std::ops::Drop for TimelineSemaphore {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device.destroy_semaphore(self.vk_semaphore, None);
+        }
+    }
+}
+
+impl TimelineSemaphore {
+    pub fn new(device: &Device, initial_value: u64) -> Result<Self, SyncError> {
+        if !device.timeline_semaphores_enabled() {
+            return Err(SyncError::TimelineSemaphoreUnsupported);
+        }
+
+        let vk_device = device.vk_device();
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+
+        let vk_semaphore = unsafe {
+            vk_device
+                .create_semaphore(&info, None)
+                .map_err(SyncError::SemaphoreCreation)?
+        };
+
+        Ok(Self {
+            vk_device,
+            vk_semaphore,
+        })
+    }
+
+    /// Set the value from the host. Has to be larger than the current value.
+    pub fn signal(&self, value: u64) -> Result<(), SyncError> {
+        let info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.vk_semaphore)
+            .value(value);
+
+        unsafe {
+            self.vk_device
+                .signal_semaphore(&info)
+                .map_err(SyncError::SemaphoreSignal)
+        }
+    }
+
+    /// Wait until the value is at least `value`. Returns false if `timeout` (in nanoseconds)
+    /// passed first.
+    pub fn wait(&self, value: u64, timeout: u64) -> Result<bool, SyncError> {
+        let semaphores = [self.vk_semaphore];
+        let values = [value];
+        let info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+
+        match unsafe { self.vk_device.wait_semaphores(&info, timeout) } {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(e) => Err(SyncError::SemaphoreAwait(e)),
+        }
+    }
+
+    pub fn value(&self) -> Result<u64, SyncError> {
+        unsafe {
+            self.vk_device
+                .get_semaphore_counter_value(self.vk_semaphore)
+                .map_err(SyncError::SemaphoreQuery)
+        }
+    }
+
+    pub fn vk_semaphore(&self) -> &vk::Semaphore {
+        &self.vk_semaphore
+    }
+}
+
 #[derive(Clone)]
 pub struct Fence {
     vk_fence: vk::Fence,
@@ -112,6 +205,26 @@ impl Fence {
         Ok(())
     }
 
+    /// Returns true if the fence is signaled, without blocking
+    pub fn status(&self) -> Result<bool, SyncError> {
+        unsafe {
+            self.vk_device
+                .get_fence_status(self.vk_fence)
+                .map_err(SyncError::FenceStatus)
+        }
+    }
+
+    /// Wait for at most `timeout` nanoseconds. Returns true if the fence was signaled before the
+    /// timeout.
+    pub fn wait_timeout(&self, timeout: u64) -> Result<bool, SyncError> {
+        let fences = [self.vk_fence];
+        match unsafe { self.vk_device.wait_for_fences(&fences, true, timeout) } {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(e) => Err(SyncError::FenceAwait(e)),
+        }
+    }
+
     pub fn reset(&self) -> Result<(), SyncError> {
         let fences = [self.vk_fence];
         unsafe {
@@ -123,3 +236,53 @@ impl Fence {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::headless_renderer;
+
+    // Needs a Vulkan device with timeline semaphores, run with --ignored
+    #[test]
+    #[ignore]
+    fn timeline_semaphore_signal_and_wait() {
+        let renderer = headless_renderer();
+        let semaphore = TimelineSemaphore::new(&renderer.device, 1)
+            .expect("Failed to create timeline semaphore");
+        assert_eq!(semaphore.value().expect("Failed to query value"), 1);
+
+        // Nothing signals 2 yet
+        assert!(!semaphore.wait(2, 1_000_000).expect("Failed to wait"));
+
+        semaphore.signal(3).expect("Failed to signal");
+        assert!(semaphore.wait(2, 1_000_000).expect("Failed to wait"));
+        assert!(semaphore.wait(3, 0).expect("Failed to wait"));
+        assert_eq!(semaphore.value().expect("Failed to query value"), 3);
+
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn fence_status_and_timeout() {
+        let renderer = headless_renderer();
+
+        let unsignaled = Fence::unsignaled(&renderer.device).expect("Failed to create fence");
+        assert!(!unsignaled.status().expect("Failed to query status"));
+        // Nothing will signal it, so the timeout expires
+        assert!(!unsignaled.wait_timeout(1_000_000).expect("Failed to wait"));
+
+        let signaled = Fence::signaled(&renderer.device).expect("Failed to create fence");
+        assert!(signaled.status().expect("Failed to query status"));
+        assert!(signaled.wait_timeout(0).expect("Failed to wait"));
+
+        signaled.reset().expect("Failed to reset");
+        assert!(!signaled.status().expect("Failed to query status"));
+        assert!(!signaled.wait_timeout(0).expect("Failed to wait"));
+
+        let errors = renderer.take_validation_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+}
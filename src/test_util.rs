@@ -0,0 +1,59 @@
+// Helpers for the tests that need a Vulkan device, shared between the modules
+
+use ash::vk;
+
+use crate::util;
+use crate::vertex;
+use crate::{Renderer, RendererConfig};
+
+// A small headless renderer with the default config
+pub(crate) fn headless_renderer() -> Renderer {
+    let extent = util::Extent2D {
+        width: 4,
+        height: 4,
+    };
+    Renderer::new_headless(extent, RendererConfig::default())
+        .expect("Failed to create headless renderer")
+}
+
+#[repr(C)]
+pub(crate) struct Position(pub(crate) [f32; 2]);
+
+impl vertex::VertexDefinition for Position {
+    fn binding_description() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Position>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    fn attribute_description() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 0,
+        }]
+    }
+}
+
+pub(crate) static POSITION_SPV_VERT: &[u32] = inline_spirv::inline_spirv!(
+    r"
+    #version 450
+    layout(location = 0) in vec2 pos;
+
+    void main() {
+        gl_Position = vec4(pos, 0.0, 1.0);
+    }
+",
+    vert
+);
+
+pub(crate) fn write_spirv(path: &std::path::Path, words: &[u32]) {
+    let bytes = words
+        .iter()
+        .flat_map(|w| w.to_le_bytes().to_vec())
+        .collect::<Vec<u8>>();
+    std::fs::write(path, bytes).expect("Failed to write shader");
+}
@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 
 use ash::version::DeviceV1_0;
 use ash::vk;
 
+use image::GenericImageView;
+
 use thiserror::Error;
 
 use crate::command::CommandPool;
@@ -12,6 +15,8 @@ use crate::device::VkDeviceHandle;
 use crate::image::{ImageView, ImageViewError};
 use crate::mem::DeviceImage;
 use crate::mem::MemoryError;
+use crate::mem::StagingPool;
+use crate::mem::Uploader;
 use crate::queue::Queue;
 use crate::resource::{CachedStorage, Handle};
 
@@ -27,57 +32,475 @@ pub enum TextureError {
     Sampler(vk::Result),
     #[error("Failed to create image view: {0}")]
     ImageView(#[from] ImageViewError),
+    #[error("Raw texture data size mismatch, expected {expected} bytes but got {got}")]
+    DataSizeMismatch { expected: usize, got: usize },
+    #[error("{0:?} is not a supported block-compressed format")]
+    NotCompressed(vk::Format),
+    #[error("{0:?} can't be used for sampled images on this device")]
+    UnsupportedFormat(vk::Format),
+    #[error("Block-compressed textures can't be updated")]
+    CompressedUpdate,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub enum TextureSource {
+    File(PathBuf),
+    /// The contents of an image file (e.g. png), decoded with the image crate
+    Encoded(Vec<u8>),
+    /// Tightly packed pixels in `format`
+    Raw {
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    },
+    /// Blocks of a block-compressed `format`, with the mip levels starting at `mip_offsets`
+    Compressed {
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        mip_offsets: Vec<usize>,
+    },
+}
+
+impl TextureSource {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+// Avoid dumping the image data
+impl std::fmt::Debug for TextureSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "File({})", path.display()),
+            Self::Encoded(data) => write!(f, "Encoded({} bytes)", data.len()),
+            Self::Raw {
+                data,
+                width,
+                height,
+                format,
+            } => write!(
+                f,
+                "Raw({} bytes, {}x{}, {:?})",
+                data.len(),
+                width,
+                height,
+                format
+            ),
+            Self::Compressed {
+                data,
+                width,
+                height,
+                format,
+                mip_offsets,
+            } => write!(
+                f,
+                "Compressed({} bytes, {}x{}, {:?}, {} mip levels)",
+                data.len(),
+                width,
+                height,
+                format,
+                std::cmp::max(mip_offsets.len(), 1)
+            ),
+        }
+    }
+}
+
+/// The channels to keep when decoding an image file. Fewer channels means less memory, e.g. a
+/// single channel for grayscale masks.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum TextureChannels {
+    /// R8_UNORM
+    Luma,
+    /// R8G8_UNORM
+    LumaAlpha,
+    /// R8G8B8_SRGB. Many devices can't sample this so it is often padded to RGBA.
+    Rgb,
+    /// R8G8B8A8_SRGB
+    Rgba,
+}
+
+impl Default for TextureChannels {
+    fn default() -> Self {
+        Self::Rgba
+    }
+}
+
+impl TextureChannels {
+    pub fn format(&self) -> vk::Format {
+        match self {
+            Self::Luma => vk::Format::R8_UNORM,
+            Self::LumaAlpha => vk::Format::R8G8_UNORM,
+            Self::Rgb => vk::Format::R8G8B8_SRGB,
+            Self::Rgba => vk::Format::R8G8B8A8_SRGB,
+        }
+    }
+}
+
+/// How a texture is sampled. By default all mip levels are used, without a bias and without
+/// anisotropic filtering.
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerDescriptor {
+    mip_lod_bias: f32,
+    min_lod: f32,
+    max_lod: Option<f32>,
+    anisotropy: bool,
+}
+
+impl Default for SamplerDescriptor {
+    fn default() -> Self {
+        Self {
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: None,
+            anisotropy: false,
+        }
+    }
+}
+
+// Compares the bits like Hash, the setters only accept finite lods so this is a proper
+// equivalence and the descriptor can be used as a cache key
+impl PartialEq for SamplerDescriptor {
+    fn eq(&self, other: &Self) -> bool {
+        self.mip_lod_bias.to_bits() == other.mip_lod_bias.to_bits()
+            && self.min_lod.to_bits() == other.min_lod.to_bits()
+            && self.max_lod.map(f32::to_bits) == other.max_lod.map(f32::to_bits)
+            && self.anisotropy == other.anisotropy
+    }
+}
+
+impl Eq for SamplerDescriptor {}
+
+impl std::hash::Hash for SamplerDescriptor {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.mip_lod_bias.to_bits().hash(state);
+        self.min_lod.to_bits().hash(state);
+        self.max_lod.map(f32::to_bits).hash(state);
+        self.anisotropy.hash(state);
+    }
+}
+
+impl SamplerDescriptor {
+    /// Added to the computed level of detail, positive values select smaller (blurrier) mips.
+    /// Panics if `bias` is not finite.
+    pub fn mip_lod_bias(mut self, bias: f32) -> Self {
+        assert!(bias.is_finite(), "Invalid mip lod bias: {}", bias);
+        self.mip_lod_bias = bias;
+        self
+    }
+
+    /// Panics if `lod` is not finite
+    pub fn min_lod(mut self, lod: f32) -> Self {
+        assert!(lod.is_finite(), "Invalid min lod: {}", lod);
+        self.min_lod = lod;
+        self
+    }
+
+    /// Defaults to the mip level count of the texture. Panics if `lod` is not finite.
+    pub fn max_lod(mut self, lod: f32) -> Self {
+        assert!(lod.is_finite(), "Invalid max lod: {}", lod);
+        self.max_lod = Some(lod);
+        self
+    }
+
+    /// Only enabled if the device has the sampler anisotropy feature
+    pub fn anisotropy(mut self, enable: bool) -> Self {
+        self.anisotropy = enable;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct TextureDescriptor {
-    file_path: PathBuf,
+    source: TextureSource,
+    generate_mipmaps: bool,
+    channels: TextureChannels,
+    sampler: SamplerDescriptor,
 }
 
 impl TextureDescriptor {
-    pub fn new(file_path: PathBuf) -> Self {
-        Self { file_path }
+    pub fn new(file_path: PathBuf, generate_mipmaps: bool) -> Self {
+        Self {
+            source: TextureSource::File(file_path),
+            generate_mipmaps,
+            channels: TextureChannels::default(),
+            sampler: SamplerDescriptor::default(),
+        }
+    }
+
+    /// `bytes` are tightly packed pixels in `format`, no decoding is done
+    pub fn from_raw(
+        bytes: Vec<u8>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        generate_mipmaps: bool,
+    ) -> Self {
+        Self {
+            source: TextureSource::Raw {
+                data: bytes,
+                width,
+                height,
+                format,
+            },
+            generate_mipmaps,
+            channels: TextureChannels::default(),
+            sampler: SamplerDescriptor::default(),
+        }
     }
-}
 
-pub fn load_image(desc: &TextureDescriptor) -> Result<image::RgbaImage, image::ImageError> {
-    let path = &desc.file_path;
+    /// Already decoded pixels, e.g. generated at runtime. `format` has to have four bytes per
+    /// pixel, e.g. R8G8B8A8_SRGB or R8G8B8A8_UNORM. The pixels are copied into the descriptor,
+    /// which is keyed by its contents like `from_raw`.
+    pub fn from_rgba_image(
+        image: &image::RgbaImage,
+        format: vk::Format,
+        generate_mipmaps: bool,
+    ) -> Self {
+        Self::from_raw(
+            image.as_raw().clone(),
+            image.width(),
+            image.height(),
+            format,
+            generate_mipmaps,
+        )
+    }
 
-    log::trace!("Trying to load image from {}", path.display());
-    let image = image::open(path)?.to_rgba();
+    /// `bytes` are blocks of a block-compressed `format` (BC1/BC3/BC7 or ETC2), e.g. from a DDS or
+    /// KTX file, which are uploaded as they are. Mip level `i` starts at `mip_offsets[i]`, an
+    /// empty `mip_offsets` means a single level at offset 0. Mipmaps can't be generated for
+    /// compressed formats.
+    pub fn compressed(
+        bytes: Vec<u8>,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_offsets: Vec<usize>,
+    ) -> Self {
+        Self {
+            source: TextureSource::Compressed {
+                data: bytes,
+                width,
+                height,
+                format,
+                mip_offsets,
+            },
+            generate_mipmaps: false,
+            channels: TextureChannels::default(),
+            sampler: SamplerDescriptor::default(),
+        }
+    }
+
+    /// `bytes` are the contents of an image file, e.g. from `include_bytes!`
+    pub fn from_encoded(bytes: Vec<u8>, generate_mipmaps: bool) -> Self {
+        Self {
+            source: TextureSource::Encoded(bytes),
+            generate_mipmaps,
+            channels: TextureChannels::default(),
+            sampler: SamplerDescriptor::default(),
+        }
+    }
 
+    /// Only used for encoded images (`new`/`from_encoded`), raw data already has a format
+    pub fn with_channels(mut self, channels: TextureChannels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: SamplerDescriptor) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn source(&self) -> &TextureSource {
+        &self.source
+    }
+
+    pub fn channels(&self) -> TextureChannels {
+        self.channels
+    }
+
+    pub fn sampler(&self) -> &SamplerDescriptor {
+        &self.sampler
+    }
+}
+
+fn log_loaded(image: &image::DynamicImage) {
     log::trace!(
-        "Loaded RGBA image with dimensions: {:?}",
+        "Loaded {:?} image with dimensions: {:?}",
+        image.color(),
         image.dimensions()
     );
+}
+
+pub fn load_image(path: &Path) -> Result<image::DynamicImage, image::ImageError> {
+    log::trace!("Trying to load image from {}", path.display());
+    let image = image::open(path)?;
+    log_loaded(&image);
+
+    Ok(image)
+}
+
+pub fn decode_image(bytes: &[u8]) -> Result<image::DynamicImage, image::ImageError> {
+    log::trace!("Trying to decode image from {} bytes", bytes.len());
+    let image = image::load_from_memory(bytes)?;
+    log_loaded(&image);
 
     Ok(image)
 }
 
+fn bytes_per_pixel(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM => Some(4),
+        vk::Format::R8G8B8_SRGB | vk::Format::R8G8B8_UNORM => Some(3),
+        vk::Format::R8_UNORM => Some(1),
+        vk::Format::R8G8_UNORM => Some(2),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+// Formats that bytes_per_pixel doesn't know are not checked
+fn check_data_size(
+    data: &[u8],
+    extent: util::Extent2D,
+    format: vk::Format,
+) -> Result<(), TextureError> {
+    if let Some(bpp) = bytes_per_pixel(format) {
+        let expected = extent.width as usize * extent.height as usize * bpp;
+        if data.len() != expected {
+            return Err(TextureError::DataSizeMismatch {
+                expected,
+                got: data.len(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Fall back to RGBA if the device can't use `requested` for a texture. RGBA support is checked
+/// during device selection.
+fn supported_channels(
+    device: &Device,
+    requested: TextureChannels,
+    generate_mipmaps: bool,
+) -> TextureChannels {
+    let mut features = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST;
+    if generate_mipmaps {
+        features |= vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+    }
+
+    if requested == TextureChannels::Rgba
+        || device.supports_optimal_tiling_features(requested.format(), features)
+    {
+        requested
+    } else {
+        log::info!(
+            "{:?} is not supported for textures on this device, padding to RGBA",
+            requested.format()
+        );
+        TextureChannels::Rgba
+    }
+}
+
+/// The extent and offset in the data of each mip level of a compressed image, checking that they
+/// are within `data_len`
+fn compressed_mip_levels(
+    format: util::Format,
+    extent: util::Extent2D,
+    mip_offsets: &[usize],
+    data_len: usize,
+) -> Result<Vec<(util::Extent2D, u64)>, TextureError> {
+    let mip_offsets = if mip_offsets.is_empty() {
+        &[0]
+    } else {
+        mip_offsets
+    };
+
+    mip_offsets
+        .iter()
+        .enumerate()
+        .map(|(mip_level, &offset)| {
+            let level_extent = util::Extent2D {
+                width: std::cmp::max(extent.width >> mip_level, 1),
+                height: std::cmp::max(extent.height >> mip_level, 1),
+            };
+            let size = format
+                .compressed_size(level_extent)
+                .ok_or_else(|| TextureError::NotCompressed(format.into()))?;
+            if offset + size > data_len {
+                return Err(TextureError::DataSizeMismatch {
+                    expected: offset + size,
+                    got: data_len,
+                });
+            }
+            Ok((level_extent, offset as u64))
+        })
+        .collect()
+}
+
+/// Tightly packed pixels with `channels`, the row pitch is derived from the format
+fn image_data(
+    image: image::DynamicImage,
+    channels: TextureChannels,
+) -> (Cow<'static, [u8]>, util::Extent2D, util::Format) {
+    let extents = util::Extent2D {
+        width: image.width(),
+        height: image.height(),
+    };
+    let data = match channels {
+        TextureChannels::Luma => image.to_luma().into_raw(),
+        TextureChannels::LumaAlpha => image.to_luma_alpha().into_raw(),
+        TextureChannels::Rgb => image.to_rgb().into_raw(),
+        TextureChannels::Rgba => image.to_rgba().into_raw(),
+    };
+    (Cow::Owned(data), extents, channels.format().into())
+}
+
 pub struct Sampler {
     vk_device: VkDeviceHandle,
     vk_sampler: vk::Sampler,
 }
 
 impl Sampler {
-    pub fn new(device: &Device) -> Result<Self, TextureError> {
+    pub fn new(
+        device: &Device,
+        mip_levels: u32,
+        descriptor: &SamplerDescriptor,
+    ) -> Result<Self, TextureError> {
+        let has_anisotropy = device.features().sampler_anisotropy == vk::TRUE;
+        if descriptor.anisotropy && !has_anisotropy {
+            log::warn!("Sampler anisotropy is not supported by the device, disabling it");
+        }
+
         let info = vk::SamplerCreateInfo::builder()
             .mag_filter(vk::Filter::LINEAR)
             .min_filter(vk::Filter::LINEAR)
             .address_mode_u(vk::SamplerAddressMode::REPEAT)
             .address_mode_v(vk::SamplerAddressMode::REPEAT)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
-            .anisotropy_enable(true)
-            .max_anisotropy(16.0)
+            .anisotropy_enable(descriptor.anisotropy && has_anisotropy)
+            .max_anisotropy(device.limits().max_sampler_anisotropy)
             .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
             .unnormalized_coordinates(false)
             .compare_enable(false)
             .compare_op(vk::CompareOp::ALWAYS)
             .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .mip_lod_bias(0.0)
-            .min_lod(0.0)
-            // From ARM Mali recommendations. 1000 is large enough for any texture
-            .max_lod(1000.0);
+            .mip_lod_bias(descriptor.mip_lod_bias)
+            .min_lod(descriptor.min_lod)
+            .max_lod(descriptor.max_lod.unwrap_or(mip_levels as f32));
 
         let vk_device = device.vk_device();
         let vk_sampler = unsafe {
@@ -109,6 +532,9 @@ pub struct Texture {
     sampler: Sampler,
     image_view: ImageView,
     image: DeviceImage,
+    extent: util::Extent2D,
+    format: util::Format,
+    mip_levels: u32,
 }
 
 impl Texture {
@@ -116,43 +542,216 @@ impl Texture {
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
         descriptor: &TextureDescriptor,
     ) -> Result<Self, TextureError> {
-        let image = load_image(descriptor)?;
-        let extents = util::Extent2D {
+        let mut uploader = Uploader::begin(device, queue, command_pool, staging_pool)?;
+        let texture = Self::upload(device, &mut uploader, descriptor)?;
+        uploader.submit_and_wait()?;
+
+        Ok(texture)
+    }
+
+    /// Upload already decoded pixels, see `TextureDescriptor::from_rgba_image`. The texture is
+    /// not tracked by the renderer.
+    pub fn from_rgba_image(
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        image: &image::RgbaImage,
+        format: vk::Format,
+    ) -> Result<Self, TextureError> {
+        let extent = util::Extent2D {
             width: image.width(),
             height: image.height(),
         };
+        check_data_size(image.as_raw(), extent, format)?;
+
+        let mut uploader = Uploader::begin(device, queue, command_pool, None)?;
+        let texture = Self::upload_pixels(
+            device,
+            &mut uploader,
+            image.as_raw(),
+            extent,
+            format.into(),
+            false,
+            &SamplerDescriptor::default(),
+        )?;
+        uploader.submit_and_wait()?;
+
+        Ok(texture)
+    }
 
-        let mip_levels = (extents.max_dim() as f32).log2().floor() as u32 + 1;
+    /// Record the upload of the texture in `uploader`. It can't be used before the uploader has
+    /// been submitted.
+    pub fn upload(
+        device: &Device,
+        uploader: &mut Uploader,
+        descriptor: &TextureDescriptor,
+    ) -> Result<Self, TextureError> {
+        let texture = Self::upload_source(device, uploader, descriptor)?;
+        let name = match descriptor.source.path() {
+            Some(path) => path.display().to_string(),
+            None => format!("{:?}", descriptor.source),
+        };
+        device.name_object(*texture.vk_image(), &name);
+        device.name_object(*texture.vk_image_view(), &name);
 
-        let format: util::Format = vk::Format::R8G8B8A8_SRGB.into();
+        Ok(texture)
+    }
 
-        let raw_image_data = image.into_raw();
-        let device_image = DeviceImage::device_local_mipmapped(
+    fn upload_source(
+        device: &Device,
+        uploader: &mut Uploader,
+        descriptor: &TextureDescriptor,
+    ) -> Result<Self, TextureError> {
+        let channels =
+            || supported_channels(device, descriptor.channels, descriptor.generate_mipmaps);
+        let (raw_image_data, extents, format) = match &descriptor.source {
+            TextureSource::File(path) => image_data(load_image(path)?, channels()),
+            TextureSource::Encoded(bytes) => image_data(decode_image(bytes)?, channels()),
+            TextureSource::Raw {
+                data,
+                width,
+                height,
+                format,
+            } => {
+                let extents = util::Extent2D {
+                    width: *width,
+                    height: *height,
+                };
+                check_data_size(data, extents, *format)?;
+                (
+                    Cow::Borrowed(data.as_slice()),
+                    extents,
+                    util::Format::from(*format),
+                )
+            }
+            TextureSource::Compressed {
+                data,
+                width,
+                height,
+                format,
+                mip_offsets,
+            } => {
+                let extent = util::Extent2D {
+                    width: *width,
+                    height: *height,
+                };
+                return Self::upload_compressed(
+                    device,
+                    uploader,
+                    data,
+                    extent,
+                    util::Format::from(*format),
+                    mip_offsets,
+                    &descriptor.sampler,
+                );
+            }
+        };
+        Self::upload_pixels(
             device,
-            queue,
-            command_pool,
+            uploader,
+            &raw_image_data,
             extents,
             format,
-            mip_levels,
-            &raw_image_data,
-        )?;
+            descriptor.generate_mipmaps,
+            &descriptor.sampler,
+        )
+    }
+
+    fn upload_pixels(
+        device: &Device,
+        uploader: &mut Uploader,
+        data: &[u8],
+        extents: util::Extent2D,
+        format: util::Format,
+        generate_mipmaps: bool,
+        sampler: &SamplerDescriptor,
+    ) -> Result<Self, TextureError> {
+        let mip_levels = if generate_mipmaps {
+            util::mip_levels_for(extents)
+        } else {
+            1
+        };
+        let device_image = uploader.upload_image(extents, format, mip_levels, data)?;
 
         let aspect = vk::ImageAspectFlags::COLOR;
 
         let image_view =
             ImageView::new(device, device_image.vk_image(), format, aspect, mip_levels)?;
 
-        let sampler = Sampler::new(device)?;
+        let sampler = Sampler::new(device, mip_levels, sampler)?;
 
         Ok(Self {
             image: device_image,
             image_view,
             sampler,
+            extent: extents,
+            format,
+            mip_levels,
         })
     }
 
+    fn upload_compressed(
+        device: &Device,
+        uploader: &mut Uploader,
+        data: &[u8],
+        extent: util::Extent2D,
+        format: util::Format,
+        mip_offsets: &[usize],
+        sampler: &SamplerDescriptor,
+    ) -> Result<Self, TextureError> {
+        let levels = compressed_mip_levels(format, extent, mip_offsets, data.len())?;
+        let features = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST;
+        if !device.supports_optimal_tiling_features(format.into(), features) {
+            return Err(TextureError::UnsupportedFormat(format.into()));
+        }
+
+        let mip_levels = levels.len() as u32;
+        let image = uploader.upload_compressed_image(format, &levels, data)?;
+        let image_view = ImageView::new(
+            device,
+            image.vk_image(),
+            format,
+            vk::ImageAspectFlags::COLOR,
+            mip_levels,
+        )?;
+        let sampler = Sampler::new(device, mip_levels, sampler)?;
+
+        Ok(Self {
+            image,
+            image_view,
+            sampler,
+            extent,
+            format,
+            mip_levels,
+        })
+    }
+
+    /// Replace the pixels with `data`, tightly packed in the format of the texture. Mipmaps are
+    /// generated again, if the texture has them. Waits for the upload to finish, but the caller
+    /// has to make sure that the texture is not used by any frame in flight.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        data: &[u8],
+    ) -> Result<(), TextureError> {
+        if self.format.is_compressed() {
+            return Err(TextureError::CompressedUpdate);
+        }
+
+        check_data_size(data, self.extent, self.format.into())?;
+
+        let mut uploader = Uploader::begin(device, queue, command_pool, None)?;
+        uploader.update_image(&self.image, self.extent, self.format, self.mip_levels, data)?;
+        uploader.submit_and_wait()?;
+
+        Ok(())
+    }
+
     pub fn vk_image(&self) -> &vk::Image {
         &self.image.vk_image()
     }
@@ -164,17 +763,64 @@ impl Texture {
     pub fn vk_sampler(&self) -> &vk::Sampler {
         &self.sampler.vk_sampler()
     }
+
+    pub fn image_view(&self) -> &ImageView {
+        &self.image_view
+    }
+
+    pub fn sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+
+    /// Device memory used by the image, including all mip levels
+    pub fn size_in_bytes(&self) -> u64 {
+        self.image.size() as u64
+    }
+
+    pub fn extent(&self) -> util::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format.into()
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
 }
 
 #[derive(Default)]
 pub struct Textures {
     storage: CachedStorage<TextureDescriptor, Texture>,
+    budget: Option<u64>,
 }
 
 impl Textures {
     pub fn new() -> Self {
         Self {
             storage: CachedStorage::<TextureDescriptor, Texture>::new(),
+            budget: None,
+        }
+    }
+
+    /// Once the textures use more than `bytes` of device memory, released textures are evicted
+    /// by `evict`, least recently used first. There is no budget by default.
+    pub fn set_budget(&mut self, bytes: u64) {
+        self.budget = Some(bytes);
+    }
+
+    /// Drop a reference added by `create` or `create_batch`
+    pub fn release(&mut self, h: Handle<Texture>) -> bool {
+        self.storage.release(h)
+    }
+
+    /// The evicted textures may still be in use by frames in flight, so the caller is
+    /// responsible for keeping them alive until those have finished.
+    pub fn evict(&mut self) -> Vec<Texture> {
+        match self.budget {
+            Some(budget) => self.storage.evict(budget, Texture::size_in_bytes),
+            None => Vec::new(),
         }
     }
 
@@ -182,15 +828,407 @@ impl Textures {
         self.storage.get(h)
     }
 
+    pub fn remove(&mut self, h: Handle<Texture>) -> Option<Texture> {
+        self.storage.remove(h)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<Texture>, &Texture)> {
+        self.storage.iter()
+    }
+
+    /// The descriptor the texture was created from. None after its contents have been updated.
+    pub fn descriptor(&self, h: &Handle<Texture>) -> Option<&TextureDescriptor> {
+        self.storage.descriptor(h)
+    }
+
+    /// For updating the contents. The texture is no longer reused for its descriptor.
+    pub fn get_mut(&mut self, h: &Handle<Texture>) -> Option<&mut Texture> {
+        self.storage.get_mut_uncached(h)
+    }
+
     pub fn create(
         &mut self,
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
         descriptor: TextureDescriptor,
     ) -> Result<Handle<Texture>, TextureError> {
         self.storage.create_or_add(descriptor, |desc| {
-            Texture::create(device, queue, command_pool, &desc)
+            Texture::create(device, queue, command_pool, staging_pool, &desc)
         })
     }
+
+    /// Upload all textures that are not already cached with a single submit. If the submit
+    /// fails, the textures have been added but their contents are undefined.
+    pub fn create_batch(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
+        descriptors: Vec<TextureDescriptor>,
+    ) -> Result<Vec<Handle<Texture>>, TextureError> {
+        let mut uploader = Uploader::begin(device, queue, command_pool, staging_pool)?;
+        let handles = descriptors
+            .into_iter()
+            .map(|descriptor| {
+                let uploader = &mut uploader;
+                self.storage
+                    .create_or_add(descriptor, |desc| Texture::upload(device, uploader, &desc))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        uploader.submit_and_wait()?;
+
+        Ok(handles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem;
+    use crate::mesh;
+    use crate::pipeline;
+    use crate::test_util::{headless_renderer, write_spirv, Position, POSITION_SPV_VERT};
+    use crate::uniform;
+    use crate::{RenderError, ResourceManager};
+
+    #[test]
+    fn descriptor_keys() {
+        let raw = |data: Vec<u8>| {
+            TextureDescriptor::from_raw(data, 1, 1, vk::Format::R8G8B8A8_SRGB, false)
+        };
+        assert_eq!(raw(vec![0, 0, 0, 255]), raw(vec![0, 0, 0, 255]));
+        assert_ne!(raw(vec![0, 0, 0, 255]), raw(vec![255, 0, 0, 255]));
+        assert_ne!(
+            TextureDescriptor::from_encoded(vec![1, 2, 3], false),
+            TextureDescriptor::from_encoded(vec![1, 2, 3], true)
+        );
+        let biased = SamplerDescriptor::default().mip_lod_bias(1.0);
+        assert_ne!(raw(vec![0; 4]), raw(vec![0; 4]).with_sampler(biased));
+        assert_eq!(
+            raw(vec![0; 4]).with_sampler(biased),
+            raw(vec![0; 4]).with_sampler(SamplerDescriptor::default().mip_lod_bias(1.0))
+        );
+        assert_eq!(
+            format!("{:?}", raw(vec![0; 4]).source()),
+            "Raw(4 bytes, 1x1, R8G8B8A8_SRGB)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid max lod")]
+    fn sampler_rejects_non_finite_lod() {
+        let _ = SamplerDescriptor::default().max_lod(f32::INFINITY);
+    }
+
+    #[test]
+    fn rgba_image_descriptor() {
+        let image = image::RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8, 0, 0, 255]));
+        let descriptor =
+            TextureDescriptor::from_rgba_image(&image, vk::Format::R8G8B8A8_UNORM, false);
+        assert_eq!(
+            descriptor,
+            TextureDescriptor::from_raw(
+                vec![0, 0, 0, 255, 1, 0, 0, 255],
+                2,
+                1,
+                vk::Format::R8G8B8A8_UNORM,
+                false
+            )
+        );
+
+        let e = util::Extent2D {
+            width: 2,
+            height: 1,
+        };
+        assert!(check_data_size(image.as_raw(), e, vk::Format::R8G8B8A8_UNORM).is_ok());
+        assert!(matches!(
+            check_data_size(image.as_raw(), e, vk::Format::R8_UNORM),
+            Err(TextureError::DataSizeMismatch {
+                expected: 2,
+                got: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn compressed_levels() {
+        let bc1 = util::Format::from(vk::Format::BC1_RGBA_UNORM_BLOCK);
+        let e = |width, height| util::Extent2D { width, height };
+
+        // 8x8, 4x4, 2x2 and 1x1, the last two are one (partial) block each
+        let levels = compressed_mip_levels(bc1, e(8, 8), &[0, 32, 40, 48], 56).unwrap();
+        assert_eq!(
+            levels,
+            vec![(e(8, 8), 0), (e(4, 4), 32), (e(2, 2), 40), (e(1, 1), 48)]
+        );
+        assert_eq!(
+            compressed_mip_levels(bc1, e(8, 8), &[], 32).unwrap(),
+            vec![(e(8, 8), 0)]
+        );
+
+        assert!(matches!(
+            compressed_mip_levels(bc1, e(8, 8), &[0, 32], 36),
+            Err(TextureError::DataSizeMismatch {
+                expected: 40,
+                got: 36
+            })
+        ));
+        let rgba = util::Format::from(vk::Format::R8G8B8A8_UNORM);
+        assert!(matches!(
+            compressed_mip_levels(rgba, e(8, 8), &[], 256),
+            Err(TextureError::NotCompressed(vk::Format::R8G8B8A8_UNORM))
+        ));
+    }
+
+    #[test]
+    fn grayscale_upload_size() {
+        let (width, height) = (5, 3);
+        let gray = image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(
+            width,
+            height,
+            image::Luma([128]),
+        ));
+        let mut png = Vec::new();
+        gray.write_to(&mut png, image::ImageOutputFormat::Png)
+            .expect("Failed to encode png");
+
+        let decoded = decode_image(&png).expect("Failed to decode png");
+        let (data, extent, format) = image_data(decoded.clone(), TextureChannels::Luma);
+        assert_eq!(data.len(), (width * height) as usize);
+        assert!(data.iter().all(|&x| x == 128));
+        assert_eq!(extent.width, width);
+        assert_eq!(extent.height, height);
+        assert_eq!(vk::Format::from(format), vk::Format::R8_UNORM);
+
+        let (data, _, format) = image_data(decoded, TextureChannels::Rgba);
+        assert_eq!(data.len(), (width * height * 4) as usize);
+        assert_eq!(vk::Format::from(format), vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(
+            bytes_per_pixel(TextureChannels::Rgb.format()),
+            Some(3),
+            "Rgb uploads are tightly packed"
+        );
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn compressed_texture() {
+        let mut renderer = headless_renderer();
+        let format = vk::Format::BC1_RGBA_UNORM_BLOCK;
+        // 8x8 with two mip levels: four blocks and then one. Each BC1 block is two 16-bit colors
+        // and 2-bit indices, all zero is black.
+        let descriptor = TextureDescriptor::compressed(vec![0; 40], format, 8, 8, vec![0, 32]);
+
+        let handle: Handle<Texture> = match renderer.create_resource(descriptor) {
+            Err(TextureError::UnsupportedFormat(f)) => {
+                assert_eq!(f, format);
+                log::warn!("BC1 is not supported on this device, skipping");
+                return;
+            }
+            x => x.expect("Failed to create compressed texture"),
+        };
+        let texture = renderer.get_resource(&handle).unwrap();
+        assert_eq!(texture.format(), format);
+        assert_eq!(texture.mip_levels(), 2);
+        assert_eq!(
+            texture.extent(),
+            util::Extent2D {
+                width: 8,
+                height: 8
+            }
+        );
+
+        let too_short = TextureDescriptor::compressed(vec![0; 31], format, 8, 8, vec![]);
+        assert!(matches!(
+            renderer.create_resource(too_short),
+            Err(TextureError::DataSizeMismatch {
+                expected: 32,
+                got: 31
+            })
+        ));
+    }
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn update_texture() {
+        let mut renderer = headless_renderer();
+        let extent = renderer.swapchain_extent();
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let size = (extent.width * extent.height * 4) as usize;
+        // Mipmaps make the image a transfer source, which the readback needs
+        let descriptor =
+            TextureDescriptor::from_raw(vec![0; size], extent.width, extent.height, format, true);
+        let handle = renderer
+            .create_resource(descriptor.clone())
+            .expect("Failed to create texture");
+
+        assert!(matches!(
+            renderer.update_texture(&handle, &[0; 3]),
+            Err(RenderError::Texture(TextureError::DataSizeMismatch { .. }))
+        ));
+
+        let pixels = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+        renderer
+            .update_texture(&handle, &pixels)
+            .expect("Failed to update texture");
+
+        let texture = renderer.get_resource(&handle).unwrap();
+        let readback =
+            mem::DeviceBuffer::readback_empty(&renderer.device, size).expect("Failed to create");
+        let cmd_buf = renderer
+            .util_command_pool
+            .begin_single_submit()
+            .expect("Failed to begin");
+        let cmd_buf = mem::transition_image_layout(
+            cmd_buf,
+            texture.vk_image(),
+            1,
+            format,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )
+        .copy_image_to_buffer(texture.vk_image(), readback.vk_buffer(), &extent)
+        .end()
+        .expect("Failed to end");
+        renderer
+            .device
+            .util_queue()
+            .submit_and_wait(&cmd_buf)
+            .expect("Failed to submit");
+        assert_eq!(readback.read_data().expect("Failed to read"), pixels);
+
+        // The updated texture no longer matches its descriptor
+        let recreated = renderer
+            .create_resource(descriptor)
+            .expect("Failed to create texture");
+        assert!(recreated != handle);
+    }
+
+    static SCREEN_UV_SPV_FRAG: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450
+        layout(set = 0, binding = 0) uniform Params {
+            vec4 inv_extent;
+        } params;
+        layout(set = 0, binding = 1) uniform sampler2D tex;
+        layout(location = 0) out vec4 color;
+
+        void main() {
+            color = texture(tex, gl_FragCoord.xy * params.inv_extent.xy);
+        }
+    ",
+        frag
+    );
+
+    // Needs a Vulkan device, run with --ignored
+    #[test]
+    #[ignore]
+    fn sampler_lod_bias() {
+        let dir = std::env::temp_dir().join("trekanten_sampler_lod_bias");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vert, frag) = (dir.join("vert.spv"), dir.join("frag.spv"));
+        write_spirv(&vert, POSITION_SPV_VERT);
+        write_spirv(&frag, SCREEN_UV_SPV_FRAG);
+
+        let mut renderer = headless_renderer();
+
+        let pipeline_handle = renderer
+            .create_resource(
+                pipeline::GraphicsPipelineDescriptor::builder()
+                    .vertex_shader(&vert)
+                    .fragment_shader(&frag)
+                    .vertex_type::<Position>()
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .depth_test_enable(false)
+                    .build()
+                    .expect("Failed to create pipeline descriptor"),
+            )
+            .expect("Failed to create pipeline");
+        let params = [[0.25f32, 0.25, 0.0, 0.0]];
+        let params_handle = renderer
+            .create_resource(uniform::UniformBufferDescriptor::from_slice(&params))
+            .expect("Failed to create uniform buffer");
+        let quad = [
+            Position([-1.0, -1.0]),
+            Position([1.0, -1.0]),
+            Position([-1.0, 1.0]),
+            Position([1.0, 1.0]),
+        ];
+        let mesh_handle = renderer
+            .create_resource(mesh::MeshDescriptor::from_slices(
+                &quad,
+                &[0u32, 1, 2, 2, 1, 3],
+            ))
+            .expect("Failed to create mesh");
+
+        // 8x8 texture seen from a distance on 4x4 pixels, i.e. lod 1. Its checkerboard of 2x2
+        // blocks is a checkerboard of single texels in mip 1 and gray in mip 2.
+        let checker = ::image::RgbaImage::from_fn(8, 8, |x, y| {
+            if (x / 2 + y / 2) % 2 == 0 {
+                ::image::Rgba([255, 255, 255, 255])
+            } else {
+                ::image::Rgba([0, 0, 0, 255])
+            }
+        });
+        let mut render = |bias: f32| {
+            let texture_handle = renderer
+                .create_resource(
+                    TextureDescriptor::from_rgba_image(&checker, vk::Format::R8G8B8A8_UNORM, true)
+                        .with_sampler(SamplerDescriptor::default().mip_lod_bias(bias)),
+                )
+                .expect("Failed to create texture");
+            let desc_set_handle = renderer
+                .create_descriptor_set(&pipeline_handle, &params_handle, &texture_handle)
+                .expect("Failed to create descriptor set");
+
+            let mut frame = renderer.next_frame().expect("Failed to get frame");
+            let gfx_pipeline = renderer.get_resource(&pipeline_handle).unwrap();
+            let cmd_buf = frame
+                .new_command_buffer()
+                .expect("Failed to create command buffer")
+                .begin_render_pass(
+                    renderer.render_pass(),
+                    renderer.framebuffer(&frame),
+                    renderer.swapchain_extent(),
+                )
+                .bind_graphics_pipeline(gfx_pipeline)
+                .bind_descriptor_set(
+                    renderer.get_descriptor_set(&desc_set_handle).unwrap(),
+                    gfx_pipeline,
+                )
+                .draw_mesh(renderer.get_resource(&mesh_handle).unwrap())
+                .end_render_pass()
+                .end()
+                .expect("Failed to end command buffer");
+            frame
+                .add_command_buffer(cmd_buf)
+                .expect("Failed to add command buffer");
+            renderer
+                .request_readback(&mut frame)
+                .expect("Failed to request readback");
+            renderer.submit(frame).expect("Failed to submit");
+            renderer.read_framebuffer().expect("Failed to read back")
+        };
+
+        let unbiased = render(0.0);
+        assert!(
+            unbiased.data.iter().all(|&c| c < 10 || c > 245),
+            "{:?}",
+            unbiased.data
+        );
+        let biased = render(1.0);
+        assert!(
+            biased
+                .data
+                .chunks(4)
+                .all(|px| px[..3].iter().all(|&c| c > 50 && c < 205)),
+            "{:?}",
+            biased.data
+        );
+    }
 }
@@ -4,16 +4,27 @@ use crate::command::CommandPool;
 use crate::device::Device;
 use crate::mem::DeviceBuffer;
 use crate::mem::MemoryError;
+use crate::mem::StagingPool;
 use crate::queue::Queue;
 use crate::resource::{BufferedStorage, Handle};
 
-use crate::common::MAX_FRAMES_IN_FLIGHT;
-
 use crate::util;
 
 pub enum UniformBufferDescriptor<'a> {
-    Initialized { data: &'a [u8], elem_size: usize },
-    Uninitialized { elem_size: usize, n_elems: usize },
+    Initialized {
+        data: &'a [u8],
+        elem_size: usize,
+    },
+    Uninitialized {
+        elem_size: usize,
+        n_elems: usize,
+    },
+    /// A single buffer holding `n_elems` elements, each aligned to the device's
+    /// `minUniformBufferOffsetAlignment` so that they can be bound with a dynamic offset.
+    Dynamic {
+        elem_size: usize,
+        n_elems: usize,
+    },
 }
 
 impl<'a> UniformBufferDescriptor<'a> {
@@ -32,12 +43,32 @@ impl<'a> UniformBufferDescriptor<'a> {
             n_elems,
         }
     }
+
+    pub fn dynamic<V>(n_elems: usize) -> Self {
+        Self::Dynamic {
+            elem_size: std::mem::size_of::<V>(),
+            n_elems,
+        }
+    }
+}
+
+/// Round `elem_size` up to the next multiple of `alignment`, which is a power of two (or zero, if
+/// the device has no requirement).
+fn aligned_elem_size(elem_size: usize, alignment: usize) -> usize {
+    if alignment == 0 {
+        return elem_size;
+    }
+
+    assert!(alignment.is_power_of_two());
+    (elem_size + alignment - 1) & !(alignment - 1)
 }
 
 pub struct UniformBuffer {
     buffer: DeviceBuffer,
     elem_size: usize,
     n_elems: usize,
+    stride: usize,
+    dynamic: bool,
 }
 
 impl UniformBuffer {
@@ -45,44 +76,102 @@ impl UniformBuffer {
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        staging_pool: Option<&mut StagingPool>,
         descriptor: &UniformBufferDescriptor<'a>,
     ) -> Result<Self, MemoryError> {
-        let (buffer, elem_size, n_elems) = match descriptor {
+        let (buffer, elem_size, n_elems, stride, dynamic) = match descriptor {
             UniformBufferDescriptor::Initialized { data, elem_size } => (
                 DeviceBuffer::device_local_by_staging(
                     device,
                     queue,
                     command_pool,
+                    staging_pool,
                     vk::BufferUsageFlags::UNIFORM_BUFFER,
                     data,
                 )?,
                 *elem_size,
                 data.len() / elem_size,
+                *elem_size,
+                false,
             ),
             UniformBufferDescriptor::Uninitialized { elem_size, n_elems } => (
-                DeviceBuffer::empty(
+                DeviceBuffer::persistent_mapped(
                     device,
                     elem_size * n_elems,
                     vk::BufferUsageFlags::UNIFORM_BUFFER,
-                    vk_mem::MemoryUsage::CpuToGpu,
                 )?,
                 *elem_size,
                 *n_elems,
+                *elem_size,
+                false,
             ),
+            UniformBufferDescriptor::Dynamic { elem_size, n_elems } => {
+                let stride = aligned_elem_size(
+                    *elem_size,
+                    device.min_uniform_buffer_offset_alignment() as usize,
+                );
+                (
+                    DeviceBuffer::persistent_mapped(
+                        device,
+                        stride * n_elems,
+                        vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    )?,
+                    *elem_size,
+                    *n_elems,
+                    stride,
+                    true,
+                )
+            }
         };
 
         Ok(Self {
             buffer,
             elem_size,
             n_elems,
+            stride,
+            dynamic,
         })
     }
 
+    fn check_elem_size<T>(&self) -> Result<(), MemoryError> {
+        let got = std::mem::size_of::<T>();
+        if got != self.elem_size {
+            return Err(MemoryError::SizeMismatch {
+                expected: self.elem_size,
+                got,
+            });
+        }
+
+        Ok(())
+    }
+
     pub fn update_with<T>(&mut self, data: &T) -> Result<(), MemoryError> {
+        self.check_elem_size::<T>()?;
         let raw_data = util::as_bytes(data);
         self.buffer.update_data_at(raw_data, 0)
     }
 
+    /// The current contents, e.g. for debugging. The frame that the buffer belongs to must not be
+    /// in flight.
+    pub fn read_data(&self) -> Result<Vec<u8>, MemoryError> {
+        self.buffer.read_data()
+    }
+
+    /// Write `data` to element `idx`. Bind it with `dynamic_offset(idx)` if this is a dynamic
+    /// uniform buffer.
+    pub fn update_element<T>(&mut self, idx: usize, data: &T) -> Result<(), MemoryError> {
+        self.check_elem_size::<T>()?;
+        if idx >= self.n_elems {
+            return Err(MemoryError::ElementOutOfRange {
+                index: idx,
+                n_elems: self.n_elems,
+            });
+        }
+
+        let raw_data = util::as_bytes(data);
+        self.buffer.update_data_at(raw_data, idx * self.stride)
+    }
+
     pub fn vk_buffer(&self) -> &vk::Buffer {
         &self.buffer.vk_buffer()
     }
@@ -95,20 +184,37 @@ impl UniformBuffer {
         self.n_elems
     }
 
+    /// Distance in bytes between the start of two consecutive elements
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    /// The offset to pass when binding element `idx` of a dynamic uniform buffer
+    pub fn dynamic_offset(&self, idx: usize) -> u32 {
+        assert!(idx < self.n_elems, "Uniform buffer element out of range");
+        (idx * self.stride) as u32
+    }
+
     pub fn size(&self) -> usize {
-        self.n_elems * self.elem_size
+        self.n_elems * self.stride
     }
 }
 
-#[derive(Default)]
+/// One UniformBuffer per frame in flight for each handle
 pub struct UniformBuffers {
     storage: BufferedStorage<UniformBuffer>,
+    frames_in_flight: usize,
 }
 
 impl UniformBuffers {
-    pub fn new() -> Self {
+    pub fn new(frames_in_flight: usize) -> Self {
         Self {
             storage: Default::default(),
+            frames_in_flight,
         }
     }
 
@@ -117,24 +223,44 @@ impl UniformBuffers {
         device: &Device,
         queue: &Queue,
         command_pool: &CommandPool,
+        mut staging_pool: Option<&mut StagingPool>,
         descriptor: &UniformBufferDescriptor<'a>,
     ) -> Result<Handle<UniformBuffer>, MemoryError> {
-        let u_buffer0 = UniformBuffer::create(device, queue, command_pool, descriptor)?;
-        let u_buffer1 = UniformBuffer::create(device, queue, command_pool, descriptor)?;
-        Ok(self.storage.add([u_buffer0, u_buffer1]))
+        let buffers = (0..self.frames_in_flight)
+            .map(|_| {
+                UniformBuffer::create(
+                    device,
+                    queue,
+                    command_pool,
+                    staging_pool.as_deref_mut(),
+                    descriptor,
+                )
+            })
+            .collect::<Result<Vec<_>, MemoryError>>()?;
+        Ok(self.storage.add(buffers))
     }
 
     pub fn get(&self, h: &Handle<UniformBuffer>, frame_idx: usize) -> Option<&UniformBuffer> {
         self.storage.get(h, frame_idx)
     }
 
-    pub fn get_all(
-        &self,
-        h: &Handle<UniformBuffer>,
-    ) -> Option<&[UniformBuffer; MAX_FRAMES_IN_FLIGHT]> {
+    pub fn get_all(&self, h: &Handle<UniformBuffer>) -> Option<&[UniformBuffer]> {
         self.storage.get_all(h)
     }
 
+    /// The buffers used for frame `frame_idx`
+    pub fn iter(
+        &self,
+        frame_idx: usize,
+    ) -> impl Iterator<Item = (Handle<UniformBuffer>, &UniformBuffer)> {
+        self.storage.iter_with_handles(frame_idx)
+    }
+
+    /// Removes the buffers for all frames in flight
+    pub fn remove(&mut self, h: Handle<UniformBuffer>) -> Option<Vec<UniformBuffer>> {
+        self.storage.remove(h)
+    }
+
     pub fn get_mut(
         &mut self,
         h: &Handle<UniformBuffer>,
@@ -143,3 +269,27 @@ impl UniformBuffers {
         self.storage.get_mut(h, frame_idx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elem_size_alignment() {
+        assert_eq!(aligned_elem_size(192, 0), 192);
+        assert_eq!(aligned_elem_size(192, 1), 192);
+        assert_eq!(aligned_elem_size(192, 64), 192);
+        assert_eq!(aligned_elem_size(192, 256), 256);
+        assert_eq!(aligned_elem_size(200, 64), 256);
+        assert_eq!(aligned_elem_size(1, 16), 16);
+
+        for &alignment in &[4, 16, 64, 256] {
+            for elem_size in 1..1024 {
+                let aligned = aligned_elem_size(elem_size, alignment);
+                assert!(aligned >= elem_size);
+                assert!(aligned - elem_size < alignment);
+                assert_eq!(aligned % alignment, 0);
+            }
+        }
+    }
+}
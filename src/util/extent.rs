@@ -8,6 +8,20 @@ impl Extent2D {
     pub fn max_dim(&self) -> u32 {
         std::cmp::max(self.width, self.height)
     }
+
+    /// True if the extent has zero area, e.g. for a minimized window
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Both dimensions multiplied by `scale` and rounded, but at least 1
+    pub fn scaled(&self, scale: f32) -> Self {
+        let scale_dim = |dim: u32| std::cmp::max((dim as f32 * scale).round() as u32, 1);
+        Self {
+            width: scale_dim(self.width),
+            height: scale_dim(self.height),
+        }
+    }
 }
 
 impl From<ash::vk::Extent2D> for Extent2D {
@@ -28,6 +42,16 @@ impl From<Extent2D> for ash::vk::Extent2D {
     }
 }
 
+/// The rect at the origin with this extent
+impl From<Extent2D> for ash::vk::Rect2D {
+    fn from(e: Extent2D) -> Self {
+        Self {
+            offset: ash::vk::Offset2D { x: 0, y: 0 },
+            extent: e.into(),
+        }
+    }
+}
+
 impl std::fmt::Display for Extent2D {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}x{}", self.width, self.height)
@@ -80,3 +104,31 @@ impl std::fmt::Display for Extent3D {
         write!(f, "{}x{}x{}", self.width, self.height, self.depth)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled() {
+        let extent = Extent2D {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(extent.scaled(1.0), extent);
+        assert_eq!(
+            extent.scaled(0.75),
+            Extent2D {
+                width: 1440,
+                height: 810
+            }
+        );
+        assert_eq!(
+            extent.scaled(0.0001),
+            Extent2D {
+                width: 1,
+                height: 1
+            }
+        );
+    }
+}
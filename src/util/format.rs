@@ -1,10 +1,85 @@
 use ash::vk;
 
+use super::Extent2D;
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
 pub struct Format {
     vk_format: vk::Format,
 }
 
+impl Format {
+    /// The size in bytes of a 4x4 texel block, None if this is not a supported block-compressed
+    /// format
+    pub fn block_size(&self) -> Option<usize> {
+        match self.vk_format {
+            vk::Format::BC1_RGB_UNORM_BLOCK
+            | vk::Format::BC1_RGB_SRGB_BLOCK
+            | vk::Format::BC1_RGBA_UNORM_BLOCK
+            | vk::Format::BC1_RGBA_SRGB_BLOCK
+            | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+            | vk::Format::ETC2_R8G8B8_SRGB_BLOCK => Some(8),
+            vk::Format::BC3_UNORM_BLOCK
+            | vk::Format::BC3_SRGB_BLOCK
+            | vk::Format::BC7_UNORM_BLOCK
+            | vk::Format::BC7_SRGB_BLOCK
+            | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+            | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => Some(16),
+            _ => None,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.block_size().is_some()
+    }
+
+    pub fn has_depth(&self) -> bool {
+        matches!(
+            self.vk_format,
+            vk::Format::D16_UNORM
+                | vk::Format::X8_D24_UNORM_PACK32
+                | vk::Format::D32_SFLOAT
+                | vk::Format::D16_UNORM_S8_UINT
+                | vk::Format::D24_UNORM_S8_UINT
+                | vk::Format::D32_SFLOAT_S8_UINT
+        )
+    }
+
+    /// Depth and/or stencil, i.e. a format for depth/stencil attachments
+    pub fn is_depth_stencil(&self) -> bool {
+        self.has_depth() || self.has_stencil()
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        matches!(
+            self.vk_format,
+            vk::Format::S8_UINT
+                | vk::Format::D16_UNORM_S8_UINT
+                | vk::Format::D24_UNORM_S8_UINT
+                | vk::Format::D32_SFLOAT_S8_UINT
+        )
+    }
+
+    /// The aspects of a depth/stencil format, e.g. for views of it or clearing it
+    pub fn depth_stencil_aspect(&self) -> vk::ImageAspectFlags {
+        let mut aspect = vk::ImageAspectFlags::empty();
+        if self.has_depth() {
+            aspect |= vk::ImageAspectFlags::DEPTH;
+        }
+        if self.has_stencil() {
+            aspect |= vk::ImageAspectFlags::STENCIL;
+        }
+        aspect
+    }
+
+    /// The size in bytes of `extent` in this block-compressed format, where blocks that are
+    /// partially outside of it count as whole blocks
+    pub fn compressed_size(&self, extent: Extent2D) -> Option<usize> {
+        let blocks = |dim: u32| ((dim + 3) / 4) as usize;
+        self.block_size()
+            .map(|size| blocks(extent.width) * blocks(extent.height) * size)
+    }
+}
+
 impl From<Format> for vk::Format {
     fn from(f: Format) -> vk::Format {
         f.vk_format
@@ -16,3 +91,56 @@ impl From<vk::Format> for Format {
         Self { vk_format: f }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_size() {
+        let e = |width, height| Extent2D { width, height };
+        let bc1 = Format::from(vk::Format::BC1_RGBA_UNORM_BLOCK);
+        let bc7 = Format::from(vk::Format::BC7_SRGB_BLOCK);
+        assert_eq!(bc1.compressed_size(e(8, 8)), Some(4 * 8));
+        assert_eq!(bc7.compressed_size(e(8, 8)), Some(4 * 16));
+        // Partial blocks
+        assert_eq!(bc1.compressed_size(e(1, 1)), Some(8));
+        assert_eq!(bc1.compressed_size(e(5, 4)), Some(2 * 8));
+        assert_eq!(bc7.compressed_size(e(6, 10)), Some(2 * 3 * 16));
+
+        let rgba = Format::from(vk::Format::R8G8B8A8_UNORM);
+        assert!(!rgba.is_compressed());
+        assert_eq!(rgba.compressed_size(e(8, 8)), None);
+    }
+
+    #[test]
+    fn stencil_formats() {
+        assert!(Format::from(vk::Format::D24_UNORM_S8_UINT).has_stencil());
+        assert!(Format::from(vk::Format::D32_SFLOAT_S8_UINT).has_stencil());
+        assert!(!Format::from(vk::Format::D32_SFLOAT).has_stencil());
+        assert!(!Format::from(vk::Format::R8G8B8A8_UNORM).has_stencil());
+        assert!(Format::from(vk::Format::D32_SFLOAT).is_depth_stencil());
+        assert!(Format::from(vk::Format::S8_UINT).is_depth_stencil());
+        assert!(!Format::from(vk::Format::S8_UINT).has_depth());
+        assert!(!Format::from(vk::Format::B8G8R8A8_SRGB).is_depth_stencil());
+        assert_eq!(
+            Format::from(vk::Format::D24_UNORM_S8_UINT).depth_stencil_aspect(),
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        );
+        assert_eq!(
+            Format::from(vk::Format::D32_SFLOAT).depth_stencil_aspect(),
+            vk::ImageAspectFlags::DEPTH
+        );
+        assert_eq!(
+            Format::from(vk::Format::S8_UINT).depth_stencil_aspect(),
+            vk::ImageAspectFlags::STENCIL
+        );
+    }
+
+    #[test]
+    fn bgra_round_trip() {
+        for &f in &[vk::Format::B8G8R8A8_SRGB, vk::Format::B8G8R8A8_UNORM] {
+            assert_eq!(vk::Format::from(Format::from(f)), f);
+        }
+    }
+}
@@ -2,15 +2,63 @@ pub mod extent;
 pub mod ffi;
 pub mod format;
 pub mod lifetime;
+pub mod rect;
 pub mod vk_debug;
 
 pub use extent::*;
 pub use format::*;
+pub use rect::*;
 
 pub fn clamp<T: Ord>(v: T, min: T, max: T) -> T {
     std::cmp::max(min, std::cmp::min(v, max))
 }
 
+/// Number of mip levels needed to reduce `extent` down to 1x1
+pub fn mip_levels_for(extent: Extent2D) -> u32 {
+    (extent.max_dim() as f32).log2().floor() as u32 + 1
+}
+
+/// Swap the R and B channels of tightly packed 4-byte pixels, in place
+pub fn swizzle_bgra_to_rgba(data: &mut [u8]) {
+    assert_eq!(data.len() % 4, 0);
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+}
+
+fn srgb_channel_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_channel_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decode sRGB-encoded color channels (e.g. from a color picker) to linear ones
+pub fn srgb_to_linear(c: [f32; 3]) -> [f32; 3] {
+    [
+        srgb_channel_to_linear(c[0]),
+        srgb_channel_to_linear(c[1]),
+        srgb_channel_to_linear(c[2]),
+    ]
+}
+
+pub fn linear_to_srgb(c: [f32; 3]) -> [f32; 3] {
+    [
+        linear_channel_to_srgb(c[0]),
+        linear_channel_to_srgb(c[1]),
+        linear_channel_to_srgb(c[2]),
+    ]
+}
+
 pub fn as_byte_slice<T>(slice: &[T]) -> &[u8] {
     let ptr = slice.as_ptr() as *const u8;
     let size = std::mem::size_of::<T>() * slice.len();
@@ -22,3 +70,59 @@ pub fn as_bytes<T>(v: &T) -> &[u8] {
     let size = std::mem::size_of::<T>();
     unsafe { std::slice::from_raw_parts(ptr, size) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_levels() {
+        let e = |width, height| Extent2D { width, height };
+        assert_eq!(mip_levels_for(e(1, 1)), 1);
+        assert_eq!(mip_levels_for(e(2, 2)), 2);
+        assert_eq!(mip_levels_for(e(3, 1)), 2);
+        assert_eq!(mip_levels_for(e(1, 1024)), 11);
+        assert_eq!(mip_levels_for(e(1024, 768)), 11);
+        assert_eq!(mip_levels_for(e(1025, 1)), 11);
+    }
+
+    #[test]
+    fn bgra_swizzle() {
+        let mut data = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        swizzle_bgra_to_rgba(&mut data);
+        assert_eq!(data, vec![3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn srgb_conversion() {
+        let close =
+            |a: [f32; 3], b: [f32; 3]| a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-4);
+
+        assert_eq!(srgb_to_linear([0.0, 1.0, 0.0]), [0.0, 1.0, 0.0]);
+        assert!(close(
+            srgb_to_linear([0.5, 0.5, 0.5]),
+            [0.21404, 0.21404, 0.21404]
+        ));
+        assert!(close(
+            srgb_to_linear([0.04045, 0.2, 0.8]),
+            [0.0031308, 0.033105, 0.603828]
+        ));
+        assert!(close(
+            linear_to_srgb([0.21404, 0.0031308, 0.5]),
+            [0.5, 0.04045, 0.735357]
+        ));
+
+        let c = [0.1, 0.25, 0.9];
+        assert!(close(linear_to_srgb(srgb_to_linear(c)), c));
+    }
+
+    #[test]
+    fn empty_extent() {
+        let e = |width, height| Extent2D { width, height };
+        assert!(e(0, 0).is_empty());
+        assert!(e(0, 600).is_empty());
+        assert!(e(800, 0).is_empty());
+        assert!(!e(1, 1).is_empty());
+        assert!(!e(800, 600).is_empty());
+    }
+}
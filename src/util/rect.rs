@@ -0,0 +1,152 @@
+use super::Extent2D;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Offset2D {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl From<ash::vk::Offset2D> for Offset2D {
+    fn from(o: ash::vk::Offset2D) -> Self {
+        Self { x: o.x, y: o.y }
+    }
+}
+
+impl From<Offset2D> for ash::vk::Offset2D {
+    fn from(o: Offset2D) -> Self {
+        Self { x: o.x, y: o.y }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect2D {
+    pub offset: Offset2D,
+    pub extent: Extent2D,
+}
+
+impl Rect2D {
+    /// The rect at the origin with this extent
+    pub fn from_extent(extent: Extent2D) -> Self {
+        Self {
+            offset: Offset2D::default(),
+            extent,
+        }
+    }
+}
+
+impl From<ash::vk::Rect2D> for Rect2D {
+    fn from(r: ash::vk::Rect2D) -> Self {
+        Self {
+            offset: r.offset.into(),
+            extent: r.extent.into(),
+        }
+    }
+}
+
+impl From<Rect2D> for ash::vk::Rect2D {
+    fn from(r: Rect2D) -> Self {
+        Self {
+            offset: r.offset.into(),
+            extent: r.extent.into(),
+        }
+    }
+}
+
+/// The mapping from normalized device coordinates to framebuffer coordinates. A negative height
+/// flips it vertically.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl Viewport {
+    /// Covers all of `extent`, with depth from 0 to 1
+    pub fn from_extent(extent: Extent2D) -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+}
+
+impl From<ash::vk::Viewport> for Viewport {
+    fn from(v: ash::vk::Viewport) -> Self {
+        Self {
+            x: v.x,
+            y: v.y,
+            width: v.width,
+            height: v.height,
+            min_depth: v.min_depth,
+            max_depth: v.max_depth,
+        }
+    }
+}
+
+impl From<Viewport> for ash::vk::Viewport {
+    fn from(v: Viewport) -> Self {
+        Self {
+            x: v.x,
+            y: v.y,
+            width: v.width,
+            height: v.height,
+            min_depth: v.min_depth,
+            max_depth: v.max_depth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_round_trip() {
+        let rect = Rect2D {
+            offset: Offset2D { x: -3, y: 7 },
+            extent: Extent2D {
+                width: 640,
+                height: 480,
+            },
+        };
+        let vk_rect = ash::vk::Rect2D::from(rect);
+        assert_eq!((vk_rect.offset.x, vk_rect.offset.y), (-3, 7));
+        assert_eq!((vk_rect.extent.width, vk_rect.extent.height), (640, 480));
+        assert_eq!(Rect2D::from(vk_rect), rect);
+
+        let origin = Rect2D::from_extent(rect.extent);
+        assert_eq!(Rect2D::from(ash::vk::Rect2D::from(rect.extent)), origin);
+    }
+
+    #[test]
+    fn viewport_round_trip() {
+        let viewport = Viewport {
+            x: 10.0,
+            y: 600.0,
+            width: 800.0,
+            height: -600.0,
+            min_depth: 0.25,
+            max_depth: 0.75,
+        };
+        let vk_viewport = ash::vk::Viewport::from(viewport);
+        assert_eq!(vk_viewport.y, 600.0);
+        assert_eq!(vk_viewport.height, -600.0);
+        assert_eq!(vk_viewport.min_depth, 0.25);
+        assert_eq!(Viewport::from(vk_viewport), viewport);
+
+        let full = Viewport::from_extent(Extent2D {
+            width: 4,
+            height: 2,
+        });
+        assert_eq!((full.width, full.height), (4.0, 2.0));
+        assert_eq!((full.min_depth, full.max_depth), (0.0, 1.0));
+    }
+}
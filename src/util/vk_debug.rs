@@ -4,6 +4,8 @@ use ash::vk;
 use std::ffi::CStr;
 use std::fmt::Write;
 use std::os::raw::c_char;
+use std::sync::mpsc;
+use std::sync::Mutex;
 
 use crate::instance::Instance;
 use crate::util::lifetime::LifetimeToken;
@@ -16,9 +18,82 @@ pub enum DebugUtilsError {
     Creation(vk::Result),
 }
 
+/// What to do with validation messages at or above `ValidationErrors::min_severity`. They are
+/// always logged as well.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValidationErrorAction {
+    /// Panic on the next call to `Renderer::next_frame` or `Renderer::submit`. Panicking in the
+    /// callback itself would unwind into the vulkan loader.
+    Panic,
+    /// Keep them until `Renderer::take_validation_errors` is called
+    Collect,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ValidationErrors {
+    pub min_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub action: ValidationErrorAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidationMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}]{}", self.severity, self.message)
+    }
+}
+
+const VALIDATION_PANIC_ENV_VAR: &str = "TREK_VALIDATION_PANIC";
+
+fn parse_severity(s: &str) -> Option<vk::DebugUtilsMessageSeverityFlagsEXT> {
+    use vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
+    match s.to_ascii_lowercase().as_str() {
+        "verbose" => Some(Severity::VERBOSE),
+        "info" => Some(Severity::INFO),
+        "warning" => Some(Severity::WARNING),
+        "error" => Some(Severity::ERROR),
+        _ => None,
+    }
+}
+
+/// `TREK_VALIDATION_PANIC` overrides the requested value so that e.g. CI can panic on validation
+/// errors without a rebuild. Its value is the min severity, "error" if it is not recognized.
+fn use_validation_errors(requested: Option<ValidationErrors>) -> Option<ValidationErrors> {
+    match std::env::var(VALIDATION_PANIC_ENV_VAR) {
+        Ok(value) => Some(ValidationErrors {
+            min_severity: parse_severity(&value)
+                .unwrap_or(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR),
+            action: ValidationErrorAction::Panic,
+        }),
+        Err(_) => requested,
+    }
+}
+
+// The flags are single bits, ordered by severity
+fn at_least(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    min_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+) -> bool {
+    severity.as_raw() >= min_severity.as_raw()
+}
+
+// Owned by DebugUtils and passed to the callback as user data
+struct CallbackState {
+    min_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    // The callback may be called from any thread
+    sender: Mutex<mpsc::Sender<ValidationMessage>>,
+}
+
 pub struct DebugUtils {
     loader: ext::DebugUtils,
     callback_handle: vk::DebugUtilsMessengerEXT,
+    // Has to outlive the messenger
+    _callback_state: Option<Box<CallbackState>>,
+    receiver: Option<(mpsc::Receiver<ValidationMessage>, ValidationErrorAction)>,
     _parent_lifetime_token: LifetimeToken<Instance>,
 }
 
@@ -32,13 +107,37 @@ impl Drop for DebugUtils {
 }
 
 impl DebugUtils {
-    pub fn new(instance: &Instance) -> Result<Self, DebugUtilsError> {
+    pub fn new(
+        instance: &Instance,
+        validation_errors: Option<ValidationErrors>,
+    ) -> Result<Self, DebugUtilsError> {
         let loader = ext::DebugUtils::new(instance.entry(), instance.vk_instance());
 
+        let (callback_state, receiver) = match use_validation_errors(validation_errors) {
+            Some(ValidationErrors {
+                min_severity,
+                action,
+            }) => {
+                let (sender, receiver) = mpsc::channel();
+                let state = Box::new(CallbackState {
+                    min_severity,
+                    sender: Mutex::new(sender),
+                });
+                (Some(state), Some((receiver, action)))
+            }
+            None => (None, None),
+        };
+
+        let user_data = callback_state
+            .as_ref()
+            .map(|state| &**state as *const CallbackState as *mut std::os::raw::c_void)
+            .unwrap_or(std::ptr::null_mut());
+
         let info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
             .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
             .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
-            .pfn_user_callback(Some(vk_debug_callback));
+            .pfn_user_callback(Some(vk_debug_callback))
+            .user_data(user_data);
 
         let callback_handle = unsafe {
             loader
@@ -49,9 +148,34 @@ impl DebugUtils {
         Ok(Self {
             loader,
             callback_handle,
+            _callback_state: callback_state,
+            receiver,
             _parent_lifetime_token: instance.lifetime_token(),
         })
     }
+
+    /// The messages at or above the min severity since the last call, if the action is `Collect`
+    pub fn take_validation_errors(&self) -> Vec<ValidationMessage> {
+        match &self.receiver {
+            Some((receiver, ValidationErrorAction::Collect)) => receiver.try_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Panics if there were messages at or above the min severity and the action is `Panic`
+    pub fn check_validation_errors(&self) {
+        if let Some((receiver, ValidationErrorAction::Panic)) = &self.receiver {
+            let messages = receiver.try_iter().collect::<Vec<_>>();
+            if !messages.is_empty() {
+                let messages = messages
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                panic!("Vulkan validation errors:\n{}", messages);
+            }
+        }
+    }
 }
 
 unsafe fn write_maybe_null(mut s: &mut String, p: *const c_char) {
@@ -66,7 +190,7 @@ unsafe extern "system" fn vk_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     use vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
 
@@ -103,6 +227,37 @@ unsafe extern "system" fn vk_debug_callback(
         log::error!("{}", message);
     }
 
+    if !user_data.is_null() {
+        let state = &*(user_data as *const CallbackState);
+        if at_least(message_severity, state.min_severity) {
+            // The receiver is only gone if DebugUtils is being dropped
+            if let Ok(sender) = state.sender.lock() {
+                let _ = sender.send(ValidationMessage {
+                    severity: message_severity,
+                    message,
+                });
+            }
+        }
+    }
+
     // According to the lunarg tutorial for the callback, false => don't bail out
     0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_threshold() {
+        use vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
+
+        assert!(at_least(Severity::ERROR, Severity::WARNING));
+        assert!(at_least(Severity::WARNING, Severity::WARNING));
+        assert!(!at_least(Severity::INFO, Severity::WARNING));
+        assert!(!at_least(Severity::VERBOSE, Severity::INFO));
+
+        assert_eq!(parse_severity("Warning"), Some(Severity::WARNING));
+        assert_eq!(parse_severity("1"), None);
+    }
+}
@@ -1,20 +1,100 @@
 use ash::vk;
 
-// TODO: Cleanup traits to use this
+pub use trekanten_derive::Vertex;
+
+/// The vertex attribute formats of fields made of one to four f32s, by their size in bytes. Used
+/// by `#[derive(Vertex)]` to pick the format of each field.
+pub const SIZE_TO_VK_FORMAT: [(usize, vk::Format); 4] = [
+    (4, vk::Format::R32_SFLOAT),
+    (8, vk::Format::R32G32_SFLOAT),
+    (12, vk::Format::R32G32B32_SFLOAT),
+    (16, vk::Format::R32G32B32A32_SFLOAT),
+];
+
+// Used by the code generated by #[derive(Vertex)], so that crates using it don't need to depend
+// on ash themselves
+#[doc(hidden)]
+pub mod derive_support {
+    pub use ash::vk;
+
+    pub fn attribute_format(field: &str, size: usize) -> vk::Format {
+        super::SIZE_TO_VK_FORMAT
+            .iter()
+            .find(|(s, _)| *s == size)
+            .map(|(_, format)| *format)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Vertex field {} is {} bytes, expected one to four f32s",
+                    field, size
+                )
+            })
+    }
+}
+
+/// The owned vertex input description of a vertex type, see `VertexDefinition::format`
 #[derive(Debug, Clone)]
 pub struct VertexFormat {
     pub binding_description: Vec<vk::VertexInputBindingDescription>,
     pub attribute_description: Vec<vk::VertexInputAttributeDescription>,
 }
 
+impl VertexFormat {
+    /// Add the bindings of `instance` after the existing ones, with a per-instance input rate.
+    /// The attribute locations of `instance` are kept as is so they may not overlap with the
+    /// existing ones.
+    pub fn with_instance_format(mut self, instance: VertexFormat) -> Self {
+        let first_binding = self
+            .binding_description
+            .iter()
+            .map(|b| b.binding + 1)
+            .max()
+            .unwrap_or(0);
+
+        self.binding_description
+            .extend(instance.binding_description.into_iter().map(|b| {
+                vk::VertexInputBindingDescription {
+                    binding: b.binding + first_binding,
+                    input_rate: vk::VertexInputRate::INSTANCE,
+                    ..b
+                }
+            }));
+        self.attribute_description
+            .extend(instance.attribute_description.into_iter().map(|a| {
+                vk::VertexInputAttributeDescription {
+                    binding: a.binding + first_binding,
+                    ..a
+                }
+            }));
+
+        self
+    }
+}
+
+/// Describes the memory layout of a vertex type to the pipeline. Use `#[derive(Vertex)]` for
+/// structs of f32 fields, e.g. the `Vertex` type in `examples/main.rs`, or implement it by hand
+/// with one attribute per field.
 pub trait VertexDefinition {
     fn binding_description() -> Vec<vk::VertexInputBindingDescription>;
     fn attribute_description() -> Vec<vk::VertexInputAttributeDescription>;
+
+    fn format() -> VertexFormat {
+        VertexFormat {
+            binding_description: Self::binding_description(),
+            attribute_description: Self::attribute_description(),
+        }
+    }
 }
 
 pub trait VertexSource {
     fn binding_description(&self) -> Vec<vk::VertexInputBindingDescription>;
     fn attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription>;
+
+    fn format(&self) -> VertexFormat {
+        VertexFormat {
+            binding_description: self.binding_description(),
+            attribute_description: self.attribute_description(),
+        }
+    }
 }
 
 impl<V: VertexDefinition> VertexSource for Vec<V> {
@@ -1,4 +1,6 @@
 use crate::util;
+
+#[cfg(feature = "glfw")]
 use std::time::Duration;
 
 pub trait Window {
@@ -8,10 +10,13 @@ pub trait Window {
 
 pub const WINDOW_HEIGHT: u32 = 300;
 pub const WINDOW_WIDTH: u32 = 300;
+#[cfg(any(feature = "glfw", feature = "winit"))]
 const WINDOW_TITLE: &str = "Trekanten";
 
+#[cfg(feature = "glfw")]
 pub type GlfwWindowEvents = std::sync::mpsc::Receiver<(f64, glfw::WindowEvent)>;
 
+#[cfg(feature = "glfw")]
 pub struct GlfwWindow {
     pub glfw: glfw::Glfw,
     pub window: glfw::Window,
@@ -20,6 +25,7 @@ pub struct GlfwWindow {
     frame_time_idx: usize,
 }
 
+#[cfg(feature = "glfw")]
 impl GlfwWindow {
     pub fn new() -> Self {
         let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).expect("Failed to init glfw");
@@ -69,6 +75,7 @@ impl GlfwWindow {
     }
 }
 
+#[cfg(feature = "glfw")]
 impl Window for GlfwWindow {
     fn required_instance_extensions(&self) -> Vec<String> {
         self.glfw
@@ -85,8 +92,57 @@ impl Window for GlfwWindow {
     }
 }
 
+#[cfg(feature = "glfw")]
 unsafe impl raw_window_handle::HasRawWindowHandle for GlfwWindow {
     fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
         self.window.raw_window_handle()
     }
 }
+
+#[cfg(feature = "winit")]
+pub struct WinitWindow {
+    pub window: winit::window::Window,
+}
+
+#[cfg(feature = "winit")]
+impl WinitWindow {
+    pub fn new<T: 'static>(event_loop: &winit::event_loop::EventLoopWindowTarget<T>) -> Self {
+        let window = winit::window::WindowBuilder::new()
+            .with_title(WINDOW_TITLE)
+            .with_inner_size(winit::dpi::PhysicalSize::new(WINDOW_WIDTH, WINDOW_HEIGHT))
+            .build(event_loop)
+            .expect("Failed to create winit window.");
+
+        Self { window }
+    }
+}
+
+#[cfg(feature = "winit")]
+impl Window for WinitWindow {
+    fn required_instance_extensions(&self) -> Vec<String> {
+        ash_window::enumerate_required_extensions(&self.window)
+            .expect("Could not get required instance extensions")
+            .iter()
+            .map(|ext| {
+                ext.to_str()
+                    .expect("Invalid instance extension name")
+                    .to_owned()
+            })
+            .collect()
+    }
+
+    fn extents(&self) -> util::Extent2D {
+        let size = self.window.inner_size();
+        util::Extent2D {
+            width: size.width,
+            height: size.height,
+        }
+    }
+}
+
+#[cfg(feature = "winit")]
+unsafe impl raw_window_handle::HasRawWindowHandle for WinitWindow {
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        self.window.raw_window_handle()
+    }
+}
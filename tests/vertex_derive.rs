@@ -0,0 +1,78 @@
+use ash::vk;
+
+use nalgebra_glm as glm;
+
+use trekanten::vertex::{Vertex, VertexDefinition};
+
+// The vertex type of examples/main.rs
+#[derive(Vertex)]
+#[repr(C, packed)]
+struct VertexTy {
+    pos: glm::Vec3,
+    col: glm::Vec3,
+    tex_coord: glm::Vec2,
+}
+
+#[derive(Vertex)]
+#[repr(C)]
+struct Tuple([f32; 4], f32);
+
+#[test]
+fn packed_struct() {
+    let bindings = VertexTy::binding_description();
+    assert_eq!(bindings.len(), 1);
+    assert_eq!(bindings[0].binding, 0);
+    assert_eq!(bindings[0].stride, 32);
+    assert_eq!(bindings[0].input_rate, vk::VertexInputRate::VERTEX);
+
+    let attributes = VertexTy::attribute_description()
+        .iter()
+        .map(|a| (a.binding, a.location, a.format, a.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        attributes,
+        vec![
+            (0, 0, vk::Format::R32G32B32_SFLOAT, 0),
+            (0, 1, vk::Format::R32G32B32_SFLOAT, 12),
+            (0, 2, vk::Format::R32G32_SFLOAT, 24),
+        ]
+    );
+}
+
+#[test]
+fn tuple_struct() {
+    assert_eq!(Tuple::binding_description()[0].stride, 20);
+    let attributes = Tuple::attribute_description()
+        .iter()
+        .map(|a| (a.location, a.format, a.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        attributes,
+        vec![
+            (0, vk::Format::R32G32B32A32_SFLOAT, 0),
+            (1, vk::Format::R32_SFLOAT, 16),
+        ]
+    );
+}
+
+#[derive(Vertex)]
+#[repr(C, packed(2))]
+struct Packed2(f32, [f64; 1], f32);
+
+#[derive(Vertex)]
+#[repr(C)]
+struct Padded {
+    a: f32,
+    b: [f64; 1],
+}
+
+#[test]
+fn field_alignment() {
+    let offsets = |attributes: Vec<vk::VertexInputAttributeDescription>| {
+        attributes.iter().map(|a| a.offset).collect::<Vec<_>>()
+    };
+    assert_eq!(offsets(Packed2::attribute_description()), vec![0, 4, 12]);
+    assert_eq!(Packed2::binding_description()[0].stride, 16);
+    assert_eq!(offsets(Padded::attribute_description()), vec![0, 8]);
+    assert_eq!(Padded::binding_description()[0].stride, 16);
+}
@@ -0,0 +1,139 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Fields, Index, Lit, Member, Meta, NestedMeta,
+};
+
+/// Implements `trekanten::vertex::VertexDefinition` for a struct. All fields are read from
+/// binding 0 and get consecutive locations in declaration order. Their formats are derived from
+/// their sizes, see `trekanten::vertex::SIZE_TO_VK_FORMAT`, so each field has to be one to four
+/// f32s, e.g. `[f32; 3]` or `glm::Vec3`. The struct has to be `#[repr(C)]`, optionally packed,
+/// as the offsets are computed from the C layout rules.
+#[proc_macro_derive(Vertex)]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match vertex_definition(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn vertex_definition(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "Vertex can only be derived for structs",
+            ))
+        }
+    };
+
+    let members = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|f| Member::Named(f.ident.clone().unwrap()))
+            .collect::<Vec<_>>(),
+        Fields::Unnamed(unnamed) => (0..unnamed.unnamed.len())
+            .map(|i| Member::Unnamed(Index::from(i)))
+            .collect::<Vec<_>>(),
+        Fields::Unit => Vec::new(),
+    };
+    if members.is_empty() {
+        return Err(syn::Error::new_spanned(
+            input,
+            "Vertex needs at least one field",
+        ));
+    }
+
+    // The alignment of each field is capped at this, like for #[repr(packed(N))]
+    let max_align = match repr_c_packing(input)? {
+        Some(packing) => quote! { #packing },
+        None => quote! { ::std::usize::MAX },
+    };
+
+    let field_types = fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+    let field_names = members.iter().map(|m| match m {
+        Member::Named(ident) => ident.to_string(),
+        Member::Unnamed(index) => index.index.to_string(),
+    });
+    let locations = 0..members.len() as u32;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let support = quote! { ::trekanten::vertex::derive_support };
+
+    Ok(quote! {
+        impl #impl_generics ::trekanten::vertex::VertexDefinition for #name #ty_generics #where_clause {
+            fn binding_description() -> Vec<#support::vk::VertexInputBindingDescription> {
+                vec![#support::vk::VertexInputBindingDescription {
+                    binding: 0,
+                    stride: ::std::mem::size_of::<Self>() as u32,
+                    input_rate: #support::vk::VertexInputRate::VERTEX,
+                }]
+            }
+
+            fn attribute_description() -> Vec<#support::vk::VertexInputAttributeDescription> {
+                let mut attributes = Vec::new();
+                // The end of the previous field
+                let mut end = 0usize;
+                #(
+                    let align = ::std::cmp::min(::std::mem::align_of::<#field_types>(), #max_align);
+                    let offset = (end + align - 1) / align * align;
+                    end = offset + ::std::mem::size_of::<#field_types>();
+                    attributes.push(#support::vk::VertexInputAttributeDescription {
+                        binding: 0,
+                        location: #locations,
+                        format: #support::attribute_format(
+                            #field_names,
+                            ::std::mem::size_of::<#field_types>(),
+                        ),
+                        offset: offset as u32,
+                    });
+                )*
+                let _ = end;
+                attributes
+            }
+        }
+    })
+}
+
+/// Checks that the struct is `#[repr(C)]` and returns `N` for `packed(N)`, with `packed` being
+/// `packed(1)`
+fn repr_c_packing(input: &DeriveInput) -> syn::Result<Option<usize>> {
+    let mut is_c = false;
+    let mut packing = None;
+    for attr in input.attrs.iter().filter(|a| a.path.is_ident("repr")) {
+        for nested in repr_items(attr)? {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("C") => is_c = true,
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("packed") => packing = Some(1),
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("packed") => {
+                    match list.nested.first() {
+                        Some(NestedMeta::Lit(Lit::Int(n))) => packing = Some(n.base10_parse()?),
+                        _ => return Err(syn::Error::new_spanned(list, "Expected packed(N)")),
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if !is_c {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Vertex can only be derived for #[repr(C)] structs",
+        ));
+    }
+
+    Ok(packing)
+}
+
+fn repr_items(attr: &Attribute) -> syn::Result<Vec<NestedMeta>> {
+    match attr.parse_meta()? {
+        Meta::List(list) => Ok(list.nested.into_iter().collect()),
+        meta => Err(syn::Error::new_spanned(meta, "Expected #[repr(...)]")),
+    }
+}